@@ -0,0 +1,101 @@
+/* Copyright 2021-2023 Protocol Labs
+ * SPDX-License-Identifier: Apache-2.0, MIT
+ *
+ * C bindings for the Filecoin Virtual Machine. See the fvm-ffi crate documentation for the
+ * ownership rules: inputs are borrowed for the duration of a call, and every FvmBytes is owned by
+ * the library and must be released with fvm_bytes_free.
+ */
+#ifndef FVM_H
+#define FVM_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define FVM_OK 0
+#define FVM_ERR (-1)
+#define FVM_NOT_FOUND 1
+
+#define FVM_APPLY_EXPLICIT 0
+#define FVM_APPLY_IMPLICIT 1
+
+typedef struct FvmMachine FvmMachine;
+
+typedef struct FvmBytes {
+    uint8_t *ptr;
+    size_t len;
+} FvmBytes;
+
+typedef struct FvmMachineConfig {
+    uint32_t network_version;
+    uint64_t chain_id;
+    int64_t epoch;
+    uint64_t timestamp;
+    /* Binary CID of the initial state root. */
+    const uint8_t *state_root;
+    size_t state_root_len;
+    /* Unsigned big-endian attoFIL. */
+    const uint8_t *base_fee;
+    size_t base_fee_len;
+    /* Unsigned big-endian attoFIL. */
+    const uint8_t *circ_supply;
+    size_t circ_supply_len;
+} FvmMachineConfig;
+
+typedef struct FvmBlockstore {
+    void *ctx;
+    /* Returns FVM_OK (with *out allocated by fvm_bytes_alloc) or FVM_NOT_FOUND. */
+    int32_t (*get)(void *ctx, const uint8_t *cid, size_t cid_len, FvmBytes *out);
+    int32_t (*put)(void *ctx, const uint8_t *cid, size_t cid_len, const uint8_t *data,
+                   size_t data_len);
+} FvmBlockstore;
+
+/* A fault_type of 0 means no fault; otherwise 1 (double-fork mining), 2 (parent grinding), or 3
+ * (time-offset mining), and target is the ID of the miner at fault. */
+typedef struct FvmConsensusFault {
+    uint8_t fault_type;
+    uint64_t target;
+    int64_t epoch;
+} FvmConsensusFault;
+
+typedef struct FvmExterns {
+    void *ctx;
+    int32_t (*get_chain_randomness)(void *ctx, int64_t pers, int64_t round, const uint8_t *entropy,
+                                    size_t entropy_len, uint8_t (*out)[32]);
+    int32_t (*get_beacon_randomness)(void *ctx, int64_t pers, int64_t round,
+                                     const uint8_t *entropy, size_t entropy_len,
+                                     uint8_t (*out)[32]);
+    int32_t (*verify_consensus_fault)(void *ctx, const uint8_t *h1, size_t h1_len,
+                                      const uint8_t *h2, size_t h2_len, const uint8_t *extra,
+                                      size_t extra_len, FvmConsensusFault *fault,
+                                      int64_t *gas_used);
+    /* Writes the binary CID (allocated by fvm_bytes_alloc) to *out. */
+    int32_t (*get_tipset_cid)(void *ctx, int64_t epoch, FvmBytes *out);
+} FvmExterns;
+
+FvmBytes fvm_bytes_alloc(size_t len);
+void fvm_bytes_free(FvmBytes bytes);
+
+/* Returns NULL on failure. The vtable contexts must outlive the machine, and callbacks may be
+ * invoked from any thread. */
+FvmMachine *fvm_create_machine(const FvmMachineConfig *config, FvmBlockstore blockstore,
+                               FvmExterns externs, FvmBytes *err);
+
+/* Applies a CBOR-encoded message, writing the CBOR-encoded result to *ret. */
+int32_t fvm_execute_message(FvmMachine *machine, const uint8_t *message, size_t message_len,
+                            uint32_t apply_kind, size_t raw_length, FvmBytes *ret, FvmBytes *err);
+
+/* Flushes the state, writing the binary state root CID to *root. */
+int32_t fvm_flush(FvmMachine *machine, FvmBytes *root, FvmBytes *err);
+
+void fvm_destroy_machine(FvmMachine *machine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* FVM_H */
@@ -0,0 +1,88 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::os::raw::c_void;
+
+use anyhow::{anyhow, Context};
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+
+use crate::{FvmBytes, FVM_NOT_FOUND, FVM_OK};
+
+/// A host-supplied blockstore.
+///
+/// `get` writes the block (allocated with [`fvm_bytes_alloc`](crate::fvm_bytes_alloc)) to its
+/// out-parameter and returns [`FVM_OK`], or returns [`FVM_NOT_FOUND`] if the block is missing.
+/// `put` stores a block under the given CID and returns [`FVM_OK`]. CIDs are passed in binary
+/// form, and any other return value is treated as an error.
+#[repr(C)]
+pub struct FvmBlockstore {
+    pub ctx: *mut c_void,
+    pub get: Option<FvmBlockstoreGet>,
+    pub put: Option<FvmBlockstorePut>,
+}
+
+pub type FvmBlockstoreGet = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    cid: *const u8,
+    cid_len: usize,
+    out: *mut FvmBytes,
+) -> i32;
+
+pub type FvmBlockstorePut = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    cid: *const u8,
+    cid_len: usize,
+    data: *const u8,
+    data_len: usize,
+) -> i32;
+
+/// The [`Blockstore`] implementation backed by an [`FvmBlockstore`].
+pub(crate) struct FfiBlockstore {
+    ctx: *mut c_void,
+    get: FvmBlockstoreGet,
+    put: FvmBlockstorePut,
+}
+
+// SAFETY: the host is required to make its blockstore callbacks safe to invoke from any thread
+// (see `fvm_create_machine`).
+unsafe impl Send for FfiBlockstore {}
+unsafe impl Sync for FfiBlockstore {}
+
+impl FfiBlockstore {
+    pub(crate) fn new(vtable: FvmBlockstore) -> anyhow::Result<Self> {
+        Ok(FfiBlockstore {
+            ctx: vtable.ctx,
+            get: vtable
+                .get
+                .context("blockstore get callback must not be null")?,
+            put: vtable
+                .put
+                .context("blockstore put callback must not be null")?,
+        })
+    }
+}
+
+impl Blockstore for FfiBlockstore {
+    fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        let k = k.to_bytes();
+        let mut out = FvmBytes::empty();
+        // SAFETY: the host guarantees that the callback and its context are valid, and that `out`
+        // is either left untouched or set to a buffer allocated with `fvm_bytes_alloc`.
+        let code = unsafe { (self.get)(self.ctx, k.as_ptr(), k.len(), &mut out) };
+        let block = unsafe { out.into_vec() };
+        match code {
+            FVM_OK => Ok(Some(block)),
+            FVM_NOT_FOUND => Ok(None),
+            code => Err(anyhow!("blockstore get failed with code {}", code)),
+        }
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+        let k = k.to_bytes();
+        // SAFETY: the host guarantees that the callback and its context are valid.
+        match unsafe { (self.put)(self.ctx, k.as_ptr(), k.len(), block.as_ptr(), block.len()) } {
+            FVM_OK => Ok(()),
+            code => Err(anyhow!("blockstore put failed with code {}", code)),
+        }
+    }
+}
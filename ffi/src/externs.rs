@@ -0,0 +1,216 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::os::raw::c_void;
+
+use anyhow::{anyhow, Context};
+use cid::Cid;
+use fvm::externs::{Chain, Consensus, Externs, Rand, Verifier};
+use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::consensus::{ConsensusFault, ConsensusFaultType};
+use num_traits::FromPrimitive;
+
+use crate::{FvmBytes, FVM_OK};
+
+/// A consensus fault reported by the host. A `fault_type` of 0 means no fault was found;
+/// otherwise it's a [`ConsensusFaultType`] and `target` is the ID of the miner at fault.
+#[repr(C)]
+#[derive(Default)]
+pub struct FvmConsensusFault {
+    pub fault_type: u8,
+    pub target: u64,
+    pub epoch: ChainEpoch,
+}
+
+pub type FvmRandomness = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    pers: i64,
+    round: ChainEpoch,
+    entropy: *const u8,
+    entropy_len: usize,
+    out: *mut [u8; 32],
+) -> i32;
+
+pub type FvmVerifyConsensusFault = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    h1: *const u8,
+    h1_len: usize,
+    h2: *const u8,
+    h2_len: usize,
+    extra: *const u8,
+    extra_len: usize,
+    fault: *mut FvmConsensusFault,
+    gas_used: *mut i64,
+) -> i32;
+
+pub type FvmGetTipsetCid =
+    unsafe extern "C" fn(ctx: *mut c_void, epoch: ChainEpoch, out: *mut FvmBytes) -> i32;
+
+/// The host-supplied node externs. Each callback returns [`FVM_OK`] on success, and any other
+/// value is treated as an error.
+///
+/// - `get_chain_randomness`/`get_beacon_randomness` write 32 bytes of randomness to `out`.
+/// - `verify_consensus_fault` writes the fault (if any) and the gas used to verify it.
+/// - `get_tipset_cid` writes the binary CID (allocated with
+///   [`fvm_bytes_alloc`](crate::fvm_bytes_alloc)) of the tipset at the given epoch.
+///
+/// Proofs are verified by this library and don't need to be supplied.
+#[repr(C)]
+pub struct FvmExterns {
+    pub ctx: *mut c_void,
+    pub get_chain_randomness: Option<FvmRandomness>,
+    pub get_beacon_randomness: Option<FvmRandomness>,
+    pub verify_consensus_fault: Option<FvmVerifyConsensusFault>,
+    pub get_tipset_cid: Option<FvmGetTipsetCid>,
+}
+
+/// The [`Externs`] implementation backed by an [`FvmExterns`].
+pub(crate) struct FfiExterns {
+    ctx: *mut c_void,
+    get_chain_randomness: FvmRandomness,
+    get_beacon_randomness: FvmRandomness,
+    verify_consensus_fault: FvmVerifyConsensusFault,
+    get_tipset_cid: FvmGetTipsetCid,
+}
+
+// SAFETY: the host is required to make its extern callbacks safe to invoke from any thread (see
+// `fvm_create_machine`).
+unsafe impl Send for FfiExterns {}
+unsafe impl Sync for FfiExterns {}
+
+impl FfiExterns {
+    pub(crate) fn new(vtable: FvmExterns) -> anyhow::Result<Self> {
+        Ok(FfiExterns {
+            ctx: vtable.ctx,
+            get_chain_randomness: vtable
+                .get_chain_randomness
+                .context("get_chain_randomness callback must not be null")?,
+            get_beacon_randomness: vtable
+                .get_beacon_randomness
+                .context("get_beacon_randomness callback must not be null")?,
+            verify_consensus_fault: vtable
+                .verify_consensus_fault
+                .context("verify_consensus_fault callback must not be null")?,
+            get_tipset_cid: vtable
+                .get_tipset_cid
+                .context("get_tipset_cid callback must not be null")?,
+        })
+    }
+
+    fn randomness(
+        &self,
+        name: &str,
+        f: FvmRandomness,
+        pers: i64,
+        round: ChainEpoch,
+        entropy: &[u8],
+    ) -> anyhow::Result<[u8; 32]> {
+        let mut out = [0u8; 32];
+        // SAFETY: the host guarantees that the callback and its context are valid.
+        match unsafe {
+            f(
+                self.ctx,
+                pers,
+                round,
+                entropy.as_ptr(),
+                entropy.len(),
+                &mut out,
+            )
+        } {
+            FVM_OK => Ok(out),
+            code => Err(anyhow!("{} failed with code {}", name, code)),
+        }
+    }
+}
+
+impl Externs for FfiExterns {}
+
+impl Verifier for FfiExterns {}
+
+impl Rand for FfiExterns {
+    fn get_chain_randomness(
+        &self,
+        pers: i64,
+        round: ChainEpoch,
+        entropy: &[u8],
+    ) -> anyhow::Result<[u8; 32]> {
+        self.randomness(
+            "get_chain_randomness",
+            self.get_chain_randomness,
+            pers,
+            round,
+            entropy,
+        )
+    }
+
+    fn get_beacon_randomness(
+        &self,
+        pers: i64,
+        round: ChainEpoch,
+        entropy: &[u8],
+    ) -> anyhow::Result<[u8; 32]> {
+        self.randomness(
+            "get_beacon_randomness",
+            self.get_beacon_randomness,
+            pers,
+            round,
+            entropy,
+        )
+    }
+}
+
+impl Consensus for FfiExterns {
+    fn verify_consensus_fault(
+        &self,
+        h1: &[u8],
+        h2: &[u8],
+        extra: &[u8],
+    ) -> anyhow::Result<(Option<ConsensusFault>, i64)> {
+        let mut fault = FvmConsensusFault::default();
+        let mut gas_used = 0i64;
+        // SAFETY: the host guarantees that the callback and its context are valid.
+        let code = unsafe {
+            (self.verify_consensus_fault)(
+                self.ctx,
+                h1.as_ptr(),
+                h1.len(),
+                h2.as_ptr(),
+                h2.len(),
+                extra.as_ptr(),
+                extra.len(),
+                &mut fault,
+                &mut gas_used,
+            )
+        };
+        if code != FVM_OK {
+            return Err(anyhow!("verify_consensus_fault failed with code {}", code));
+        }
+        if fault.fault_type == 0 {
+            return Ok((None, gas_used));
+        }
+        let fault_type = ConsensusFaultType::from_u8(fault.fault_type)
+            .with_context(|| format!("invalid consensus fault type {}", fault.fault_type))?;
+        Ok((
+            Some(ConsensusFault {
+                target: Address::new_id(fault.target),
+                epoch: fault.epoch,
+                fault_type,
+            }),
+            gas_used,
+        ))
+    }
+}
+
+impl Chain for FfiExterns {
+    fn get_tipset_cid(&self, epoch: ChainEpoch) -> anyhow::Result<Cid> {
+        let mut out = FvmBytes::empty();
+        // SAFETY: the host guarantees that the callback and its context are valid, and that `out`
+        // is either left untouched or set to a buffer allocated with `fvm_bytes_alloc`.
+        let code = unsafe { (self.get_tipset_cid)(self.ctx, epoch, &mut out) };
+        let cid = unsafe { out.into_vec() };
+        if code != FVM_OK {
+            return Err(anyhow!("get_tipset_cid failed with code {}", code));
+        }
+        Cid::try_from(&*cid).context("host returned an invalid tipset cid")
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! C bindings for embedding the FVM in non-Rust hosts (e.g., Lotus via CGO).
+//!
+//! The host supplies a blockstore ([`FvmBlockstore`]) and the node externs ([`FvmExterns`]) as
+//! vtables of callbacks, creates a machine with [`fvm_create_machine`], applies messages with
+//! [`fvm_execute_message`], flushes the resulting state with [`fvm_flush`], and finally releases
+//! the machine with [`fvm_destroy_machine`]. See `include/fvm.h` for the C declarations.
+//!
+//! ## Memory management
+//!
+//! All variable-length data crossing the boundary is passed as pointer/length pairs. Inputs are
+//! borrowed for the duration of the call and never retained. Outputs are returned as [`FvmBytes`],
+//! which always own memory allocated by this library: the host must release them with
+//! [`fvm_bytes_free`]. Likewise, callbacks that return data must allocate it with
+//! [`fvm_bytes_alloc`], and ownership passes to this library. This keeps every allocation on one
+//! side of the boundary, so the host's allocator (and Go's garbage collector) never sees memory it
+//! didn't allocate.
+//!
+//! ## Errors
+//!
+//! Functions return [`FVM_OK`] on success and [`FVM_ERR`] on failure, writing a UTF-8 error
+//! message to the `err` out-parameter (if non-null). Panics are caught at the boundary and
+//! reported as errors.
+use std::any::Any;
+use std::fmt::Display;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use anyhow::{anyhow, Context};
+use cid::Cid;
+use fvm::call_manager::DefaultCallManager;
+use fvm::engine::MultiEngine;
+use fvm::executor::{ApplyKind, ApplyRet, DefaultExecutor, Executor, ThreadedExecutor};
+use fvm::machine::{DefaultMachine, NetworkConfig};
+use fvm::DefaultKernel;
+use fvm_shared::bigint::{BigInt, Sign};
+use fvm_shared::chainid::ChainID;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use fvm_shared::version::NetworkVersion;
+use once_cell::sync::Lazy;
+
+mod blockstore;
+mod externs;
+
+pub use blockstore::{FvmBlockstore, FvmBlockstoreGet, FvmBlockstorePut};
+pub use externs::{
+    FvmConsensusFault, FvmExterns, FvmGetTipsetCid, FvmRandomness, FvmVerifyConsensusFault,
+};
+
+/// The call succeeded.
+pub const FVM_OK: i32 = 0;
+/// The call failed. For library functions, details are written to the `err` out-parameter.
+pub const FVM_ERR: i32 = -1;
+/// Returned by a blockstore `get` callback when the block isn't present.
+pub const FVM_NOT_FOUND: i32 = 1;
+
+/// Engines are expensive to create and cache compiled actor code, so they're shared between all
+/// machines created through this library.
+static ENGINES: Lazy<MultiEngine> = Lazy::new(|| {
+    let concurrency = std::thread::available_parallelism()
+        .map(|n| n.get() as u32)
+        .unwrap_or(1);
+    MultiEngine::new(concurrency)
+});
+
+type FfiKernel = DefaultKernel<
+    DefaultCallManager<DefaultMachine<blockstore::FfiBlockstore, externs::FfiExterns>>,
+>;
+
+/// An opaque handle to a machine and its executor.
+pub struct FvmMachine {
+    executor: ThreadedExecutor<DefaultExecutor<FfiKernel>>,
+}
+
+/// A byte buffer owned by this library. See the crate documentation for ownership rules.
+///
+/// Empty buffers have a null `ptr`.
+#[repr(C)]
+pub struct FvmBytes {
+    pub ptr: *mut u8,
+    pub len: usize,
+}
+
+impl FvmBytes {
+    const fn empty() -> Self {
+        FvmBytes {
+            ptr: ptr::null_mut(),
+            len: 0,
+        }
+    }
+
+    fn from_vec(v: Vec<u8>) -> Self {
+        if v.is_empty() {
+            return Self::empty();
+        }
+        let len = v.len();
+        let ptr = Box::into_raw(v.into_boxed_slice()) as *mut u8;
+        FvmBytes { ptr, len }
+    }
+
+    /// Takes ownership of the buffer.
+    ///
+    /// # Safety
+    ///
+    /// The buffer must have been allocated by this library and not yet freed.
+    unsafe fn into_vec(self) -> Vec<u8> {
+        if self.ptr.is_null() {
+            return Vec::new();
+        }
+        Box::from_raw(ptr::slice_from_raw_parts_mut(self.ptr, self.len)).into_vec()
+    }
+}
+
+/// The machine configuration. Token amounts are unsigned big-endian integers of attoFIL.
+#[repr(C)]
+pub struct FvmMachineConfig {
+    pub network_version: u32,
+    pub chain_id: u64,
+    pub epoch: i64,
+    pub timestamp: u64,
+    pub state_root: *const u8,
+    pub state_root_len: usize,
+    pub base_fee: *const u8,
+    pub base_fee_len: usize,
+    pub circ_supply: *const u8,
+    pub circ_supply_len: usize,
+}
+
+/// Allocates a zeroed buffer of `len` bytes for the host to fill and hand back to this library
+/// (e.g., from a blockstore `get` callback).
+#[no_mangle]
+pub extern "C" fn fvm_bytes_alloc(len: usize) -> FvmBytes {
+    FvmBytes::from_vec(vec![0u8; len])
+}
+
+/// Frees a buffer returned by this library.
+///
+/// # Safety
+///
+/// The buffer must have been allocated by this library and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn fvm_bytes_free(bytes: FvmBytes) {
+    drop(bytes.into_vec())
+}
+
+/// Creates a new machine on top of the given state root, returning null on failure.
+///
+/// The vtables' contexts must remain valid until the machine is destroyed, and the callbacks may
+/// be invoked from a thread other than the calling thread.
+///
+/// # Safety
+///
+/// All pointers must be valid for the duration of the call, and all callbacks must be non-null.
+#[no_mangle]
+pub unsafe extern "C" fn fvm_create_machine(
+    config: *const FvmMachineConfig,
+    blockstore: FvmBlockstore,
+    externs: FvmExterns,
+    err: *mut FvmBytes,
+) -> *mut FvmMachine {
+    let mut machine = ptr::null_mut();
+    ffi_call(err, || {
+        let config = config.as_ref().context("machine config must not be null")?;
+        let state_root = Cid::try_from(slice(config.state_root, config.state_root_len))
+            .context("invalid state root")?;
+
+        let mut network = NetworkConfig::new(NetworkVersion::new(config.network_version));
+        network.chain_id(ChainID::from(config.chain_id));
+        let mut context = network.for_epoch(config.epoch, config.timestamp, state_root);
+        context
+            .set_base_fee(token_amount(config.base_fee, config.base_fee_len))
+            .set_circulating_supply(token_amount(config.circ_supply, config.circ_supply_len));
+
+        let machine_impl = DefaultMachine::new(
+            &context,
+            blockstore::FfiBlockstore::new(blockstore)?,
+            externs::FfiExterns::new(externs)?,
+        )?;
+        let executor = DefaultExecutor::new(ENGINES.get(&context.network)?, machine_impl)?;
+        machine = Box::into_raw(Box::new(FvmMachine {
+            executor: ThreadedExecutor(executor),
+        }));
+        Ok(())
+    });
+    machine
+}
+
+/// Applies a CBOR-encoded message, writing the CBOR-encoded result to `ret`.
+///
+/// `apply_kind` is 0 for explicit (on-chain) messages and 1 for implicit (system) messages. The
+/// result is a tuple of the receipt fields (exit code, return data, gas used, events root)
+/// followed by the penalty, miner tip, base fee burn, over-estimation burn, refund, gas refund, gas
+/// burned, and failure information (a string, or null).
+///
+/// # Safety
+///
+/// The machine must have been created by [`fvm_create_machine`] and not yet destroyed, and must
+/// not be used concurrently. All other pointers must be valid for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn fvm_execute_message(
+    machine: *mut FvmMachine,
+    message: *const u8,
+    message_len: usize,
+    apply_kind: u32,
+    raw_length: usize,
+    ret: *mut FvmBytes,
+    err: *mut FvmBytes,
+) -> i32 {
+    ffi_call(err, || {
+        let machine = machine.as_mut().context("machine must not be null")?;
+        let ret = ret.as_mut().context("return pointer must not be null")?;
+        let message: Message = fvm_ipld_encoding::from_slice(slice(message, message_len))
+            .context("failed to decode message")?;
+        let apply_kind = match apply_kind {
+            0 => ApplyKind::Explicit,
+            1 => ApplyKind::Implicit,
+            other => return Err(anyhow!("invalid apply kind {}", other)),
+        };
+        let apply_ret = machine
+            .executor
+            .execute_message(message, apply_kind, raw_length)?;
+        *ret = FvmBytes::from_vec(encode_apply_ret(&apply_ret)?);
+        Ok(())
+    })
+}
+
+/// Flushes the machine's state, writing the new state root CID (in binary form) to `root`.
+///
+/// # Safety
+///
+/// See [`fvm_execute_message`].
+#[no_mangle]
+pub unsafe extern "C" fn fvm_flush(
+    machine: *mut FvmMachine,
+    root: *mut FvmBytes,
+    err: *mut FvmBytes,
+) -> i32 {
+    ffi_call(err, || {
+        let machine = machine.as_mut().context("machine must not be null")?;
+        let root = root.as_mut().context("root pointer must not be null")?;
+        *root = FvmBytes::from_vec(machine.executor.flush()?.to_bytes());
+        Ok(())
+    })
+}
+
+/// Destroys a machine, discarding any unflushed state. Passing null is a no-op.
+///
+/// # Safety
+///
+/// The machine must have been created by [`fvm_create_machine`] and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn fvm_destroy_machine(machine: *mut FvmMachine) {
+    if !machine.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(machine))));
+    }
+}
+
+fn encode_apply_ret(ret: &ApplyRet) -> anyhow::Result<Vec<u8>> {
+    let receipt = &ret.msg_receipt;
+    Ok(fvm_ipld_encoding::to_vec(&(
+        receipt.exit_code,
+        &receipt.return_data,
+        receipt.gas_used,
+        receipt.events_root,
+        &ret.penalty,
+        &ret.miner_tip,
+        &ret.base_fee_burn,
+        &ret.over_estimation_burn,
+        &ret.refund,
+        ret.gas_refund,
+        ret.gas_burned,
+        ret.failure_info.as_ref().map(|f| f.to_string()),
+    ))?)
+}
+
+/// Runs `f`, catching panics and converting any error into [`FVM_ERR`] (with the error message
+/// written to `err`).
+fn ffi_call(err: *mut FvmBytes, f: impl FnOnce() -> anyhow::Result<()>) -> i32 {
+    let e = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => return FVM_OK,
+        Ok(Err(e)) => format!("{:#}", e),
+        Err(panic) => format!("panic: {}", panic_message(&*panic)),
+    };
+    set_error(err, e);
+    FVM_ERR
+}
+
+fn set_error(err: *mut FvmBytes, e: impl Display) {
+    // SAFETY: the caller guarantees that `err` is either null or valid.
+    if let Some(err) = unsafe { err.as_mut() } {
+        *err = FvmBytes::from_vec(e.to_string().into_bytes());
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(s) = panic.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Borrows a host-supplied buffer. A null pointer is treated as an empty buffer.
+///
+/// # Safety
+///
+/// If non-null, `ptr` must be valid for reads of `len` bytes for the lifetime `'a`.
+unsafe fn slice<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
+unsafe fn token_amount(ptr: *const u8, len: usize) -> TokenAmount {
+    TokenAmount::from_atto(BigInt::from_bytes_be(Sign::Plus, slice(ptr, len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bytes_roundtrip() {
+        let bytes = FvmBytes::from_vec(vec![1, 2, 3]);
+        assert_eq!(bytes.len, 3);
+        assert_eq!(unsafe { bytes.into_vec() }, vec![1, 2, 3]);
+
+        let empty = fvm_bytes_alloc(0);
+        assert!(empty.ptr.is_null());
+        assert!(unsafe { empty.into_vec() }.is_empty());
+    }
+
+    #[test]
+    fn errors_and_panics() {
+        let mut err = FvmBytes::empty();
+        assert_eq!(ffi_call(&mut err, || Ok(())), FVM_OK);
+        assert!(err.ptr.is_null());
+
+        assert_eq!(ffi_call(&mut err, || Err(anyhow!("oops"))), FVM_ERR);
+        assert_eq!(unsafe { err.into_vec() }, b"oops");
+
+        let mut err = FvmBytes::empty();
+        assert_eq!(ffi_call(&mut err, || panic!("boom")), FVM_ERR);
+        assert_eq!(unsafe { err.into_vec() }, b"panic: boom");
+    }
+}
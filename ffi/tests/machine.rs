@@ -0,0 +1,290 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Drives a machine end-to-end through the C API, with the blockstore and externs implemented as
+//! host callbacks.
+use std::collections::HashMap;
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::Mutex;
+
+use cid::Cid;
+use fvm::machine::{Manifest, BURNT_FUNDS_ACTOR_ID, REWARD_ACTOR_ID};
+use fvm::state_tree::{ActorState, StateTree};
+use fvm::system_actor::{State as SystemActorState, SYSTEM_ACTOR_ID};
+use fvm_ffi::*;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_encoding::{CborStore, RawBytes};
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::message::Message;
+use fvm_shared::state::StateTreeVersion;
+use fvm_shared::METHOD_SEND;
+use multihash::Code;
+
+const SENDER: u64 = 100;
+const RECEIVER: u64 = 101;
+const BASE_FEE: u64 = 100;
+
+/// The host's blockstore. Callbacks may be invoked from any thread, so it must be thread-safe.
+#[derive(Default)]
+struct HostStore(Mutex<HashMap<Cid, Vec<u8>>>);
+
+impl Blockstore for HostStore {
+    fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.0.lock().unwrap().get(k).cloned())
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+        self.0.lock().unwrap().insert(*k, block.into());
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn host_get(
+    ctx: *mut c_void,
+    cid: *const u8,
+    cid_len: usize,
+    out: *mut FvmBytes,
+) -> i32 {
+    let store = &*(ctx as *const HostStore);
+    let cid = Cid::try_from(std::slice::from_raw_parts(cid, cid_len)).unwrap();
+    match store.get(&cid).unwrap() {
+        Some(block) => {
+            let bytes = fvm_bytes_alloc(block.len());
+            if !block.is_empty() {
+                ptr::copy_nonoverlapping(block.as_ptr(), bytes.ptr, block.len());
+            }
+            *out = bytes;
+            FVM_OK
+        }
+        None => FVM_NOT_FOUND,
+    }
+}
+
+unsafe extern "C" fn host_put(
+    ctx: *mut c_void,
+    cid: *const u8,
+    cid_len: usize,
+    data: *const u8,
+    data_len: usize,
+) -> i32 {
+    let store = &*(ctx as *const HostStore);
+    let cid = Cid::try_from(std::slice::from_raw_parts(cid, cid_len)).unwrap();
+    store
+        .put_keyed(&cid, std::slice::from_raw_parts(data, data_len))
+        .unwrap();
+    FVM_OK
+}
+
+unsafe extern "C" fn host_randomness(
+    _ctx: *mut c_void,
+    _pers: i64,
+    _round: i64,
+    _entropy: *const u8,
+    _entropy_len: usize,
+    out: *mut [u8; 32],
+) -> i32 {
+    *out = [0; 32];
+    FVM_OK
+}
+
+unsafe extern "C" fn host_verify_consensus_fault(
+    _ctx: *mut c_void,
+    _h1: *const u8,
+    _h1_len: usize,
+    _h2: *const u8,
+    _h2_len: usize,
+    _extra: *const u8,
+    _extra_len: usize,
+    _fault: *mut FvmConsensusFault,
+    gas_used: *mut i64,
+) -> i32 {
+    *gas_used = 0;
+    FVM_OK
+}
+
+unsafe extern "C" fn host_get_tipset_cid(
+    _ctx: *mut c_void,
+    _epoch: i64,
+    _out: *mut FvmBytes,
+) -> i32 {
+    FVM_ERR
+}
+
+/// Creates a state tree with the (dummy) builtin actors manifest, a funded sender, a receiver, and
+/// the actors receiving gas fees.
+fn initial_state(store: &HostStore) -> Cid {
+    let code = |name| {
+        Manifest::DUMMY_CODES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .unwrap()
+            .1
+    };
+    let manifest = store
+        .put_cbor(&Manifest::DUMMY_CODES, Code::Blake2b256)
+        .unwrap();
+    let system_state = store
+        .put_cbor(
+            &SystemActorState {
+                builtin_actors: manifest,
+            },
+            Code::Blake2b256,
+        )
+        .unwrap();
+
+    let mut tree = StateTree::new(store, StateTreeVersion::V5).unwrap();
+    tree.set_actor(
+        SYSTEM_ACTOR_ID,
+        ActorState::new(
+            code("system"),
+            system_state,
+            TokenAmount::default(),
+            0,
+            None,
+        ),
+    );
+    for id in [REWARD_ACTOR_ID, BURNT_FUNDS_ACTOR_ID, RECEIVER] {
+        tree.set_actor(id, ActorState::new_empty(code("account"), None));
+    }
+    let mut sender = ActorState::new_empty(code("account"), None);
+    sender.balance = TokenAmount::from_whole(1);
+    tree.set_actor(SENDER, sender);
+    tree.flush().unwrap()
+}
+
+/// Takes ownership of a buffer returned by the library.
+fn take(bytes: FvmBytes) -> Vec<u8> {
+    let v = if bytes.ptr.is_null() {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(bytes.ptr, bytes.len) }.to_vec()
+    };
+    unsafe { fvm_bytes_free(bytes) };
+    v
+}
+
+fn empty() -> FvmBytes {
+    FvmBytes {
+        ptr: ptr::null_mut(),
+        len: 0,
+    }
+}
+
+type EncodedApplyRet = (
+    ExitCode,
+    RawBytes,
+    u64,
+    Option<Cid>,
+    TokenAmount,
+    TokenAmount,
+    TokenAmount,
+    TokenAmount,
+    TokenAmount,
+    u64,
+    u64,
+    Option<String>,
+);
+
+#[test]
+fn create_machine_and_apply_message() {
+    let store = HostStore::default();
+    let state_root = initial_state(&store).to_bytes();
+    let base_fee = BASE_FEE.to_be_bytes();
+    let circ_supply = 1_000_000u64.to_be_bytes();
+    let config = FvmMachineConfig {
+        network_version: 18,
+        chain_id: 314,
+        epoch: 1,
+        timestamp: 0,
+        state_root: state_root.as_ptr(),
+        state_root_len: state_root.len(),
+        base_fee: base_fee.as_ptr(),
+        base_fee_len: base_fee.len(),
+        circ_supply: circ_supply.as_ptr(),
+        circ_supply_len: circ_supply.len(),
+    };
+    let blockstore = FvmBlockstore {
+        ctx: &store as *const HostStore as *mut c_void,
+        get: Some(host_get),
+        put: Some(host_put),
+    };
+    let externs = FvmExterns {
+        ctx: ptr::null_mut(),
+        get_chain_randomness: Some(host_randomness),
+        get_beacon_randomness: Some(host_randomness),
+        verify_consensus_fault: Some(host_verify_consensus_fault),
+        get_tipset_cid: Some(host_get_tipset_cid),
+    };
+
+    let mut err = empty();
+    let machine = unsafe { fvm_create_machine(&config, blockstore, externs, &mut err) };
+    assert!(
+        !machine.is_null(),
+        "failed to create machine: {}",
+        String::from_utf8_lossy(&take(err))
+    );
+
+    let message = fvm_ipld_encoding::to_vec(&Message {
+        from: Address::new_id(SENDER),
+        to: Address::new_id(RECEIVER),
+        sequence: 0,
+        value: TokenAmount::from_atto(10),
+        method_num: METHOD_SEND,
+        gas_limit: 10_000_000,
+        gas_fee_cap: TokenAmount::from_atto(BASE_FEE),
+        ..Message::default()
+    })
+    .unwrap();
+    let mut ret = empty();
+    let mut err = empty();
+    let code = unsafe {
+        fvm_execute_message(
+            machine,
+            message.as_ptr(),
+            message.len(),
+            0,
+            message.len(),
+            &mut ret,
+            &mut err,
+        )
+    };
+    assert_eq!(code, FVM_OK, "{}", String::from_utf8_lossy(&take(err)));
+    let (exit_code, _, gas_used, _, _, _, base_fee_burn, ..): EncodedApplyRet =
+        fvm_ipld_encoding::from_slice(&take(ret)).unwrap();
+    assert_eq!(exit_code, ExitCode::OK);
+    assert!(gas_used > 0);
+    assert_eq!(base_fee_burn, TokenAmount::from_atto(BASE_FEE) * gas_used);
+
+    // Invalid input is reported as an error, not a crash.
+    let mut ret = empty();
+    let mut err = empty();
+    let code =
+        unsafe { fvm_execute_message(machine, [0xff].as_ptr(), 1, 0, 1, &mut ret, &mut err) };
+    assert_eq!(code, FVM_ERR);
+    assert!(!take(err).is_empty());
+
+    let mut root = empty();
+    let mut err = empty();
+    let code = unsafe { fvm_flush(machine, &mut root, &mut err) };
+    assert_eq!(code, FVM_OK, "{}", String::from_utf8_lossy(&take(err)));
+    unsafe { fvm_destroy_machine(machine) };
+
+    // The new state was written through the host's blockstore.
+    let root = Cid::try_from(take(root)).unwrap();
+    let tree = StateTree::new_from_root(&store, &root).unwrap();
+    let sender = tree.get_actor(SENDER).unwrap().unwrap();
+    assert_eq!(sender.sequence, 1);
+    assert_eq!(
+        tree.get_actor(RECEIVER).unwrap().unwrap().balance,
+        TokenAmount::from_atto(10)
+    );
+    assert_eq!(
+        tree.get_actor(BURNT_FUNDS_ACTOR_ID)
+            .unwrap()
+            .unwrap()
+            .balance,
+        base_fee_burn
+    );
+}
@@ -0,0 +1,392 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Measures the CPU cost of syscalls (as implemented by the `DefaultKernel`) on representative
+//! inputs, and compares it to what the price list charges for them.
+//!
+//! Run with `cargo bench -p fvm --bench syscalls`. After the criterion benchmarks a report is
+//! printed with the mean time of each syscall, the compute gas charged for it, and the resulting
+//! gas per nanosecond. Prices are calibrated against 10 gas/ns, so syscalls far from that ratio are
+//! under- or over-priced on the benchmarking machine. Set `FVM_SYSCALL_BENCH_REPORT` to a file path
+//! to also write the report as CSV.
+//!
+//! Only the kernel is measured. The flat per-syscall charge (covering the wasm/host transition and
+//! memory access) isn't included.
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use cid::Cid;
+use criterion::{black_box, criterion_group, BenchmarkId, Criterion};
+use fvm::gas::{price_list_by_network_version, Gas, GasTracker, PriceList};
+use fvm::kernel::default::DefaultKernel;
+use fvm::kernel::{BlockRegistry, CryptoOps, IpldBlockOps};
+use fvm::Kernel;
+use fvm_ipld_encoding::{to_vec, BytesSer, DAG_CBOR};
+use fvm_shared::address::Address;
+use fvm_shared::crypto::hash::SupportedHashes;
+use fvm_shared::crypto::signature::SignatureType;
+use lazy_static::lazy_static;
+use num_traits::Zero;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+#[path = "../tests/dummy.rs"]
+#[allow(dead_code)]
+mod dummy;
+
+use dummy::{DummyCallManager, STUB_NETWORK_VER};
+
+type BenchKernel = DefaultKernel<DummyCallManager>;
+
+/// Kernels are replaced after this many calls so that syscalls allocating blocks don't grow the
+/// block registry without bound. Replacing a kernel isn't included in the measured time.
+const KERNEL_REUSE: u64 = 1024;
+
+/// The gas per nanosecond the price list is calibrated against.
+const TARGET_GAS_PER_NS: f64 = 10.0;
+
+const DATA_SIZES: &[usize] = &[32, 256, 1 << 10, 16 << 10, 256 << 10];
+
+lazy_static! {
+    static ref REPORT: Mutex<BTreeMap<(String, String), Row>> = Default::default();
+}
+
+/// The accumulated measurements of a single syscall/input pair.
+struct Row {
+    compute_gas: Gas,
+    iterations: u64,
+    elapsed: Duration,
+}
+
+impl Row {
+    fn ns_per_call(&self) -> f64 {
+        self.elapsed.as_nanos() as f64 / self.iterations.max(1) as f64
+    }
+
+    fn gas_per_ns(&self) -> f64 {
+        self.compute_gas.as_milligas() as f64 / 1000.0 / self.ns_per_call()
+    }
+}
+
+fn price_list() -> &'static PriceList {
+    price_list_by_network_version(STUB_NETWORK_VER)
+}
+
+fn new_kernel() -> BenchKernel {
+    let (call_manager, _) = DummyCallManager::new_with_gas(GasTracker::new(
+        Gas::from_milligas(u64::MAX),
+        Gas::zero(),
+        false,
+    ));
+    BenchKernel::new(
+        call_manager,
+        BlockRegistry::default(),
+        0,
+        0,
+        0,
+        Zero::zero(),
+        false,
+    )
+}
+
+fn random_bytes(rng: &mut StdRng, len: usize) -> Vec<u8> {
+    let mut data = vec![0; len];
+    rng.fill_bytes(&mut data);
+    data
+}
+
+/// A CBOR encoded byte string (without links) with a total size of roughly `size` bytes.
+fn cbor_block(rng: &mut StdRng, size: usize) -> Vec<u8> {
+    to_vec(&BytesSer(&random_bytes(rng, size))).unwrap()
+}
+
+/// Benchmarks a single syscall on a single input, recording the results in the report.
+///
+/// `prepare` is called on every new kernel (i.e., every [`KERNEL_REUSE`] calls) and isn't
+/// measured; its result is passed to every call of `op`. `compute_gas` is the compute gas charged
+/// by the price list for one call of `op`.
+fn bench_syscall<S>(
+    c: &mut Criterion,
+    syscall: &str,
+    input: String,
+    compute_gas: Gas,
+    prepare: impl Fn(&mut BenchKernel) -> S,
+    mut op: impl FnMut(&mut BenchKernel, &S),
+) {
+    c.bench_with_input(BenchmarkId::new(syscall, &input), &input, |b, input| {
+        b.iter_custom(|iters| {
+            let mut elapsed = Duration::ZERO;
+            let mut remaining = iters;
+            while remaining > 0 {
+                let n = remaining.min(KERNEL_REUSE);
+                remaining -= n;
+                let mut kernel = new_kernel();
+                let state = prepare(&mut kernel);
+                let start = Instant::now();
+                for _ in 0..n {
+                    op(&mut kernel, &state);
+                }
+                elapsed += start.elapsed();
+            }
+            let mut report = REPORT.lock().unwrap();
+            let row = report
+                .entry((syscall.to_owned(), input.clone()))
+                .or_insert(Row {
+                    compute_gas,
+                    iterations: 0,
+                    elapsed: Duration::ZERO,
+                });
+            row.iterations += iters;
+            row.elapsed += elapsed;
+            elapsed
+        })
+    });
+}
+
+fn bench_hashing(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    for hasher in [
+        SupportedHashes::Sha2_256,
+        SupportedHashes::Blake2b256,
+        SupportedHashes::Blake2b512,
+        SupportedHashes::Keccak256,
+        SupportedHashes::Ripemd160,
+    ] {
+        for &size in DATA_SIZES {
+            let data = random_bytes(&mut rng, size);
+            bench_syscall(
+                c,
+                "hash",
+                format!("{hasher:?}/{size}"),
+                price_list().on_hashing(hasher, size).compute_gas,
+                |_| (),
+                |k, _| {
+                    black_box(k.hash(hasher as u64, &data).unwrap());
+                },
+            );
+        }
+    }
+}
+
+fn bench_signatures(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let pl = price_list();
+
+    let secp_key = libsecp256k1::SecretKey::random(&mut rng);
+    let secp_addr =
+        Address::new_secp256k1(&libsecp256k1::PublicKey::from_secret_key(&secp_key).serialize())
+            .unwrap();
+    let secp_sign = |data: &[u8]| {
+        let hash: [u8; 32] = blake2b_simd::Params::new()
+            .hash_length(32)
+            .hash(data)
+            .as_bytes()
+            .try_into()
+            .unwrap();
+        let (sig, recovery_id) =
+            libsecp256k1::sign(&libsecp256k1::Message::parse(&hash), &secp_key);
+        let mut bytes = [0u8; 65];
+        bytes[..64].copy_from_slice(&sig.serialize());
+        bytes[64] = recovery_id.serialize();
+        (hash, bytes)
+    };
+
+    let bls_key = bls_signatures::PrivateKey::generate(&mut rng);
+    let bls_addr =
+        Address::new_bls(&bls_signatures::Serialize::as_bytes(&bls_key.public_key())).unwrap();
+
+    for &size in &DATA_SIZES[..3] {
+        let data = random_bytes(&mut rng, size);
+
+        let (_, sig) = secp_sign(&data);
+        bench_syscall(
+            c,
+            "verify_signature",
+            format!("secp256k1/{size}"),
+            pl.on_verify_signature(SignatureType::Secp256k1, size)
+                .compute_gas,
+            |_| (),
+            |k, _| {
+                assert!(k
+                    .verify_signature(SignatureType::Secp256k1, &sig, &secp_addr, &data)
+                    .unwrap());
+            },
+        );
+
+        let sig = bls_signatures::Serialize::as_bytes(&bls_key.sign(&data));
+        bench_syscall(
+            c,
+            "verify_signature",
+            format!("bls/{size}"),
+            pl.on_verify_signature(SignatureType::BLS, size).compute_gas,
+            |_| (),
+            |k, _| {
+                assert!(k
+                    .verify_signature(SignatureType::BLS, &sig, &bls_addr, &data)
+                    .unwrap());
+            },
+        );
+    }
+
+    let (hash, sig) = secp_sign(b"recover me");
+    bench_syscall(
+        c,
+        "recover_secp_public_key",
+        "65".into(),
+        pl.on_recover_secp_public_key().compute_gas,
+        |_| (),
+        |k, _| {
+            black_box(k.recover_secp_public_key(&hash, &sig).unwrap());
+        },
+    );
+
+    for signers in [1, 10, 100] {
+        let keys: Vec<_> = (0..signers)
+            .map(|_| bls_signatures::PrivateKey::generate(&mut rng))
+            .collect();
+        let messages: Vec<_> = (0..signers).map(|_| random_bytes(&mut rng, 32)).collect();
+        let sigs: Vec<_> = keys
+            .iter()
+            .zip(&messages)
+            .map(|(key, msg)| key.sign(msg))
+            .collect();
+        let aggregate: [u8; 96] =
+            bls_signatures::Serialize::as_bytes(&bls_signatures::aggregate(&sigs).unwrap())
+                .try_into()
+                .unwrap();
+        let pub_keys: Vec<[u8; 48]> = keys
+            .iter()
+            .map(|key| {
+                bls_signatures::Serialize::as_bytes(&key.public_key())
+                    .try_into()
+                    .unwrap()
+            })
+            .collect();
+        let plaintexts: Vec<&[u8]> = messages.iter().map(|m| &m[..]).collect();
+        bench_syscall(
+            c,
+            "verify_aggregate_signature",
+            format!("{signers}x32"),
+            pl.on_verify_aggregate_signature(signers, signers * 32)
+                .compute_gas,
+            |_| (),
+            |k, _| {
+                assert!(k
+                    .verify_aggregate_signature(&aggregate, &pub_keys, &plaintexts)
+                    .unwrap());
+            },
+        );
+    }
+}
+
+fn bench_blocks(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let pl = price_list();
+    let create_and_link = |k: &mut BenchKernel, data: &[u8]| -> Cid {
+        let id = k.block_create(DAG_CBOR, data).unwrap();
+        k.block_link(id, SupportedHashes::Blake2b256 as u64, 32)
+            .unwrap()
+    };
+
+    for &size in DATA_SIZES {
+        let block = cbor_block(&mut rng, size);
+        let len = block.len();
+
+        bench_syscall(
+            c,
+            "block_create",
+            size.to_string(),
+            pl.on_block_create(len).compute_gas,
+            |_| (),
+            |k, _| {
+                black_box(k.block_create(DAG_CBOR, &block).unwrap());
+            },
+        );
+
+        bench_syscall(
+            c,
+            "block_link",
+            size.to_string(),
+            pl.on_block_link(SupportedHashes::Blake2b256, len)
+                .compute_gas,
+            |k| k.block_create(DAG_CBOR, &block).unwrap(),
+            |k, &id| {
+                black_box(
+                    k.block_link(id, SupportedHashes::Blake2b256 as u64, 32)
+                        .unwrap(),
+                );
+            },
+        );
+
+        bench_syscall(
+            c,
+            "block_open",
+            size.to_string(),
+            pl.on_block_open_base().compute_gas + pl.on_block_open_per_byte(len).compute_gas,
+            |k| create_and_link(k, &block),
+            |k, cid| {
+                black_box(k.block_open(cid).unwrap());
+            },
+        );
+
+        let mut buf = vec![0; len];
+        bench_syscall(
+            c,
+            "block_read",
+            size.to_string(),
+            pl.on_block_read(len).compute_gas,
+            |k| k.block_create(DAG_CBOR, &block).unwrap(),
+            |k, &id| {
+                black_box(k.block_read(id, 0, &mut buf).unwrap());
+            },
+        );
+    }
+}
+
+/// Prints (and optionally writes) the gas/time report for all benchmarks that ran.
+fn report() {
+    let report = REPORT.lock().unwrap();
+    if report.is_empty() {
+        return;
+    }
+    let mut csv = String::from("syscall,input,iterations,ns_per_call,compute_gas,gas_per_ns\n");
+    println!(
+        "\n{:<28} {:>18} {:>14} {:>16} {:>10} {:>8}",
+        "syscall", "input", "ns/call", "compute gas", "gas/ns", "ratio"
+    );
+    for ((syscall, input), row) in report.iter() {
+        println!(
+            "{:<28} {:>18} {:>14.1} {:>16} {:>10.2} {:>8.2}",
+            syscall,
+            input,
+            row.ns_per_call(),
+            row.compute_gas,
+            row.gas_per_ns(),
+            row.gas_per_ns() / TARGET_GAS_PER_NS,
+        );
+        writeln!(
+            csv,
+            "{},{},{},{:.3},{},{:.3}",
+            syscall,
+            input,
+            row.iterations,
+            row.ns_per_call(),
+            row.compute_gas,
+            row.gas_per_ns()
+        )
+        .unwrap();
+    }
+    println!("\nratio = (gas/ns) / {TARGET_GAS_PER_NS}; < 1 means under-priced on this machine");
+    if let Some(path) = std::env::var_os("FVM_SYSCALL_BENCH_REPORT") {
+        std::fs::write(&path, csv).expect("failed to write the syscall report");
+    }
+}
+
+criterion_group!(benches, bench_hashing, bench_signatures, bench_blocks);
+
+fn main() {
+    benches();
+    Criterion::default().configure_from_args().final_summary();
+    report();
+}
@@ -2,8 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0, MIT
 //! Private blockstores for use in the FVM.
 
-mod buffered;
 mod discard;
 
-pub use buffered::BufferedBlockstore;
 pub(crate) use discard::DiscardBlockstore;
+pub use fvm_ipld_blockstore::{BlockstoreStats, BufferedBlockstore};
@@ -82,6 +82,11 @@ pub struct Frame {
     pub code: ExitCode,
     /// The abort message.
     pub message: String,
+    /// The Wasm stack at the point the actor trapped, most recent call first. This is only
+    /// captured when actor debugging is enabled.
+    pub wasm_backtrace: Vec<WasmFrame>,
+    /// The Wasm trap that aborted the actor, if it trapped.
+    pub trap: Option<TrapKind>,
 }
 
 impl Display for Frame {
@@ -93,7 +98,94 @@ impl Display for Frame {
             self.method,
             &self.message,
             self.code,
-        )
+        )?;
+        for wasm_frame in &self.wasm_backtrace {
+            write!(f, "\n    at {}", wasm_frame)?;
+        }
+        Ok(())
+    }
+}
+
+/// The kind of Wasm trap that aborted an actor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TrapKind {
+    /// The Wasm stack overflowed.
+    StackOverflow,
+    /// An out-of-bounds memory access.
+    MemoryOutOfBounds,
+    /// A misaligned atomic memory access.
+    HeapMisaligned,
+    /// An out-of-bounds table access.
+    TableOutOfBounds,
+    /// An indirect call to a null table entry.
+    IndirectCallToNull,
+    /// An indirect call with the wrong signature.
+    BadSignature,
+    /// An integer arithmetic operation overflowed.
+    IntegerOverflow,
+    /// An integer division by zero.
+    IntegerDivisionByZero,
+    /// A failed float-to-int conversion.
+    BadConversionToInteger,
+    /// An `unreachable` instruction was executed.
+    UnreachableCodeReached,
+    /// Execution was interrupted (e.g., by the execution timeout).
+    Interrupt,
+    /// An atomic wait on non-shared memory.
+    AtomicWaitNonSharedMemory,
+    /// Execution ran out of fuel (see [`crate::machine::NetworkConfig::fuel_metering`]).
+    OutOfFuel,
+    /// Any other trap.
+    Other,
+}
+
+impl From<wasmtime::Trap> for TrapKind {
+    fn from(trap: wasmtime::Trap) -> Self {
+        use wasmtime::Trap;
+        match trap {
+            Trap::StackOverflow => TrapKind::StackOverflow,
+            Trap::MemoryOutOfBounds => TrapKind::MemoryOutOfBounds,
+            Trap::HeapMisaligned => TrapKind::HeapMisaligned,
+            Trap::TableOutOfBounds => TrapKind::TableOutOfBounds,
+            Trap::IndirectCallToNull => TrapKind::IndirectCallToNull,
+            Trap::BadSignature => TrapKind::BadSignature,
+            Trap::IntegerOverflow => TrapKind::IntegerOverflow,
+            Trap::IntegerDivisionByZero => TrapKind::IntegerDivisionByZero,
+            Trap::BadConversionToInteger => TrapKind::BadConversionToInteger,
+            Trap::UnreachableCodeReached => TrapKind::UnreachableCodeReached,
+            Trap::Interrupt => TrapKind::Interrupt,
+            Trap::AtomicWaitNonSharedMemory => TrapKind::AtomicWaitNonSharedMemory,
+            Trap::OutOfFuel => TrapKind::OutOfFuel,
+            _ => TrapKind::Other,
+        }
+    }
+}
+
+/// A frame in an actor's Wasm stack.
+#[derive(Clone, Debug)]
+pub struct WasmFrame {
+    /// The index of the function within the actor's module.
+    pub func_index: u32,
+    /// The function's name, if the module has a name section.
+    pub func_name: Option<String>,
+}
+
+impl From<&wasmtime::FrameInfo> for WasmFrame {
+    fn from(frame: &wasmtime::FrameInfo) -> Self {
+        WasmFrame {
+            func_index: frame.func_index(),
+            func_name: frame.func_name().map(String::from),
+        }
+    }
+}
+
+impl Display for WasmFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.func_name {
+            Some(name) => write!(f, "{} (func {})", name, self.func_index),
+            None => write!(f, "<unknown> (func {})", self.func_index),
+        }
     }
 }
 
@@ -12,13 +12,14 @@ use fvm_shared::econ::TokenAmount;
 use fvm_shared::error::{ErrorNumber, ExitCode};
 use fvm_shared::event::StampedEvent;
 use fvm_shared::sys::BlockId;
-use fvm_shared::{ActorID, MethodNum, METHOD_SEND};
+use fvm_shared::upgrade::UpgradeInfo;
+use fvm_shared::{ActorID, MethodNum, METHOD_SEND, METHOD_UPGRADE};
 use num_traits::Zero;
 
 use super::state_access_tracker::{ActorAccessState, StateAccessTracker};
-use super::{Backtrace, CallManager, InvocationResult, NO_DATA_BLOCK_ID};
+use super::{Backtrace, CallManager, InvocationResult, ReentrancyPolicy, NO_DATA_BLOCK_ID};
 use crate::blockstore::DiscardBlockstore;
-use crate::call_manager::backtrace::Frame;
+use crate::call_manager::backtrace::{Frame, TrapKind, WasmFrame};
 use crate::call_manager::FinishRet;
 use crate::eam_actor::EAM_ACTOR_ID;
 use crate::engine::Engine;
@@ -30,8 +31,8 @@ use crate::machine::limiter::MemoryLimiter;
 use crate::machine::Machine;
 use crate::state_tree::ActorState;
 use crate::syscalls::error::Abort;
-use crate::syscalls::{charge_for_exec, update_gas_available};
-use crate::trace::{ExecutionEvent, ExecutionTrace};
+use crate::syscalls::{charge_for_exec, charge_for_fuel_exhaustion, update_gas_available};
+use crate::trace::{ExecutionEvent, ExecutionTrace, SyscallRecord};
 use crate::{syscall_error, system_actor};
 
 /// The default [`CallManager`] implementation.
@@ -65,6 +66,14 @@ pub struct InnerDefaultCallManager<M: Machine> {
     num_actors_created: u64,
     /// Current call-stack depth.
     call_stack_depth: u32,
+    /// The actors whose code is currently executing on the call stack, innermost last.
+    actor_call_stack: Vec<ActorID>,
+    /// Number of sends (including plain value transfers) made in this message execution.
+    send_count: u64,
+    /// Number of blocks written by actors in this message execution.
+    blocks_written: u64,
+    /// Total size of the values returned by calls in this message execution, in bytes.
+    return_bytes: u64,
     /// The current chain of errors, if any.
     backtrace: Backtrace,
     /// The current execution trace.
@@ -75,6 +84,9 @@ pub struct InnerDefaultCallManager<M: Machine> {
     limits: M::Limiter,
     /// Accumulator for events emitted in this call stack.
     events: EventsAccumulator,
+    /// User-deployed actor code already loaded (and charged for) in this message execution.
+    #[cfg(feature = "m2-native")]
+    loaded_code: std::collections::HashSet<Cid>,
 }
 
 #[doc(hidden)]
@@ -111,8 +123,11 @@ where
         gas_premium: TokenAmount,
     ) -> Self {
         let limits = machine.new_limiter();
-        let gas_tracker =
+        let mut gas_tracker =
             GasTracker::new(Gas::new(gas_limit), Gas::zero(), machine.context().tracing);
+        if machine.context().gas_breakdown {
+            gas_tracker.enable_breakdown();
+        }
 
         let state_access_tracker =
             StateAccessTracker::new(&machine.context().price_list.preloaded_actors);
@@ -153,11 +168,17 @@ where
             nonce,
             num_actors_created: 0,
             call_stack_depth: 0,
+            actor_call_stack: Vec::new(),
+            send_count: 0,
+            blocks_written: 0,
+            return_bytes: 0,
             backtrace: Backtrace::default(),
             exec_trace: vec![],
             invocation_count: 0,
             limits,
             events: Default::default(),
+            #[cfg(feature = "m2-native")]
+            loaded_code: Default::default(),
             state_access_tracker,
         })))
     }
@@ -179,56 +200,71 @@ where
     where
         K: Kernel<CallManager = Self>,
     {
-        if self.machine.context().tracing {
-            self.trace(ExecutionEvent::Call {
+        let call = self
+            .machine
+            .context()
+            .tracing
+            .then(|| ExecutionEvent::Call {
                 from,
                 to,
                 method,
                 params: params.as_ref().map(Into::into),
                 value: value.clone(),
             });
-        }
-
-        // If a specific gas limit has been requested, push a new limit into the gas tracker.
-        if let Some(limit) = gas_limit {
-            self.gas_tracker.push_limit(limit);
-        }
+        self.trace_call(call, |cm| {
+            // If a specific gas limit has been requested, push a new limit into the gas tracker.
+            if let Some(limit) = gas_limit {
+                cm.gas_tracker.push_limit(limit);
+            }
 
-        let mut result = self.with_stack_frame(|s| {
-            s.send_unchecked::<K>(from, to, method, params, value, read_only)
-        });
+            let mut result = cm.with_stack_frame(|s| {
+                s.send_unchecked::<K>(from, to, method, params, value, read_only)
+            });
 
-        // If we pushed a limit, pop it.
-        if gas_limit.is_some() {
-            self.gas_tracker.pop_limit()?;
-        }
-        // If we're not out of gas but the error is "out of gas" (e.g., due to a gas limit), replace
-        // the error with an explicit exit code.
-        if !self.gas_tracker.gas_available().is_zero()
-            && matches!(result, Err(ExecutionError::OutOfGas))
-        {
-            result = Ok(InvocationResult {
-                exit_code: ExitCode::SYS_OUT_OF_GAS,
-                value: None,
-            })
-        }
+            // If we pushed a limit, pop it.
+            if gas_limit.is_some() {
+                cm.gas_tracker.pop_limit()?;
+            }
+            // If we're not out of gas but the error is "out of gas" (e.g., due to a gas limit),
+            // replace the error with an explicit exit code.
+            if !cm.gas_tracker.gas_available().is_zero()
+                && matches!(result, Err(ExecutionError::OutOfGas))
+            {
+                result = Ok(InvocationResult {
+                    exit_code: ExitCode::SYS_OUT_OF_GAS,
+                    value: None,
+                })
+            }
+            result
+        })
+    }
 
-        if self.machine.context().tracing {
-            self.trace(match &result {
-                Ok(InvocationResult { exit_code, value }) => {
-                    ExecutionEvent::CallReturn(*exit_code, value.as_ref().map(Into::into))
-                }
-                Err(ExecutionError::OutOfGas) => {
-                    ExecutionEvent::CallReturn(ExitCode::SYS_OUT_OF_GAS, None)
-                }
-                Err(ExecutionError::Fatal(_)) => {
-                    ExecutionEvent::CallError(SyscallError::new(ErrorNumber::Forbidden, "fatal"))
-                }
-                Err(ExecutionError::Syscall(s)) => ExecutionEvent::CallError(s.clone()),
+    fn upgrade_actor<K>(
+        &mut self,
+        caller: ActorID,
+        actor_id: ActorID,
+        new_code_cid: Cid,
+        params: Option<Block>,
+    ) -> Result<InvocationResult>
+    where
+        K: Kernel<CallManager = Self>,
+    {
+        let call = self
+            .machine
+            .context()
+            .tracing
+            .then(|| ExecutionEvent::Call {
+                from: caller,
+                to: Address::new_id(actor_id),
+                method: METHOD_UPGRADE,
+                params: params.as_ref().map(Into::into),
+                value: TokenAmount::zero(),
             });
-        }
-
-        result
+        self.trace_call(call, |cm| {
+            cm.with_stack_frame(|s| {
+                s.upgrade_actor_unchecked::<K>(caller, actor_id, new_code_cid, params)
+            })
+        })
     }
 
     fn with_transaction(
@@ -238,6 +274,8 @@ where
         self.state_tree_mut().begin_transaction();
         self.events.begin_transaction();
         self.state_access_tracker.begin_transaction();
+        self.gas_tracker.begin_transaction();
+        let blocks_written = self.blocks_written;
 
         let (revert, res) = match f(self) {
             Ok(v) => (!v.exit_code.is_success(), Ok(v)),
@@ -247,6 +285,11 @@ where
         self.state_tree_mut().end_transaction(revert)?;
         self.events.end_transaction(revert)?;
         self.state_access_tracker.end_transaction(revert)?;
+        self.gas_tracker.end_transaction(revert)?;
+        // Blocks written by reverted calls are discarded, so they don't count towards the limit.
+        if revert {
+            self.blocks_written = blocks_written;
+        }
 
         res
     }
@@ -261,7 +304,15 @@ where
             ..
         } = *self.0.take().expect("call manager is poisoned");
 
-        let gas_used = gas_tracker.gas_used().round_up();
+        // Credit back any refunds accrued by the message, up to the network's cap.
+        let gas_used = gas_tracker.gas_used();
+        let gas_refunded = machine
+            .context()
+            .price_list
+            .cap_refund(gas_used, gas_tracker.gas_refunded());
+        let gas_used = (gas_used - gas_refunded).round_up();
+        let gas_breakdown = gas_tracker.take_breakdown();
+        machine.context().metrics.message_applied(gas_used);
 
         // Finalize any trace events, if we're tracing.
         if machine.context().tracing {
@@ -280,10 +331,12 @@ where
         (
             Ok(FinishRet {
                 gas_used,
+                gas_refunded: gas_refunded.round_down(),
                 backtrace,
                 exec_trace,
                 events,
                 events_root,
+                gas_breakdown,
             }),
             machine,
         )
@@ -404,6 +457,42 @@ where
         self.invocation_count
     }
 
+    fn call_stack(&self) -> &[ActorID] {
+        &self.actor_call_stack
+    }
+
+    fn put_block(&mut self, k: Cid, data: &[u8]) -> Result<()> {
+        self.state_tree_mut().put_block(k, data)
+    }
+
+    fn get_block(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        self.state_tree().get_block(k)
+    }
+
+    #[cfg(feature = "m2-native")]
+    fn record_code_compiled(&mut self, code: Cid) {
+        self.loaded_code.insert(code);
+    }
+
+    fn record_block_write(&mut self) -> Result<()> {
+        if matches!(
+            self.machine.context().max_blocks_written_per_message,
+            Some(max) if self.blocks_written >= max
+        ) {
+            return Err(
+                syscall_error!(LimitExceeded; "message execution exceeds block write limit").into(),
+            );
+        }
+        self.blocks_written += 1;
+        Ok(())
+    }
+
+    fn record_syscall(&mut self, record: SyscallRecord) {
+        if self.machine.context().syscall_recording {
+            self.trace(ExecutionEvent::Syscall(record));
+        }
+    }
+
     /// Resolve an address and charge for it.
     fn resolve_address(&self, address: &Address) -> Result<Option<ActorID>> {
         if let Ok(id) = address.id() {
@@ -462,6 +551,10 @@ where
                 .gas_tracker
                 .apply_charge(self.price_list().on_actor_update())?;
         }
+        if self.state_tree().get_actor(id)?.is_some() {
+            self.gas_tracker
+                .refund_gas("OnDeleteActor", self.price_list().on_delete_actor_refund());
+        }
         self.state_tree_mut().delete_actor(id);
         self.state_access_tracker.record_actor_update(id);
         Ok(())
@@ -520,6 +613,50 @@ where
         s.exec_trace.push(trace);
     }
 
+    /// Ensures that an actor's code is loaded and cached in the engine.
+    ///
+    /// The first time user-deployed (non-builtin) code is loaded in a message, it's checked against
+    /// the network's [`WasmLimits`](crate::engine::WasmLimits) and charged for compilation. We
+    /// charge even if the engine has already compiled the module as that depends on the state of
+    /// this node's module cache, not on the chain.
+    #[cfg(feature = "m2-native")]
+    fn load_actor_code(&mut self, code: &Cid) -> Result<()> {
+        let builtin = self.machine.builtin_actors().id_by_code(code) != 0;
+        if !builtin && !self.loaded_code.contains(code) {
+            let wasm = self.get_block(code)?.ok_or_else(
+                || syscall_error!(NotFound; "actor code cid does not exist {}", code),
+            )?;
+            let stats = self
+                .machine
+                .context()
+                .network
+                .wasm_limits
+                .check(&wasm)
+                .map_err(|e| {
+                    if e.is_limit_exceeded() {
+                        syscall_error!(LimitExceeded; "actor code {} exceeds limits: {}", code, e)
+                    } else {
+                        syscall_error!(IllegalArgument; "actor code {} is invalid: {}", code, e)
+                    }
+                })?;
+            let _ = self.charge_gas(
+                self.price_list()
+                    .on_compile_actor(stats.size, stats.functions),
+            )?;
+            // The code may have been installed by this message, so it may not be in the
+            // blockstore yet.
+            self.engine.prepare_wasm_bytecode(code, &wasm).map_err(
+                |e| syscall_error!(IllegalArgument; "failed to load actor code {}: {}", code, e),
+            )?;
+            self.loaded_code.insert(*code);
+            return Ok(());
+        }
+        self.engine
+            .prepare_actor_code(code, self.blockstore())
+            .map_err(|_| syscall_error!(NotFound; "actor code cid does not exist {}", code))?;
+        Ok(())
+    }
+
     /// Helper method to create an uninitialized actor due to a send.
     fn create_actor_from_send(&mut self, addr: &Address, act: ActorState) -> Result<ActorID> {
         // This will charge for the address assignment and the actor storage, but not the actor
@@ -587,7 +724,101 @@ where
         self.create_actor_from_send(addr, state)
     }
 
+    /// Runs a call, recording it and its result in the execution trace if `call` is specified (i.e.,
+    /// if tracing is enabled).
+    fn trace_call(
+        &mut self,
+        call: Option<ExecutionEvent>,
+        f: impl FnOnce(&mut Self) -> Result<InvocationResult>,
+    ) -> Result<InvocationResult> {
+        let call = match call {
+            Some(call) => call,
+            None => return f(self),
+        };
+
+        let gas_used_before = self.gas_tracker.gas_used();
+        self.trace(call);
+        let result = f(self);
+        let gas_used = self.gas_tracker.gas_used() - gas_used_before;
+        self.trace(match &result {
+            Ok(InvocationResult { exit_code, value }) => {
+                ExecutionEvent::CallReturn(*exit_code, value.as_ref().map(Into::into), gas_used)
+            }
+            Err(ExecutionError::OutOfGas) => {
+                ExecutionEvent::CallReturn(ExitCode::SYS_OUT_OF_GAS, None, gas_used)
+            }
+            Err(ExecutionError::Fatal(_)) => {
+                ExecutionEvent::CallError(SyscallError::new(ErrorNumber::Forbidden, "fatal"))
+            }
+            Err(ExecutionError::Syscall(s)) => ExecutionEvent::CallError(s.clone()),
+        });
+        result
+    }
+
+    /// Upgrade an actor without checking the call depth.
+    fn upgrade_actor_unchecked<K>(
+        &mut self,
+        caller: ActorID,
+        actor_id: ActorID,
+        new_code_cid: Cid,
+        params: Option<Block>,
+    ) -> Result<InvocationResult>
+    where
+        K: Kernel<CallManager = Self>,
+    {
+        // Outer invocations of a re-entered actor would otherwise resume running the old code.
+        if self
+            .actor_call_stack
+            .iter()
+            .filter(|&&id| id == actor_id)
+            .count()
+            > 1
+        {
+            return Err(
+                syscall_error!(Forbidden; "cannot upgrade actor {} while it's re-entered", actor_id)
+                    .into(),
+            );
+        }
+
+        let mut state = self
+            .get_actor(actor_id)?
+            .ok_or_else(|| syscall_error!(NotFound; "actor does not exist: {}", actor_id))?;
+
+        // Make sure the new code exists. Without M2 native, only builtin actors may be deployed.
+        #[cfg(feature = "m2-native")]
+        self.load_actor_code(&new_code_cid)?;
+        #[cfg(not(feature = "m2-native"))]
+        if self.machine.builtin_actors().id_by_code(&new_code_cid) == 0 {
+            return Err(syscall_error!(NotFound;
+                "actor code cid is not a builtin actor {}", &new_code_cid)
+            .into());
+        }
+
+        let old_code_cid = std::mem::replace(&mut state.code, new_code_cid);
+        self.set_actor(actor_id, state)?;
+
+        log::trace!(
+            "upgrading {} from {} to {}",
+            actor_id,
+            old_code_cid,
+            new_code_cid
+        );
+        self.call_actor::<K>(
+            caller,
+            actor_id,
+            &new_code_cid,
+            Entrypoint::Upgrade(UpgradeInfo { old_code_cid }),
+            params,
+            &TokenAmount::zero(),
+            false,
+        )
+    }
+
     /// Send without checking the call depth.
+    ///
+    /// If the receiver doesn't exist and is addressed by a key (f1/f3) address, an account actor is
+    /// created (and its constructor invoked) before the transfer, charging for the creation. An f4
+    /// receiver in the EAM's namespace gets a placeholder actor instead.
     fn send_unchecked<K>(
         &mut self,
         from: ActorID,
@@ -660,6 +891,49 @@ where
             return Ok(InvocationResult::default());
         }
 
+        self.call_actor::<K>(
+            from,
+            to,
+            &state.code,
+            Entrypoint::Invoke(method),
+            params,
+            value,
+            read_only,
+        )
+    }
+
+    /// Invokes the given entrypoint of an actor's code. Any value has already been transferred.
+    #[allow(clippy::too_many_arguments)]
+    fn call_actor<K>(
+        &mut self,
+        from: ActorID,
+        to: ActorID,
+        code: &Cid,
+        entrypoint: Entrypoint,
+        params: Option<Block>,
+        value: &TokenAmount,
+        read_only: bool,
+    ) -> Result<InvocationResult>
+    where
+        K: Kernel<CallManager = Self>,
+    {
+        let method = entrypoint.method_num();
+
+        // Upgrades always re-enter the upgraded actor, so the policy only applies to invocations.
+        if matches!(entrypoint, Entrypoint::Invoke(_)) && self.actor_call_stack.contains(&to) {
+            match self.machine.context().reentrancy_policy {
+                ReentrancyPolicy::Allow => {}
+                ReentrancyPolicy::Warn => {
+                    log::warn!("reentrant call {} -> {}::{}", from, to, method)
+                }
+                ReentrancyPolicy::Deny => {
+                    return Err(
+                        syscall_error!(Forbidden; "reentrant call into actor {}", to).into(),
+                    )
+                }
+            }
+        }
+
         // Charge the invocation gas.
         let t = self.charge_gas(self.price_list().on_method_invocation())?;
 
@@ -671,6 +945,15 @@ where
             NO_DATA_BLOCK_ID
         };
 
+        // The upgrade entrypoint also receives information about the upgrade.
+        let upgrade_info_id = match &entrypoint {
+            Entrypoint::Invoke(_) => None,
+            Entrypoint::Upgrade(info) => {
+                let data = to_vec(info).or_fatal()?;
+                Some(block_registry.put(Block::new(CBOR, data))?)
+            }
+        };
+
         // Increment invocation count
         self.invocation_count += 1;
 
@@ -678,13 +961,10 @@ where
         // NOTE: this does not cover the EVM smart contract actor, which is a built-in actor, is
         // listed the manifest, and therefore preloaded during system initialization.
         #[cfg(feature = "m2-native")]
-        self.engine
-            .prepare_actor_code(&state.code, self.blockstore())
-            .map_err(
-                |_| syscall_error!(NotFound; "actor code cid does not exist {}", &state.code),
-            )?;
+        self.load_actor_code(code)?;
 
         log::trace!("calling {} -> {}::{}", from, to, method);
+        self.actor_call_stack.push(to);
         self.map_mut(|cm| {
             let engine = cm.engine.clone(); // reference the RC.
 
@@ -702,11 +982,15 @@ where
             // Make a store.
             let mut store = engine.new_store(kernel);
 
+            // Captured if the actor traps (the Wasm backtrace only if Wasm backtraces are enabled).
+            let mut wasm_backtrace = Vec::new();
+            let mut trap = None;
+
             // From this point on, there are no more syscall errors, only aborts.
             let result: std::result::Result<BlockId, Abort> = (|| {
                 // Instantiate the module.
                 let instance = engine
-                    .instantiate(&mut store, &state.code)?
+                    .instantiate(&mut store, code)?
                     .context("actor not found")
                     .map_err(Abort::Fatal)?;
 
@@ -718,35 +1002,72 @@ where
 
                 store.data_mut().memory = memory;
 
-                // Lookup the invoke method.
-                let invoke: wasmtime::TypedFunc<(u32,), u32> = instance
-                    .get_typed_func(&mut store, "invoke")
-                    // All actors will have an invoke method.
-                    .map_err(Abort::Fatal)?;
+                // Lookup the entrypoint.
+                let func = match upgrade_info_id {
+                    None => EntrypointFunc::Invoke(
+                        instance
+                            .get_typed_func(&mut store, "invoke")
+                            // All actors will have an invoke method.
+                            .map_err(Abort::Fatal)?,
+                    ),
+                    Some(upgrade_info_id) => EntrypointFunc::Upgrade(
+                        instance
+                            .get_typed_func(&mut store, "upgrade")
+                            // But not all actors can be upgraded.
+                            .map_err(|_| {
+                                Abort::Exit(
+                                    ExitCode::SYS_INVALID_RECEIVER,
+                                    String::from("actor has no upgrade entrypoint"),
+                                    NO_DATA_BLOCK_ID,
+                                )
+                            })?,
+                        upgrade_info_id,
+                    ),
+                };
 
                 // Set the available gas.
                 update_gas_available(&mut store)?;
 
                 // Invoke it.
-                let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-                    invoke.call(&mut store, (params_id,))
+                let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| match func {
+                    EntrypointFunc::Invoke(invoke) => invoke.call(&mut store, (params_id,)),
+                    EntrypointFunc::Upgrade(upgrade, upgrade_info_id) => {
+                        upgrade.call(&mut store, (params_id, upgrade_info_id))
+                    }
                 }))
                 .map_err(|panic| Abort::Fatal(anyhow!("panic within actor: {:?}", panic)))?;
 
                 // Charge for any remaining uncharged execution gas, returning an error if we run
-                // out.
-                charge_for_exec(&mut store)?;
+                // out. Running out of fuel means running out of gas, whatever gas remains.
+                let out_of_fuel = matches!(
+                    &res,
+                    Err(e) if matches!(e.downcast_ref(), Some(wasmtime::Trap::OutOfFuel))
+                );
+                if out_of_fuel {
+                    charge_for_fuel_exhaustion(&mut store)?;
+                } else {
+                    charge_for_exec(&mut store)?;
+                }
 
                 // If the invocation failed due to running out of exec_units, we have already
                 // detected it and returned OutOfGas above. Any other invocation failure is returned
                 // here as an Abort
-
-                Ok(res?)
+                res.map_err(|e| {
+                    if let Some(bt) = e.downcast_ref::<wasmtime::WasmBacktrace>() {
+                        wasm_backtrace = bt.frames().iter().map(WasmFrame::from).collect();
+                    }
+                    trap = e
+                        .downcast_ref::<wasmtime::Trap>()
+                        .copied()
+                        .map(TrapKind::from);
+                    Abort::from(e)
+                })
             })();
 
             let invocation_data = store.into_data();
             let last_error = invocation_data.last_error;
             let (mut cm, block_registry) = invocation_data.kernel.into_inner();
+            cm.actor_call_stack.pop();
 
             // Resolve the return block's ID into an actual block, converting to an abort if it
             // doesn't exist.
@@ -817,6 +1138,8 @@ where
                             method,
                             message,
                             code,
+                            wasm_backtrace,
+                            trap,
                         });
                     }
 
@@ -824,6 +1147,35 @@ where
                 }
             };
 
+            // Enforce the return value size limits, charging for the returned value.
+            let ret = match ret {
+                Ok(InvocationResult {
+                    exit_code,
+                    value: Some(blk),
+                }) => match cm.accept_return_value(&blk) {
+                    Ok(None) => Ok(InvocationResult {
+                        exit_code,
+                        value: Some(blk),
+                    }),
+                    Ok(Some(message)) => {
+                        cm.backtrace.push_frame(Frame {
+                            source: to,
+                            method,
+                            message,
+                            code: ExitCode::SYS_LIMIT_EXCEEDED,
+                            wasm_backtrace: Vec::new(),
+                            trap: None,
+                        });
+                        Ok(InvocationResult {
+                            exit_code: ExitCode::SYS_LIMIT_EXCEEDED,
+                            value: None,
+                        })
+                    }
+                    Err(e) => Err(e),
+                },
+                ret => ret,
+            };
+
             // Log the results if tracing is enabled.
             if log::log_enabled!(log::Level::Trace) {
                 match &ret {
@@ -843,6 +1195,29 @@ where
         })
     }
 
+    /// Checks a value returned by a call against the return size limits, recording and charging for
+    /// it if it's within them. Otherwise, returns the reason it was rejected.
+    fn accept_return_value(&mut self, value: &Block) -> Result<Option<String>> {
+        let size = value.size();
+        let ctx = self.machine.context();
+        if matches!(ctx.max_return_size, Some(max) if size > max) {
+            return Ok(Some(format!(
+                "return value of {} bytes exceeds the maximum return size",
+                size
+            )));
+        }
+        let return_bytes = self.return_bytes + size as u64;
+        if matches!(ctx.max_return_bytes_per_message, Some(max) if return_bytes > max) {
+            return Ok(Some(format!(
+                "return value of {} bytes exceeds the per-message return limit",
+                size
+            )));
+        }
+        self.return_bytes = return_bytes;
+        let _ = self.charge_gas(self.price_list().on_send_return(size as usize))?;
+        Ok(None)
+    }
+
     /// Temporarily replace `self` with a version that contains `None` for the inner part,
     /// to be able to hand over ownership of `self` to a new kernel, while the older kernel
     /// has a reference to the hollowed out version.
@@ -853,20 +1228,36 @@ where
         replace_with::replace_with_and_return(self, || DefaultCallManager(None), f)
     }
 
-    /// Check that we're not violating the call stack depth, then envelope a call
-    /// with an increase/decrease of the depth to make sure none of them are missed.
+    /// Check that we're not violating the call stack depth or the send limit, then envelope a
+    /// call with an increase/decrease of the depth to make sure none of them are missed.
     fn with_stack_frame<F, V>(&mut self, f: F) -> Result<V>
     where
         F: FnOnce(&mut Self) -> Result<V>,
     {
-        if self.call_stack_depth >= self.machine.context().max_call_depth {
-            let sys_err = syscall_error!(LimitExceeded, "message execution exceeds call depth");
+        let sys_err = if self.call_stack_depth >= self.machine.context().max_call_depth {
+            Some(syscall_error!(
+                LimitExceeded,
+                "message execution exceeds call depth"
+            ))
+        } else if matches!(
+            self.machine.context().max_sends_per_message,
+            Some(max) if self.send_count >= max
+        ) {
+            Some(syscall_error!(
+                LimitExceeded,
+                "message execution exceeds send limit"
+            ))
+        } else {
+            None
+        };
+        if let Some(sys_err) = sys_err {
             if self.machine.context().tracing {
                 self.trace(ExecutionEvent::CallError(sys_err.clone()));
             }
             return Err(sys_err.into());
         }
 
+        self.send_count += 1;
         self.call_stack_depth += 1;
         let res = <<<DefaultCallManager<M> as CallManager>::Machine as Machine>::Limiter>::with_stack_frame(
             self,
@@ -878,6 +1269,30 @@ where
     }
 }
 
+/// The entrypoint of an actor invocation.
+enum Entrypoint {
+    /// The `invoke` entrypoint, invoked when a message is sent to the actor.
+    Invoke(MethodNum),
+    /// The `upgrade` entrypoint, invoked when the actor is upgraded to new code.
+    Upgrade(UpgradeInfo),
+}
+
+impl Entrypoint {
+    /// The method number reported to the actor (and in traces and backtraces).
+    fn method_num(&self) -> MethodNum {
+        match self {
+            Entrypoint::Invoke(method) => *method,
+            Entrypoint::Upgrade(_) => METHOD_UPGRADE,
+        }
+    }
+}
+
+/// A resolved (wasm) entrypoint, along with any extra arguments beyond the parameters.
+enum EntrypointFunc {
+    Invoke(wasmtime::TypedFunc<(u32,), u32>),
+    Upgrade(wasmtime::TypedFunc<(u32, u32), u32>, BlockId),
+}
+
 /// Stores events in layers as they are emitted by actors. As the call stack progresses, when an
 /// actor exits normally, its events should be merged onto the previous layer (merge_last_layer).
 /// If an actor aborts, the last layer should be discarded (discard_last_layer). This will also
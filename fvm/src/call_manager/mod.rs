@@ -7,7 +7,7 @@ use fvm_shared::error::ExitCode;
 use fvm_shared::{ActorID, MethodNum};
 
 use crate::engine::Engine;
-use crate::gas::{Gas, GasCharge, GasTimer, GasTracker, PriceList};
+use crate::gas::{Gas, GasBreakdown, GasCharge, GasTimer, GasTracker, PriceList};
 use crate::kernel::{self, Result};
 use crate::machine::{Machine, MachineContext};
 use crate::state_tree::ActorState;
@@ -22,7 +22,7 @@ mod default;
 pub use default::DefaultCallManager;
 use fvm_shared::event::StampedEvent;
 
-use crate::trace::ExecutionTrace;
+use crate::trace::{ExecutionTrace, SyscallRecord};
 
 /// BlockID representing nil parameters or return data.
 pub const NO_DATA_BLOCK_ID: u32 = 0;
@@ -74,7 +74,25 @@ pub trait CallManager: 'static {
         read_only: bool,
     ) -> Result<InvocationResult>;
 
+    /// Upgrade an actor's code in-place, then invoke the new code's `upgrade` entrypoint on
+    /// behalf of `caller`. The actor's address, balance, and state are preserved. The type
+    /// parameter `K` specifies the the _kernel_ on top of which the new code should execute.
+    ///
+    /// This must be called within a transaction (see [`CallManager::with_transaction`]) so the
+    /// code change is reverted if the upgrade fails.
+    fn upgrade_actor<K: Kernel<CallManager = Self>>(
+        &mut self,
+        caller: ActorID,
+        actor_id: ActorID,
+        new_code_cid: Cid,
+        params: Option<kernel::Block>,
+    ) -> Result<InvocationResult>;
+
     /// Execute some operation (usually a send) within a transaction.
+    ///
+    /// All state-tree changes, events, and blocks written (see [`CallManager::put_block`]) by the
+    /// operation are reverted if it fails or returns a non-success exit code, so an aborted callee
+    /// never leaves partial state behind.
     fn with_transaction(
         &mut self,
         f: impl FnOnce(&mut Self) -> Result<InvocationResult>,
@@ -100,7 +118,7 @@ pub trait CallManager: 'static {
     /// Getter for origin actor.
     fn origin(&self) -> ActorID;
 
-    /// Get the actor address (f2) that will should be assigned to the next actor created.
+    /// Get the actor address (f2) that should be assigned to the next actor created.
     ///
     /// This method doesn't have any side-effects and will continue to return the same address until
     /// `create_actor` is called next.
@@ -139,6 +157,33 @@ pub trait CallManager: 'static {
     /// Gets the total invocations done on this call stack.
     fn invocation_count(&self) -> u64;
 
+    /// Returns the actors whose code is currently executing on the call stack, outermost first.
+    /// Plain value transfers don't appear on the call stack.
+    fn call_stack(&self) -> &[ActorID];
+
+    /// Records that an actor is about to write a block, failing with `LimitExceeded` if the
+    /// message has already written the maximum number of blocks (see
+    /// [`NetworkConfig::max_blocks_written_per_message`](crate::machine::NetworkConfig::max_blocks_written_per_message)).
+    fn record_block_write(&mut self) -> Result<()>;
+
+    /// Writes a block written by an actor. Within a transaction (see
+    /// [`CallManager::with_transaction`]), the block is buffered until the outermost transaction
+    /// completes successfully and is discarded if any enclosing transaction is reverted.
+    fn put_block(&mut self, k: Cid, data: &[u8]) -> Result<()>;
+
+    /// Reads a block, including blocks buffered by [`CallManager::put_block`] that haven't been
+    /// written to the blockstore yet.
+    fn get_block(&self, k: &Cid) -> Result<Option<Vec<u8>>>;
+
+    /// Records that user-deployed actor code was compiled, and charged for, in this message, so
+    /// that loading it again within the message isn't charged again.
+    #[cfg(feature = "m2-native")]
+    fn record_code_compiled(&mut self, code: Cid);
+
+    /// Records a syscall in the execution trace, if syscall recording is enabled (see
+    /// [`MachineContext::syscall_recording`]).
+    fn record_syscall(&mut self, record: SyscallRecord);
+
     /// Returns the current price list.
     fn price_list(&self) -> &PriceList {
         self.machine().context().price_list
@@ -171,6 +216,19 @@ pub trait CallManager: 'static {
     fn append_event(&mut self, evt: StampedEvent);
 }
 
+/// What to do when an actor is called while it's already executing further up the call stack (see
+/// [`NetworkConfig::reentrancy_policy`](crate::machine::NetworkConfig::reentrancy_policy)).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ReentrancyPolicy {
+    /// Allow reentrant calls.
+    #[default]
+    Allow,
+    /// Allow reentrant calls, but log a warning.
+    Warn,
+    /// Reject reentrant calls with a `Forbidden` syscall error.
+    Deny,
+}
+
 /// The result of a method invocation.
 #[derive(Clone, Debug)]
 pub struct InvocationResult {
@@ -191,9 +249,13 @@ impl Default for InvocationResult {
 
 /// The returned values upon finishing a call manager.
 pub struct FinishRet {
+    /// The gas used by the message, net of refunds.
     pub gas_used: u64,
+    /// The gas refunded for deleting state (already deducted from `gas_used`).
+    pub gas_refunded: u64,
     pub backtrace: Backtrace,
     pub exec_trace: ExecutionTrace,
     pub events: Vec<StampedEvent>,
     pub events_root: Option<Cid>,
+    pub gas_breakdown: Option<GasBreakdown>,
 }
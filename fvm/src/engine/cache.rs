@@ -0,0 +1,285 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use cid::Cid;
+use wasmtime::Module;
+
+/// Configuration for the compiled module cache of an [`EnginePool`](super::EnginePool).
+///
+/// Compiled modules are cached per engine pool, keyed by the actor's code CID. None of these
+/// options affect consensus.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ModuleCacheConfig {
+    /// The maximum number of compiled modules to keep in memory. When full, the least recently
+    /// used module is evicted and will be recompiled (or reloaded from disk) on next use.
+    ///
+    /// DEFAULT: `None` (unbounded)
+    pub max_modules: Option<usize>,
+
+    /// A directory in which to persist compiled modules, so they don't need to be recompiled when
+    /// the process restarts. Modules are stored per engine configuration.
+    ///
+    /// This directory must only be written by the FVM: modules found in it are loaded without
+    /// being re-validated.
+    ///
+    /// DEFAULT: `None` (don't persist)
+    pub persist_dir: Option<PathBuf>,
+}
+
+impl ModuleCacheConfig {
+    /// Limit the number of compiled modules kept in memory.
+    pub fn max_modules(&mut self, max_modules: usize) -> &mut Self {
+        self.max_modules = Some(max_modules);
+        self
+    }
+
+    /// Persist compiled modules to the specified directory.
+    pub fn persist_to(&mut self, dir: impl Into<PathBuf>) -> &mut Self {
+        self.persist_dir = Some(dir.into());
+        self
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct ModuleRecord {
+    pub module: Module,
+    /// Byte size of the original Wasm.
+    pub size: usize,
+}
+
+struct CacheEntry {
+    record: ModuleRecord,
+    last_used: u64,
+}
+
+/// An in-memory LRU cache of compiled modules, optionally backed by a directory on disk.
+pub(super) struct ModuleCache {
+    config: ModuleCacheConfig,
+    /// A unique name for the engine configuration, used to namespace persisted modules.
+    namespace: String,
+    modules: HashMap<Cid, CacheEntry>,
+    /// Modules that are never evicted (see [`ModuleCache::pin`]).
+    pinned: HashSet<Cid>,
+    clock: u64,
+}
+
+impl ModuleCache {
+    pub fn new(config: ModuleCacheConfig, namespace: String) -> Self {
+        ModuleCache {
+            config,
+            namespace,
+            modules: HashMap::new(),
+            pinned: HashSet::new(),
+            clock: 0,
+        }
+    }
+
+    /// Lookup a module in the in-memory cache, marking it as recently used.
+    pub fn get(&mut self, k: &Cid) -> Option<ModuleRecord> {
+        self.clock += 1;
+        let entry = self.modules.get_mut(k)?;
+        entry.last_used = self.clock;
+        Some(entry.record.clone())
+    }
+
+    /// Insert a module into the in-memory cache, evicting the least recently used (unpinned)
+    /// module if the cache is full. If all cached modules are pinned, the cache grows beyond its
+    /// limit instead.
+    pub fn insert(&mut self, k: Cid, record: ModuleRecord) {
+        if let Some(max) = self.config.max_modules {
+            while self.modules.len() >= max.max(1) && !self.modules.contains_key(&k) {
+                let lru = match self
+                    .modules
+                    .iter()
+                    .filter(|(k, _)| !self.pinned.contains(k))
+                    .min_by_key(|(_, e)| e.last_used)
+                {
+                    Some((k, _)) => *k,
+                    None => break,
+                };
+                log::trace!("evicting compiled module {lru} from the module cache");
+                self.modules.remove(&lru);
+            }
+        }
+        self.clock += 1;
+        self.modules.insert(
+            k,
+            CacheEntry {
+                record,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// Pins a module, preventing it from being evicted. Modules can be pinned before they're
+    /// inserted.
+    pub fn pin(&mut self, k: Cid) {
+        self.pinned.insert(k);
+    }
+
+    /// Returns true if the module is pinned.
+    pub fn is_pinned(&self, k: &Cid) -> bool {
+        self.pinned.contains(k)
+    }
+
+    /// Returns the namespace under which compiled modules are persisted.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    fn persist_path(&self, k: &Cid) -> Option<PathBuf> {
+        let dir = self.config.persist_dir.as_ref()?;
+        Some(module_path(dir, &self.namespace, k))
+    }
+
+    /// Load a previously persisted module from disk, if present.
+    pub fn load_persisted(&self, engine: &wasmtime::Engine, k: &Cid) -> Option<ModuleRecord> {
+        let path = self.persist_path(k)?;
+        let data = fs::read(&path).ok()?;
+        if data.len() < 8 {
+            return None;
+        }
+        let (size, compiled) = data.split_at(8);
+        let size = u64::from_le_bytes(size.try_into().expect("8 bytes")) as usize;
+        // SAFETY: we only load modules that we've previously compiled and written to the cache
+        // directory, and wasmtime checks that the module was compiled for a compatible engine.
+        match unsafe { Module::deserialize(engine, compiled) } {
+            Ok(module) => Some(ModuleRecord { module, size }),
+            Err(e) => {
+                log::warn!("failed to load persisted module {}: {e}", path.display());
+                None
+            }
+        }
+    }
+
+    /// Persist a compiled module to disk, if configured. Failures are logged and ignored.
+    pub fn persist(&self, k: &Cid, record: &ModuleRecord) {
+        let path = match self.persist_path(k) {
+            Some(path) => path,
+            None => return,
+        };
+        if let Err(e) = write_module(&path, record) {
+            log::warn!("failed to persist module to {}: {e}", path.display());
+        }
+    }
+}
+
+/// Returns the path at which the compiled module for `k` is stored under `dir`.
+pub(super) fn module_path(dir: &Path, namespace: &str, k: &Cid) -> PathBuf {
+    dir.join(namespace).join(format!("{k}.cwasm"))
+}
+
+/// Writes a compiled module to `path`, creating the parent directory if necessary.
+pub(super) fn write_module(path: &Path, record: &ModuleRecord) -> anyhow::Result<()> {
+    let compiled = record.module.serialize()?;
+    let dir = path.parent().context("invalid module cache path")?;
+    fs::create_dir_all(dir)?;
+    // Write to a temporary file first so that concurrent readers never observe a partially
+    // written module.
+    let tmp = path.with_extension(format!("tmp-{}", std::process::id()));
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(&(record.size as u64).to_le_bytes())?;
+    file.write_all(&compiled)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use cid::Cid;
+    use multihash::{Code, MultihashDigest};
+
+    use super::*;
+
+    fn record(engine: &wasmtime::Engine, size: usize) -> ModuleRecord {
+        ModuleRecord {
+            module: Module::new(engine, b"\0asm\x01\0\0\0").unwrap(),
+            size,
+        }
+    }
+
+    fn cid(i: u8) -> Cid {
+        Cid::new_v1(0x55, Code::Blake2b256.digest(&[i]))
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let engine = wasmtime::Engine::default();
+        let mut cache = ModuleCache::new(
+            ModuleCacheConfig {
+                max_modules: Some(2),
+                persist_dir: None,
+            },
+            "test".into(),
+        );
+
+        cache.insert(cid(1), record(&engine, 1));
+        cache.insert(cid(2), record(&engine, 2));
+        // Touch 1 so that 2 becomes the least recently used.
+        assert_eq!(cache.get(&cid(1)).unwrap().size, 1);
+        cache.insert(cid(3), record(&engine, 3));
+
+        assert!(cache.get(&cid(2)).is_none());
+        assert_eq!(cache.get(&cid(1)).unwrap().size, 1);
+        assert_eq!(cache.get(&cid(3)).unwrap().size, 3);
+
+        // Re-inserting an existing key doesn't evict anything.
+        cache.insert(cid(3), record(&engine, 4));
+        assert_eq!(cache.get(&cid(1)).unwrap().size, 1);
+        assert_eq!(cache.get(&cid(3)).unwrap().size, 4);
+    }
+
+    #[test]
+    fn never_evicts_pinned_modules() {
+        let engine = wasmtime::Engine::default();
+        let mut cache = ModuleCache::new(
+            ModuleCacheConfig {
+                max_modules: Some(2),
+                persist_dir: None,
+            },
+            "test".into(),
+        );
+
+        cache.pin(cid(1));
+        cache.insert(cid(1), record(&engine, 1));
+        cache.insert(cid(2), record(&engine, 2));
+        // 1 is the least recently used, but it's pinned.
+        cache.insert(cid(3), record(&engine, 3));
+        assert!(cache.is_pinned(&cid(1)));
+        assert!(cache.get(&cid(2)).is_none());
+
+        // When everything is pinned, the cache grows past its limit.
+        cache.pin(cid(3));
+        cache.insert(cid(4), record(&engine, 4));
+        for i in [1, 3, 4] {
+            assert_eq!(cache.get(&cid(i)).unwrap().size, i as usize);
+        }
+    }
+
+    #[test]
+    fn persists_modules() {
+        let engine = wasmtime::Engine::default();
+        let dir = std::env::temp_dir().join(format!("fvm-module-cache-{}", std::process::id()));
+        let cache = ModuleCache::new(
+            ModuleCacheConfig {
+                max_modules: None,
+                persist_dir: Some(dir.clone()),
+            },
+            "test".into(),
+        );
+
+        assert!(cache.load_persisted(&engine, &cid(1)).is_none());
+        cache.persist(&cid(1), &record(&engine, 42));
+        assert!(module_path(&dir, "test", &cid(1)).is_file());
+        assert_eq!(cache.load_persisted(&engine, &cid(1)).unwrap().size, 42);
+        assert!(cache.load_persisted(&engine, &cid(2)).is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1342 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::any::{Any, TypeId};
+use std::collections::hash_map::Entry::{Occupied, Vacant};
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_shared::error::ExitCode;
+use fvm_shared::version::NetworkVersion;
+use fvm_wasm_instrument::gas_metering::GAS_COUNTER_NAME;
+use num_traits::Zero;
+use wasmtime::OptLevel::Speed;
+use wasmtime::{
+    Global, GlobalType, InstanceAllocationStrategy, Linker, Memory, MemoryType, Module, Mutability,
+    Val, ValType,
+};
+
+use crate::gas::{Gas, GasTimer, WasmGasPrices};
+use crate::machine::limiter::MemoryLimiter;
+use crate::machine::{Machine, NetworkConfig};
+use crate::metrics::{Metrics, NoopMetrics};
+use crate::syscalls::error::Abort;
+use crate::syscalls::{
+    bind_syscalls, charge_for_exec, charge_for_init, record_init_time, update_gas_available,
+    InvocationData, SyscallLinker,
+};
+use crate::Kernel;
+
+mod cache;
+mod validation;
+
+pub use cache::ModuleCacheConfig;
+use cache::{ModuleCache, ModuleRecord};
+pub use validation::{validate_wasm_for_deployment, ModuleStats, WasmLimits, WasmValidationError};
+
+/// Container managing engines with different consensus-affecting configurations.
+///
+/// Engines (and their compiled module caches) aren't owned by machines: the same [`EnginePool`]
+/// can back any number of machines, each with its own [`MachineContext`](crate::machine::MachineContext)
+/// and blockstore. `concurrency` is the number of those machines that may execute messages at the
+/// same time (e.g., one per tipset being validated in parallel); any others block until an engine
+/// is released.
+pub struct MultiEngine {
+    engines: Mutex<HashMap<EngineConfig, EnginePool>>,
+    concurrency: u32,
+    module_cache: ModuleCacheConfig,
+    instance_pool: InstancePoolConfig,
+    metrics: Arc<dyn Metrics>,
+}
+
+/// Configuration for the pool of Wasm instance slots backing an [`EnginePool`].
+///
+/// Every engine pre-allocates an instance slot (memory, table, etc.) for each level of the call
+/// stack of each concurrently executing message, and reuses these slots across sends and across
+/// messages. When a slot is reused, its memory is reset to its initial state. None of these options
+/// affect consensus.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct InstancePoolConfig {
+    /// The number of bytes of each instance's linear memory to keep resident (and reset by
+    /// zeroing) when the slot is reused, instead of returning the memory to the OS. Larger values
+    /// make re-instantiating actors cheaper at the cost of holding on to more host memory.
+    ///
+    /// DEFAULT: 0 (return all memory to the OS)
+    pub memory_keep_resident: usize,
+
+    /// Like `memory_keep_resident`, but for each instance's table.
+    ///
+    /// DEFAULT: 0 (return all table memory to the OS)
+    pub table_keep_resident: usize,
+}
+
+impl InstancePoolConfig {
+    /// Keep up to `bytes` of each instance's linear memory resident between instantiations.
+    pub fn memory_keep_resident(&mut self, bytes: usize) -> &mut Self {
+        self.memory_keep_resident = bytes;
+        self
+    }
+
+    /// Keep up to `bytes` of each instance's table resident between instantiations.
+    pub fn table_keep_resident(&mut self, bytes: usize) -> &mut Self {
+        self.table_keep_resident = bytes;
+        self
+    }
+}
+
+/// The optional Wasm proposals actors may use. Actors using a disabled feature fail validation
+/// when deployed or loaded.
+///
+/// These affect which actors are valid and how they execute, so they're consensus-critical and
+/// must only change at a network upgrade (see [`WasmFeatures::for_network_version`]). Threads,
+/// relaxed SIMD, multi-memory, and memory64 are never enabled as they're either inherently
+/// non-deterministic or unsupported by the FVM's memory model.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct WasmFeatures {
+    /// Fixed-width SIMD (the `v128` type and its instructions).
+    ///
+    /// NOTE: The gas and stack-limit instrumentation doesn't support SIMD yet, so actors using it
+    /// will fail to load unless fuel metering is enabled.
+    pub simd: bool,
+    /// Bulk memory operations (`memory.copy`, `memory.fill`, etc.).
+    pub bulk_memory: bool,
+    /// Reference types (`externref`, `funcref` values, and multiple tables). Requires
+    /// `bulk_memory`.
+    pub reference_types: bool,
+    /// Functions and blocks returning multiple values.
+    pub multi_value: bool,
+}
+
+impl WasmFeatures {
+    /// Returns the Wasm features enabled in the given network version.
+    ///
+    /// When enabling a new feature, do so for the network version introducing it (and later) only,
+    /// so historical messages continue to validate and execute exactly as they did.
+    pub fn for_network_version(_network_version: NetworkVersion) -> Self {
+        // All currently supported network versions share the same features.
+        WasmFeatures {
+            simd: false,
+            bulk_memory: true,
+            reference_types: false,
+            multi_value: false,
+        }
+    }
+}
+
+/// The proper way of getting this struct is to convert from `NetworkConfig`
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub struct EngineConfig {
+    pub max_call_depth: u32,
+    pub max_wasm_stack: u32,
+    pub max_inst_memory_bytes: u64,
+    pub concurrency: u32,
+    pub wasm_prices: &'static WasmGasPrices,
+    pub actor_redirect: Vec<(Cid, Cid)>,
+    pub fuel_metering: bool,
+    pub deterministic: bool,
+    pub wasm_backtrace: bool,
+    pub wasm_features: WasmFeatures,
+    pub execution_timeout: Option<Duration>,
+    pub module_cache: ModuleCacheConfig,
+    pub instance_pool: InstancePoolConfig,
+    /// The network version, which determines the syscalls available to actors.
+    pub network_version: NetworkVersion,
+}
+
+impl From<&NetworkConfig> for EngineConfig {
+    fn from(nc: &NetworkConfig) -> Self {
+        EngineConfig {
+            max_call_depth: nc.max_call_depth,
+            max_wasm_stack: nc.max_wasm_stack,
+            max_inst_memory_bytes: nc.max_inst_memory_bytes,
+            wasm_prices: &nc.price_list.wasm_rules,
+            actor_redirect: nc.actor_redirect.clone(),
+            concurrency: 1,
+            fuel_metering: nc.fuel_metering,
+            deterministic: nc.deterministic,
+            wasm_backtrace: nc.actor_debugging,
+            wasm_features: nc.wasm_features,
+            execution_timeout: nc.execution_timeout,
+            module_cache: Default::default(),
+            instance_pool: Default::default(),
+            network_version: nc.network_version,
+        }
+    }
+}
+
+impl MultiEngine {
+    pub fn new(concurrency: u32) -> MultiEngine {
+        if concurrency == 0 {
+            panic!("concurrency must be positive");
+        }
+        MultiEngine {
+            engines: Mutex::new(HashMap::new()),
+            concurrency,
+            module_cache: Default::default(),
+            instance_pool: Default::default(),
+            metrics: Arc::new(NoopMetrics),
+        }
+    }
+
+    /// Configure the sink into which all engines created by this [`MultiEngine`] record wasm
+    /// compilation metrics.
+    pub fn with_metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Configure the compiled module cache used by all engines created by this [`MultiEngine`].
+    pub fn with_module_cache(mut self, config: ModuleCacheConfig) -> Self {
+        self.module_cache = config;
+        self
+    }
+
+    /// Configure the instance pool used by all engines created by this [`MultiEngine`].
+    pub fn with_instance_pool(mut self, config: InstancePoolConfig) -> Self {
+        self.instance_pool = config;
+        self
+    }
+
+    pub fn get(&self, nc: &NetworkConfig) -> anyhow::Result<EnginePool> {
+        let mut engines = self
+            .engines
+            .lock()
+            .map_err(|_| anyhow::Error::msg("multiengine lock is poisoned"))?;
+
+        let mut ec: EngineConfig = nc.into();
+        ec.concurrency = self.concurrency;
+        ec.module_cache = self.module_cache.clone();
+        ec.instance_pool = self.instance_pool.clone();
+
+        let pool = match engines.entry(ec.clone()) {
+            Occupied(entry) => entry.into_mut(),
+            Vacant(entry) => entry.insert(EnginePool::new_with_metrics(
+                &wasmtime_config(&ec)?,
+                ec,
+                self.metrics.clone(),
+            )?),
+        };
+
+        Ok(pool.clone())
+    }
+}
+
+impl Default for MultiEngine {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+fn wasmtime_config(ec: &EngineConfig) -> anyhow::Result<wasmtime::Config> {
+    let instance_count = (1 + ec.max_call_depth) * ec.concurrency;
+    let instance_memory_maximum_size = ec.max_inst_memory_bytes;
+    if instance_memory_maximum_size % wasmtime_environ::WASM_PAGE_SIZE as u64 != 0 {
+        return Err(anyhow!(
+            "requested memory limit {} not a multiple of the WASM_PAGE_SIZE {}",
+            instance_memory_maximum_size,
+            wasmtime_environ::WASM_PAGE_SIZE
+        ));
+    }
+    if ec.deterministic && ec.execution_timeout.is_some() {
+        return Err(anyhow!(
+            "execution timeouts are non-deterministic, but deterministic execution is required"
+        ));
+    }
+
+    let mut c = wasmtime::Config::default();
+
+    // wasmtime default: OnDemand
+    // We want to pre-allocate all permissible memory to support the maximum allowed recursion limit.
+
+    let mut alloc_strat_cfg = wasmtime::PoolingAllocationConfig::default();
+    alloc_strat_cfg.instance_count(instance_count);
+
+    // Adjust the maximum amount of host memory that can be committed to an instance to
+    // match the static linear memory size we reserve for each slot.
+    alloc_strat_cfg.instance_memory_pages(
+        instance_memory_maximum_size / (wasmtime_environ::WASM_PAGE_SIZE as u64),
+    );
+    alloc_strat_cfg.linear_memory_keep_resident(ec.instance_pool.memory_keep_resident);
+    alloc_strat_cfg.table_keep_resident(ec.instance_pool.table_keep_resident);
+    c.allocation_strategy(InstanceAllocationStrategy::Pooling(alloc_strat_cfg));
+
+    // wasmtime default: true
+    // We disable this as we always charge for memory regardless and `memory_init_cow` can baloon compiled wasm modules.
+    c.memory_init_cow(false);
+
+    // wasmtime default: 4GB
+    c.static_memory_maximum_size(instance_memory_maximum_size);
+
+    // wasmtime default: false
+    // We don't want threads, there is no way to ensure determisism
+    c.wasm_threads(false);
+
+    // wasmtime default: true
+    // simd isn't supported in wasm-instrument, so it's disabled unless a network version enables it.
+    // Note: stack limits may need adjusting after this is enabled
+    c.wasm_simd(ec.wasm_features.simd);
+
+    // wasmtime default: false
+    // Relaxed SIMD instructions have implementation-defined (host-dependent) results.
+    c.wasm_relaxed_simd(false);
+    c.relaxed_simd_deterministic(true);
+
+    // wasmtime default: false
+    c.wasm_multi_memory(false);
+
+    // wasmtime default: false
+    c.wasm_memory64(false);
+
+    // wasmtime default: true
+    // Note: wasm-instrument only supports this at a basic level, for M2 we will
+    // need to add more advanced support
+    c.wasm_bulk_memory(ec.wasm_features.bulk_memory);
+
+    // wasmtime default: true
+    // we should be able to enable this for M2, just need to make sure that it's
+    // handled correctly in wasm-instrument
+    c.wasm_multi_value(ec.wasm_features.multi_value);
+
+    // wasmtime default: false
+    //
+    // from wasmtime docs:
+    // > When Cranelift is used as a code generation backend this will
+    // > configure it to replace NaNs with a single canonical value. This
+    // > is useful for users requiring entirely deterministic WebAssembly
+    // > computation. This is not required by the WebAssembly spec, so it is
+    // > not enabled by default.
+    //
+    // Always enabled: floating-point results must never depend on the host.
+    c.cranelift_nan_canonicalization(true);
+
+    // wasmtime default: 512KiB
+    // Set to something much higher than the instrumented limiter.
+    // Note: This is in bytes, while the instrumented limit is in stack elements
+    c.max_wasm_stack(4 << 20);
+
+    // Execution cost accouting is done through wasm instrumentation, unless fuel metering has
+    // been explicitly enabled.
+    c.consume_fuel(ec.fuel_metering);
+    // Epoch interruption is only used to enforce wall-clock execution timeouts, if configured.
+    c.epoch_interruption(ec.execution_timeout.is_some());
+
+    // Disable debug-related things, wasm-instrument doesn't fix debug info
+    // yet, so those aren't useful, just add overhead
+    c.debug_info(false);
+    c.generate_address_map(false);
+    c.cranelift_debug_verifier(false);
+    c.native_unwind_info(false);
+    // Wasm backtraces are only captured when debugging actors, to help track down traps.
+    #[allow(deprecated)] // TODO https://github.com/bytecodealliance/wasmtime/issues/5037
+    c.wasm_backtrace(ec.wasm_backtrace);
+    c.wasm_reference_types(ec.wasm_features.reference_types);
+
+    // Reiterate some defaults
+    c.guard_before_linear_memory(true);
+    c.parallel_compilation(true);
+
+    #[cfg(feature = "wasmtime/async")]
+    c.async_support(false);
+
+    // Doesn't seem to have significant impact on the time it takes to load code
+    // todo(M2): make sure this is guaranteed to run in linear time.
+    c.cranelift_opt_level(Speed);
+
+    Ok(c)
+}
+
+/// The version of `fvm-wasm-instrument` in our manifest. Instrumentation is baked into compiled
+/// modules, so it's part of the module cache namespace.
+const WASM_INSTRUMENT_VERSION: &str = "0.4.0";
+
+/// Returns the namespace under which modules compiled with the given engine config are persisted.
+///
+/// Persisted modules depend on how they were instrumented and compiled, so we hash a canonical
+/// encoding of the relevant parts of the config along with the FVM and instrumentation versions.
+/// Unlike `std`'s hashers, the result is stable across builds and processes.
+fn cache_namespace(ec: &EngineConfig) -> String {
+    let WasmGasPrices {
+        instruction_default,
+        math_default,
+        jump_unconditional,
+        jump_conditional,
+        jump_indirect,
+        call,
+        memory_fill_base_cost,
+        memory_fill_per_byte_cost,
+        memory_access_cost,
+        memory_copy_per_byte_cost,
+        fuel_exchange_rate,
+    } = ec.wasm_prices;
+    let WasmFeatures {
+        simd,
+        bulk_memory,
+        reference_types,
+        multi_value,
+    } = ec.wasm_features;
+
+    let mut state = blake2b_simd::Params::new().hash_length(16).to_state();
+    for version in [env!("CARGO_PKG_VERSION"), WASM_INSTRUMENT_VERSION] {
+        state.update(&(version.len() as u64).to_le_bytes());
+        state.update(version.as_bytes());
+    }
+    state.update(&ec.max_wasm_stack.to_le_bytes());
+    for price in [
+        instruction_default,
+        math_default,
+        jump_unconditional,
+        jump_conditional,
+        jump_indirect,
+        call,
+        memory_fill_base_cost,
+        memory_fill_per_byte_cost,
+        memory_access_cost,
+        memory_copy_per_byte_cost,
+        fuel_exchange_rate,
+    ] {
+        state.update(&price.as_milligas().to_le_bytes());
+    }
+    for flag in [
+        ec.fuel_metering,
+        ec.wasm_backtrace,
+        simd,
+        bulk_memory,
+        reference_types,
+        multi_value,
+        ec.execution_timeout.is_some(),
+    ] {
+        state.update(&[flag as u8]);
+    }
+    state.finalize().to_hex().to_string()
+}
+
+struct EngineInner {
+    limit: Mutex<u32>,
+    condv: Condvar,
+
+    engine: wasmtime::Engine,
+
+    /// These two fields are used used in the store constructor to avoid resolve a chicken & egg
+    /// situation: We need the store before we can get the real values, but we need to create the
+    /// `InvocationData` before we can make the store.
+    ///
+    /// Alternatively, we could use `Option`s. But then we need to unwrap everywhere.
+    dummy_gas_global: Global,
+    dummy_memory: Memory,
+
+    module_cache: Mutex<ModuleCache>,
+    instance_cache: Mutex<HashMap<TypeId, Box<dyn Any + Send>>>,
+    config: EngineConfig,
+
+    actor_redirect: HashMap<Cid, Cid>,
+
+    /// The number of times the epoch ticker has incremented the engine's epoch (only used when
+    /// execution timeouts are enabled).
+    epoch: AtomicU64,
+
+    /// The sink into which wasm compilation metrics are recorded.
+    metrics: Arc<dyn Metrics>,
+}
+
+impl EngineInner {
+    fn increment_epoch(&self) {
+        self.epoch.fetch_add(1, Ordering::SeqCst);
+        self.engine.increment_epoch();
+    }
+
+    /// Computes the epoch deadline for a message starting now, if execution timeouts are enabled.
+    fn new_deadline(&self) -> Option<u64> {
+        let timeout = self.config.execution_timeout?;
+        let tick = EPOCH_TICK.as_nanos();
+        let ticks = ((timeout.as_nanos() + tick - 1) / tick).max(1);
+        Some(
+            self.epoch
+                .load(Ordering::SeqCst)
+                .saturating_add(ticks.try_into().unwrap_or(u64::MAX)),
+        )
+    }
+}
+
+/// How often the engine's epoch is incremented when execution timeouts are enabled.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// EnginePool represents a limited pool of engines.
+///
+/// Clones share the same engines and module cache, so a single pool can be handed to multiple
+/// executors (and machines) running concurrently. See [`MultiEngine`].
+#[derive(Clone)]
+pub struct EnginePool(Arc<EngineInner>);
+
+impl EnginePool {
+    /// Acquire an [`Engine`]. This method will block until an [`Engine`] is available, and will
+    /// release the engine on drop.
+    pub fn acquire(&self) -> Engine {
+        *self
+            .0
+            .condv
+            .wait_while(self.0.limit.lock().unwrap(), |limit| *limit == 0)
+            .unwrap() -= 1;
+        Engine {
+            deadline: self.0.new_deadline(),
+            inner: self.0.clone(),
+        }
+    }
+
+    /// Try to acquire an [`Engine`]. Returns `None` if the call would block, or if the lock is
+    /// poisoned.
+    ///
+    /// The [`Engine`] is released on drop.
+    pub fn try_acquire(&self) -> Option<Engine> {
+        self.0
+            .limit
+            .try_lock()
+            .ok()
+            .filter(|limit| **limit > 0)
+            .map(|mut limit| {
+                *limit -= 1;
+                Engine {
+                    deadline: self.0.new_deadline(),
+                    inner: self.0.clone(),
+                }
+            })
+    }
+
+    pub fn new_default(ec: EngineConfig) -> anyhow::Result<Self> {
+        EnginePool::new(&wasmtime_config(&ec)?, ec)
+    }
+
+    /// Create a new Engine from a wasmtime config.
+    pub fn new(c: &wasmtime::Config, ec: EngineConfig) -> anyhow::Result<Self> {
+        Self::new_with_metrics(c, ec, Arc::new(NoopMetrics))
+    }
+
+    /// Create a new Engine from a wasmtime config, recording wasm compilation metrics into the
+    /// given sink.
+    pub fn new_with_metrics(
+        c: &wasmtime::Config,
+        ec: EngineConfig,
+        metrics: Arc<dyn Metrics>,
+    ) -> anyhow::Result<Self> {
+        let engine = wasmtime::Engine::new(c)?;
+
+        let mut dummy_store = wasmtime::Store::new(&engine, ());
+        let gg_type = GlobalType::new(ValType::I64, Mutability::Var);
+        let dummy_gg = Global::new(&mut dummy_store, gg_type, Val::I64(0))
+            .expect("failed to create dummy gas global");
+
+        let dummy_memory = Memory::new(&mut dummy_store, MemoryType::new(0, Some(0)))
+            .expect("failed to create dummy memory");
+
+        let actor_redirect = ec.actor_redirect.iter().cloned().collect();
+
+        let module_cache = ModuleCache::new(ec.module_cache.clone(), cache_namespace(&ec));
+
+        let execution_timeout = ec.execution_timeout;
+        let inner = Arc::new(EngineInner {
+            limit: Mutex::new(ec.concurrency),
+            condv: Condvar::new(),
+            engine,
+            dummy_memory,
+            dummy_gas_global: dummy_gg,
+            module_cache: Mutex::new(module_cache),
+            instance_cache: Mutex::new(HashMap::new()),
+            config: ec,
+            actor_redirect,
+            epoch: AtomicU64::new(0),
+            metrics,
+        });
+
+        if execution_timeout.is_some() {
+            // Tick until the engine is dropped.
+            let weak = Arc::downgrade(&inner);
+            std::thread::Builder::new()
+                .name("fvm-epoch-ticker".into())
+                .spawn(move || loop {
+                    std::thread::sleep(EPOCH_TICK);
+                    match weak.upgrade() {
+                        Some(inner) => inner.increment_epoch(),
+                        None => break,
+                    }
+                })?;
+        }
+
+        Ok(EnginePool(inner))
+    }
+}
+
+struct Cache<K> {
+    linker: wasmtime::Linker<InvocationData<K>>,
+}
+
+/// An `Engine` represents a single, caching wasm engine. It should not be shared between concurrent
+/// call stacks.
+///
+/// The `Engine` will be returned to the [`EnginePool`] on drop.
+pub struct Engine {
+    inner: Arc<EngineInner>,
+    /// The epoch by which the message being executed on this engine must complete, if execution
+    /// timeouts are enabled.
+    deadline: Option<u64>,
+}
+
+impl Deref for Engine {
+    type Target = wasmtime::Engine;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner.engine
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        let mut limit = self.inner.limit.lock().unwrap();
+        *limit += 1;
+        self.inner.condv.notify_one();
+    }
+}
+
+impl Engine {
+    /// Loads an actor's Wasm code from the blockstore by CID, and prepares
+    /// it for execution by instantiating and caching the Wasm module. This
+    /// method errors if the code CID is not found in the store.
+    ///
+    /// Return the original byte code size.
+    pub fn prepare_actor_code<BS: Blockstore>(
+        &self,
+        code_cid: &Cid,
+        blockstore: BS,
+    ) -> anyhow::Result<usize> {
+        let code_cid = self.with_redirect(code_cid);
+        if let Some(item) = self.module_cache().get(code_cid) {
+            return Ok(item.size);
+        }
+        let wasm = blockstore.get(code_cid)?.ok_or_else(|| {
+            anyhow!(
+                "no wasm bytecode in blockstore for CID {}",
+                &code_cid.to_string()
+            )
+        })?;
+        // compile and cache instantiated WASM module
+        self.prepare_wasm_bytecode(code_cid, &wasm)
+    }
+
+    /// Instantiates and caches the Wasm modules for the bytecodes addressed by
+    /// the supplied CIDs. Only uncached entries are actually fetched and
+    /// instantiated. Blockstore failures and entry inexistence shortcircuit
+    /// make this method return an Err immediately.
+    ///
+    /// Returns the total original byte size of the modules
+    pub fn preload<'a, BS, I>(&self, blockstore: BS, cids: I) -> anyhow::Result<usize>
+    where
+        BS: Blockstore,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        let mut total_size = 0usize;
+        for cid in cids {
+            log::trace!("preloading code CID {cid}");
+            let size = self.prepare_actor_code(cid, &blockstore).with_context(|| {
+                anyhow!("could not prepare actor with code CID {}", &cid.to_string())
+            })?;
+            total_size += size;
+        }
+        Ok(total_size)
+    }
+
+    /// Like [`Engine::preload`], but also pins the modules in the module cache so they're never
+    /// evicted (see [`ModuleCacheConfig::max_modules`]). Use this for actors that are invoked
+    /// frequently, so they never need to be recompiled in the middle of a block.
+    ///
+    /// Returns the total original byte size of the modules.
+    pub fn preload_pinned<'a, BS, I>(&self, blockstore: BS, cids: I) -> anyhow::Result<usize>
+    where
+        BS: Blockstore,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        let mut total_size = 0usize;
+        for cid in cids {
+            // Pin first so that preloading the rest can't evict this module.
+            self.module_cache().pin(*self.with_redirect(cid));
+            total_size += self.preload(&blockstore, [cid])?;
+        }
+        Ok(total_size)
+    }
+
+    /// Returns the namespace under which compiled modules are stored for this engine's
+    /// configuration (see [`ModuleCacheConfig::persist_dir`]). Compiled modules can only be loaded
+    /// by engines with the same configuration, FVM version, and `fvm-wasm-instrument` version.
+    /// Wasmtime additionally refuses to load modules compiled by a different wasmtime version.
+    pub fn module_namespace(&self) -> String {
+        self.module_cache().namespace().to_owned()
+    }
+
+    /// Ahead-of-time compiles the actors with the supplied code CIDs, writing the compiled modules
+    /// into `dir` using the layout of [`ModuleCacheConfig::persist_dir`].
+    ///
+    /// Engines with the same configuration and with `dir` as their persistence directory will load
+    /// these modules instead of compiling the actors' Wasm. This avoids paying for compilation the
+    /// first time each builtin actor is invoked.
+    ///
+    /// Returns the number of modules written.
+    pub fn precompile<'a, BS, I>(
+        &self,
+        blockstore: BS,
+        cids: I,
+        dir: impl AsRef<Path>,
+    ) -> anyhow::Result<usize>
+    where
+        BS: Blockstore,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        let namespace = self.module_namespace();
+        let mut count = 0;
+        for cid in cids {
+            let cid = self.with_redirect(cid);
+            let wasm = blockstore
+                .get(cid)?
+                .ok_or_else(|| anyhow!("no wasm bytecode in blockstore for CID {}", cid))?;
+            let record = self
+                .compile(&wasm)
+                .with_context(|| format!("failed to compile actor with code CID {}", cid))?;
+            let path = cache::module_path(dir.as_ref(), &namespace, cid);
+            cache::write_module(&path, &record)
+                .with_context(|| format!("failed to write module to {}", path.display()))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn module_cache(&self) -> std::sync::MutexGuard<'_, ModuleCache> {
+        self.inner
+            .module_cache
+            .lock()
+            .expect("module_cache poisoned")
+    }
+
+    fn with_redirect<'a>(&'a self, k: &'a Cid) -> &'a Cid {
+        match &self.inner.actor_redirect.get(k) {
+            Some(cid) => cid,
+            None => k,
+        }
+    }
+
+    /// Loads some Wasm code into the engine and prepares it for execution.
+    pub fn prepare_wasm_bytecode(&self, k: &Cid, wasm: &[u8]) -> anyhow::Result<usize> {
+        let k = self.with_redirect(k);
+        let mut cache = self.module_cache();
+        let size = match cache.get(k) {
+            Some(item) => item.size,
+            None => {
+                let m = self.load_raw(&cache, k, wasm)?;
+                let s = m.size;
+                cache.insert(*k, m);
+                s
+            }
+        };
+        Ok(size)
+    }
+
+    /// Compiles the given Wasm (or loads it from the persistent module cache, if present) without
+    /// inserting it into the in-memory cache.
+    fn load_raw(
+        &self,
+        cache: &ModuleCache,
+        k: &Cid,
+        raw_wasm: &[u8],
+    ) -> anyhow::Result<ModuleRecord> {
+        if let Some(record) = cache.load_persisted(&self.inner.engine, k) {
+            return Ok(record);
+        }
+        let start = Instant::now();
+        let record = self.compile(raw_wasm)?;
+        self.inner.metrics.module_compiled(start.elapsed());
+        cache.persist(k, &record);
+        Ok(record)
+    }
+
+    fn compile(&self, raw_wasm: &[u8]) -> anyhow::Result<ModuleRecord> {
+        // First make sure that non-instrumented wasm is valid
+        Module::validate(&self.inner.engine, raw_wasm)
+            .map_err(anyhow::Error::msg)
+            .with_context(|| "failed to validate actor wasm")?;
+
+        // Note: when adding debug mode support (with recorded syscall replay) don't instrument to
+        // avoid breaking debug info
+
+        use fvm_wasm_instrument::{gas_metering, stack_limiter};
+
+        // stack limiter adds post/pre-ambles to call instructions; We want to do that
+        // before injecting gas accounting calls to avoid this overhead in every single
+        // block of code.
+        let raw_wasm = stack_limiter::inject(raw_wasm, self.inner.config.max_wasm_stack)
+            .map_err(anyhow::Error::msg)?;
+
+        // inject gas metering based on a price list. This function will
+        // * add a new mutable i64 global import, gas.gas_counter
+        // * push a gas counter function which deduces gas from the global, and
+        //   traps when gas.gas_counter is less than zero
+        // * optionally push a function which wraps memory.grow instruction
+        //   making it charge gas based on memory requested
+        // * divide code into metered blocks, and add a call to the gas counter
+        //   function before entering each metered block
+        // * NOTE: Currently cannot instrument and charge for `table.grow` because the instruction
+        //   (code `0xFC 15`) uses what parity-wasm calls the `BULK_PREFIX` but it was added later in
+        //   https://github.com/WebAssembly/reference-types/issues/29 and is not recognised by the
+        //   parity-wasm module parser, so the contract cannot grow the tables.
+        //
+        // When metering with fuel, wasmtime charges for instructions, so we only instrument the
+        // per-byte cost of bulk memory operations.
+        let raw_wasm = if self.inner.config.fuel_metering {
+            gas_metering::inject(&raw_wasm, &FuelRules(self.inner.config.wasm_prices), "gas")
+        } else {
+            gas_metering::inject(&raw_wasm, self.inner.config.wasm_prices, "gas")
+        }
+        .map_err(|_| anyhow::Error::msg("injecting gas counter failed"))?;
+
+        let module = Module::from_binary(&self.inner.engine, &raw_wasm)?;
+
+        Ok(ModuleRecord {
+            module,
+            size: raw_wasm.len(),
+        })
+    }
+
+    /// Load compiled wasm code into the engine.
+    ///
+    /// # Safety
+    ///
+    /// See [`wasmtime::Module::deserialize`] for safety information.
+    pub unsafe fn load_compiled(&self, k: &Cid, compiled: &[u8]) -> anyhow::Result<Module> {
+        let k = self.with_redirect(k);
+        let mut cache = self.module_cache();
+        let module = match cache.get(k) {
+            Some(m) => m.module,
+            None => {
+                let module = Module::deserialize(&self.inner.engine, compiled)?;
+                cache.insert(
+                    *k,
+                    ModuleRecord {
+                        module: module.clone(),
+                        size: compiled.len(),
+                    },
+                );
+                module
+            }
+        };
+        Ok(module)
+    }
+
+    /// Lookup a loaded wasmtime module.
+    pub fn get_module(
+        &self,
+        blockstore: &impl Blockstore,
+        k: &Cid,
+    ) -> anyhow::Result<Option<Module>> {
+        let k = self.with_redirect(k);
+        let mut cache = self.module_cache();
+        if let Some(record) = cache.get(k) {
+            return Ok(Some(record.module));
+        }
+        let raw_wasm = match blockstore
+            .get(k)
+            .context("failed to lookup wasm module in blockstore")?
+        {
+            Some(raw_wasm) => raw_wasm,
+            None => return Ok(None),
+        };
+        let record = self.load_raw(&cache, k, &raw_wasm)?;
+        let module = record.module.clone();
+        cache.insert(*k, record);
+        Ok(Some(module))
+    }
+
+    /// Lookup and instantiate a loaded wasmtime module with the given store. This will cache the
+    /// linker, syscalls, etc.
+    ///
+    /// This returns an `Abort` as it may need to execute initialization code, charge gas, etc.
+    pub fn instantiate<K: Kernel>(
+        &self,
+        store: &mut wasmtime::Store<InvocationData<K>>,
+        k: &Cid,
+    ) -> Result<Option<wasmtime::Instance>, Abort> {
+        let k = self.with_redirect(k);
+        let mut instance_cache = self.inner.instance_cache.lock().expect("cache poisoned");
+
+        let type_id = TypeId::of::<K>();
+        let cache: &mut Cache<K> = match instance_cache.entry(type_id) {
+            Occupied(e) => &mut *e
+                .into_mut()
+                .downcast_mut()
+                .expect("invalid instance cache entry"),
+            Vacant(e) => &mut *e
+                .insert({
+                    let mut linker: Linker<InvocationData<K>> = Linker::new(&self.inner.engine);
+                    linker.allow_shadowing(true);
+
+                    bind_syscalls(&mut linker, self.inner.config.network_version)
+                        .map_err(Abort::Fatal)?;
+                    K::bind_custom_syscalls(&mut SyscallLinker::new(&mut linker))
+                        .map_err(Abort::Fatal)?;
+                    Box::new(Cache { linker })
+                })
+                .downcast_mut()
+                .expect("invalid instance cache entry"),
+        };
+        let gas_global = store.data_mut().avail_gas_global;
+        cache
+            .linker
+            .define(&store, "gas", GAS_COUNTER_NAME, gas_global)
+            .context("failed to define gas counter")
+            .map_err(Abort::Fatal)?;
+
+        let mut module_cache = self.module_cache();
+
+        let instantiate = |store: &mut wasmtime::Store<InvocationData<K>>, module| {
+            // Before we instantiate the module, we should make sure the user has sufficient gas to
+            // pay for the minimum memory requirements. The module instrumentation in `inject` only
+            // adds code to charge for _growing_ the memory, but not for the amount made accessible
+            // initially. The limits are checked by wasmtime during instantiation, though.
+            let t = charge_for_init(store, module).map_err(Abort::from_error_as_fatal)?;
+
+            // Pre-instantiate to catch any linker errors. These are considered fatal as it means
+            // the wasm module wasn't properly validated.
+            let pre_instance = cache
+                .linker
+                .instantiate_pre(module)
+                .context("failed to link actor module")?;
+
+            // Update the gas _just_ in case.
+            update_gas_available(store)?;
+            let res = pre_instance.instantiate(&mut *store);
+            charge_for_exec(store)?;
+
+            let inst = res.map_err(|e| {
+                // We can't really tell what type of error happened, so we have to assume that we
+                // either ran out of memory or trapped. Given that we've already type-checked the
+                // module, this is the most likely case anyways. That or there'a a bug in the FVM.
+                Abort::Exit(
+                    ExitCode::SYS_ILLEGAL_INSTRUCTION,
+                    format!("failed to instantiate module: {e}"),
+                    0,
+                )
+            })?;
+
+            // Record the time it took for the linker to instantiate the module.
+            // This should also include everything that happens above in this method.
+            // Note that this does _not_ contain the time it took the load the Wasm file,
+            // which could have been cached already.
+            record_init_time(store, t);
+
+            Ok(Some(inst))
+        };
+
+        let module = match module_cache.get(k) {
+            Some(record) => record.module,
+            None => match store
+                .data()
+                .kernel
+                .machine()
+                .blockstore()
+                .get(k)
+                .context("failed to lookup wasm module in blockstore")
+                .map_err(Abort::Fatal)?
+            {
+                Some(raw_wasm) => {
+                    let record = self
+                        .load_raw(&module_cache, k, &raw_wasm)
+                        .map_err(Abort::Fatal)?;
+                    let module = record.module.clone();
+                    module_cache.insert(*k, record);
+                    module
+                }
+                None => return Ok(None),
+            },
+        };
+        drop(module_cache);
+        instantiate(store, &module)
+    }
+
+    /// Construct a new wasmtime "store" from the given kernel.
+    pub fn new_store<K: Kernel>(&self, mut kernel: K) -> wasmtime::Store<InvocationData<K>> {
+        let memory_bytes = kernel.limiter_mut().memory_used();
+
+        let id = InvocationData {
+            kernel,
+            last_error: None,
+            avail_gas_global: self.inner.dummy_gas_global,
+            last_gas_available: Gas::zero(),
+            last_fuel_available: self.inner.config.fuel_metering.then_some(0),
+            last_memory_bytes: memory_bytes,
+            last_charge_time: GasTimer::start(),
+            memory: self.inner.dummy_memory,
+        };
+
+        let mut store = wasmtime::Store::new(&self.inner.engine, id);
+        let ggtype = GlobalType::new(ValType::I64, Mutability::Var);
+        let gg = Global::new(&mut store, ggtype, Val::I64(0))
+            .expect("failed to create available_gas global");
+        store.data_mut().avail_gas_global = gg;
+
+        fn as_wasmtime_limiter<K: Kernel>(
+            data: &mut InvocationData<K>,
+        ) -> &mut dyn wasmtime::ResourceLimiter {
+            // SAFETY: This is safe because WasmtimeLimiter is `repr(transparent)`.
+            // Unfortunately, we can't simply wrap the limiter as we need to return a reference.
+            let limiter: &mut WasmtimeLimiter<K::Limiter> = unsafe {
+                let limiter_ref = data.kernel.limiter_mut();
+                // (debug)-assert that these types have the same layout (guaranteed by
+                // `repr(transparent)`).
+                debug_assert_eq!(
+                    std::alloc::Layout::for_value(&*limiter_ref),
+                    std::alloc::Layout::new::<WasmtimeLimiter<K::Limiter>>()
+                );
+                // Then cast.
+                &mut *(limiter_ref as *mut K::Limiter as *mut WasmtimeLimiter<K::Limiter>)
+            };
+            limiter as &mut dyn wasmtime::ResourceLimiter
+        }
+
+        store.limiter(as_wasmtime_limiter);
+
+        if let Some(deadline) = self.deadline {
+            let now = self.inner.epoch.load(Ordering::SeqCst);
+            store.set_epoch_deadline(deadline.saturating_sub(now));
+            store.epoch_deadline_trap();
+        }
+
+        store
+    }
+}
+
+#[repr(transparent)]
+struct WasmtimeLimiter<L>(L);
+
+impl<L: MemoryLimiter> wasmtime::ResourceLimiter for WasmtimeLimiter<L> {
+    fn memory_growing(&mut self, current: usize, desired: usize, maximum: Option<usize>) -> bool {
+        if maximum.map_or(false, |m| desired > m) {
+            return false;
+        }
+
+        self.0.grow_instance_memory(current, desired)
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> bool {
+        if maximum.map_or(false, |m| desired > m) {
+            return false;
+        }
+        self.0.grow_instance_table(current, desired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use cid::Cid;
+    use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
+    use fvm_shared::version::NetworkVersion;
+    use fvm_shared::IPLD_RAW;
+    use multihash::{Code, MultihashDigest};
+    use wasmtime::ResourceLimiter;
+
+    use crate::engine::{
+        wasmtime_config, EngineConfig, EnginePool, ModuleStats, WasmFeatures, WasmLimits,
+        WasmValidationError, WasmtimeLimiter,
+    };
+    use crate::machine::limiter::MemoryLimiter;
+    use crate::machine::NetworkConfig;
+    use crate::syscalls::error::Abort;
+
+    // (module (func (export "spin") (loop (br 0))))
+    const SPIN_WASM: &[u8] = b"\0asm\x01\0\0\0\
+        \x01\x04\x01\x60\x00\x00\
+        \x03\x02\x01\x00\
+        \x07\x08\x01\x04spin\x00\x00\
+        \x0a\x09\x01\x07\x00\x03\x40\x0c\x00\x0b\x0b";
+
+    #[derive(Default)]
+    struct Limiter {
+        memory: usize,
+    }
+    impl MemoryLimiter for Limiter {
+        fn memory_used(&self) -> usize {
+            unimplemented!()
+        }
+
+        fn grow_memory(&mut self, bytes: usize) -> bool {
+            self.memory += bytes;
+            true
+        }
+
+        fn with_stack_frame<T, G, F, R>(_: &mut T, _: G, _: F) -> R
+        where
+            G: Fn(&mut T) -> &mut Self,
+            F: FnOnce(&mut T) -> R,
+        {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn memory() {
+        let mut limits = WasmtimeLimiter(Limiter::default());
+        assert!(limits.memory_growing(0, 3, None));
+        assert_eq!(limits.0.memory, 3);
+
+        // The maximum in the args takes precedence.
+        assert!(!limits.memory_growing(3, 4, Some(2)));
+        assert_eq!(limits.0.memory, 3);
+
+        // Increase by 2.
+        assert!(limits.memory_growing(2, 4, None));
+        assert_eq!(limits.0.memory, 5);
+    }
+
+    #[test]
+    fn table() {
+        let mut limits = WasmtimeLimiter(Limiter::default());
+        assert!(limits.table_growing(0, 3, None));
+        assert_eq!(limits.0.memory, 3 * 8);
+
+        // The maximum in the args takes precedence.
+        assert!(!limits.table_growing(3, 4, Some(2)));
+        assert_eq!(limits.0.memory, 3 * 8);
+
+        // Increase by 2.
+        assert!(limits.table_growing(2, 4, None));
+        assert_eq!(limits.0.memory, 5 * 8);
+    }
+
+    #[test]
+    fn canonicalizes_nans() {
+        // (module (func (export "nan") (result i32)
+        //   (i32.reinterpret_f32 (f32.div (f32.const 0) (f32.const 0)))))
+        const NAN_WASM: &[u8] = b"\0asm\x01\0\0\0\
+            \x01\x05\x01\x60\x00\x01\x7f\
+            \x03\x02\x01\x00\
+            \x07\x07\x01\x03nan\x00\x00\
+            \x0a\x10\x01\x0e\x00\x43\0\0\0\0\x43\0\0\0\0\x95\xbc\x0b";
+
+        let ec = EngineConfig::from(&NetworkConfig::new(NetworkVersion::V18));
+        let engine = wasmtime::Engine::new(&wasmtime_config(&ec).unwrap()).unwrap();
+        let module = wasmtime::Module::new(&engine, NAN_WASM).unwrap();
+        let mut store = wasmtime::Store::new(&engine, ());
+        let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+        let nan = instance
+            .get_typed_func::<(), i32>(&mut store, "nan")
+            .unwrap()
+            .call(&mut store, ())
+            .unwrap();
+        assert_eq!(nan as u32, f32::NAN.to_bits());
+    }
+
+    #[test]
+    fn wasm_features() {
+        // (module (func (export "two") (result i32 i32) (i32.const 1) (i32.const 2)))
+        const MULTI_VALUE_WASM: &[u8] = b"\0asm\x01\0\0\0\
+            \x01\x06\x01\x60\x00\x02\x7f\x7f\
+            \x03\x02\x01\x00\
+            \x07\x07\x01\x03two\x00\x00\
+            \x0a\x08\x01\x06\x00\x41\x01\x41\x02\x0b";
+
+        let validate = |nc: &NetworkConfig| {
+            let engine = wasmtime::Engine::new(&wasmtime_config(&nc.into()).unwrap()).unwrap();
+            wasmtime::Module::validate(&engine, MULTI_VALUE_WASM)
+        };
+
+        let mut nc = NetworkConfig::new(NetworkVersion::V18);
+        assert_eq!(
+            nc.wasm_features,
+            WasmFeatures::for_network_version(NetworkVersion::V18)
+        );
+        assert!(!nc.wasm_features.multi_value);
+        assert!(validate(&nc).is_err());
+
+        nc.wasm_features(WasmFeatures {
+            multi_value: true,
+            ..nc.wasm_features
+        });
+        validate(&nc).unwrap();
+
+        // Machines with different features get different engines.
+        assert!(
+            EngineConfig::from(&nc) != EngineConfig::from(&NetworkConfig::new(NetworkVersion::V18))
+        );
+    }
+
+    #[test]
+    fn wasm_limits() {
+        let limits = WasmLimits::for_network_version(NetworkVersion::V18);
+        assert_eq!(
+            limits.check(SPIN_WASM).unwrap(),
+            ModuleStats {
+                size: SPIN_WASM.len(),
+                imports: 0,
+                functions: 1,
+            }
+        );
+
+        let err = WasmLimits {
+            max_functions: 0,
+            ..limits
+        }
+        .check(SPIN_WASM)
+        .unwrap_err();
+        assert_eq!(
+            err,
+            WasmValidationError::TooManyFunctions { count: 1, max: 0 }
+        );
+        assert!(err.is_limit_exceeded());
+
+        let err = WasmLimits {
+            max_module_size: SPIN_WASM.len() - 1,
+            ..limits
+        }
+        .check(SPIN_WASM)
+        .unwrap_err();
+        assert!(matches!(err, WasmValidationError::ModuleTooLarge { .. }));
+
+        let err = limits.check(b"not wasm").unwrap_err();
+        assert!(matches!(err, WasmValidationError::Invalid(_)));
+        assert!(!err.is_limit_exceeded());
+    }
+
+    #[test]
+    fn execution_timeout() {
+        let mut nc = NetworkConfig::new(NetworkVersion::V18);
+        nc.execution_timeout(Duration::from_millis(50));
+        // Timeouts are non-deterministic, so they're rejected unless determinism isn't required.
+        assert!(EnginePool::new_default((&nc).into()).is_err());
+        nc.deterministic = false;
+        let pool = EnginePool::new_default((&nc).into()).unwrap();
+        let engine = pool.acquire();
+        let deadline = engine.deadline.expect("expected a deadline");
+
+        let module = wasmtime::Module::new(&engine, SPIN_WASM).unwrap();
+        let mut store = wasmtime::Store::new(&engine, ());
+        store.set_epoch_deadline(deadline);
+        store.epoch_deadline_trap();
+        let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+        let err = instance
+            .get_typed_func::<(), ()>(&mut store, "spin")
+            .unwrap()
+            .call(&mut store, ())
+            .unwrap_err();
+        assert!(matches!(Abort::from(err), Abort::Fatal(_)));
+    }
+
+    #[test]
+    fn instance_pool() {
+        // (module (memory (export "memory") 1))
+        const MEMORY_WASM: &[u8] = b"\0asm\x01\0\0\0\
+            \x05\x03\x01\x00\x01\
+            \x07\x0a\x01\x06memory\x02\x00";
+
+        // A single instance slot.
+        let mut ec = EngineConfig::from(&NetworkConfig::new(NetworkVersion::V18));
+        ec.max_call_depth = 0;
+        ec.instance_pool
+            .memory_keep_resident(1 << 20)
+            .table_keep_resident(1 << 12);
+        let engine = EnginePool::new_default(ec).unwrap().acquire();
+        let module = wasmtime::Module::new(&engine, MEMORY_WASM).unwrap();
+
+        let mut store = wasmtime::Store::new(&engine, ());
+        let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+        let memory = instance.get_memory(&mut store, "memory").unwrap();
+        memory.data_mut(&mut store)[..4].copy_from_slice(b"dirt");
+        let slot = memory.data_ptr(&store);
+
+        // The only slot is taken.
+        let mut other = wasmtime::Store::new(&engine, ());
+        assert!(wasmtime::Instance::new(&mut other, &module, &[]).is_err());
+        drop(store);
+
+        // Once released, the slot (and its resident memory) is reused, but reset.
+        for _ in 0..3 {
+            let mut store = wasmtime::Store::new(&engine, ());
+            let instance = wasmtime::Instance::new(&mut store, &module, &[]).unwrap();
+            let memory = instance.get_memory(&mut store, "memory").unwrap();
+            assert_eq!(memory.data_ptr(&store), slot);
+            assert_eq!(&memory.data(&store)[..4], &[0; 4]);
+        }
+    }
+
+    #[test]
+    fn preload_pinned() {
+        // (module (func))
+        const EMPTY_WASM: &[u8] = b"\0asm\x01\0\0\0\
+            \x01\x04\x01\x60\x00\x00\
+            \x03\x02\x01\x00\
+            \x0a\x04\x01\x02\x00\x0b";
+
+        let bs = MemoryBlockstore::default();
+        let spin = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(SPIN_WASM));
+        let empty = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(EMPTY_WASM));
+        bs.put_keyed(&spin, SPIN_WASM).unwrap();
+        bs.put_keyed(&empty, EMPTY_WASM).unwrap();
+
+        let mut ec = EngineConfig::from(&NetworkConfig::new(NetworkVersion::V18));
+        ec.module_cache.max_modules(1);
+        let engine = EnginePool::new_default(ec).unwrap().acquire();
+        assert!(engine.preload_pinned(&bs, [&spin]).unwrap() > 0);
+        engine.preload(&bs, [&empty]).unwrap();
+
+        // The cache is full, but the pinned module wasn't evicted.
+        let mut cache = engine.module_cache();
+        assert!(cache.is_pinned(&spin));
+        assert!(cache.get(&spin).is_some());
+        assert!(cache.get(&empty).is_some());
+    }
+
+    #[test]
+    fn cache_namespace() {
+        let nc = NetworkConfig::new(NetworkVersion::V18);
+        let ec = EngineConfig::from(&nc);
+        assert_eq!(
+            super::cache_namespace(&ec),
+            super::cache_namespace(&EngineConfig::from(&nc))
+        );
+
+        let mut fuel = ec.clone();
+        fuel.fuel_metering = true;
+        assert_ne!(super::cache_namespace(&ec), super::cache_namespace(&fuel));
+
+        // The instrumentation version must track the manifest.
+        let manifest = include_str!("../../Cargo.toml");
+        assert!(manifest.contains(&format!(
+            "fvm-wasm-instrument = \"{}\"",
+            super::WASM_INSTRUMENT_VERSION
+        )));
+    }
+
+    #[test]
+    fn precompile() {
+        let bs = MemoryBlockstore::default();
+        let code = Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(SPIN_WASM));
+        bs.put_keyed(&code, SPIN_WASM).unwrap();
+        let dir = std::env::temp_dir().join(format!("fvm-precompile-{}", std::process::id()));
+
+        let nc = NetworkConfig::new(NetworkVersion::V18);
+        let engine = EnginePool::new_default((&nc).into()).unwrap().acquire();
+        assert_eq!(engine.precompile(&bs, [&code], &dir).unwrap(), 1);
+        let path = dir
+            .join(engine.module_namespace())
+            .join(format!("{code}.cwasm"));
+        assert!(path.is_file());
+
+        // Engines with the same config share the namespace, and load the precompiled module.
+        let mut ec = EngineConfig::from(&nc);
+        ec.module_cache.persist_to(&dir);
+        let engine2 = EnginePool::new_default(ec).unwrap().acquire();
+        assert_eq!(engine2.module_namespace(), engine.module_namespace());
+        assert!(engine2.get_module(&bs, &code).unwrap().is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Up-front checks on user-deployed Wasm modules, performed before they're compiled.
+use fvm_shared::version::NetworkVersion;
+use wasmparser::{Parser, Payload, TypeRef, Validator};
+
+use super::WasmFeatures;
+use crate::machine::NetworkConfig;
+use crate::syscalls::{is_syscall_available, SYSCALLS};
+
+/// Consensus limits on the shape of user-deployed Wasm modules. Modules exceeding these limits are
+/// rejected before being compiled (or charged for compilation).
+///
+/// Builtin actors aren't subject to these limits.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct WasmLimits {
+    /// The maximum size of a module, in bytes.
+    pub max_module_size: usize,
+    /// The maximum number of imports (of any kind) a module may declare.
+    pub max_imports: u32,
+    /// The maximum number of functions a module may define (not counting imported functions).
+    pub max_functions: u32,
+    /// The maximum number of tables a module may declare.
+    pub max_tables: u32,
+    /// The maximum initial (and, if declared, maximum) number of elements of each table.
+    pub max_table_elements: u32,
+    /// The maximum initial (and, if declared, maximum) size of the module's memory, in Wasm pages.
+    pub max_memory_pages: u64,
+    /// Whether modules may use floating-point types and instructions when deployed. These are
+    /// deterministic because NaNs are always canonicalized.
+    pub allow_floats: bool,
+}
+
+impl WasmLimits {
+    /// Returns the Wasm module limits in the given network version.
+    pub fn for_network_version(_network_version: NetworkVersion) -> Self {
+        // All currently supported network versions share the same limits.
+        WasmLimits {
+            max_module_size: 4 << 20,
+            max_imports: 512,
+            max_functions: 65_536,
+            max_tables: 1,
+            max_table_elements: 10_000,
+            max_memory_pages: 1024,
+            allow_floats: false,
+        }
+    }
+
+    /// Checks that a module is within these limits, returning the statistics used to price its
+    /// compilation. The size is checked before the module is parsed, and parsing stops at the
+    /// first section exceeding a limit.
+    ///
+    /// This only parses the module's headers. Function bodies are validated when the module is
+    /// compiled, and the rules that only apply at deployment are checked by
+    /// [`validate_wasm_for_deployment`].
+    pub fn check(&self, wasm: &[u8]) -> Result<ModuleStats, WasmValidationError> {
+        if wasm.len() > self.max_module_size {
+            return Err(WasmValidationError::ModuleTooLarge {
+                size: wasm.len(),
+                max: self.max_module_size,
+            });
+        }
+
+        let mut stats = ModuleStats {
+            size: wasm.len(),
+            imports: 0,
+            functions: 0,
+        };
+        for payload in Parser::new(0).parse_all(wasm) {
+            match payload.map_err(|e| WasmValidationError::Invalid(e.to_string()))? {
+                Payload::ImportSection(imports) => {
+                    stats.imports = imports.count();
+                    if stats.imports > self.max_imports {
+                        return Err(WasmValidationError::TooManyImports {
+                            count: stats.imports,
+                            max: self.max_imports,
+                        });
+                    }
+                }
+                Payload::FunctionSection(functions) => {
+                    stats.functions = functions.count();
+                    if stats.functions > self.max_functions {
+                        return Err(WasmValidationError::TooManyFunctions {
+                            count: stats.functions,
+                            max: self.max_functions,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// Validates a Wasm module before it's deployed as user actor code, under the network's
+/// [`WasmFeatures`] and [`WasmLimits`]. In addition to [`WasmLimits::check`] and the usual Wasm
+/// validation, deployed modules:
+///
+/// - May not use floating-point types or instructions, unless allowed by
+///   [`WasmLimits::allow_floats`] and NaNs are canonicalized.
+/// - May not declare a start function, as it would run before the actor is invoked (and without
+///   the usual invocation context).
+/// - Must stay within the table and memory limits.
+/// - May only import FVM syscalls available at the network version (no memories, tables, or
+///   globals).
+pub fn validate_wasm_for_deployment(
+    wasm: &[u8],
+    nc: &NetworkConfig,
+) -> Result<ModuleStats, WasmValidationError> {
+    let limits = &nc.wasm_limits;
+    let stats = limits.check(wasm)?;
+
+    // Validate with floats enabled first so we can tell invalid modules from modules that are
+    // only invalid because they use floats.
+    let types = Validator::new_with_features(parser_features(&nc.wasm_features, true))
+        .validate_all(wasm)
+        .map_err(|e| WasmValidationError::Invalid(e.to_string()))?;
+    if !limits.allow_floats {
+        Validator::new_with_features(parser_features(&nc.wasm_features, false))
+            .validate_all(wasm)
+            .map_err(|e| WasmValidationError::FloatingPoint(e.to_string()))?;
+    }
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        // The module has already been validated, so parsing can't fail here.
+        match payload.map_err(|e| WasmValidationError::Invalid(e.to_string()))? {
+            Payload::StartSection { .. } => return Err(WasmValidationError::StartFunction),
+            Payload::TableSection(tables) if tables.count() > limits.max_tables => {
+                return Err(WasmValidationError::TooManyTables {
+                    count: tables.count(),
+                    max: limits.max_tables,
+                });
+            }
+            Payload::ImportSection(imports) => {
+                for import in imports {
+                    let import = import.map_err(|e| WasmValidationError::Invalid(e.to_string()))?;
+                    let is_syscall = matches!(import.ty, TypeRef::Func(_))
+                        && SYSCALLS.iter().any(|(module, names)| {
+                            *module == import.module && names.contains(&import.name)
+                        })
+                        && is_syscall_available(import.module, import.name, nc.network_version);
+                    if !is_syscall {
+                        return Err(WasmValidationError::IllegalImport {
+                            module: import.module.to_owned(),
+                            name: import.name.to_owned(),
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Imported tables and memories have already been rejected, so these are all declared by the
+    // module itself.
+    for table in (0..).map_while(|i| types.table_at(i)) {
+        let elements = table.maximum.unwrap_or(0).max(table.initial);
+        if elements > limits.max_table_elements {
+            return Err(WasmValidationError::TableTooLarge {
+                elements,
+                max: limits.max_table_elements,
+            });
+        }
+    }
+    for memory in (0..).map_while(|i| types.memory_at(i)) {
+        let pages = memory.maximum.unwrap_or(0).max(memory.initial);
+        if pages > limits.max_memory_pages {
+            return Err(WasmValidationError::MemoryTooLarge {
+                pages,
+                max: limits.max_memory_pages,
+            });
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Returns the wasmparser features matching the network's Wasm features.
+fn parser_features(features: &WasmFeatures, floats: bool) -> wasmparser::WasmFeatures {
+    wasmparser::WasmFeatures {
+        simd: features.simd,
+        bulk_memory: features.bulk_memory,
+        reference_types: features.reference_types,
+        multi_value: features.multi_value,
+        relaxed_simd: false,
+        threads: false,
+        multi_memory: false,
+        memory64: false,
+        floats,
+        ..Default::default()
+    }
+}
+
+/// The properties of a Wasm module that determine the cost of compiling it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ModuleStats {
+    /// The size of the module, in bytes.
+    pub size: usize,
+    /// The number of imports the module declares.
+    pub imports: u32,
+    /// The number of functions the module defines.
+    pub functions: u32,
+}
+
+/// The reason a user-deployed Wasm module was rejected.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum WasmValidationError {
+    #[error("module is {size} bytes, exceeding the limit of {max} bytes")]
+    ModuleTooLarge { size: usize, max: usize },
+    #[error("module declares {count} imports, exceeding the limit of {max}")]
+    TooManyImports { count: u32, max: u32 },
+    #[error("module defines {count} functions, exceeding the limit of {max}")]
+    TooManyFunctions { count: u32, max: u32 },
+    #[error("module declares {count} tables, exceeding the limit of {max}")]
+    TooManyTables { count: u32, max: u32 },
+    #[error("module declares a table with {elements} elements, exceeding the limit of {max}")]
+    TableTooLarge { elements: u32, max: u32 },
+    #[error("module declares a memory of {pages} pages, exceeding the limit of {max}")]
+    MemoryTooLarge { pages: u64, max: u64 },
+    #[error("module uses floating-point types or instructions: {0}")]
+    FloatingPoint(String),
+    #[error("module declares a start function")]
+    StartFunction,
+    #[error("module imports {module}::{name}, which isn't an FVM syscall")]
+    IllegalImport { module: String, name: String },
+    #[error("invalid wasm module: {0}")]
+    Invalid(String),
+}
+
+impl WasmValidationError {
+    /// Returns true if the module was rejected for exceeding a [`WasmLimits`] limit (rather than
+    /// for being malformed or breaking a deployment rule).
+    pub fn is_limit_exceeded(&self) -> bool {
+        matches!(
+            self,
+            WasmValidationError::ModuleTooLarge { .. }
+                | WasmValidationError::TooManyImports { .. }
+                | WasmValidationError::TooManyFunctions { .. }
+                | WasmValidationError::TooManyTables { .. }
+                | WasmValidationError::TableTooLarge { .. }
+                | WasmValidationError::MemoryTooLarge { .. }
+        )
+    }
+}
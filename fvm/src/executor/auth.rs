@@ -0,0 +1,46 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Sender authentication through the sender actor's `AuthenticateMessage` method.
+use fvm_ipld_encoding::tuple::*;
+use fvm_ipld_encoding::{strict_bytes, RawBytes};
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use fvm_shared::MethodNum;
+use num_traits::Zero;
+
+/// The exported (FRC-0042) `AuthenticateMessage` method implemented by account-like actors (e.g.,
+/// account and Ethereum account actors).
+pub const AUTHENTICATE_MESSAGE_METHOD: MethodNum = 2643134072;
+
+/// Parameters to an actor's `AuthenticateMessage` method.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct AuthenticateMessageParams {
+    /// The signature to verify.
+    #[serde(with = "strict_bytes")]
+    pub signature: Vec<u8>,
+    /// The signed payload (e.g., the message's signing bytes or an RLP-encoded Ethereum
+    /// transaction).
+    #[serde(with = "strict_bytes")]
+    pub message: Vec<u8>,
+}
+
+/// Returns the read-only message asking `sender` to authenticate the given signature over the
+/// given payload. The sender calls itself so that it's subject to the usual sender validation.
+pub(super) fn authenticate_message(
+    sender: &Address,
+    params: &AuthenticateMessageParams,
+) -> anyhow::Result<Message> {
+    Ok(Message {
+        version: 0,
+        from: *sender,
+        to: *sender,
+        sequence: 0,
+        value: TokenAmount::zero(),
+        method_num: AUTHENTICATE_MESSAGE_METHOD,
+        params: RawBytes::serialize(params)?,
+        gas_limit: 0,
+        gas_fee_cap: TokenAmount::zero(),
+        gas_premium: TokenAmount::zero(),
+    })
+}
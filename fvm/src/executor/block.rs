@@ -0,0 +1,247 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Types for block message validation and packing. See
+//! [`Executor::validate_block_messages`](super::Executor::validate_block_messages) and
+//! [`BlockPacker`].
+use cid::multihash::{Code, MultihashDigest};
+use cid::Cid;
+use fvm_ipld_encoding::{to_vec, DAG_CBOR};
+use fvm_shared::crypto::signature::Signature;
+use fvm_shared::message::Message;
+use fvm_shared::ActorID;
+
+use super::{
+    ApplyFailure, ApplyKind, ApplyRet, Executor, MachineStats, PreflightError, BLOCK_GAS_LIMIT,
+};
+
+/// The messages included in a block, as passed to
+/// [`Executor::validate_block_messages`](super::Executor::validate_block_messages).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct BlockMessages {
+    /// The block's BLS messages, in block order.
+    pub bls_messages: Vec<Message>,
+    /// The aggregate signature over all BLS messages. Required if there are any BLS messages.
+    pub bls_aggregate: Option<Signature>,
+    /// The block's secp256k1 messages, in block order, along with their signatures.
+    pub secp_messages: Vec<(Message, Signature)>,
+}
+
+impl BlockMessages {
+    /// Returns the block's messages along with their raw (on-chain) lengths, BLS messages first.
+    /// This is the order in which they're validated and applied.
+    pub fn iter(&self) -> impl Iterator<Item = anyhow::Result<(&Message, usize)>> + '_ {
+        let bls = self
+            .bls_messages
+            .iter()
+            .map(|msg| Ok((msg, to_vec(msg)?.len())));
+        let secp = self
+            .secp_messages
+            .iter()
+            .map(|(msg, sig)| Ok((msg, to_vec(&(msg, sig))?.len())));
+        bls.chain(secp)
+    }
+
+    /// Returns the total number of messages in the block.
+    pub fn len(&self) -> usize {
+        self.bls_messages.len() + self.secp_messages.len()
+    }
+
+    /// Returns true if the block has no messages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The reason a block's messages are invalid. Messages are identified by their index in the
+/// block, counting the BLS messages first (see [`BlockMessages::iter`]).
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BlockValidationError {
+    /// A message fails pre-validation (see
+    /// [`Executor::preflight`](super::Executor::preflight)), taking into account the nonces of
+    /// previous messages from the same sender in the block.
+    #[error("message {index} is invalid: {error}")]
+    InvalidMessage { index: usize, error: PreflightError },
+    /// A message's signature is invalid, or its sender doesn't have a key address.
+    #[error("message {index} has an invalid signature: {reason}")]
+    InvalidSignature { index: usize, reason: String },
+    /// The BLS aggregate signature is missing or invalid.
+    #[error("invalid BLS aggregate signature")]
+    InvalidBlsAggregate,
+    /// The sum of the messages' gas limits exceeds the block gas limit.
+    #[error("block gas limit exceeded: {gas_limit} > {max}")]
+    GasLimitExceeded { gas_limit: u64, max: u64 },
+}
+
+/// Returns the CID of an (unsigned) message. This is what senders sign.
+pub(super) fn message_cid(msg: &Message) -> anyhow::Result<Cid> {
+    Ok(Cid::new_v1(
+        DAG_CBOR,
+        Code::Blake2b256.digest(&to_vec(msg)?),
+    ))
+}
+
+/// Returned by [`BlockPacker::pack_message`] when a message doesn't fit in the current block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("block is full: message gas limit {gas_limit} exceeds the remaining block gas {remaining}")]
+pub struct BlockFullError {
+    /// The gas limit of the rejected message.
+    pub gas_limit: u64,
+    /// The block gas remaining.
+    pub remaining: u64,
+}
+
+/// An executor that applies explicit messages to a block with a bounded total gas limit, for block
+/// packers selecting messages by executing them.
+///
+/// Like [`Executor::validate_block_messages`], this bounds the sum of the _gas limits_ of the
+/// messages in the block (not the gas they use). Messages that would exceed the limit are rejected
+/// without being applied, and messages that fail pre-validation (which can't be included in a
+/// block) don't count towards it. Implicit messages are never limited. Call
+/// [`BlockPacker::reset`] before packing the next block.
+///
+/// When used as an [`Executor`], applying an explicit message that doesn't fit fails with a
+/// [`BlockFullError`] (which can be recovered with [`anyhow::Error::downcast_ref`]).
+pub struct BlockPacker<E> {
+    executor: E,
+    gas_limit: u64,
+    gas_packed: u64,
+    messages_packed: usize,
+}
+
+impl<E> BlockPacker<E>
+where
+    E: Executor,
+{
+    /// Packs messages into blocks limited to [`BLOCK_GAS_LIMIT`].
+    pub fn new(executor: E) -> Self {
+        Self::with_gas_limit(executor, BLOCK_GAS_LIMIT)
+    }
+
+    /// Packs messages into blocks limited to the given gas limit.
+    pub fn with_gas_limit(executor: E, gas_limit: u64) -> Self {
+        BlockPacker {
+            executor,
+            gas_limit,
+            gas_packed: 0,
+            messages_packed: 0,
+        }
+    }
+
+    /// Applies an explicit message if it fits in the current block, counting its gas limit towards
+    /// the block's. Returns a [`BlockFullError`] (without applying the message) if it doesn't fit.
+    pub fn pack_message(
+        &mut self,
+        msg: Message,
+        raw_length: usize,
+    ) -> anyhow::Result<Result<ApplyRet, BlockFullError>> {
+        let gas_limit = msg.gas_limit;
+        let remaining = self.gas_remaining();
+        if gas_limit > remaining {
+            return Ok(Err(BlockFullError {
+                gas_limit,
+                remaining,
+            }));
+        }
+
+        let ret = self
+            .executor
+            .execute_message(msg, ApplyKind::Explicit, raw_length)?;
+        if !matches!(ret.failure_info, Some(ApplyFailure::PreValidation(_))) {
+            self.gas_packed += gas_limit;
+            self.messages_packed += 1;
+        }
+        Ok(Ok(ret))
+    }
+
+    /// Starts packing a new block.
+    pub fn reset(&mut self) {
+        self.gas_packed = 0;
+        self.messages_packed = 0;
+    }
+
+    /// Returns the block gas limit.
+    pub fn gas_limit(&self) -> u64 {
+        self.gas_limit
+    }
+
+    /// Returns the sum of the gas limits of the messages packed into the current block.
+    pub fn gas_packed(&self) -> u64 {
+        self.gas_packed
+    }
+
+    /// Returns the gas remaining in the current block.
+    pub fn gas_remaining(&self) -> u64 {
+        self.gas_limit.saturating_sub(self.gas_packed)
+    }
+
+    /// Returns the number of messages packed into the current block.
+    pub fn messages_packed(&self) -> usize {
+        self.messages_packed
+    }
+
+    /// Returns the wrapped executor.
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    /// Returns the wrapped executor. Messages applied directly through it aren't counted towards
+    /// the block gas limit.
+    pub fn executor_mut(&mut self) -> &mut E {
+        &mut self.executor
+    }
+
+    /// Unwraps the executor.
+    pub fn into_inner(self) -> E {
+        self.executor
+    }
+}
+
+impl<E> Executor for BlockPacker<E>
+where
+    E: Executor,
+{
+    type Kernel = E::Kernel;
+
+    fn execute_message(
+        &mut self,
+        msg: Message,
+        apply_kind: ApplyKind,
+        raw_length: usize,
+    ) -> anyhow::Result<ApplyRet> {
+        match apply_kind {
+            ApplyKind::Explicit => Ok(self.pack_message(msg, raw_length)??),
+            ApplyKind::Implicit => self.executor.execute_message(msg, apply_kind, raw_length),
+        }
+    }
+
+    fn estimate_gas(&mut self, msg: Message, raw_length: usize) -> anyhow::Result<ApplyRet> {
+        self.executor.estimate_gas(msg, raw_length)
+    }
+
+    fn call_readonly(&mut self, msg: Message) -> anyhow::Result<ApplyRet> {
+        self.executor.call_readonly(msg)
+    }
+
+    fn preflight(
+        &self,
+        msg: &Message,
+        raw_length: usize,
+    ) -> anyhow::Result<Result<ActorID, PreflightError>> {
+        self.executor.preflight(msg, raw_length)
+    }
+
+    fn validate_block_messages(
+        &self,
+        msgs: &BlockMessages,
+    ) -> anyhow::Result<Result<(), BlockValidationError>> {
+        self.executor.validate_block_messages(msgs)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<Cid> {
+        self.executor.flush()
+    }
+
+    fn finish(self) -> anyhow::Result<(Cid, MachineStats)> {
+        self.executor.finish()
+    }
+}
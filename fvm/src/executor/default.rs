@@ -1,27 +1,37 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+use std::collections::HashMap;
 use std::ops::{Deref, DerefMut};
 use std::result::Result as StdResult;
 
 use anyhow::{anyhow, Result};
 use cid::Cid;
-use fvm_ipld_encoding::{RawBytes, CBOR};
-use fvm_shared::address::Payload;
+use fvm_ipld_encoding::{CborStore, RawBytes, CBOR};
+use fvm_shared::address::{Address, Payload, Protocol};
+use fvm_shared::crypto::signature::{self, SignatureType};
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::error::{ErrorNumber, ExitCode};
 use fvm_shared::event::StampedEvent;
 use fvm_shared::message::Message;
 use fvm_shared::receipt::Receipt;
+use fvm_shared::version::NetworkVersion;
 use fvm_shared::{ActorID, IPLD_RAW, METHOD_SEND};
 use num_traits::Zero;
-
-use super::{ApplyFailure, ApplyKind, ApplyRet, Executor};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use super::block::message_cid;
+use super::invariants::Balances;
+use super::{
+    ApplyFailure, ApplyKind, ApplyRet, BlockMessages, BlockValidationError, Executor, FailureInfo,
+    InvariantViolation, MachineStats, PreflightError, BLOCK_GAS_LIMIT, IMPLICIT_MESSAGE_GAS_LIMIT,
+};
 use crate::call_manager::{backtrace, Backtrace, CallManager, InvocationResult};
 use crate::eam_actor::EAM_ACTOR_ID;
 use crate::engine::EnginePool;
-use crate::gas::{Gas, GasCharge, GasOutputs};
+use crate::gas::{Gas, GasBreakdown, GasCharge, GasOutputs};
 use crate::kernel::{Block, ClassifyResult, Context as _, ExecutionError, Kernel};
 use crate::machine::{Machine, BURNT_FUNDS_ACTOR_ID, REWARD_ACTOR_ID};
+use crate::state_tree::ActorState;
 use crate::trace::ExecutionTrace;
 
 /// The default [`Executor`].
@@ -35,6 +45,16 @@ pub struct DefaultExecutor<K: Kernel> {
     engine_pool: EnginePool,
     // If the inner value is `None` it means the machine got poisoned and is unusable.
     machine: Option<<K::CallManager as CallManager>::Machine>,
+    stats: MachineStats,
+}
+
+/// A message that passed pre-validation.
+struct ValidatedMessage {
+    sender_id: ActorID,
+    /// The sender's state, for explicit messages.
+    sender_state: Option<ActorState>,
+    gas_cost: TokenAmount,
+    inclusion_cost: GasCharge,
 }
 
 impl<K: Kernel> Deref for DefaultExecutor<K> {
@@ -64,6 +84,120 @@ where
         apply_kind: ApplyKind,
         raw_length: usize,
     ) -> anyhow::Result<ApplyRet> {
+        self.apply_message(msg, apply_kind, raw_length, false)
+    }
+
+    fn estimate_gas(&mut self, msg: Message, raw_length: usize) -> anyhow::Result<ApplyRet> {
+        self.simulate_message(msg, raw_length, false)
+    }
+
+    fn call_readonly(&mut self, msg: Message) -> anyhow::Result<ApplyRet> {
+        if !msg.value.is_zero() {
+            return Err(anyhow!("read-only calls cannot transfer value"));
+        }
+        let raw_length = fvm_ipld_encoding::to_vec(&msg)?.len();
+        self.simulate_message(msg, raw_length, true)
+    }
+
+    fn preflight(
+        &self,
+        msg: &Message,
+        raw_length: usize,
+    ) -> anyhow::Result<StdResult<ActorID, PreflightError>> {
+        if let Err(e) = msg.check() {
+            return Ok(Err(PreflightError::InvalidMessage(e.to_string())));
+        }
+        Ok(self
+            .validate_message(msg, ApplyKind::Explicit, raw_length)?
+            .map(|validated| validated.sender_id))
+    }
+
+    fn validate_block_messages(
+        &self,
+        msgs: &BlockMessages,
+    ) -> anyhow::Result<StdResult<(), BlockValidationError>> {
+        self.validate_block(msgs)
+    }
+
+    /// Flush the state-tree to the underlying blockstore.
+    fn flush(&mut self) -> anyhow::Result<Cid> {
+        let k = (**self).flush()?;
+        Ok(k)
+    }
+
+    fn finish(mut self) -> anyhow::Result<(Cid, MachineStats)> {
+        if self.machine.is_none() {
+            return Err(anyhow!("machine poisoned"));
+        }
+        let root = self.flush()?;
+        let mut stats = std::mem::take(&mut self.stats);
+        stats.blockstore_stats = self.blockstore_stats();
+        Ok((root, stats))
+    }
+}
+
+impl<K> DefaultExecutor<K>
+where
+    K: Kernel,
+{
+    /// Create a new [`DefaultExecutor`] for executing messages on the [`Machine`].
+    pub fn new(
+        engine_pool: EnginePool,
+        machine: <K::CallManager as CallManager>::Machine,
+    ) -> anyhow::Result<Self> {
+        // Skip preloading all builtin actors when testing.
+        #[cfg(not(any(test, feature = "testing")))]
+        {
+            // Preload any uncached modules.
+            // This interface works for now because we know all actor CIDs
+            // ahead of time, but with user-supplied code, we won't have that
+            // guarantee.
+            let engine = engine_pool.acquire();
+            // Pin the actors used by every block first, so they're never evicted.
+            let hot: Vec<Cid> = machine
+                .builtin_actors()
+                .hot_actor_codes()
+                .copied()
+                .collect();
+            machine.preload_modules(&engine, &hot)?;
+            engine.preload(
+                machine.blockstore(),
+                machine.builtin_actors().builtin_actor_codes(),
+            )?;
+        }
+        Ok(Self {
+            engine_pool,
+            machine: Some(machine),
+            stats: MachineStats::default(),
+        })
+    }
+
+    /// Returns the engine pool used by this executor.
+    pub fn engine_pool(&self) -> &EnginePool {
+        &self.engine_pool
+    }
+
+    /// Consume consumes the executor and returns the Machine. If the Machine had
+    /// been poisoned during execution, the Option will be None.
+    pub fn into_machine(self) -> Option<<K::CallManager as CallManager>::Machine> {
+        self.machine
+    }
+
+    /// Applies a message, optionally in read-only mode (see [`Executor::call_readonly`]).
+    fn apply_message(
+        &mut self,
+        msg: Message,
+        apply_kind: ApplyKind,
+        raw_length: usize,
+        read_only: bool,
+    ) -> anyhow::Result<ApplyRet> {
+        let blockstore_stats = self.blockstore_stats();
+        let balances = if self.context().invariant_checks {
+            Some(Balances::collect(self.state_tree())?)
+        } else {
+            None
+        };
+
         // Validate if the message was correct, charge for it, and extract some preliminary data.
         let (sender_id, gas_cost, inclusion_cost) =
             match self.preflight_message(&msg, apply_kind, raw_length)? {
@@ -78,6 +212,7 @@ where
             exec_trace: ExecutionTrace,
             events_root: Option<Cid>,
             events: Vec<StampedEvent>, // TODO consider removing if nothing in the client ends up using it.
+            gas_breakdown: Option<GasBreakdown>,
         }
 
         // Pre-resolve the message receiver's address, if known.
@@ -94,6 +229,20 @@ where
             .min(&msg.gas_fee_cap - &self.context().base_fee)
             .max(TokenAmount::zero());
 
+        // Implicit messages aren't charged for gas, so their gas limit only bounds execution. From
+        // NV21, a zero gas limit means "as much as the system allows", and we never allow more than
+        // that. Before NV21, the message's gas limit is used as-is.
+        let gas_limit = match apply_kind {
+            ApplyKind::Implicit if self.context().network_version >= NetworkVersion::V21 => {
+                if msg.gas_limit == 0 {
+                    IMPLICIT_MESSAGE_GAS_LIMIT
+                } else {
+                    msg.gas_limit.min(IMPLICIT_MESSAGE_GAS_LIMIT)
+                }
+            }
+            _ => msg.gas_limit,
+        };
+
         // Acquire an engine from the pool. This may block if there are concurrently executing
         // messages inside other executors sharing the same pool.
         let engine = self.engine_pool.acquire();
@@ -104,7 +253,7 @@ where
             let mut cm = K::CallManager::new(
                 machine,
                 engine,
-                msg.gas_limit,
+                gas_limit,
                 sender_id,
                 msg.from,
                 receiver_id,
@@ -148,7 +297,7 @@ where
                     params,
                     &msg.value,
                     None,
-                    false,
+                    read_only,
                 )?;
 
                 // Charge for including the result (before we end the transaction).
@@ -176,6 +325,7 @@ where
                     exec_trace: res.exec_trace,
                     events_root: res.events_root,
                     events: res.events,
+                    gas_breakdown: res.gas_breakdown,
                 }),
                 machine,
             )
@@ -188,15 +338,17 @@ where
             exec_trace,
             events_root,
             events,
+            gas_breakdown,
         } = ret;
 
         // Extract the exit code and build the result of the message application.
         let receipt = match res {
             Ok(InvocationResult { exit_code, value }) => {
                 // Convert back into a top-level return "value". We throw away the codec here,
-                // unfortunately.
+                // unfortunately. By now, the call manager has dropped all other handles to the
+                // block, so this usually doesn't need to copy the data.
                 let return_data = value
-                    .map(|blk| RawBytes::from(blk.data().to_vec()))
+                    .map(|blk| RawBytes::from(Vec::from(blk.into_data())))
                     .unwrap_or_default();
 
                 if exit_code.is_success() {
@@ -222,6 +374,14 @@ where
                 let exit_code = match err.1 {
                     ErrorNumber::InsufficientFunds => ExitCode::SYS_INSUFFICIENT_FUNDS,
                     ErrorNumber::NotFound => ExitCode::SYS_INVALID_RECEIVER,
+                    // Before NV21, exceeding a limit was an assertion failure.
+                    ErrorNumber::LimitExceeded
+                        if self.context().network_version >= NetworkVersion::V21 =>
+                    {
+                        ExitCode::SYS_LIMIT_EXCEEDED
+                    }
+                    // Only possible in read-only calls, which are never applied on-chain.
+                    ErrorNumber::ReadOnly => ExitCode::USR_READ_ONLY,
                     _ => ExitCode::SYS_ASSERTION_FAILED,
                 };
 
@@ -260,13 +420,18 @@ where
             }
         };
 
+        let failure = (!receipt.exit_code.is_success())
+            .then(|| FailureInfo::from_backtrace(receipt.exit_code, &backtrace));
         let failure_info = if backtrace.is_empty() || receipt.exit_code.is_success() {
             None
         } else {
             Some(ApplyFailure::MessageBacktrace(backtrace))
         };
 
-        match apply_kind {
+        let epoch = self.context().epoch;
+        let checked_msg = balances.is_some().then(|| msg.clone());
+
+        let mut ret = match apply_kind {
             ApplyKind::Explicit => self.finish_message(
                 sender_id,
                 msg,
@@ -286,50 +451,210 @@ where
                 gas_refund: 0,
                 gas_burned: 0,
                 failure_info,
+                failure: None,
                 exec_trace,
                 events,
+                gas_breakdown: None,
+                blockstore_stats: None,
             }),
+        }?;
+        ret.gas_breakdown = gas_breakdown;
+        ret.failure = failure;
+        self.stats.messages_applied += 1;
+        self.stats.gas_used = self.stats.gas_used.saturating_add(ret.msg_receipt.gas_used);
+        ret.blockstore_stats = blockstore_stats
+            .zip(self.blockstore_stats())
+            .map(|(before, after)| after.since(&before));
+
+        if let (Some(before), Some(msg)) = (balances, checked_msg) {
+            let after = Balances::collect(self.state_tree())?;
+            if let Some(violation) =
+                InvariantViolation::check(epoch, &msg, ret.msg_receipt.exit_code, before, after)
+            {
+                // Poison the machine, there's no way to recover from this.
+                self.machine = None;
+                return Err(violation.into());
+            }
         }
+        Ok(ret)
     }
 
-    /// Flush the state-tree to the underlying blockstore.
-    fn flush(&mut self) -> anyhow::Result<Cid> {
-        let k = (**self).flush()?;
-        Ok(k)
+    /// Applies an explicit message on top of the current state, reverting all changes afterwards.
+    /// See [`Executor::estimate_gas`].
+    fn simulate_message(
+        &mut self,
+        mut msg: Message,
+        raw_length: usize,
+        read_only: bool,
+    ) -> anyhow::Result<ApplyRet> {
+        let sender = match self.state_tree().lookup_id(&msg.from)? {
+            Some(id) => self.state_tree().get_actor(id)?,
+            None => None,
+        };
+        if let Some(sender) = sender {
+            msg.sequence = sender.sequence;
+        }
+        msg.gas_fee_cap = TokenAmount::zero();
+        msg.gas_premium = TokenAmount::zero();
+        if msg.gas_limit == 0 {
+            msg.gas_limit = BLOCK_GAS_LIMIT;
+        }
+
+        let snapshot = self.state_tree_mut().snapshot();
+        let stats = self.stats.clone();
+        let ret = self.apply_message(msg, ApplyKind::Explicit, raw_length, read_only);
+        self.stats = stats;
+
+        // If the machine was poisoned, there's nothing left to revert.
+        if let Some(machine) = &mut self.machine {
+            machine.state_tree_mut().revert_to_snapshot(snapshot)?;
+        }
+        ret
     }
-}
 
-impl<K> DefaultExecutor<K>
-where
-    K: Kernel,
-{
-    /// Create a new [`DefaultExecutor`] for executing messages on the [`Machine`].
-    pub fn new(
-        engine_pool: EnginePool,
-        machine: <K::CallManager as CallManager>::Machine,
-    ) -> anyhow::Result<Self> {
-        // Skip preloading all builtin actors when testing.
-        #[cfg(not(any(test, feature = "testing")))]
-        {
-            // Preload any uncached modules.
-            // This interface works for now because we know all actor CIDs
-            // ahead of time, but with user-supplied code, we won't have that
-            // guarantee.
-            engine_pool.acquire().preload(
-                machine.blockstore(),
-                machine.builtin_actors().builtin_actor_codes(),
-            )?;
+    /// Validates a block's messages. See [`Executor::validate_block_messages`].
+    fn validate_block(&self, msgs: &BlockMessages) -> Result<StdResult<(), BlockValidationError>> {
+        let mut gas_limit = 0u64;
+        // The next expected nonce and remaining balance of each sender seen so far.
+        let mut senders = HashMap::<ActorID, (u64, TokenAmount)>::new();
+        let mut bls_data = Vec::new();
+        let mut bls_keys = Vec::new();
+        let mut secp_sigs = Vec::new();
+        for (index, msg) in msgs.iter().enumerate() {
+            let (msg, raw_length) = msg?;
+            let invalid = |error| Ok(Err(BlockValidationError::InvalidMessage { index, error }));
+
+            gas_limit = gas_limit.saturating_add(msg.gas_limit);
+            if gas_limit > BLOCK_GAS_LIMIT {
+                return Ok(Err(BlockValidationError::GasLimitExceeded {
+                    gas_limit,
+                    max: BLOCK_GAS_LIMIT,
+                }));
+            }
+            if let Err(e) = msg.check() {
+                return invalid(PreflightError::InvalidMessage(e.to_string()));
+            }
+
+            let sender_id = match self.state_tree().lookup_id(&msg.from)? {
+                Some(id) => id,
+                None => return invalid(PreflightError::SenderNotFound),
+            };
+            let pending = senders.remove(&sender_id);
+            if let Some((expected, _)) = pending {
+                if msg.sequence != expected {
+                    return invalid(PreflightError::SequenceMismatch {
+                        expected,
+                        actual: msg.sequence,
+                    });
+                }
+            }
+            let balance = match (
+                self.validate_message(msg, ApplyKind::Explicit, raw_length)?,
+                pending,
+            ) {
+                // We've already checked the nonce against the sender's previous message, and the
+                // previous messages' gas has already been deducted from the sender's balance.
+                (Err(PreflightError::SequenceMismatch { .. }), Some((_, balance))) => balance,
+                (Ok(validated), _) => {
+                    validated
+                        .sender_state
+                        .expect("explicit messages have a sender state")
+                        .balance
+                }
+                (Err(e), _) => return invalid(e),
+            };
+
+            // Every message in the block must be able to cover its gas, so the sender's balance
+            // must cover all of them.
+            let gas_cost = msg.gas_fee_cap.clone() * msg.gas_limit;
+            if balance < gas_cost {
+                return invalid(PreflightError::InsufficientFunds {
+                    balance,
+                    required: gas_cost,
+                });
+            }
+            let next_sequence = match msg.sequence.checked_add(1) {
+                Some(sequence) => sequence,
+                None => {
+                    return invalid(PreflightError::InvalidMessage(
+                        "sequence overflows".to_owned(),
+                    ))
+                }
+            };
+            senders.insert(sender_id, (next_sequence, balance - gas_cost));
+
+            let key = match self.key_address(&msg.from, sender_id)? {
+                Some(key) => key,
+                None => {
+                    return Ok(Err(BlockValidationError::InvalidSignature {
+                        index,
+                        reason: format!("sender {} has no key address", msg.from),
+                    }))
+                }
+            };
+            let cid = message_cid(msg)?.to_bytes();
+            if index < msgs.bls_messages.len() {
+                if key.protocol() != Protocol::BLS {
+                    return Ok(Err(BlockValidationError::InvalidSignature {
+                        index,
+                        reason: format!("BLS message sent from non-BLS address {}", key),
+                    }));
+                }
+                bls_data.push(cid);
+                bls_keys.push(key.payload_bytes());
+            } else {
+                secp_sigs.push((index, cid, key));
+            }
         }
-        Ok(Self {
-            engine_pool,
-            machine: Some(machine),
+
+        if !bls_data.is_empty() {
+            let valid = match &msgs.bls_aggregate {
+                Some(sig) => signature::ops::verify_bls_aggregate(
+                    &bls_data.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+                    &bls_keys.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+                    sig,
+                ),
+                None => false,
+            };
+            if !valid {
+                return Ok(Err(BlockValidationError::InvalidBlsAggregate));
+            }
+        }
+
+        let secp_offset = msgs.bls_messages.len();
+        let failure = secp_sigs
+            .par_iter()
+            .filter_map(|(index, cid, key)| {
+                let (_, sig) = &msgs.secp_messages[index - secp_offset];
+                let res = if sig.sig_type == SignatureType::Secp256k1 {
+                    sig.verify(cid, key)
+                } else {
+                    Err("not a secp256k1 signature".to_owned())
+                };
+                res.err().map(|reason| (*index, reason))
+            })
+            .min_by_key(|(index, _)| *index);
+        Ok(match failure {
+            Some((index, reason)) => Err(BlockValidationError::InvalidSignature { index, reason }),
+            None => Ok(()),
         })
     }
 
-    /// Consume consumes the executor and returns the Machine. If the Machine had
-    /// been poisoned during execution, the Option will be None.
-    pub fn into_machine(self) -> Option<<K::CallManager as CallManager>::Machine> {
-        self.machine
+    /// Returns the key address of a sender, if it has one: either the sender's address itself, or
+    /// the address recorded in its account actor's state.
+    fn key_address(&self, sender: &Address, sender_id: ActorID) -> Result<Option<Address>> {
+        if matches!(sender.protocol(), Protocol::BLS | Protocol::Secp256k1) {
+            return Ok(Some(*sender));
+        }
+        let actor = match self.state_tree().get_actor(sender_id)? {
+            Some(actor) if self.builtin_actors().is_account_actor(&actor.code) => actor,
+            _ => return Ok(None),
+        };
+        let state: crate::account_actor::State = self
+            .blockstore()
+            .get_cbor(&actor.state)?
+            .ok_or_else(|| anyhow!("account actor state not found"))?;
+        Ok(Some(state.address))
     }
 
     // TODO: The return type here is very strange because we have three cases:
@@ -345,30 +670,74 @@ where
     ) -> Result<StdResult<(ActorID, TokenAmount, GasCharge), ApplyRet>> {
         msg.check().or_fatal()?;
 
+        let validated = match self.validate_message(msg, apply_kind, raw_length)? {
+            Ok(validated) => validated,
+            Err(e) => {
+                // Implicit messages never incur a miner penalty, and explicit messages that can't
+                // cover their inclusion cost are only penalized for that.
+                let context = self.context();
+                let fee_policy = context.network.fee_policy;
+                let penalty = match (apply_kind, &e) {
+                    (ApplyKind::Implicit, _) => TokenAmount::zero(),
+                    (_, PreflightError::OutOfGas { inclusion_gas, .. }) => {
+                        fee_policy.prevalidation_penalty(&context.base_fee, *inclusion_gas)
+                    }
+                    _ => fee_policy.prevalidation_penalty(&context.base_fee, msg.gas_limit),
+                };
+                return Ok(Err(ApplyRet::prevalidation_fail(
+                    e.exit_code(),
+                    e.to_string(),
+                    penalty,
+                )));
+            }
+        };
+
+        let ValidatedMessage {
+            sender_id,
+            sender_state,
+            gas_cost,
+            inclusion_cost,
+        } = validated;
+
+        if let Some(mut sender_state) = sender_state {
+            sender_state.sequence += 1;
+            sender_state.deduct_funds(&gas_cost)?;
+
+            // Update the actor in the state tree
+            self.state_tree_mut().set_actor(sender_id, sender_state);
+        }
+
+        Ok(Ok((sender_id, gas_cost, inclusion_cost)))
+    }
+
+    /// Validates a message against the current state without modifying it. Returns the sender's
+    /// updated state (for explicit messages) but leaves it to the caller to update the sequence and
+    /// deduct the gas funds.
+    fn validate_message(
+        &self,
+        msg: &Message,
+        apply_kind: ApplyKind,
+        raw_length: usize,
+    ) -> Result<StdResult<ValidatedMessage, PreflightError>> {
         // TODO We don't like having price lists _inside_ the FVM, but passing
         //  these across the boundary is also a no-go.
         let pl = &self.context().price_list;
 
-        let (inclusion_cost, miner_penalty_amount) = match apply_kind {
-            ApplyKind::Implicit => (
-                GasCharge::new("none", Gas::zero(), Gas::zero()),
-                Default::default(),
-            ),
+        let inclusion_cost = match apply_kind {
+            ApplyKind::Implicit => GasCharge::new("none", Gas::zero(), Gas::zero()),
             ApplyKind::Explicit => {
                 let inclusion_cost = pl.on_chain_message(raw_length);
                 let inclusion_total = inclusion_cost.total().round_up();
 
                 // Verify the cost of the message is not over the message gas limit.
                 if inclusion_total > msg.gas_limit {
-                    return Ok(Err(ApplyRet::prevalidation_fail(
-                        ExitCode::SYS_OUT_OF_GAS,
-                        format!("Out of gas ({} > {})", inclusion_total, msg.gas_limit),
-                        &self.context().base_fee * inclusion_total,
-                    )));
+                    return Ok(Err(PreflightError::OutOfGas {
+                        inclusion_gas: inclusion_total,
+                        gas_limit: msg.gas_limit,
+                    }));
                 }
 
-                let miner_penalty_amount = &self.context().base_fee * msg.gas_limit;
-                (inclusion_cost, miner_penalty_amount)
+                inclusion_cost
             }
         };
 
@@ -379,17 +748,16 @@ where
             .with_context(|| format!("failed to lookup actor {}", &msg.from))?
         {
             Some(id) => id,
-            None => {
-                return Ok(Err(ApplyRet::prevalidation_fail(
-                    ExitCode::SYS_SENDER_INVALID,
-                    "Sender invalid",
-                    miner_penalty_amount,
-                )));
-            }
+            None => return Ok(Err(PreflightError::SenderNotFound)),
         };
 
         if apply_kind == ApplyKind::Implicit {
-            return Ok(Ok((sender_id, TokenAmount::zero(), inclusion_cost)));
+            return Ok(Ok(ValidatedMessage {
+                sender_id,
+                sender_state: None,
+                gas_cost: TokenAmount::zero(),
+                inclusion_cost,
+            }));
         }
 
         let mut sender_state = match self
@@ -398,13 +766,7 @@ where
             .with_context(|| format!("failed to lookup actor {}", &msg.from))?
         {
             Some(act) => act,
-            None => {
-                return Ok(Err(ApplyRet::prevalidation_fail(
-                    ExitCode::SYS_SENDER_INVALID,
-                    "Sender invalid",
-                    miner_penalty_amount,
-                )));
-            }
+            None => return Ok(Err(PreflightError::SenderNotFound)),
         };
 
         // Sender is valid if it is:
@@ -428,46 +790,32 @@ where
         }
 
         if !sender_is_valid {
-            return Ok(Err(ApplyRet::prevalidation_fail(
-                ExitCode::SYS_SENDER_INVALID,
-                "Send not from valid sender",
-                miner_penalty_amount,
-            )));
+            return Ok(Err(PreflightError::InvalidSender));
         };
 
         // Check sequence is correct
         if msg.sequence != sender_state.sequence {
-            return Ok(Err(ApplyRet::prevalidation_fail(
-                ExitCode::SYS_SENDER_STATE_INVALID,
-                format!(
-                    "Actor sequence invalid: {} != {}",
-                    msg.sequence, sender_state.sequence
-                ),
-                miner_penalty_amount,
-            )));
+            return Ok(Err(PreflightError::SequenceMismatch {
+                expected: sender_state.sequence,
+                actual: msg.sequence,
+            }));
         };
 
-        sender_state.sequence += 1;
-
         // Ensure from actor has enough balance to cover the gas cost of the message.
         let gas_cost: TokenAmount = msg.gas_fee_cap.clone() * msg.gas_limit;
         if sender_state.balance < gas_cost {
-            return Ok(Err(ApplyRet::prevalidation_fail(
-                ExitCode::SYS_SENDER_STATE_INVALID,
-                format!(
-                    "Actor balance less than needed: {} < {}",
-                    sender_state.balance, gas_cost
-                ),
-                miner_penalty_amount,
-            )));
+            return Ok(Err(PreflightError::InsufficientFunds {
+                balance: sender_state.balance,
+                required: gas_cost,
+            }));
         }
 
-        sender_state.deduct_funds(&gas_cost)?;
-
-        // Update the actor in the state tree
-        self.state_tree_mut().set_actor(sender_id, sender_state);
-
-        Ok(Ok((sender_id, gas_cost, inclusion_cost)))
+        Ok(Ok(ValidatedMessage {
+            sender_id,
+            sender_state: Some(sender_state),
+            gas_cost,
+            inclusion_cost,
+        }))
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -482,6 +830,17 @@ where
         events: Vec<StampedEvent>,
     ) -> anyhow::Result<ApplyRet> {
         // NOTE: we don't support old network versions in the FVM, so we always burn.
+        let gas_outputs = self.context().network.fee_policy.gas_outputs(
+            receipt.gas_used,
+            msg.gas_limit,
+            &self.context().base_fee,
+            &msg.gas_fee_cap,
+            &msg.gas_premium,
+        );
+        if gas_outputs.total_cost() != gas_cost {
+            // Sanity check. This could be a fatal error.
+            return Err(anyhow!("Gas handling math is wrong"));
+        }
         let GasOutputs {
             base_fee_burn,
             over_estimation_burn,
@@ -490,13 +849,7 @@ where
             refund,
             gas_refund,
             gas_burned,
-        } = GasOutputs::compute(
-            receipt.gas_used,
-            msg.gas_limit,
-            &self.context().base_fee,
-            &msg.gas_fee_cap,
-            &msg.gas_premium,
-        );
+        } = gas_outputs;
 
         let mut transfer_to_actor = |addr: ActorID, amt: &TokenAmount| -> anyhow::Result<()> {
             if amt.is_negative() {
@@ -524,10 +877,6 @@ where
         // refund unused gas
         transfer_to_actor(sender_id, &refund)?;
 
-        if (&base_fee_burn + &over_estimation_burn + &refund + &miner_tip) != gas_cost {
-            // Sanity check. This could be a fatal error.
-            return Err(anyhow!("Gas handling math is wrong"));
-        }
         Ok(ApplyRet {
             msg_receipt: receipt,
             penalty: miner_penalty,
@@ -538,8 +887,11 @@ where
             gas_refund,
             gas_burned,
             failure_info,
+            failure: None,
             exec_trace,
             events,
+            gas_breakdown: None,
+            blockstore_stats: None,
         })
     }
 
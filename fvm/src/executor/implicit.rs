@@ -0,0 +1,82 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Constructors for the implicit messages applied by the system every epoch.
+use fvm_ipld_encoding::tuple::*;
+use fvm_ipld_encoding::RawBytes;
+use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use fvm_shared::MethodNum;
+
+use super::BLOCK_GAS_LIMIT;
+use crate::machine::{CRON_ACTOR_ID, REWARD_ACTOR_ID};
+use crate::system_actor::SYSTEM_ACTOR_ID;
+
+/// The cron actor's `EpochTick` method.
+pub const CRON_EPOCH_TICK_METHOD: MethodNum = 2;
+
+/// The reward actor's `AwardBlockReward` method.
+pub const AWARD_BLOCK_REWARD_METHOD: MethodNum = 2;
+
+/// The gas limit used for implicit system messages. Implicit messages aren't charged for gas, but
+/// they still run out of gas if they exceed this limit.
+pub const IMPLICIT_MESSAGE_GAS_LIMIT: u64 = BLOCK_GAS_LIMIT * 10_000;
+
+/// Parameters to the reward actor's `AwardBlockReward` method.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize_tuple, Deserialize_tuple)]
+pub struct AwardBlockRewardParams {
+    /// The miner that produced the block.
+    pub miner: Address,
+    /// Penalty to be burnt from the miner's balance (e.g., for including invalid messages).
+    pub penalty: TokenAmount,
+    /// Gas fees (miner tips) collected from the block's messages.
+    pub gas_reward: TokenAmount,
+    /// The number of winning election proofs in the block.
+    pub win_count: i64,
+}
+
+/// Returns the implicit message that triggers the cron actor's end-of-epoch tick.
+pub fn cron_message(epoch: ChainEpoch) -> Message {
+    implicit_message(
+        Address::new_id(CRON_ACTOR_ID),
+        epoch,
+        CRON_EPOCH_TICK_METHOD,
+        RawBytes::default(),
+    )
+}
+
+/// Returns the implicit message that awards a block reward (and gas rewards) to a block's miner.
+pub fn reward_message(
+    epoch: ChainEpoch,
+    params: &AwardBlockRewardParams,
+) -> anyhow::Result<Message> {
+    Ok(implicit_message(
+        Address::new_id(REWARD_ACTOR_ID),
+        epoch,
+        AWARD_BLOCK_REWARD_METHOD,
+        RawBytes::serialize(params)?,
+    ))
+}
+
+fn implicit_message(
+    to: Address,
+    epoch: ChainEpoch,
+    method_num: MethodNum,
+    params: RawBytes,
+) -> Message {
+    Message {
+        version: 0,
+        from: Address::new_id(SYSTEM_ACTOR_ID),
+        to,
+        // Implicit messages ignore the sequence, but we set it to the epoch so that the message
+        // (and its CID) is unique per epoch.
+        sequence: epoch as u64,
+        value: TokenAmount::default(),
+        method_num,
+        params,
+        gas_limit: IMPLICIT_MESSAGE_GAS_LIMIT,
+        gas_fee_cap: TokenAmount::default(),
+        gas_premium: TokenAmount::default(),
+    }
+}
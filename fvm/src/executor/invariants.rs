@@ -0,0 +1,205 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Value transfer invariant checks. See
+//! [`MachineContext::invariant_checks`](crate::machine::MachineContext::invariant_checks).
+use std::collections::BTreeMap;
+use std::fmt;
+
+use fvm_ipld_blockstore::Blockstore;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::message::Message;
+use fvm_shared::ActorID;
+
+use crate::state_tree::StateTree;
+
+/// A summary of the balances of all actors in a state tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Balances {
+    /// The sum of all actor balances.
+    pub total: TokenAmount,
+    /// All actors with negative balances, in order of actor ID.
+    pub negative: Vec<(ActorID, TokenAmount)>,
+}
+
+impl Balances {
+    /// Sums the balances of all actors in the state tree, including pending (unflushed) changes.
+    ///
+    /// This walks the entire state tree so it's _very_ slow on large trees.
+    pub fn collect<B: Blockstore>(tree: &StateTree<B>) -> anyhow::Result<Self> {
+        let mut balances = BTreeMap::new();
+        tree.for_each(|addr, actor| {
+            balances.insert(addr.id()?, actor.balance.clone());
+            Ok(())
+        })?;
+        tree.for_each_cached_actor(|id, pending| match pending {
+            Some(Some(actor)) => {
+                balances.insert(id, actor.balance.clone());
+            }
+            Some(None) => {
+                balances.remove(&id);
+            }
+            None => {}
+        });
+
+        let mut total = TokenAmount::default();
+        let mut negative = Vec::new();
+        for (id, balance) in balances {
+            if balance.is_negative() {
+                negative.push((id, balance.clone()));
+            }
+            total += balance;
+        }
+        Ok(Balances { total, negative })
+    }
+}
+
+/// Returned (as the error) by [`Executor::execute_message`](super::Executor::execute_message) when
+/// invariant checks are enabled and applying a message didn't conserve the total FIL supply or left
+/// an actor with a negative balance. The machine is poisoned when this happens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantViolation {
+    /// The epoch in which the message was applied.
+    pub epoch: ChainEpoch,
+    /// The offending message.
+    pub message: Message,
+    /// The message's exit code.
+    pub exit_code: ExitCode,
+    /// The sum of all actor balances before the message was applied.
+    pub supply_before: TokenAmount,
+    /// The sum of all actor balances after the message was applied.
+    pub supply_after: TokenAmount,
+    /// All actors with negative balances after the message was applied.
+    pub negative_balances: Vec<(ActorID, TokenAmount)>,
+}
+
+impl InvariantViolation {
+    /// Checks the balances before and after applying a message, returning a violation if the total
+    /// supply changed or any balances are negative.
+    pub(crate) fn check(
+        epoch: ChainEpoch,
+        message: &Message,
+        exit_code: ExitCode,
+        before: Balances,
+        after: Balances,
+    ) -> Option<Self> {
+        if before.total == after.total && after.negative.is_empty() {
+            return None;
+        }
+        Some(InvariantViolation {
+            epoch,
+            message: message.clone(),
+            exit_code,
+            supply_before: before.total,
+            supply_after: after.total,
+            negative_balances: after.negative,
+        })
+    }
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "value transfer invariant violated at epoch {}",
+            self.epoch
+        )?;
+        writeln!(
+            f,
+            "message: from={}, to={}, seq={}, m={}, value={}, exit_code={}",
+            self.message.from,
+            self.message.to,
+            self.message.sequence,
+            self.message.method_num,
+            self.message.value,
+            self.exit_code,
+        )?;
+        writeln!(
+            f,
+            "supply: before={}, after={}, delta={}",
+            self.supply_before,
+            self.supply_after,
+            &self.supply_after - &self.supply_before,
+        )?;
+        write!(f, "negative balances:")?;
+        if self.negative_balances.is_empty() {
+            write!(f, " none")?;
+        }
+        for (id, balance) in &self.negative_balances {
+            write!(f, "\n  f0{}: {}", id, balance)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+#[cfg(test)]
+mod tests {
+    use cid::Cid;
+    use fvm_ipld_blockstore::MemoryBlockstore;
+    use fvm_shared::address::Address;
+    use fvm_shared::state::StateTreeVersion;
+    use num_traits::Zero;
+
+    use super::*;
+    use crate::state_tree::ActorState;
+
+    fn actor(balance: i64) -> ActorState {
+        ActorState::new(
+            Cid::default(),
+            Cid::default(),
+            TokenAmount::from_atto(balance),
+            0,
+            None,
+        )
+    }
+
+    fn message() -> Message {
+        Message {
+            version: 0,
+            from: Address::new_id(100),
+            to: Address::new_id(101),
+            sequence: 0,
+            value: TokenAmount::zero(),
+            method_num: 0,
+            params: Default::default(),
+            gas_limit: 0,
+            gas_fee_cap: TokenAmount::zero(),
+            gas_premium: TokenAmount::zero(),
+        }
+    }
+
+    #[test]
+    fn collect_balances() {
+        let mut tree = StateTree::new(MemoryBlockstore::default(), StateTreeVersion::V5).unwrap();
+        tree.set_actor(100, actor(10));
+        tree.set_actor(101, actor(5));
+        tree.flush().unwrap();
+        let before = Balances::collect(&tree).unwrap();
+        assert_eq!(before.total, TokenAmount::from_atto(15));
+        assert!(before.negative.is_empty());
+
+        // Pending changes are included.
+        tree.set_actor(100, actor(-1));
+        tree.set_actor(102, actor(16));
+        tree.delete_actor(101);
+        let after = Balances::collect(&tree).unwrap();
+        assert_eq!(after.total, TokenAmount::from_atto(15));
+        assert_eq!(after.negative, vec![(100, TokenAmount::from_atto(-1))]);
+
+        let violation =
+            InvariantViolation::check(1, &message(), ExitCode::OK, before.clone(), after)
+                .expect("expected a violation");
+        assert_eq!(violation.supply_before, violation.supply_after);
+        assert!(violation
+            .to_string()
+            .contains("f0100: -0.000000000000000001"));
+
+        assert_eq!(
+            InvariantViolation::check(1, &message(), ExitCode::OK, before.clone(), before),
+            None
+        );
+    }
+}
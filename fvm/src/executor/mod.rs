@@ -1,23 +1,45 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+mod auth;
+mod block;
 mod default;
+mod implicit;
+mod invariants;
+mod shared;
 mod threaded;
 
 use std::fmt::Display;
 
+use anyhow::Context;
+pub use auth::{AuthenticateMessageParams, AUTHENTICATE_MESSAGE_METHOD};
+pub use block::{BlockFullError, BlockMessages, BlockPacker, BlockValidationError};
 use cid::Cid;
 pub use default::DefaultExecutor;
+use fvm_ipld_blockstore::BlockstoreStats;
 use fvm_ipld_encoding::RawBytes;
+use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::error::ExitCode;
 use fvm_shared::event::StampedEvent;
 use fvm_shared::message::Message;
 use fvm_shared::receipt::Receipt;
+use fvm_shared::ActorID;
+pub use implicit::{
+    cron_message, reward_message, AwardBlockRewardParams, AWARD_BLOCK_REWARD_METHOD,
+    CRON_EPOCH_TICK_METHOD, IMPLICIT_MESSAGE_GAS_LIMIT,
+};
+pub use invariants::InvariantViolation;
 use num_traits::Zero;
+use serde::de::DeserializeOwned;
+pub use shared::SharedExecutor;
 pub use threaded::ThreadedExecutor;
+pub(crate) use threaded::EXEC_POOL;
 
+use crate::call_manager::backtrace::{Cause, TrapKind};
 use crate::call_manager::Backtrace;
-use crate::trace::ExecutionTrace;
+use crate::gas::{GasBreakdown, GasCharge, GasOutputs};
+use crate::trace::{ExecutionEvent, ExecutionTrace};
 use crate::Kernel;
 
 /// An executor executes messages on the underlying machine/kernel. It's responsible for:
@@ -42,8 +64,256 @@ pub trait Executor {
         raw_length: usize,
     ) -> anyhow::Result<ApplyRet>;
 
+    /// Estimates the gas required to apply a message without modifying the state-tree.
+    ///
+    /// The message is applied as an explicit message on top of the current state, and all changes
+    /// are reverted afterwards. To make estimation possible for messages that haven't been fully
+    /// populated, the message's sequence is replaced with the sender's current sequence, its fee
+    /// cap and premium are zeroed (so the sender doesn't need to be able to cover gas fees), and a
+    /// gas limit of zero is replaced by [`BLOCK_GAS_LIMIT`].
+    ///
+    /// The returned receipt's `gas_used` is the projected gas usage. The execution trace is only
+    /// populated if tracing is enabled on the machine.
+    ///
+    /// By default, this fails: executors must opt into gas estimation.
+    fn estimate_gas(&mut self, _msg: Message, _raw_length: usize) -> anyhow::Result<ApplyRet> {
+        Err(anyhow::anyhow!(
+            "this executor doesn't support gas estimation"
+        ))
+    }
+
+    /// Applies a message in read-only mode on top of the current state, without modifying the
+    /// state-tree. This is intended for side-effect-free simulations (e.g., `StateCall` RPC
+    /// endpoints).
+    ///
+    /// The message is prepared as in [`Executor::estimate_gas`] (and its raw length is taken to be
+    /// the length of its serialized form), but the receiver (and anything it calls) is invoked in
+    /// read-only mode: attempts to modify state, transfer value, emit events, or create actors
+    /// fail with a `ReadOnly` error. Messages that transfer value are rejected with an error.
+    ///
+    /// By default, this fails: executors must opt into read-only calls.
+    fn call_readonly(&mut self, _msg: Message) -> anyhow::Result<ApplyRet> {
+        Err(anyhow::anyhow!(
+            "this executor doesn't support read-only calls"
+        ))
+    }
+
+    /// Checks whether an explicit message would pass pre-validation (message sanity checks,
+    /// inclusion gas, sender validity, nonce, and balance for gas) on top of the current state,
+    /// without executing it or modifying the state-tree. Returns the sender's ID on success.
+    ///
+    /// These are exactly the checks applied by [`Executor::execute_message`] before executing a
+    /// message. A message that fails them would fail with the error's
+    /// [exit code](PreflightError::exit_code) instead of being executed.
+    ///
+    /// By default, this fails: executors must opt into preflight checks.
+    fn preflight(
+        &self,
+        _msg: &Message,
+        _raw_length: usize,
+    ) -> anyhow::Result<Result<ActorID, PreflightError>> {
+        Err(anyhow::anyhow!(
+            "this executor doesn't support preflight checks"
+        ))
+    }
+
+    /// Validates all messages in a block on top of the current state, without executing them or
+    /// modifying the state-tree. In a single pass, this checks that:
+    ///
+    /// 1. Every message passes pre-validation (see [`Executor::preflight`]), except that messages
+    ///    from the same sender must have consecutive nonces starting at the sender's current nonce,
+    ///    and the sender's balance must cover the gas (`gas_fee_cap * gas_limit`) of all of them.
+    /// 2. Every secp256k1 message is signed by its sender, and the BLS aggregate signature covers
+    ///    all BLS messages and their senders. Senders must have key addresses (i.e., be accounts).
+    /// 3. The messages' gas limits sum to at most [`BLOCK_GAS_LIMIT`].
+    ///
+    /// By default, this fails: executors must opt into block validation.
+    fn validate_block_messages(
+        &self,
+        _msgs: &BlockMessages,
+    ) -> anyhow::Result<Result<(), BlockValidationError>> {
+        Err(anyhow::anyhow!(
+            "this executor doesn't support block validation"
+        ))
+    }
+
+    /// Applies a chain of explicit messages from a single sender, in sequence (nonce) order. Each
+    /// message is paired with its raw length (see [`Executor::execute_message`]).
+    ///
+    /// The messages are sorted by sequence before being applied, and must all have the same
+    /// sender and consecutive sequences; otherwise, an error is returned without applying any of
+    /// them. The chain stops at the first message that fails pre-validation (which is not applied)
+    /// or whose receipt has a non-zero exit code (which is applied and included in the result).
+    ///
+    /// Returns the [`ApplyRet`] of each applied message, in the order in which they were applied.
+    fn execute_chain(&mut self, mut msgs: Vec<(Message, usize)>) -> anyhow::Result<Vec<ApplyRet>> {
+        msgs.sort_by_key(|(msg, _)| msg.sequence);
+        for pair in msgs.windows(2) {
+            let (prev, msg) = (&pair[0].0, &pair[1].0);
+            if msg.from != prev.from {
+                return Err(anyhow::anyhow!(
+                    "message chain has multiple senders: {} and {}",
+                    prev.from,
+                    msg.from
+                ));
+            }
+            // Checked, so a chain can't wrap around from u64::MAX back to 0.
+            if prev.sequence.checked_add(1) != Some(msg.sequence) {
+                return Err(anyhow::anyhow!(
+                    "message chain has a sequence gap: {} follows {}",
+                    msg.sequence,
+                    prev.sequence
+                ));
+            }
+        }
+
+        let mut rets = Vec::with_capacity(msgs.len());
+        for (msg, raw_length) in msgs {
+            if self.preflight(&msg, raw_length)?.is_err() {
+                break;
+            }
+            let ret = self.execute_message(msg, ApplyKind::Explicit, raw_length)?;
+            let failed = !ret.msg_receipt.exit_code.is_success();
+            rets.push(ret);
+            if failed {
+                break;
+            }
+        }
+        Ok(rets)
+    }
+
+    /// Asks the `sender`'s actor to authenticate `signature` over `payload` by invoking its
+    /// [`AUTHENTICATE_MESSAGE_METHOD`] in read-only mode (see [`Executor::call_readonly`]). This lets
+    /// senders that aren't plain key-addressed accounts (e.g., Ethereum accounts) define their own
+    /// signature schemes.
+    ///
+    /// Returns `false` if the sender doesn't exist, isn't a valid sender, or rejects the signature.
+    fn authenticate_message(
+        &mut self,
+        sender: &Address,
+        signature: &[u8],
+        payload: &[u8],
+    ) -> anyhow::Result<bool> {
+        let params = AuthenticateMessageParams {
+            signature: signature.to_vec(),
+            message: payload.to_vec(),
+        };
+        let ret = self.call_readonly(auth::authenticate_message(sender, &params)?)?;
+        if !ret.msg_receipt.exit_code.is_success() {
+            return Ok(false);
+        }
+        // Older account actors return nothing on success, newer ones return a boolean.
+        Ok(ret.decode_return::<bool>()?.unwrap_or(true))
+    }
+
+    /// Authenticates the message's sender (see [`Executor::authenticate_message`]) then, if
+    /// successful, applies the message as in [`Executor::execute_message`].
+    ///
+    /// If authentication fails, the message isn't applied and the returned receipt has the
+    /// [`ExitCode::SYS_SENDER_INVALID`] exit code, with no miner penalty.
+    fn execute_authenticated_message(
+        &mut self,
+        msg: Message,
+        signature: &[u8],
+        payload: &[u8],
+        apply_kind: ApplyKind,
+        raw_length: usize,
+    ) -> anyhow::Result<ApplyRet> {
+        if !self.authenticate_message(&msg.from, signature, payload)? {
+            return Ok(ApplyRet::prevalidation_fail(
+                ExitCode::SYS_SENDER_INVALID,
+                format!("failed to authenticate message from {}", msg.from),
+                TokenAmount::zero(),
+            ));
+        }
+        self.execute_message(msg, apply_kind, raw_length)
+    }
+
+    /// Applies an implicit (system) message. Implicit messages ignore the sender's nonce, don't
+    /// charge the sender for gas, and never incur a miner penalty.
+    ///
+    /// From NV21, the message may use up to [`IMPLICIT_MESSAGE_GAS_LIMIT`] gas (also used if its
+    /// gas limit is zero). The gas it actually used is still reported in the receipt.
+    fn apply_implicit_message(&mut self, msg: Message) -> anyhow::Result<ApplyRet> {
+        // Implicit messages don't pay for inclusion, so the raw length is irrelevant.
+        self.execute_message(msg, ApplyKind::Implicit, 0)
+    }
+
+    /// Runs the end-of-epoch cron tick for the given epoch. See [`cron_message`].
+    fn run_cron(&mut self, epoch: ChainEpoch) -> anyhow::Result<ApplyRet> {
+        self.apply_implicit_message(cron_message(epoch))
+    }
+
+    /// Awards the block reward for a block mined in the given epoch. See [`reward_message`].
+    fn apply_reward(
+        &mut self,
+        epoch: ChainEpoch,
+        params: &AwardBlockRewardParams,
+    ) -> anyhow::Result<ApplyRet> {
+        self.apply_implicit_message(reward_message(epoch, params)?)
+    }
+
     /// Flushes the state-tree, returning the new root CID.
     fn flush(&mut self) -> anyhow::Result<Cid>;
+
+    /// Flushes the state-tree and consumes the executor, returning the final state root along with
+    /// aggregate statistics about the messages applied by this executor.
+    ///
+    /// Fails if the machine was poisoned.
+    ///
+    /// By default, this flushes the state-tree and reports empty statistics.
+    fn finish(mut self) -> anyhow::Result<(Cid, MachineStats)>
+    where
+        Self: Sized,
+    {
+        Ok((self.flush()?, MachineStats::default()))
+    }
+}
+
+/// The maximum amount of gas that can be used by all messages in a block.
+pub const BLOCK_GAS_LIMIT: u64 = 10_000_000_000;
+
+/// The reason a message failed pre-validation. See [`Executor::preflight`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum PreflightError {
+    /// The message is malformed (e.g., it has no gas limit). Executing such a message fails with a
+    /// fatal error.
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+    /// The message's gas limit doesn't cover its inclusion cost.
+    #[error("Out of gas ({inclusion_gas} > {gas_limit})")]
+    OutOfGas { inclusion_gas: u64, gas_limit: u64 },
+    /// The sender doesn't exist.
+    #[error("Sender invalid")]
+    SenderNotFound,
+    /// The sender isn't allowed to send messages (e.g., it isn't an account).
+    #[error("Send not from valid sender")]
+    InvalidSender,
+    /// The message's sequence doesn't match the sender's nonce.
+    #[error("Actor sequence invalid: {actual} != {expected}")]
+    SequenceMismatch { expected: u64, actual: u64 },
+    /// The sender can't cover the message's maximum gas cost.
+    #[error("Actor balance less than needed: {balance} < {required}")]
+    InsufficientFunds {
+        balance: TokenAmount,
+        required: TokenAmount,
+    },
+}
+
+impl PreflightError {
+    /// The exit code of the receipt for a message that fails with this error.
+    pub fn exit_code(&self) -> ExitCode {
+        match self {
+            PreflightError::InvalidMessage(_) => ExitCode::SYS_ASSERTION_FAILED,
+            PreflightError::OutOfGas { .. } => ExitCode::SYS_OUT_OF_GAS,
+            PreflightError::SenderNotFound | PreflightError::InvalidSender => {
+                ExitCode::SYS_SENDER_INVALID
+            }
+            PreflightError::SequenceMismatch { .. } | PreflightError::InsufficientFunds { .. } => {
+                ExitCode::SYS_SENDER_STATE_INVALID
+            }
+        }
+    }
 }
 
 /// A description of some failure encountered when applying a message.
@@ -70,12 +340,86 @@ impl Display for ApplyFailure {
     }
 }
 
+/// A machine-readable classification of why a message failed. See [`ApplyRet::failure`].
+///
+/// This classifies the root cause of the failure: when an actor aborts because a call it made
+/// failed, the failure is classified by the innermost failing call. Human-readable details are
+/// available in [`ApplyRet::failure_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FailureInfo {
+    /// The message ran out of gas, either during execution or because its gas limit doesn't cover
+    /// its inclusion cost.
+    OutOfGas,
+    /// The message failed pre-validation because it's malformed.
+    InvalidMessage,
+    /// The message failed pre-validation because its sender doesn't exist or isn't allowed to send
+    /// messages.
+    SysErrSenderInvalid,
+    /// The message failed pre-validation because its sequence doesn't match the sender's nonce, or
+    /// because the sender can't cover its maximum gas cost.
+    SysErrSenderStateInvalid,
+    /// An actor aborted with the given exit code.
+    ActorAbort { code: ExitCode },
+    /// An actor trapped (e.g., by executing an `unreachable` instruction or accessing memory out
+    /// of bounds).
+    Trap { kind: TrapKind },
+    /// A syscall failed, and the actor making it aborted. The name is of the form
+    /// `module::function`. A `send::send` failure at the top level means the message couldn't be
+    /// delivered to its receiver at all (e.g., because the sender can't afford the value).
+    Syscall { name: String },
+    /// The message failed with a fatal error (a bug in the FVM, or a failure in the blockstore or
+    /// externs).
+    Fatal,
+}
+
+impl FailureInfo {
+    /// Classifies a pre-validation failure by its exit code (see [`PreflightError::exit_code`]).
+    fn from_prevalidation(code: ExitCode) -> Self {
+        match code {
+            ExitCode::SYS_OUT_OF_GAS => FailureInfo::OutOfGas,
+            ExitCode::SYS_SENDER_INVALID => FailureInfo::SysErrSenderInvalid,
+            ExitCode::SYS_SENDER_STATE_INVALID => FailureInfo::SysErrSenderStateInvalid,
+            _ => FailureInfo::InvalidMessage,
+        }
+    }
+
+    /// Classifies an execution failure by the message's exit code and the failure's backtrace.
+    fn from_backtrace(code: ExitCode, backtrace: &Backtrace) -> Self {
+        if code == ExitCode::SYS_OUT_OF_GAS {
+            return FailureInfo::OutOfGas;
+        }
+        // Frames are recorded from the innermost failing call outwards.
+        if let Some(kind) = backtrace.frames.first().and_then(|f| f.trap) {
+            return FailureInfo::Trap { kind };
+        }
+        match &backtrace.cause {
+            Some(Cause::Fatal { .. }) => FailureInfo::Fatal,
+            Some(Cause::Syscall {
+                module, function, ..
+            }) => FailureInfo::Syscall {
+                name: format!("{}::{}", module, function),
+            },
+            None => FailureInfo::ActorAbort { code },
+        }
+    }
+}
+
 /// Apply message return data.
 #[derive(Clone, Debug)]
 pub struct ApplyRet {
     /// Message receipt for the transaction. This data is stored on chain.
     pub msg_receipt: Receipt,
-    /// Gas penalty from transaction, if any.
+    /// The penalty charged to the miner for including the message, if any:
+    ///
+    /// - Messages that fail pre-validation (see [`PreflightError`]) are penalized the base fee times
+    ///   their gas limit or, if their gas limit doesn't cover their inclusion cost, times their
+    ///   inclusion gas.
+    /// - Executed messages are penalized the difference between the base fee and their fee cap (if
+    ///   the fee cap is lower) times the gas used and burned (see the [`FeePolicy`]).
+    /// - Implicit messages are never penalized.
+    ///
+    /// [`FeePolicy`]: crate::gas::FeePolicy
     pub penalty: TokenAmount,
     /// Tip given to miner from message.
     pub miner_tip: TokenAmount,
@@ -89,13 +433,75 @@ pub struct ApplyRet {
 
     /// Additional failure information for debugging, if any.
     pub failure_info: Option<ApplyFailure>,
-    /// Execution trace information, for debugging.
+    /// The classification of the failure, if the message failed (exited with a non-zero exit
+    /// code).
+    pub failure: Option<FailureInfo>,
+    /// Execution trace information, for debugging. This is only populated when tracing is enabled
+    /// (see [`MachineContext::enable_tracing`](crate::machine::MachineContext::enable_tracing)) and
+    /// records every internal send, its return, and the gas charged along the way.
     pub exec_trace: ExecutionTrace,
-    /// Events generated while applying the message.
+    /// Events emitted by actors (via the `emit_event` syscall) while applying the message, in
+    /// emission order. Events emitted by calls that were later reverted are not included. The root
+    /// of the AMT holding these events is recorded in the receipt's `events_root`.
     pub events: Vec<StampedEvent>,
+    /// A breakdown of the gas used by category. This is only populated when enabled (see
+    /// [`MachineContext::enable_gas_breakdown`](crate::machine::MachineContext::enable_gas_breakdown)).
+    pub gas_breakdown: Option<GasBreakdown>,
+    /// Blockstore I/O performed while applying the message (including the blocks buffered for the
+    /// new state), if the machine tracks it.
+    pub blockstore_stats: Option<BlockstoreStats>,
 }
 
 impl ApplyRet {
+    /// Decodes the message's return value into a typed value, returning `None` if the message
+    /// returned nothing. Fails if the message didn't exit successfully (including the failure
+    /// information, if any) or its return value can't be decoded as a `T`.
+    pub fn decode_return<T: DeserializeOwned>(&self) -> anyhow::Result<Option<T>> {
+        let exit_code = self.msg_receipt.exit_code;
+        if !exit_code.is_success() {
+            return Err(match &self.failure_info {
+                Some(info) => {
+                    anyhow::anyhow!("message failed with exit code {}: {}", exit_code, info)
+                }
+                None => anyhow::anyhow!("message failed with exit code {}", exit_code),
+            });
+        }
+        self.msg_receipt
+            .decode_return()
+            .context("failed to decode message return value")
+    }
+
+    /// Returns every gas charge made while applying the message (name, compute gas, and other gas),
+    /// in the order in which they were made. Like [`ApplyRet::exec_trace`], this is only populated
+    /// when tracing is enabled.
+    pub fn gas_charges(&self) -> impl Iterator<Item = &GasCharge> + '_ {
+        self.exec_trace.iter().filter_map(|event| match event {
+            ExecutionEvent::GasCharge(charge) => Some(charge),
+            _ => None,
+        })
+    }
+
+    /// Returns the root of the AMT holding the events emitted while applying the message, if any
+    /// were emitted. See [`ApplyRet::events`].
+    pub fn events_root(&self) -> Option<Cid> {
+        self.msg_receipt.events_root
+    }
+
+    /// Returns the message's gas outputs: how the gas funds reserved from the sender before
+    /// execution were settled afterwards (burnt, paid to the miner, or refunded), along with the
+    /// miner penalty. For explicit messages, [`GasOutputs::total_cost`] is the amount reserved.
+    pub fn gas_outputs(&self) -> GasOutputs {
+        GasOutputs {
+            base_fee_burn: self.base_fee_burn.clone(),
+            over_estimation_burn: self.over_estimation_burn.clone(),
+            miner_penalty: self.penalty.clone(),
+            miner_tip: self.miner_tip.clone(),
+            refund: self.refund.clone(),
+            gas_refund: self.gas_refund,
+            gas_burned: self.gas_burned,
+        }
+    }
+
     #[inline]
     pub fn prevalidation_fail(
         code: ExitCode,
@@ -117,20 +523,96 @@ impl ApplyRet {
             gas_refund: 0,
             gas_burned: 0,
             failure_info: Some(ApplyFailure::PreValidation(message.into())),
+            failure: Some(FailureInfo::from_prevalidation(code)),
             exec_trace: vec![],
             events: vec![],
+            gas_breakdown: None,
+            blockstore_stats: None,
         }
     }
 }
 
+/// Aggregate statistics about the messages applied by an executor. See [`Executor::finish`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MachineStats {
+    /// The number of messages applied (explicit and implicit), including those that exited with a
+    /// non-zero exit code. Messages that failed pre-validation, and simulated messages (see
+    /// [`Executor::estimate_gas`] and [`Executor::call_readonly`]), aren't counted.
+    pub messages_applied: u64,
+    /// The total gas used by all applied messages, as reported in their receipts.
+    pub gas_used: u64,
+    /// The I/O performed by the machine's blockstore since the machine was created, including the
+    /// final flush, if the machine tracks it.
+    pub blockstore_stats: Option<BlockstoreStats>,
+}
+
+impl MachineStats {
+    /// Returns the number of blocks written to the underlying blockstore, or 0 if the machine
+    /// doesn't track blockstore I/O.
+    pub fn blocks_written(&self) -> u64 {
+        self.blockstore_stats
+            .as_ref()
+            .map_or(0, |stats| stats.blocks_flushed)
+    }
+}
+
 /// The kind of message being applied:
 ///
 /// 1. Explicit messages may only come from account actors and charge the sending account for gas
 /// consumed.
 /// 2. Implicit messages may come from any actor, ignore the nonce, and charge no gas (but still
-/// account for it).
+/// account for it). They aren't checked against the sender's balance, never incur a miner penalty,
+/// and don't increment the sender's nonce. From NV21, their gas limit is capped at
+/// [`IMPLICIT_MESSAGE_GAS_LIMIT`], which is also used if the message's gas limit is zero.
 #[derive(Eq, PartialEq, Copy, Clone, Debug)]
 pub enum ApplyKind {
     Explicit,
     Implicit,
 }
+
+#[cfg(test)]
+mod tests {
+    use fvm_ipld_blockstore::MemoryBlockstore;
+
+    use super::*;
+    use crate::call_manager::DefaultCallManager;
+    use crate::machine::DefaultMachine;
+    use crate::test::DummyExterns;
+    use crate::DefaultKernel;
+
+    /// An executor implementing only the required methods.
+    struct MinimalExecutor(Cid);
+
+    impl Executor for MinimalExecutor {
+        type Kernel =
+            DefaultKernel<DefaultCallManager<DefaultMachine<MemoryBlockstore, DummyExterns>>>;
+
+        fn execute_message(
+            &mut self,
+            _msg: Message,
+            _apply_kind: ApplyKind,
+            _raw_length: usize,
+        ) -> anyhow::Result<ApplyRet> {
+            Err(anyhow::anyhow!("not implemented"))
+        }
+
+        fn flush(&mut self) -> anyhow::Result<Cid> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn default_methods() {
+        let mut executor = MinimalExecutor(*crate::EMPTY_ARR_CID);
+        assert!(executor.estimate_gas(Message::default(), 100).is_err());
+        assert!(executor.call_readonly(Message::default()).is_err());
+        assert!(executor.preflight(&Message::default(), 100).is_err());
+        assert!(executor
+            .validate_block_messages(&BlockMessages::default())
+            .is_err());
+
+        let (root, stats) = executor.finish().unwrap();
+        assert_eq!(root, *crate::EMPTY_ARR_CID);
+        assert_eq!(stats, MachineStats::default());
+    }
+}
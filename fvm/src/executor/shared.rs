@@ -0,0 +1,239 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_shared::message::Message;
+use fvm_shared::ActorID;
+
+use super::{
+    ApplyKind, ApplyRet, BlockMessages, BlockValidationError, DefaultExecutor, Executor,
+    PreflightError,
+};
+use crate::call_manager::{CallManager, DefaultCallManager};
+use crate::engine::EnginePool;
+use crate::externs::Externs;
+use crate::kernel::Kernel;
+use crate::machine::{DefaultMachine, MachineContext};
+use crate::DefaultKernel;
+
+/// A cloneable, `Send + Sync` handle for driving machines from async runtimes and multi-threaded
+/// RPC servers.
+///
+/// The handle shares an engine pool, blockstore, and externs between threads, but never a
+/// machine: every call runs on a fresh machine of its own, on top of the handle's state root.
+/// Calls therefore don't contend with each other (beyond waiting for an engine from the pool), and
+/// read-only calls ([`call_readonly`], [`estimate_gas`], etc.) may run concurrently on any number
+/// of threads.
+///
+/// Messages are applied through an [`executor`] for the handle's state root. Once flushed, the new
+/// state root can be shared with other threads through [`at`].
+///
+/// Calls block the current thread, so async callers should dispatch them to a blocking thread pool
+/// (e.g., `tokio::task::spawn_blocking`). The blockstore `B` is cloned for each machine, so all
+/// clones must share the same underlying storage (e.g., an `Arc` around a thread-safe store).
+///
+/// [`call_readonly`]: SharedExecutor::call_readonly
+/// [`estimate_gas`]: SharedExecutor::estimate_gas
+/// [`executor`]: SharedExecutor::executor
+/// [`at`]: SharedExecutor::at
+pub struct SharedExecutor<B, E, K = DefaultKernel<DefaultCallManager<DefaultMachine<B, E>>>> {
+    inner: Arc<Inner<B, E>>,
+    state_root: Cid,
+    _kernel: PhantomData<fn() -> K>,
+}
+
+/// The parts of a [`SharedExecutor`] shared between all of its handles.
+struct Inner<B, E> {
+    engine_pool: EnginePool,
+    context: MachineContext,
+    blockstore: B,
+    externs: E,
+}
+
+impl<B, E, K> Clone for SharedExecutor<B, E, K> {
+    fn clone(&self) -> Self {
+        SharedExecutor {
+            inner: self.inner.clone(),
+            state_root: self.state_root,
+            _kernel: PhantomData,
+        }
+    }
+}
+
+impl<B, E, K> SharedExecutor<B, E, K>
+where
+    B: Blockstore + Clone,
+    E: Externs + Clone,
+    K: Kernel,
+    K::CallManager: CallManager<Machine = DefaultMachine<B, E>>,
+{
+    /// Create a new [`SharedExecutor`] on top of the context's `initial_state_root`.
+    pub fn new(
+        engine_pool: EnginePool,
+        context: MachineContext,
+        blockstore: B,
+        externs: E,
+    ) -> Self {
+        let state_root = context.initial_state_root;
+        SharedExecutor {
+            inner: Arc::new(Inner {
+                engine_pool,
+                context,
+                blockstore,
+                externs,
+            }),
+            state_root,
+            _kernel: PhantomData,
+        }
+    }
+
+    /// Returns the state root calls on this handle run on top of.
+    pub fn state_root(&self) -> Cid {
+        self.state_root
+    }
+
+    /// Returns a handle sharing this handle's engine pool, blockstore, and externs, whose calls run
+    /// on top of the given state root.
+    pub fn at(&self, state_root: Cid) -> Self {
+        SharedExecutor {
+            inner: self.inner.clone(),
+            state_root,
+            _kernel: PhantomData,
+        }
+    }
+
+    /// Returns a new executor, with a machine of its own, on top of this handle's state root.
+    /// Messages applied by the executor are only visible to other handles once it has been
+    /// flushed and they've been moved to the new state root with [`at`](SharedExecutor::at).
+    pub fn executor(&self) -> anyhow::Result<DefaultExecutor<K>> {
+        let Inner {
+            engine_pool,
+            context,
+            blockstore,
+            externs,
+        } = &*self.inner;
+        let mut context = context.clone();
+        context.initial_state_root = self.state_root;
+        let machine = DefaultMachine::new(&context, blockstore.clone(), externs.clone())?;
+        DefaultExecutor::new(engine_pool.clone(), machine)
+    }
+
+    /// See [`Executor::estimate_gas`].
+    pub fn estimate_gas(&self, msg: Message, raw_length: usize) -> anyhow::Result<ApplyRet> {
+        self.executor()?.estimate_gas(msg, raw_length)
+    }
+
+    /// See [`Executor::call_readonly`].
+    pub fn call_readonly(&self, msg: Message) -> anyhow::Result<ApplyRet> {
+        self.executor()?.call_readonly(msg)
+    }
+
+    /// See [`Executor::preflight`].
+    pub fn preflight(
+        &self,
+        msg: &Message,
+        raw_length: usize,
+    ) -> anyhow::Result<Result<ActorID, PreflightError>> {
+        self.executor()?.preflight(msg, raw_length)
+    }
+
+    /// See [`Executor::validate_block_messages`].
+    pub fn validate_block_messages(
+        &self,
+        msgs: &BlockMessages,
+    ) -> anyhow::Result<Result<(), BlockValidationError>> {
+        self.executor()?.validate_block_messages(msgs)
+    }
+
+    /// Applies the messages, in order, on a new executor and flushes it, returning their results
+    /// along with a handle on top of the resulting state root.
+    pub fn execute_messages(
+        &self,
+        msgs: Vec<(Message, ApplyKind, usize)>,
+    ) -> anyhow::Result<(Vec<ApplyRet>, Self)> {
+        let mut executor = self.executor()?;
+        let rets = msgs
+            .into_iter()
+            .map(|(msg, apply_kind, raw_length)| {
+                executor.execute_message(msg, apply_kind, raw_length)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let root = executor.flush()?;
+        Ok((rets, self.at(root)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use fvm_ipld_encoding::CborStore;
+    use fvm_shared::state::StateTreeVersion;
+    use multihash::Code;
+
+    use super::*;
+    use crate::machine::{Machine, Manifest, NetworkConfig};
+    use crate::state_tree::StateTree;
+    use crate::test::DummyExterns;
+
+    /// A thread-safe in-memory blockstore.
+    #[derive(Default)]
+    struct SyncBlockstore(Mutex<HashMap<Cid, Vec<u8>>>);
+
+    impl Blockstore for SyncBlockstore {
+        fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().unwrap().get(k).cloned())
+        }
+
+        fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+            self.0.lock().unwrap().insert(*k, block.into());
+            Ok(())
+        }
+    }
+
+    type TestExecutor = SharedExecutor<Arc<SyncBlockstore>, DummyExterns>;
+
+    fn assert_send_sync<T: Send + Sync + Clone + 'static>() {}
+
+    #[test]
+    fn shared_executor_is_send_sync() {
+        assert_send_sync::<TestExecutor>();
+    }
+
+    #[test]
+    fn machines_per_call() {
+        let bs = Arc::new(SyncBlockstore::default());
+        let mut st = StateTree::new(bs.clone(), StateTreeVersion::V5).unwrap();
+        let root = st.flush().unwrap();
+
+        // An empty built-in actors manifest.
+        let manifest_cid = bs
+            .put_cbor(&Manifest::DUMMY_CODES, Code::Blake2b256)
+            .unwrap();
+        let actors_cid = bs.put_cbor(&(1, manifest_cid), Code::Blake2b256).unwrap();
+
+        let mc = NetworkConfig::new(fvm_shared::version::NetworkVersion::V18)
+            .override_actors(actors_cid)
+            .for_epoch(0, 0, root);
+        let engine = EnginePool::new_default((&mc.network).into()).unwrap();
+        let shared = TestExecutor::new(engine, mc, bs, DummyExterns);
+
+        // Every thread gets a machine of its own, without waiting for the others to finish.
+        std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| shared.executor().unwrap().context().initial_state_root))
+                .collect();
+            for handle in handles {
+                assert_eq!(handle.join().unwrap(), root);
+            }
+        });
+
+        let other = shared.at(manifest_cid);
+        assert_eq!(other.state_root(), manifest_cid);
+        assert_eq!(shared.state_root(), root);
+    }
+}
@@ -3,12 +3,16 @@
 use anyhow::anyhow;
 use cid::Cid;
 use fvm_shared::message::Message;
+use fvm_shared::ActorID;
 use lazy_static::lazy_static;
 
-use super::{ApplyKind, ApplyRet, Executor};
+use super::{
+    ApplyKind, ApplyRet, BlockMessages, BlockValidationError, Executor, MachineStats,
+    PreflightError,
+};
 
 lazy_static! {
-    static ref EXEC_POOL: yastl::Pool = yastl::Pool::with_config(
+    pub(crate) static ref EXEC_POOL: yastl::Pool = yastl::Pool::with_config(
         8,
         yastl::ThreadConfig::new()
             .prefix("fvm-executor")
@@ -47,7 +51,48 @@ where
         ret
     }
 
+    fn estimate_gas(&mut self, msg: Message, raw_length: usize) -> anyhow::Result<ApplyRet> {
+        let mut ret = Err(anyhow!("failed to estimate gas"));
+
+        EXEC_POOL.scoped(|scope| {
+            scope.execute(|| ret = self.0.estimate_gas(msg, raw_length));
+        });
+
+        ret
+    }
+
+    fn call_readonly(&mut self, msg: Message) -> anyhow::Result<ApplyRet> {
+        let mut ret = Err(anyhow!("failed to call message"));
+
+        EXEC_POOL.scoped(|scope| {
+            scope.execute(|| ret = self.0.call_readonly(msg));
+        });
+
+        ret
+    }
+
+    fn preflight(
+        &self,
+        msg: &Message,
+        raw_length: usize,
+    ) -> anyhow::Result<Result<ActorID, PreflightError>> {
+        // Pre-validation doesn't execute any actor code, so it doesn't need a large stack.
+        self.0.preflight(msg, raw_length)
+    }
+
+    fn validate_block_messages(
+        &self,
+        msgs: &BlockMessages,
+    ) -> anyhow::Result<Result<(), BlockValidationError>> {
+        // Like pre-validation, this doesn't execute any actor code.
+        self.0.validate_block_messages(msgs)
+    }
+
     fn flush(&mut self) -> anyhow::Result<Cid> {
         self.0.flush()
     }
+
+    fn finish(self) -> anyhow::Result<(Cid, MachineStats)> {
+        self.0.finish()
+    }
 }
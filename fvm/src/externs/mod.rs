@@ -6,11 +6,20 @@ use cid::Cid;
 use fvm_shared::clock::ChainEpoch;
 use fvm_shared::consensus::ConsensusFault;
 
-pub trait Externs: Rand + Consensus + Chain {}
+pub(crate) mod verifier;
+
+pub use self::verifier::Verifier;
+
+pub trait Externs: Rand + Consensus + Chain + Verifier {}
 
 /// Consensus related methods.
 pub trait Consensus {
-    /// Verify a consensus fault.
+    /// Verify a consensus fault, returning the fault (if any) along with the gas used by the node
+    /// to verify it.
+    ///
+    /// The headers' signatures must be checked against the miner's worker key as of the lookback
+    /// state for the fault epoch (the higher epoch of the two blocks). The reported fault target
+    /// must be an ID address, and the fault epoch must not be after the current epoch.
     fn verify_consensus_fault(
         &self,
         h1: &[u8],
@@ -21,8 +30,8 @@ pub trait Consensus {
 
 /// Randomness provider trait
 pub trait Rand {
-    /// Gets 32 bytes of randomness for ChainRand paramaterized by the DomainSeparationTag,
-    /// ChainEpoch, Entropy from the ticket chain.
+    /// Gets 32 bytes of randomness from the ticket chain, parameterized by the
+    /// DomainSeparationTag, ChainEpoch, and Entropy.
     fn get_chain_randomness(
         &self,
         pers: i64,
@@ -30,8 +39,8 @@ pub trait Rand {
         entropy: &[u8],
     ) -> anyhow::Result<[u8; 32]>;
 
-    /// Gets 32 bytes of randomness for ChainRand paramaterized by the DomainSeparationTag,
-    /// ChainEpoch, Entropy from the latest beacon entry.
+    /// Gets 32 bytes of randomness from the latest beacon entry, parameterized by the
+    /// DomainSeparationTag, ChainEpoch, and Entropy.
     fn get_beacon_randomness(
         &self,
         pers: i64,
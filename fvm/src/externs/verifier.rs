@@ -0,0 +1,348 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+
+use anyhow::{anyhow, Context};
+use filecoin_proofs_api::{self as proofs, ProverId, PublicReplicaInfo, SectorId};
+use fvm_ipld_encoding::bytes_32;
+use fvm_shared::address::Address;
+use fvm_shared::commcid;
+use fvm_shared::randomness::Randomness;
+use fvm_shared::sector::{
+    AggregateSealVerifyProofAndInfos, PoStProof, RegisteredPoStProof, RegisteredSealProof,
+    ReplicaUpdateInfo, SealVerifyInfo, SectorInfo, WindowPoStVerifyInfo, WinningPoStVerifyInfo,
+};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+/// Proof verification provider.
+///
+/// The default methods verify proofs with the reference proofs implementation
+/// (`filecoin-proofs-api`). Implementations may override them to verify proofs with a different
+/// proofs library, or to skip expensive verification entirely when testing.
+///
+/// An error indicates that the inputs were malformed and will be reported to the calling actor as
+/// an illegal argument. Gas is charged by the kernel before the verifier is invoked.
+///
+/// Verifiers must be `Sync` as batches of seals are verified in parallel.
+pub trait Verifier: Sync {
+    /// Verifies a sector seal proof.
+    fn verify_seal(&self, info: &SealVerifyInfo) -> anyhow::Result<bool> {
+        verify_seal(info)
+    }
+
+    /// Verifies an aggregated batch of sector seal proofs.
+    fn verify_aggregate_seals(
+        &self,
+        aggregate: &AggregateSealVerifyProofAndInfos,
+    ) -> anyhow::Result<bool> {
+        verify_aggregate_seals(aggregate)
+    }
+
+    /// Verifies a replica update (SnapDeals) proof.
+    fn verify_replica_update(&self, replica: &ReplicaUpdateInfo) -> anyhow::Result<bool> {
+        verify_replica_update(replica)
+    }
+
+    /// Verifies a window proof of spacetime.
+    fn verify_post(&self, info: &WindowPoStVerifyInfo) -> anyhow::Result<bool> {
+        verify_window_post(info)
+    }
+
+    /// Verifies a winning proof of spacetime.
+    fn verify_winning_post(&self, info: &WinningPoStVerifyInfo) -> anyhow::Result<bool> {
+        verify_winning_post(info)
+    }
+}
+
+fn verify_seal(vi: &SealVerifyInfo) -> anyhow::Result<bool> {
+    let commr = commcid::cid_to_replica_commitment_v1(&vi.sealed_cid).map_err(|e| anyhow!(e))?;
+    let commd = commcid::cid_to_data_commitment_v1(&vi.unsealed_cid).map_err(|e| anyhow!(e))?;
+    let prover_id = prover_id_from_u64(vi.sector_id.miner);
+
+    proofs::seal::verify_seal(
+        vi.registered_proof
+            .try_into()
+            .map_err(|e| anyhow!("invalid proof type {:?}: {}", vi.registered_proof, e))?,
+        commr,
+        commd,
+        prover_id,
+        SectorId::from(vi.sector_id.number),
+        bytes_32(&vi.randomness.0),
+        bytes_32(&vi.interactive_randomness.0),
+        &vi.proof,
+    )
+    .context("failed to verify seal proof")
+}
+
+fn verify_aggregate_seals(aggregate: &AggregateSealVerifyProofAndInfos) -> anyhow::Result<bool> {
+    if aggregate.infos.is_empty() {
+        return Err(anyhow!("no seal verify infos"));
+    }
+    let spt: proofs::RegisteredSealProof =
+        aggregate.seal_proof.try_into().map_err(|e| anyhow!(e))?;
+    let prover_id = prover_id_from_u64(aggregate.miner);
+    struct AggregationInputs {
+        // replica
+        commr: [u8; 32],
+        // data
+        commd: [u8; 32],
+        sector_id: SectorId,
+        ticket: [u8; 32],
+        seed: [u8; 32],
+    }
+    let inputs: Vec<AggregationInputs> = aggregate
+        .infos
+        .iter()
+        .map(|info| {
+            let commr = commcid::cid_to_replica_commitment_v1(&info.sealed_cid)?;
+            let commd = commcid::cid_to_data_commitment_v1(&info.unsealed_cid)?;
+            Ok(AggregationInputs {
+                commr,
+                commd,
+                ticket: bytes_32(&info.randomness.0),
+                seed: bytes_32(&info.interactive_randomness.0),
+                sector_id: SectorId::from(info.sector_number),
+            })
+        })
+        .collect::<Result<Vec<_>, &'static str>>()
+        .map_err(|e| anyhow!(e))?;
+
+    let inp: Vec<Vec<_>> = inputs
+        .par_iter()
+        .map(|input| {
+            proofs::seal::get_seal_inputs(
+                spt,
+                input.commr,
+                input.commd,
+                prover_id,
+                input.sector_id,
+                input.ticket,
+                input.seed,
+            )
+        })
+        .try_reduce(Vec::new, |mut acc, current| {
+            acc.extend(current);
+            Ok(acc)
+        })?;
+
+    let commrs: Vec<[u8; 32]> = inputs.iter().map(|input| input.commr).collect();
+    let seeds: Vec<[u8; 32]> = inputs.iter().map(|input| input.seed).collect();
+
+    proofs::seal::verify_aggregate_seal_commit_proofs(
+        spt,
+        aggregate
+            .aggregate_proof
+            .try_into()
+            .map_err(|e| anyhow!("invalid aggregate proof type: {}", e))?,
+        aggregate.proof.clone(),
+        &commrs,
+        &seeds,
+        inp,
+    )
+}
+
+fn verify_replica_update(replica: &ReplicaUpdateInfo) -> anyhow::Result<bool> {
+    let up: proofs::RegisteredUpdateProof = replica
+        .update_proof_type
+        .try_into()
+        .map_err(|e| anyhow!(e))?;
+
+    let commr_old =
+        commcid::cid_to_replica_commitment_v1(&replica.old_sealed_cid).map_err(|e| anyhow!(e))?;
+    let commr_new =
+        commcid::cid_to_replica_commitment_v1(&replica.new_sealed_cid).map_err(|e| anyhow!(e))?;
+    let commd =
+        commcid::cid_to_data_commitment_v1(&replica.new_unsealed_cid).map_err(|e| anyhow!(e))?;
+
+    proofs::update::verify_empty_sector_update_proof(
+        up,
+        &replica.proof,
+        commr_old,
+        commr_new,
+        commd,
+    )
+}
+
+fn verify_window_post(info: &WindowPoStVerifyInfo) -> anyhow::Result<bool> {
+    let proof_type = post_proof_type(&info.proofs)?;
+
+    // Convert sector info into public replica
+    let replicas = to_fil_public_replica_infos(&info.challenged_sectors, proof_type, |seal| {
+        check_valid_window_proof_type(proof_type, seal)
+    })?;
+
+    // Convert PoSt proofs into proofs-api format
+    let proofs: Vec<(proofs::RegisteredPoStProof, _)> = info
+        .proofs
+        .iter()
+        .map(|p| Ok((p.post_proof.try_into()?, p.proof_bytes.as_ref())))
+        .collect::<Result<_, String>>()
+        .map_err(|e| anyhow!(e))?;
+
+    // Generate prover bytes from ID
+    let prover_id = prover_id_from_u64(info.prover);
+
+    // Verify Proof
+    proofs::post::verify_window_post(
+        &post_randomness(&info.randomness),
+        &proofs,
+        &replicas,
+        prover_id,
+    )
+}
+
+fn verify_winning_post(info: &WinningPoStVerifyInfo) -> anyhow::Result<bool> {
+    let proof_type = post_proof_type(&info.proofs)?;
+    if info.proofs.len() != 1 {
+        return Err(anyhow!(
+            "expected exactly one winning PoSt proof, got {}",
+            info.proofs.len()
+        ));
+    }
+
+    // Convert sector info into public replica
+    let replicas = to_fil_public_replica_infos(&info.challenge_sectors, proof_type, |seal| {
+        seal.registered_winning_post_proof().ok() == Some(proof_type)
+    })?;
+
+    // Generate prover bytes from ID
+    let prover_id = prover_id_from_u64(info.prover);
+
+    // Verify Proof
+    proofs::post::verify_winning_post(
+        &post_randomness(&info.randomness),
+        &info.proofs[0].proof_bytes,
+        &replicas,
+        prover_id,
+    )
+}
+
+/// Returns the proof type shared by all the given PoSt proofs.
+fn post_proof_type(proofs: &[PoStProof]) -> anyhow::Result<RegisteredPoStProof> {
+    let proof_type = proofs
+        .first()
+        .context("no PoSt proofs supplied")?
+        .post_proof;
+    for proof in proofs {
+        if proof.post_proof != proof_type {
+            return Err(anyhow!(
+                "all proof types must be the same (found both {:?} and {:?})",
+                proof_type,
+                proof.post_proof
+            ));
+        }
+    }
+    Ok(proof_type)
+}
+
+fn post_randomness(randomness: &Randomness) -> [u8; 32] {
+    let mut randomness = bytes_32(&randomness.0);
+    // Necessary to be valid bls12 381 element.
+    randomness[31] &= 0x3f;
+    randomness
+}
+
+fn prover_id_from_u64(id: u64) -> ProverId {
+    let mut prover_id = ProverId::default();
+    let prover_bytes = Address::new_id(id).payload().to_raw_bytes();
+    prover_id[..prover_bytes.len()].copy_from_slice(&prover_bytes);
+    prover_id
+}
+
+fn to_fil_public_replica_infos(
+    src: &[SectorInfo],
+    typ: RegisteredPoStProof,
+    valid_proof_type: impl Fn(RegisteredSealProof) -> bool,
+) -> anyhow::Result<BTreeMap<SectorId, PublicReplicaInfo>> {
+    src.iter()
+        .map::<Result<(SectorId, PublicReplicaInfo), String>, _>(|sector_info: &SectorInfo| {
+            let commr = commcid::cid_to_replica_commitment_v1(&sector_info.sealed_cid)?;
+            if !valid_proof_type(sector_info.proof) {
+                return Err("invalid proof type".to_string());
+            }
+            let replica = PublicReplicaInfo::new(typ.try_into()?, commr);
+            Ok((SectorId::from(sector_info.sector_number), replica))
+        })
+        .collect::<Result<BTreeMap<SectorId, PublicReplicaInfo>, _>>()
+        .map_err(|e| anyhow!(e))
+}
+
+fn check_valid_window_proof_type(
+    post_type: RegisteredPoStProof,
+    seal_type: RegisteredSealProof,
+) -> bool {
+    let proof_type_v1p1 = seal_type
+        .registered_window_post_proof()
+        .unwrap_or(RegisteredPoStProof::Invalid(-1));
+    let proof_type_v1 = match proof_type_v1p1 {
+        RegisteredPoStProof::StackedDRGWindow2KiBV1P1 => {
+            RegisteredPoStProof::StackedDRGWindow2KiBV1
+        }
+        RegisteredPoStProof::StackedDRGWindow8MiBV1P1 => {
+            RegisteredPoStProof::StackedDRGWindow8MiBV1
+        }
+        RegisteredPoStProof::StackedDRGWindow512MiBV1P1 => {
+            RegisteredPoStProof::StackedDRGWindow512MiBV1
+        }
+        RegisteredPoStProof::StackedDRGWindow32GiBV1P1 => {
+            RegisteredPoStProof::StackedDRGWindow32GiBV1
+        }
+        RegisteredPoStProof::StackedDRGWindow64GiBV1P1 => {
+            RegisteredPoStProof::StackedDRGWindow64GiBV1
+        }
+        _ => {
+            return false;
+        }
+    };
+
+    proof_type_v1 == post_type || proof_type_v1p1 == post_type
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proof(post_proof: RegisteredPoStProof) -> PoStProof {
+        PoStProof {
+            post_proof,
+            proof_bytes: vec![0; 192],
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_aggregates() {
+        let aggregate = AggregateSealVerifyProofAndInfos {
+            miner: 1000,
+            seal_proof: RegisteredSealProof::StackedDRG32GiBV1P1,
+            aggregate_proof: fvm_shared::sector::RegisteredAggregateProof::SnarkPackV1,
+            proof: Vec::new(),
+            infos: Vec::new(),
+        };
+        assert!(verify_aggregate_seals(&aggregate).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_posts() {
+        assert!(verify_window_post(&WindowPoStVerifyInfo::default()).is_err());
+        assert!(verify_winning_post(&WinningPoStVerifyInfo::default()).is_err());
+
+        let mixed = WindowPoStVerifyInfo {
+            proofs: vec![
+                proof(RegisteredPoStProof::StackedDRGWindow32GiBV1P1),
+                proof(RegisteredPoStProof::StackedDRGWindow64GiBV1P1),
+            ],
+            ..Default::default()
+        };
+        assert!(verify_window_post(&mixed).is_err());
+
+        let multiple = WinningPoStVerifyInfo {
+            proofs: vec![
+                proof(RegisteredPoStProof::StackedDRGWinning32GiBV1),
+                proof(RegisteredPoStProof::StackedDRGWinning32GiBV1),
+            ],
+            ..Default::default()
+        };
+        assert!(verify_winning_post(&multiple).is_err());
+    }
+}
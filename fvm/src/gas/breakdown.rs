@@ -0,0 +1,101 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use super::Gas;
+
+/// A breakdown of the gas consumed by a message, by category.
+///
+/// This is only collected when enabled (see
+/// [`MachineContext::enable_gas_breakdown`](crate::machine::MachineContext::enable_gas_breakdown))
+/// and is intended for profiling actors. The categories always sum to the total gas used.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GasBreakdown {
+    /// Gas charged for executing Wasm instructions.
+    pub compute: Gas,
+    /// Gas charged for growing and initializing Wasm memories and tables, and for copying data into
+    /// and out of the actor's memory in syscalls (other than block reads and writes).
+    pub memory: Gas,
+    /// Gas charged for reading state (opening, reading, and statting blocks, looking up actors).
+    pub storage_reads: Gas,
+    /// Gas charged for writing state (creating and linking blocks, creating and updating actors).
+    pub storage_writes: Gas,
+    /// All other gas (syscalls, message inclusion, invocations, etc.), keyed by charge name.
+    pub syscalls: BTreeMap<Cow<'static, str>, Gas>,
+    /// Named milestones recorded by actors (see `fvm_sdk::gas::milestone`), along with the total gas
+    /// used by the message at the time each milestone was recorded, in the order they were
+    /// recorded. Milestones don't consume gas and aren't included in the total.
+    pub milestones: Vec<(Cow<'static, str>, Gas)>,
+}
+
+impl GasBreakdown {
+    /// Records gas consumed by the charge with the given name.
+    pub(crate) fn record(&mut self, name: &str, gas: Gas) {
+        match name {
+            "wasm_exec" => self.compute += gas,
+            "wasm_memory_grow" | "wasm_memory_init" | "wasm_table_init" | "OnMemoryCopyIn"
+            | "OnMemoryCopyOut" => self.memory += gas,
+            "OnBlockOpenBase" | "OnBlockOpenPerByte" | "OnBlockRead" | "OnBlockStat"
+            | "OnActorLookup" => self.storage_reads += gas,
+            "OnBlockCreate" | "OnBlockLink" | "OnActorUpdate" | "OnActorCreate" => {
+                self.storage_writes += gas
+            }
+            _ => match self.syscalls.get_mut(name) {
+                Some(total) => *total += gas,
+                None => {
+                    self.syscalls.insert(Cow::Owned(name.to_owned()), gas);
+                }
+            },
+        }
+    }
+
+    /// Records a named milestone at the given total gas used.
+    pub(crate) fn record_milestone(&mut self, name: &str, gas_used: Gas) {
+        self.milestones
+            .push((Cow::Owned(name.to_owned()), gas_used));
+    }
+
+    /// Returns the total gas charged for reading and writing state: the per-byte charges for
+    /// opening, reading, creating, and linking blocks, and the flat charges for looking up,
+    /// creating, and updating actors. This is the gas actually charged for these operations, not
+    /// the cost of the underlying blockstore accesses (which also depend on caching and on the
+    /// state-tree's structure).
+    pub fn storage(&self) -> Gas {
+        self.storage_reads + self.storage_writes
+    }
+
+    /// Returns the total gas across all categories.
+    pub fn total(&self) -> Gas {
+        self.syscalls
+            .values()
+            .fold(self.compute + self.memory + self.storage(), |a, b| a + *b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn categorizes_charges() {
+        let mut breakdown = GasBreakdown::default();
+        breakdown.record("wasm_exec", Gas::new(1));
+        breakdown.record("wasm_exec", Gas::new(1));
+        breakdown.record("wasm_memory_grow", Gas::new(2));
+        breakdown.record("OnBlockRead", Gas::new(3));
+        breakdown.record("OnBlockLink", Gas::new(4));
+        breakdown.record("OnHashing", Gas::new(5));
+        breakdown.record("OnHashing", Gas::new(5));
+        breakdown.record_milestone("halfway", Gas::new(7));
+
+        assert_eq!(breakdown.compute, Gas::new(2));
+        assert_eq!(breakdown.memory, Gas::new(2));
+        assert_eq!(breakdown.storage_reads, Gas::new(3));
+        assert_eq!(breakdown.storage_writes, Gas::new(4));
+        assert_eq!(breakdown.storage(), Gas::new(7));
+        assert_eq!(breakdown.syscalls.get("OnHashing"), Some(&Gas::new(10)));
+        assert_eq!(breakdown.total(), Gas::new(21));
+        assert_eq!(breakdown.milestones, vec![("halfway".into(), Gas::new(7))]);
+    }
+}
@@ -9,12 +9,20 @@ use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
 use anyhow::Context;
 use num_traits::Zero;
 
+pub use self::breakdown::GasBreakdown;
 pub use self::charge::GasCharge;
-pub(crate) use self::outputs::GasOutputs;
-pub use self::price_list::{price_list_by_network_version, PriceList, WasmGasPrices};
+pub use self::outputs::{DefaultFeePolicy, FeePolicy, GasOutputs};
+#[cfg(test)]
+pub(crate) use self::price_list::unregister_price_list;
+pub(crate) use self::price_list::{has_registered_price_list, FuelRules};
+pub use self::price_list::{
+    price_list_by_network_version, register_price_list, supported_network_versions, PriceList,
+    WasmGasPrices,
+};
 pub use self::timer::{GasInstant, GasTimer};
 use crate::kernel::{ClassifyResult, ExecutionError, Result};
 
+mod breakdown;
 mod charge;
 mod outputs;
 mod price_list;
@@ -169,6 +177,9 @@ pub struct GasTracker {
     gas_used: Cell<Gas>,
     gas_snapshots: Vec<GasSnapshot>,
     trace: Option<RefCell<Vec<GasCharge>>>,
+    breakdown: Option<RefCell<GasBreakdown>>,
+    gas_refunded: Cell<Gas>,
+    refund_snapshots: Vec<Gas>,
 }
 
 impl GasTracker {
@@ -186,27 +197,43 @@ impl GasTracker {
             gas_used: Cell::new(gas_used),
             gas_snapshots: Vec::new(),
             trace: enable_tracing.then_some(Default::default()),
+            breakdown: None,
+            gas_refunded: Cell::new(Gas::zero()),
+            refund_snapshots: Vec::new(),
         }
     }
 
-    fn charge_gas_inner(&self, to_use: Gas) -> Result<()> {
+    /// Start recording a [`GasBreakdown`] of all gas charged from now on.
+    pub fn enable_breakdown(&mut self) {
+        self.breakdown = Some(Default::default());
+    }
+
+    fn charge_gas_inner(&self, name: &str, to_use: Gas) -> Result<()> {
+        let gas_used_before = self.gas_used.get();
         // The gas type uses saturating math.
-        let gas_used = self.gas_used.get() + to_use;
-        if gas_used > self.gas_limit {
+        let gas_used = gas_used_before + to_use;
+        let res = if gas_used > self.gas_limit {
             log::trace!("gas limit reached");
             self.gas_used.set(self.gas_limit);
             Err(ExecutionError::OutOfGas)
         } else {
             self.gas_used.set(gas_used);
             Ok(())
+        };
+        if let Some(breakdown) = &self.breakdown {
+            // Only record the gas actually consumed (we may have run out).
+            breakdown
+                .borrow_mut()
+                .record(name, self.gas_used.get() - gas_used_before);
         }
+        res
     }
 
     /// Safely consumes gas and returns an out of gas error if there is not sufficient
     /// enough gas remaining for charge.
     pub fn charge_gas(&self, name: &str, to_use: Gas) -> Result<GasTimer> {
         log::trace!("charging gas: {} {}", name, to_use);
-        let res = self.charge_gas_inner(to_use);
+        let res = self.charge_gas_inner(name, to_use);
         if let Some(trace) = &self.trace {
             let mut charge = GasCharge::new(name.to_owned(), to_use, Gas::zero());
             let timer = GasTimer::new(&mut charge.elapsed);
@@ -221,7 +248,7 @@ impl GasTracker {
     pub fn apply_charge(&self, mut charge: GasCharge) -> Result<GasTimer> {
         let to_use = charge.total();
         log::trace!("charging gas: {} {}", &charge.name, to_use);
-        let res = self.charge_gas_inner(to_use);
+        let res = self.charge_gas_inner(&charge.name, to_use);
         if let Some(trace) = &self.trace {
             let timer = GasTimer::new(&mut charge.elapsed);
             trace.borrow_mut().push(charge);
@@ -269,6 +296,56 @@ impl GasTracker {
         self.gas_limit - self.gas_used.get()
     }
 
+    /// Accrues a gas refund, to be credited back at the end of the message (see
+    /// [`PriceList::cap_refund`]). Refunds accrued inside a transaction are discarded if the
+    /// transaction is reverted.
+    pub fn refund_gas(&self, name: &str, to_refund: Gas) {
+        if to_refund.is_zero() {
+            return;
+        }
+        log::trace!("refunding gas: {} {}", name, to_refund);
+        self.gas_refunded.set(self.gas_refunded.get() + to_refund);
+    }
+
+    /// Getter for the (uncapped) gas refunds accrued so far.
+    pub fn gas_refunded(&self) -> Gas {
+        self.gas_refunded.get()
+    }
+
+    /// Begin a transaction, recording the refunds accrued so far.
+    pub fn begin_transaction(&mut self) {
+        self.refund_snapshots.push(self.gas_refunded.get());
+    }
+
+    /// End a transaction, discarding the refunds accrued within it if `revert` is true.
+    pub fn end_transaction(&mut self, revert: bool) -> Result<()> {
+        let refunded = self
+            .refund_snapshots
+            .pop()
+            .context("no transaction to end")
+            .or_fatal()?;
+        if revert {
+            self.gas_refunded.set(refunded);
+        }
+        Ok(())
+    }
+
+    /// Records a named milestone at the current gas used, for profiling. Milestones are only
+    /// recorded when gas breakdowns are enabled (see [`GasBreakdown::milestones`]).
+    pub fn record_milestone(&self, name: &str) {
+        if let Some(breakdown) = &self.breakdown {
+            log::trace!("gas milestone: {} {}", name, self.gas_used.get());
+            breakdown
+                .borrow_mut()
+                .record_milestone(name, self.gas_used.get());
+        }
+    }
+
+    /// Takes the recorded [`GasBreakdown`], if enabled.
+    pub fn take_breakdown(&self) -> Option<GasBreakdown> {
+        self.breakdown.as_ref().map(RefCell::take)
+    }
+
     pub fn drain_trace(&self) -> impl Iterator<Item = GasCharge> + '_ {
         self.trace
             .as_ref()
@@ -308,6 +385,57 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn gas_refunds() -> Result<()> {
+        let mut t = GasTracker::new(Gas::new(20), Gas::zero(), false);
+        t.refund_gas("", Gas::new(1));
+
+        t.begin_transaction();
+        t.refund_gas("", Gas::new(2));
+        t.begin_transaction();
+        t.refund_gas("", Gas::new(4));
+        t.end_transaction(true)?;
+        assert_eq!(t.gas_refunded(), Gas::new(3));
+        t.end_transaction(false)?;
+        assert_eq!(t.gas_refunded(), Gas::new(3));
+
+        assert!(t.end_transaction(false).is_err());
+
+        let schedule = price_list::RefundSchedule {
+            delete_actor: Gas::new(10),
+            max_refund_quotient: 5,
+        };
+        assert_eq!(schedule.cap(Gas::new(20), Gas::new(3)), Gas::new(3));
+        assert_eq!(schedule.cap(Gas::new(10), Gas::new(3)), Gas::new(2));
+        assert_eq!(
+            price_list::RefundSchedule::disabled().cap(Gas::new(20), Gas::new(3)),
+            Gas::zero()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn milestones() -> Result<()> {
+        let mut t = GasTracker::new(Gas::new(20), Gas::zero(), false);
+        t.record_milestone("ignored");
+        t.enable_breakdown();
+        let _ = t.charge_gas("", Gas::new(5))?;
+        t.record_milestone("first");
+        let _ = t.charge_gas("", Gas::new(3))?;
+        t.record_milestone("second");
+
+        let breakdown = t.take_breakdown().unwrap();
+        assert_eq!(
+            breakdown.milestones,
+            vec![
+                ("first".into(), Gas::new(5)),
+                ("second".into(), Gas::new(8))
+            ]
+        );
+        assert_eq!(breakdown.total(), Gas::new(8));
+        Ok(())
+    }
+
     #[test]
     fn milligas_to_gas_round() {
         assert_eq!(milligas_to_gas(100, false), 0);
@@ -1,10 +1,53 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+use std::fmt::Debug;
 
 use fvm_shared::econ::TokenAmount;
 
-#[derive(Clone, Default)]
-pub(crate) struct GasOutputs {
+/// Determines how the gas fees paid by explicit messages are split between burning, the miner, and
+/// the sender (refund). Configured through
+/// [`NetworkConfig::fee_policy`](crate::machine::NetworkConfig::fee_policy).
+///
+/// This is consensus-critical. Mainnet (and all networks that want to match it) must use the
+/// [`DefaultFeePolicy`], but testnets and devnets may, e.g., burn nothing or everything.
+pub trait FeePolicy: Debug + Send + Sync {
+    /// Computes the gas outputs of an executed message given its gas usage, gas limit, fee cap,
+    /// and premium, along with the current base fee.
+    ///
+    /// The sender is charged `fee_cap * gas_limit` up-front, so the returned base fee burn,
+    /// over-estimation burn, miner tip, and refund must add up to exactly that amount (the
+    /// executor fails the message with a fatal error otherwise). The miner penalty is charged
+    /// separately.
+    fn gas_outputs(
+        &self,
+        gas_used: u64,
+        gas_limit: u64,
+        base_fee: &TokenAmount,
+        fee_cap: &TokenAmount,
+        gas_premium: &TokenAmount,
+    ) -> GasOutputs {
+        GasOutputs::compute(gas_used, gas_limit, base_fee, fee_cap, gas_premium)
+    }
+
+    /// Computes the penalty charged to the miner for including a message that failed
+    /// pre-validation, given the current base fee and the amount of gas the message is penalized
+    /// for.
+    fn prevalidation_penalty(&self, base_fee: &TokenAmount, gas: u64) -> TokenAmount {
+        base_fee * gas
+    }
+}
+
+/// The mainnet fee policy: the base fee and over-estimation are burnt, the miner receives the
+/// premium (capped by the fee cap), and the miner is penalized for any base fee exceeding the fee
+/// cap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultFeePolicy;
+
+impl FeePolicy for DefaultFeePolicy {}
+
+/// The gas outputs of a message (see [`FeePolicy`]).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GasOutputs {
     pub base_fee_burn: TokenAmount,
     pub over_estimation_burn: TokenAmount,
     pub miner_penalty: TokenAmount,
@@ -17,6 +60,7 @@ pub(crate) struct GasOutputs {
 }
 
 impl GasOutputs {
+    /// Computes the gas outputs according to the mainnet rules. See [`DefaultFeePolicy`].
     pub fn compute(
         // In whole gas units.
         gas_used: u64,
@@ -57,6 +101,13 @@ impl GasOutputs {
 
         out
     }
+
+    /// Returns the total amount the sender paid for gas up-front (`fee_cap * gas_limit`), which the
+    /// base fee burn, over-estimation burn, miner tip, and refund split between them. The miner
+    /// penalty is charged separately, so it isn't included.
+    pub fn total_cost(&self) -> TokenAmount {
+        &self.base_fee_burn + &self.over_estimation_burn + &self.miner_tip + &self.refund
+    }
 }
 
 fn compute_gas_overestimation_burn(gas_used: u64, gas_limit: u64) -> (u64, u64) {
@@ -162,3 +213,20 @@ fn gas_outputs_test() {
     do_test(100, 110, 10, 1, 1_000, 0, 0, 0, 100);
     do_test(100, 110, 6, 1, 600, 0, 400, 0, 60);
 }
+
+#[test]
+fn default_fee_policy_test() {
+    let base_fee = TokenAmount::from_atto(10);
+    let fee_cap = TokenAmount::from_atto(11);
+    let premium = TokenAmount::from_atto(1);
+    let output = DefaultFeePolicy.gas_outputs(100, 130, &base_fee, &fee_cap, &premium);
+    assert_eq!(
+        output,
+        GasOutputs::compute(100, 130, &base_fee, &fee_cap, &premium)
+    );
+    assert_eq!(output.total_cost(), &fee_cap * 130);
+    assert_eq!(
+        DefaultFeePolicy.prevalidation_penalty(&base_fee, 100),
+        TokenAmount::from_atto(1_000)
+    );
+}
@@ -2,16 +2,18 @@
 // Copyright 2019-2022 ChainSafe Systems
 // SPDX-License-Identifier: Apache-2.0, MIT
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Mul;
+use std::sync::RwLock;
 
 use anyhow::Context;
 use fvm_shared::crypto::signature::SignatureType;
 use fvm_shared::event::{ActorEvent, Flags};
 use fvm_shared::piece::PieceInfo;
 use fvm_shared::sector::{
-    AggregateSealVerifyProofAndInfos, RegisteredPoStProof, RegisteredSealProof, ReplicaUpdateInfo,
-    SealVerifyInfo, WindowPoStVerifyInfo,
+    AggregateSealVerifyProofAndInfos, RegisteredPoStProof, RegisteredSealProof,
+    RegisteredUpdateProof, ReplicaUpdateInfo, SealVerifyInfo, WindowPoStVerifyInfo,
+    WinningPoStVerifyInfo,
 };
 use fvm_shared::version::NetworkVersion;
 use fvm_shared::ActorID;
@@ -100,6 +102,13 @@ lazy_static! {
             }
         },
         secp256k1_recover_cost: Gas::new(1637292),
+        // A single BLS signature verification costs two pairings (plus hashing). Aggregate
+        // verification costs one pairing per signer plus one for the aggregate signature.
+        bls_pairing_cost: Gas::new(8299302),
+        bls_hashing_cost: ScalingCost {
+            flat: Gas::zero(),
+            scale: Gas::new(26),
+        },
         hashing_cost: total_enum_map! {
             SupportedHashes {
                 Sha2_256 => ScalingCost {
@@ -178,7 +187,16 @@ lazy_static! {
 
         verify_consensus_fault: Gas::new(516422),
 
-        verify_replica_update: Gas::new(36316136),
+        verify_replica_update_lookup: [
+            (
+                RegisteredUpdateProof::StackedDRG32GiBV1,
+                Gas::new(36316136)
+            ),
+            (
+                RegisteredUpdateProof::StackedDRG64GiBV1,
+                Gas::new(36316136)
+            )
+        ].iter().copied().collect(),
         verify_post_lookup: [
             (
                 RegisteredPoStProof::StackedDRGWindow512MiBV1,
@@ -225,6 +243,34 @@ lazy_static! {
         .iter()
         .copied()
         .collect(),
+        // Winning PoSt is never verified on-chain by the builtin actors, so it's priced the same
+        // as a Window PoSt over the same number of sectors.
+        verify_winning_post_lookup: [
+            (
+                RegisteredPoStProof::StackedDRGWinning512MiBV1,
+                ScalingCost {
+                    flat: Gas::new(117680921),
+                    scale: Gas::new(43780),
+                },
+            ),
+            (
+                RegisteredPoStProof::StackedDRGWinning32GiBV1,
+                ScalingCost {
+                    flat: Gas::new(117680921),
+                    scale: Gas::new(43780),
+                },
+            ),
+            (
+                RegisteredPoStProof::StackedDRGWinning64GiBV1,
+                ScalingCost {
+                    flat: Gas::new(117680921),
+                    scale: Gas::new(43780),
+                },
+            ),
+        ]
+        .iter()
+        .copied()
+        .collect(),
 
         // TODO(#1277): Implement this first before benchmarking.
         // TODO(#1384): Reprice
@@ -240,6 +286,10 @@ lazy_static! {
             scale: Gas::from_milligas(400),
         },
 
+        // Copies across the wasm boundary are only priced from NV21.
+        memory_copy_in: ScalingCost::zero(),
+        memory_copy_out: ScalingCost::zero(),
+
         block_memory_retention_minimum: ScalingCost {
             flat: Gas::zero(),
             scale: Gas::new(10),
@@ -257,6 +307,11 @@ lazy_static! {
             scale: Gas::zero(),
         },
 
+        // Scanning DAG-CBOR blocks for links is only done when reachability checks or link limits
+        // are enabled. This covers decoding and hashing each CID, as benchmarked upstream for
+        // link tracking.
+        block_scan_per_link: Gas::new(950),
+
         block_persist_storage: ScalingCost {
             flat: Gas::new(334000), // ~ Assume about 100 bytes of metadata per block.
             scale: Gas::new(3340),
@@ -275,6 +330,16 @@ lazy_static! {
 
         install_wasm_per_byte_cost: Zero::zero(),
 
+        compile_wasm: ScalingCost {
+            flat: Gas::new(1_000_000),
+            // Cranelift compiles (instrumented) code at roughly 50ns/byte.
+            scale: Gas::new(500),
+        },
+        compile_wasm_per_function: Gas::new(10_000),
+
+        // Return values are only priced from NV21.
+        send_return_per_byte: Zero::zero(),
+
         wasm_rules: WasmGasPrices{
             // Use the default instruction cost of 4 everywhere.
             instruction_default: Gas::new(4),
@@ -291,6 +356,10 @@ lazy_static! {
             // Charge 0.4gas/byte for copying/fill.
             memory_copy_per_byte_cost: Gas::from_milligas(400),
             memory_fill_per_byte_cost: Gas::from_milligas(400),
+
+            // Wasmtime charges one unit of fuel per instruction, so match the default instruction
+            // cost.
+            fuel_exchange_rate: Gas::new(4),
         },
 
         // These parameters are specifically sized for EVM events. They will need
@@ -319,6 +388,27 @@ lazy_static! {
 
         // Preloaded actor IDs per FIP-0055.
         preloaded_actors: vec![0, 1, 2, 3, 4, 5, 6, 7, 10, 99],
+
+        // Gas refunds are not part of the protocol (yet).
+        refunds: RefundSchedule::disabled(),
+    };
+
+    static ref WATERMELON_PRICES: PriceList = PriceList {
+        // Copies across the wasm boundary cost the same as any other memcpy.
+        memory_copy_in: ScalingCost {
+            flat: Gas::zero(),
+            scale: Gas::from_milligas(400),
+        },
+        memory_copy_out: ScalingCost {
+            flat: Gas::zero(),
+            scale: Gas::from_milligas(400),
+        },
+
+        // Return values are copied out of the callee's block registry (and, at the top level, into
+        // the receipt), so they're priced like any other memcpy.
+        send_return_per_byte: Gas::from_milligas(400),
+
+        ..HYGGE_PRICES.clone()
     };
 }
 
@@ -354,6 +444,40 @@ impl ScalingCost {
     }
 }
 
+/// Gas credited back to the message sender at the end of a message for deleting state.
+///
+/// Refunds accrued within a call are discarded if the call reverts, and the total refund is capped
+/// at `1/max_refund_quotient` of the gas used by the message (similar to EVM refunds, see
+/// EIP-3529).
+///
+/// Only actor deletion is refunded: actors can't delete blocks, as unreachable blocks are simply
+/// garbage collected by the client.
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub(crate) struct RefundSchedule {
+    /// Gas refunded for deleting an actor.
+    pub delete_actor: Gas,
+    /// The maximum refund is the gas used divided by this quotient. Zero disables refunds.
+    pub max_refund_quotient: u64,
+}
+
+impl RefundSchedule {
+    /// A schedule that refunds nothing.
+    pub fn disabled() -> Self {
+        Self {
+            delete_actor: Gas::zero(),
+            max_refund_quotient: 0,
+        }
+    }
+
+    /// Caps the accrued refund based on the gas used by the message.
+    pub fn cap(&self, gas_used: Gas, refund: Gas) -> Gas {
+        match gas_used.as_milligas().checked_div(self.max_refund_quotient) {
+            Some(max) => refund.min(Gas::from_milligas(max)),
+            None => Gas::zero(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct StepCost(Vec<Step>);
 
@@ -418,6 +542,11 @@ pub struct PriceList {
     /// Gas cost for recovering secp256k1 signer public key
     pub(crate) secp256k1_recover_cost: Gas,
 
+    /// Gas cost of a single BLS pairing, used to price aggregate signature verification.
+    pub(crate) bls_pairing_cost: Gas,
+    /// Gas cost of hashing plaintexts to the curve when verifying BLS aggregate signatures.
+    pub(crate) bls_hashing_cost: ScalingCost,
+
     pub(crate) hashing_cost: HashMap<SupportedHashes, ScalingCost>,
 
     /// Gas cost for looking up the last tipset CID.
@@ -431,8 +560,9 @@ pub struct PriceList {
     pub(crate) verify_aggregate_seal_steps: HashMap<RegisteredSealProof, StepCost>,
 
     pub(crate) verify_post_lookup: HashMap<RegisteredPoStProof, ScalingCost>,
+    pub(crate) verify_winning_post_lookup: HashMap<RegisteredPoStProof, ScalingCost>,
     pub(crate) verify_consensus_fault: Gas,
-    pub(crate) verify_replica_update: Gas,
+    pub(crate) verify_replica_update_lookup: HashMap<RegisteredUpdateProof, Gas>,
 
     /// Gas cost for fetching a randomness seed for an epoch. We charge separately for extracting
     /// randomness (hashing).
@@ -441,6 +571,14 @@ pub struct PriceList {
     /// Gas cost per byte copied.
     pub(crate) block_memcpy: ScalingCost,
 
+    /// Gas cost per byte of syscall inputs copied out of an actor's memory into the host. Blocks
+    /// and events are priced by `block_memcpy` instead.
+    pub(crate) memory_copy_in: ScalingCost,
+
+    /// Gas cost per byte of syscall outputs copied from the host into an actor's memory. Block
+    /// reads are priced by `block_memcpy` instead.
+    pub(crate) memory_copy_out: ScalingCost,
+
     /// Gas cost per byte allocated (computation cost).
     pub(crate) block_allocate: ScalingCost,
 
@@ -454,6 +592,9 @@ pub struct PriceList {
     /// Gas cost for opening a block.
     pub(crate) block_open: ScalingCost,
 
+    /// Gas cost per link found when scanning a DAG-CBOR block for links.
+    pub(crate) block_scan_per_link: Gas,
+
     /// Gas cost for persisting a block over time.
     pub(crate) block_persist_storage: ScalingCost,
 
@@ -484,8 +625,22 @@ pub struct PriceList {
     /// Gas cost of compiling a Wasm module during install.
     pub(crate) install_wasm_per_byte_cost: Gas,
 
+    /// Gas cost of compiling a user-deployed Wasm module the first time it's loaded in a message,
+    /// scaling per byte of Wasm.
+    pub(crate) compile_wasm: ScalingCost,
+    /// Additional gas cost of compiling a user-deployed Wasm module, per function defined by the
+    /// module.
+    pub(crate) compile_wasm_per_function: Gas,
+
+    /// Gas cost per byte of a value returned from one actor to another (or to the top-level
+    /// message).
+    pub(crate) send_return_per_byte: Gas,
+
     /// Actor IDs that can be updated for free.
     pub(crate) preloaded_actors: Vec<ActorID>,
+
+    /// Gas refunds for deleting state.
+    pub(crate) refunds: RefundSchedule,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -511,6 +666,10 @@ pub struct WasmGasPrices {
     pub(crate) memory_access_cost: Gas,
     /// Gas cost for every byte copied in Wasm memory.
     pub(crate) memory_copy_per_byte_cost: Gas,
+
+    /// Gas cost per unit of wasmtime fuel, when metering execution with fuel instead of
+    /// instrumentation (see [`crate::machine::NetworkConfig::fuel_metering`]).
+    pub(crate) fuel_exchange_rate: Gas,
 }
 
 impl PriceList {
@@ -547,6 +706,16 @@ impl PriceList {
         GasCharge::new("OnMethodInvocation", self.send_invoke_method, Zero::zero())
     }
 
+    /// Returns the gas required for returning a value of the given size from a call.
+    #[inline]
+    pub fn on_send_return(&self, data_size: usize) -> GasCharge {
+        GasCharge::new(
+            "OnSendReturn",
+            self.send_return_per_byte * data_size,
+            Zero::zero(),
+        )
+    }
+
     /// Returns the gas cost to be applied on a syscall.
     pub fn on_syscall(&self) -> GasCharge {
         GasCharge::new("OnSyscall", self.syscall_cost, Zero::zero())
@@ -569,6 +738,19 @@ impl PriceList {
         GasCharge::new("OnDeleteActor", Zero::zero(), Zero::zero())
     }
 
+    /// Returns the gas refunded (at the end of the message) for deleting an actor.
+    #[inline]
+    pub fn on_delete_actor_refund(&self) -> Gas {
+        self.refunds.delete_actor
+    }
+
+    /// Returns the gas actually refunded to a message that used `gas_used` gas and accrued
+    /// `refund` gas in refunds.
+    #[inline]
+    pub fn cap_refund(&self, gas_used: Gas, refund: Gas) -> Gas {
+        self.refunds.cap(gas_used, refund)
+    }
+
     /// Returns gas required for signature verification.
     #[inline]
     pub fn on_verify_signature(&self, sig_type: SignatureType, data_len: usize) -> GasCharge {
@@ -577,6 +759,14 @@ impl PriceList {
         GasCharge::new("OnVerifySignature", gas, Zero::zero())
     }
 
+    /// Returns gas required for verifying a BLS aggregate signature over `num_sigs` plaintexts
+    /// totalling `data_len` bytes.
+    #[inline]
+    pub fn on_verify_aggregate_signature(&self, num_sigs: usize, data_len: usize) -> GasCharge {
+        let gas = self.bls_pairing_cost * (num_sigs + 1) + self.bls_hashing_cost.apply(data_len);
+        GasCharge::new("OnVerifyAggregateSignature", gas, Zero::zero())
+    }
+
     /// Returns gas required for recovering signer pubkey from signature
     #[inline]
     pub fn on_recover_secp_public_key(&self) -> GasCharge {
@@ -614,6 +804,7 @@ impl PriceList {
     pub fn on_verify_seal(&self, _info: &SealVerifyInfo) -> GasCharge {
         GasCharge::new("OnVerifySeal", self.verify_seal_base, Zero::zero())
     }
+
     #[inline]
     pub fn on_verify_aggregate_seals(
         &self,
@@ -652,12 +843,16 @@ impl PriceList {
 
     /// Returns gas required for replica verification.
     #[inline]
-    pub fn on_verify_replica_update(&self, _replica: &ReplicaUpdateInfo) -> GasCharge {
-        GasCharge::new(
-            "OnVerifyReplicaUpdate",
-            self.verify_replica_update,
-            Zero::zero(),
-        )
+    pub fn on_verify_replica_update(&self, replica: &ReplicaUpdateInfo) -> GasCharge {
+        let cost = *self
+            .verify_replica_update_lookup
+            .get(&replica.update_proof_type)
+            .unwrap_or_else(|| {
+                self.verify_replica_update_lookup
+                    .get(&RegisteredUpdateProof::StackedDRG32GiBV1)
+                    .expect("32GiB lookup must exist in price table")
+            });
+        GasCharge::new("OnVerifyReplicaUpdate", cost, Zero::zero())
     }
 
     /// Returns gas required for PoSt verification.
@@ -679,6 +874,28 @@ impl PriceList {
         GasCharge::new("OnVerifyPost", gas_used, Zero::zero())
     }
 
+    /// Returns gas required for Winning PoSt verification.
+    #[inline]
+    pub fn on_verify_winning_post(&self, info: &WinningPoStVerifyInfo) -> GasCharge {
+        let p_proof = info
+            .proofs
+            .first()
+            .map(|p| p.post_proof)
+            .unwrap_or(RegisteredPoStProof::StackedDRGWinning512MiBV1);
+        let cost = self
+            .verify_winning_post_lookup
+            .get(&p_proof)
+            .unwrap_or_else(|| {
+                self.verify_winning_post_lookup
+                    .get(&RegisteredPoStProof::StackedDRGWinning512MiBV1)
+                    .expect("512MiB lookup must exist in price table")
+            });
+
+        let gas_used = cost.apply(info.challenge_sectors.len());
+
+        GasCharge::new("OnVerifyWinningPost", gas_used, Zero::zero())
+    }
+
     /// Returns gas required for verifying consensus fault.
     #[inline]
     pub fn on_verify_consensus_fault(
@@ -749,6 +966,29 @@ impl PriceList {
         )
     }
 
+    /// Returns the gas required for copying syscall inputs out of an actor's memory. Block and
+    /// event inputs are charged by [`PriceList::on_block_create`] and
+    /// [`PriceList::on_actor_event_validate`] instead.
+    #[inline]
+    pub fn on_memory_copy_in(&self, size: usize) -> GasCharge {
+        GasCharge::new(
+            "OnMemoryCopyIn",
+            self.memory_copy_in.apply(size),
+            Zero::zero(),
+        )
+    }
+
+    /// Returns the gas required for copying syscall outputs into an actor's memory. Block reads are
+    /// charged by [`PriceList::on_block_read`] instead.
+    #[inline]
+    pub fn on_memory_copy_out(&self, size: usize) -> GasCharge {
+        GasCharge::new(
+            "OnMemoryCopyOut",
+            self.memory_copy_out.apply(size),
+            Zero::zero(),
+        )
+    }
+
     /// Returns the gas required for adding an object to the FVM cache.
     #[inline]
     pub fn on_block_create(&self, data_size: usize) -> GasCharge {
@@ -762,6 +1002,16 @@ impl PriceList {
         GasCharge::new("OnBlockCreate", compute, retention_surcharge)
     }
 
+    /// Returns the gas required for scanning a DAG-CBOR block with the given number of links.
+    #[inline]
+    pub fn on_block_scan_links(&self, links: usize) -> GasCharge {
+        GasCharge::new(
+            "OnBlockScanLinks",
+            self.block_scan_per_link * links,
+            Zero::zero(),
+        )
+    }
+
     /// Returns the gas required for committing an object to the state blockstore.
     #[inline]
     pub fn on_block_link(&self, hash_code: SupportedHashes, data_size: usize) -> GasCharge {
@@ -900,6 +1150,17 @@ impl PriceList {
         )
     }
 
+    /// Returns the gas required for compiling a user-deployed actor's Wasm module, given its size
+    /// and the number of functions it defines.
+    #[cfg(feature = "m2-native")]
+    pub fn on_compile_actor(&self, wasm_size: usize, functions: u32) -> GasCharge {
+        GasCharge::new(
+            "OnCompileActor",
+            self.compile_wasm.apply(wasm_size) + self.compile_wasm_per_function * functions,
+            Zero::zero(),
+        )
+    }
+
     /// Returns the gas required for initializing memory.
     pub fn init_memory_gas(&self, min_memory_bytes: usize) -> Gas {
         self.wasm_rules.memory_fill_base_cost
@@ -976,14 +1237,100 @@ impl PriceList {
     }
 }
 
+lazy_static! {
+    /// Price schedules registered (at runtime) for network versions without a builtin schedule.
+    static ref REGISTERED_PRICES: RwLock<BTreeMap<NetworkVersion, &'static PriceList>> =
+        Default::default();
+}
+
+/// Returns the builtin price schedule for the given network version, if any.
+fn builtin_price_list(network_version: NetworkVersion) -> Option<&'static PriceList> {
+    match network_version {
+        NetworkVersion::V18 | NetworkVersion::V19 | NetworkVersion::V20 => Some(&HYGGE_PRICES),
+        NetworkVersion::V21 => Some(&WATERMELON_PRICES),
+        _ => None,
+    }
+}
+
+/// Registers the price schedule for a network version without a builtin schedule (e.g., to price
+/// a future network upgrade). Fails if the network version already has a schedule: schedules are
+/// consensus-critical and can't be replaced once selected.
+pub fn register_price_list(
+    network_version: NetworkVersion,
+    prices: &'static PriceList,
+) -> anyhow::Result<()> {
+    if builtin_price_list(network_version).is_some() {
+        return Err(anyhow::anyhow!(
+            "network version {} has a builtin price list",
+            network_version
+        ));
+    }
+    let mut registered = REGISTERED_PRICES
+        .write()
+        .map_err(|_| anyhow::anyhow!("price list registry poisoned"))?;
+    if registered.contains_key(&network_version) {
+        return Err(anyhow::anyhow!(
+            "network version {} already has a registered price list",
+            network_version
+        ));
+    }
+    registered.insert(network_version, prices);
+    Ok(())
+}
+
+/// Returns true if a price schedule has been registered for the network version (see
+/// [`register_price_list`]).
+pub(crate) fn has_registered_price_list(network_version: NetworkVersion) -> bool {
+    REGISTERED_PRICES
+        .read()
+        .map(|r| r.contains_key(&network_version))
+        .unwrap_or(false)
+}
+
+/// Removes a registered price schedule, so tests registering one don't leak it into other tests.
+#[cfg(test)]
+pub(crate) fn unregister_price_list(network_version: NetworkVersion) {
+    if let Ok(mut registered) = REGISTERED_PRICES.write() {
+        registered.remove(&network_version);
+    }
+}
+
+/// Returns the network versions with a price schedule, builtin or registered (see
+/// [`register_price_list`]), in ascending order.
+pub fn supported_network_versions() -> Vec<NetworkVersion> {
+    let mut versions = vec![
+        NetworkVersion::V18,
+        NetworkVersion::V19,
+        NetworkVersion::V20,
+        NetworkVersion::V21,
+    ];
+    if let Ok(registered) = REGISTERED_PRICES.read() {
+        versions.extend(registered.keys().copied());
+    }
+    versions.sort();
+    versions
+}
+
 /// Returns gas price list by NetworkVersion for gas consumption.
+///
+/// Panics if the network version has neither a builtin nor a registered (see
+/// [`register_price_list`]) price schedule.
 pub fn price_list_by_network_version(network_version: NetworkVersion) -> &'static PriceList {
-    match network_version {
-        NetworkVersion::V18 | NetworkVersion::V19 | NetworkVersion::V20 => &HYGGE_PRICES,
-        #[cfg(feature = "hyperspace")]
-        _ if network_version > NetworkVersion::V18 => &HYGGE_PRICES,
-        _ => panic!("network version {nv} not supported", nv = network_version),
+    if let Some(prices) = builtin_price_list(network_version) {
+        return prices;
     }
+    if let Some(prices) = REGISTERED_PRICES
+        .read()
+        .ok()
+        .and_then(|r| r.get(&network_version).copied())
+    {
+        return prices;
+    }
+    #[cfg(feature = "hyperspace")]
+    if network_version > NetworkVersion::V18 {
+        return &HYGGE_PRICES;
+    }
+    panic!("network version {nv} not supported", nv = network_version)
 }
 
 impl Rules for WasmGasPrices {
@@ -1291,6 +1638,32 @@ impl Rules for WasmGasPrices {
     }
 }
 
+/// Instrumentation rules used when metering execution with wasmtime fuel.
+///
+/// Wasmtime charges a single unit of fuel per instruction, regardless of how many bytes a bulk
+/// memory or table operation touches. These rules only charge the per-byte (or per-element) part of
+/// such operations, leaving everything else to fuel. Memory growth is charged separately, based on
+/// the memory actually used (see `charge_for_exec`).
+pub(crate) struct FuelRules<'a>(pub &'a WasmGasPrices);
+
+impl Rules for FuelRules<'_> {
+    fn instruction_cost(&self, instruction: &Operator) -> anyhow::Result<InstructionCost> {
+        Ok(match (instruction, self.0.instruction_cost(instruction)?) {
+            (Operator::MemoryGrow { .. }, _) => InstructionCost::Fixed(0),
+            (_, InstructionCost::Linear(_, per_unit)) => InstructionCost::Linear(0, per_unit),
+            (_, InstructionCost::Fixed(_)) => InstructionCost::Fixed(0),
+        })
+    }
+
+    fn gas_charge_cost(&self) -> u64 {
+        0
+    }
+
+    fn linear_calc_cost(&self) -> u64 {
+        0
+    }
+}
+
 #[test]
 fn test_read_write() {
     // The math for these operations is complicated, so we explicitly test to make sure we're
@@ -1302,6 +1675,58 @@ fn test_read_write() {
     assert_eq!(HYGGE_PRICES.on_block_create(10).total(), Gas::new(100));
 }
 
+#[test]
+fn test_memory_copies() {
+    // Copies across the wasm boundary are free before NV21...
+    assert!(HYGGE_PRICES.on_memory_copy_in(10).total().is_zero());
+    assert!(HYGGE_PRICES.on_memory_copy_out(10).total().is_zero());
+
+    // ...and cost the same as any other memcpy after, whichever way they go.
+    assert_eq!(WATERMELON_PRICES.on_memory_copy_in(10).total(), Gas::new(4));
+    assert_eq!(
+        WATERMELON_PRICES.on_memory_copy_out(10).total(),
+        Gas::new(4)
+    );
+    assert_eq!(
+        WATERMELON_PRICES.on_block_read(10).total(),
+        HYGGE_PRICES.on_block_read(10).total()
+    );
+}
+
+#[test]
+fn test_send_return() {
+    // Return values are free before NV21, and priced like a memcpy after.
+    assert!(HYGGE_PRICES.on_send_return(1000).total().is_zero());
+    assert_eq!(
+        WATERMELON_PRICES.on_send_return(1000).total(),
+        Gas::new(400)
+    );
+}
+
+#[test]
+fn test_fuel_rules() {
+    let rules = FuelRules(&HYGGE_PRICES.wasm_rules);
+    // Fuel pays for the instructions themselves...
+    assert!(matches!(
+        rules.instruction_cost(&Operator::I32Add).unwrap(),
+        InstructionCost::Fixed(0)
+    ));
+    // ...but not for the bytes touched by bulk memory operations.
+    match rules
+        .instruction_cost(&Operator::MemoryFill { mem: 0 })
+        .unwrap()
+    {
+        InstructionCost::Linear(0, per_byte) => assert_eq!(
+            u64::from(per_byte.get()),
+            HYGGE_PRICES
+                .wasm_rules
+                .memory_fill_per_byte_cost
+                .as_milligas()
+        ),
+        _ => panic!("expected a per-byte charge for memory.fill"),
+    }
+}
+
 #[test]
 fn test_step_cost() {
     let costs = StepCost(vec![
@@ -1341,3 +1766,78 @@ fn test_step_cost_zero() {
     assert_eq!(costs.lookup(0), Gas::new(1));
     assert_eq!(costs.lookup(10), Gas::new(1));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_version_prices_every_syscall() {
+        let hashes = [
+            SupportedHashes::Sha2_256,
+            SupportedHashes::Blake2b256,
+            SupportedHashes::Blake2b512,
+            SupportedHashes::Keccak256,
+            SupportedHashes::Ripemd160,
+        ];
+        for nv in supported_network_versions() {
+            let pl = price_list_by_network_version(nv);
+            for sig_type in [SignatureType::Secp256k1, SignatureType::BLS] {
+                assert!(pl.sig_cost.contains_key(&sig_type), "nv{nv}: {sig_type:?}");
+                pl.on_verify_signature(sig_type, 100);
+            }
+            for hash in hashes {
+                assert!(pl.hashing_cost.contains_key(&hash), "nv{nv}: {hash:?}");
+                pl.on_hashing(hash, 100);
+                pl.on_block_link(hash, 100);
+            }
+            assert!(pl
+                .verify_aggregate_seal_steps
+                .contains_key(&RegisteredSealProof::StackedDRG32GiBV1P1));
+            assert!(pl
+                .verify_post_lookup
+                .contains_key(&RegisteredPoStProof::StackedDRGWindow32GiBV1));
+            assert!(pl
+                .verify_winning_post_lookup
+                .contains_key(&RegisteredPoStProof::StackedDRGWinning32GiBV1));
+            assert!(!pl.verify_replica_update_lookup.is_empty());
+
+            pl.on_chain_message(100);
+            pl.on_chain_return_value(100);
+            pl.on_recover_secp_public_key();
+            pl.on_verify_aggregate_signature(2, 100);
+            pl.on_get_randomness(32);
+            pl.on_block_open_per_byte(100);
+            pl.on_block_read(100);
+            pl.on_block_create(100);
+            pl.on_memory_copy_in(100);
+            pl.on_memory_copy_out(100);
+            pl.on_tipset_cid(true);
+            #[cfg(feature = "m2-native")]
+            {
+                pl.on_install_actor(100);
+                pl.on_compile_actor(100, 10);
+            }
+            pl.on_actor_event_validate(100);
+        }
+    }
+
+    #[test]
+    fn register_price_list() {
+        // No other test may use this network version.
+        let nv = NetworkVersion::new(1000);
+        assert!(super::register_price_list(NetworkVersion::V18, &HYGGE_PRICES).is_err());
+        super::register_price_list(nv, &HYGGE_PRICES).unwrap();
+        let result = std::panic::catch_unwind(|| {
+            assert!(super::register_price_list(nv, &HYGGE_PRICES).is_err());
+            assert!(has_registered_price_list(nv));
+            assert!(supported_network_versions().contains(&nv));
+            assert_eq!(price_list_by_network_version(nv), &*HYGGE_PRICES);
+        });
+        unregister_price_list(nv);
+        assert!(!has_registered_price_list(nv));
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
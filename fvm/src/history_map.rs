@@ -95,6 +95,17 @@ where
         }
     }
 
+    /// Remove all entries from the map, recording them in the history.
+    pub fn clear(&mut self) {
+        self.history
+            .extend(self.map.drain().map(|(k, v)| (k, Some(v))));
+    }
+
+    /// Returns true if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
     /// Returns the current history length.
     pub fn history_len(&self) -> usize {
         self.history.len()
@@ -105,6 +116,11 @@ where
         self.history.clear();
     }
 
+    /// Iterate over the current map.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.map.iter()
+    }
+
     /// Iterate mutably over the current map.
     pub fn iter_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
         self.map.iter_mut()
@@ -183,5 +199,16 @@ mod test {
         map.rollback(0); // empties the map
         assert_eq!(map.history_len(), 0);
         assert_eq!(map.get(&1), None);
+
+        // Clearing can be undone.
+        map.insert(1, "foo");
+        map.insert(2, "bar");
+        map.clear();
+        assert_eq!(map.history_len(), 4);
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.get(&2), None);
+        map.rollback(2);
+        assert_eq!(map.get(&1), Some(&"foo"));
+        assert_eq!(map.get(&2), Some(&"bar"));
     }
 }
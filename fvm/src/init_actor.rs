@@ -75,8 +75,10 @@ impl State {
             .context("init actor address could not be resolved")
             .or_fatal()?;
 
+        // The init actor's state may have been written in the current message, so it may still be
+        // buffered.
         let state = state_tree
-            .store()
+            .buffered_store()
             .get_cbor(&init_act.state)
             .or_fatal()?
             .context("init actor state not found")
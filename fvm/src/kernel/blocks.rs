@@ -74,6 +74,11 @@ impl Block {
             size: self.size(),
         }
     }
+
+    /// Returns the block's data, avoiding a copy if this is the only handle to the block.
+    pub fn into_data(self) -> Box<[u8]> {
+        Rc::try_unwrap(self.data).unwrap_or_else(|data| (*data).clone())
+    }
 }
 
 impl From<IpldBlock> for Block {
@@ -184,6 +189,11 @@ impl BlockRegistry {
             })
     }
 
+    /// Iterates over the blocks in the registry, in handle order.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.iter()
+    }
+
     pub fn is_full(&self) -> bool {
         self.blocks.len() as u32 == MAX_BLOCKS
     }
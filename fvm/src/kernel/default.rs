@@ -1,15 +1,15 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
-use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
-use std::panic::{self, UnwindSafe};
+use std::panic::{self, AssertUnwindSafe, UnwindSafe};
 use std::path::PathBuf;
 
 use anyhow::{anyhow, Context as _};
 use cid::Cid;
-use filecoin_proofs_api::{self as proofs, ProverId, PublicReplicaInfo, SectorId};
-use fvm_ipld_blockstore::Blockstore;
-use fvm_ipld_encoding::{bytes_32, IPLD_RAW};
+use filecoin_proofs_api as proofs;
+use fvm_ipld_blockstore::{scan_dag_cbor_links, Blockstore};
+use fvm_ipld_encoding::{DAG_CBOR, IPLD_RAW};
 use fvm_shared::address::Payload;
 use fvm_shared::bigint::Zero;
 use fvm_shared::chainid::ChainID;
@@ -20,12 +20,12 @@ use fvm_shared::error::ErrorNumber;
 use fvm_shared::event::ActorEvent;
 use fvm_shared::piece::{zero_piece_commitment, PaddedPieceSize};
 use fvm_shared::sector::RegisteredPoStProof::{StackedDRGWindow32GiBV1, StackedDRGWindow32GiBV1P1};
-use fvm_shared::sector::{RegisteredPoStProof, SectorInfo};
 use fvm_shared::sys::out::vm::ContextFlags;
+use fvm_shared::version::NetworkVersion;
 use fvm_shared::{commcid, ActorID};
 use lazy_static::lazy_static;
 use multihash::MultihashDigest;
-use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
 use rayon::prelude::ParallelDrainRange;
 
 use super::blocks::{Block, BlockRegistry};
@@ -33,7 +33,7 @@ use super::error::Result;
 use super::hash::SupportedHashes;
 use super::*;
 use crate::call_manager::{CallManager, InvocationResult, NO_DATA_BLOCK_ID};
-use crate::externs::{Chain, Consensus, Rand};
+use crate::externs::{Chain, Consensus, Rand, Verifier};
 use crate::gas::GasTimer;
 use crate::init_actor::INIT_ACTOR_ID;
 use crate::machine::{MachineContext, NetworkConfig};
@@ -68,9 +68,13 @@ pub struct DefaultKernel<C> {
     call_manager: C,
     /// Tracks block data and organizes it through index handles so it can be
     /// referred to.
-    ///
-    /// This does not yet reason about reachability.
     blocks: BlockRegistry,
+    /// The blocks (other than the actor's state root) the actor may open, link to, or set as its
+    /// state root, if reachability checks are enabled (see
+    /// [`NetworkConfig::reachability_checks`]). This contains the blocks linked from the
+    /// parameters, from the return values of sends, and from opened blocks, along with all blocks
+    /// linked by the actor.
+    reachable: HashSet<Cid>,
 }
 
 // Even though all children traits are implemented, Rust needs to know that the
@@ -97,7 +101,7 @@ where
         value_received: TokenAmount,
         read_only: bool,
     ) -> Self {
-        DefaultKernel {
+        let mut kernel = DefaultKernel {
             call_manager: mgr,
             blocks,
             caller,
@@ -105,7 +109,18 @@ where
             method,
             value_received,
             read_only,
+            reachable: HashSet::new(),
+        };
+        if kernel.reachability_checks() {
+            // The registry only contains the parameters at this point. They were validated (and
+            // their links paid for) when they were created (top-level message parameters are never
+            // DAG-CBOR), so this can't fail.
+            let params: Vec<_> = kernel.blocks.iter().cloned().collect();
+            for block in &params {
+                let _ = kernel.mark_links_reachable(block);
+            }
         }
+        kernel
     }
 
     fn machine(&self) -> &<Self::CallManager as CallManager>::Machine {
@@ -121,6 +136,172 @@ where
     fn get_self(&self) -> Result<Option<ActorState>> {
         self.call_manager.get_actor(self.actor_id)
     }
+
+    fn reachability_checks(&self) -> bool {
+        self.call_manager.context().reachability_checks
+    }
+
+    /// Adds the blocks linked from the given block to the reachable set, if reachability checks
+    /// are enabled, returning the number of links scanned. Fails if the block claims to be
+    /// DAG-CBOR but isn't.
+    fn mark_links_reachable(&mut self, block: &Block) -> anyhow::Result<usize> {
+        if !self.reachability_checks() || block.codec() != DAG_CBOR {
+            return Ok(0);
+        }
+        let reachable = &mut self.reachable;
+        let mut links = 0;
+        scan_dag_cbor_links(block.data(), |link| {
+            reachable.insert(link);
+            links += 1;
+            Ok(())
+        })?;
+        Ok(links)
+    }
+
+    /// Fails with `NotFound` if reachability checks are enabled and the given block isn't
+    /// reachable. Inline (identity) CIDs and piece commitments are always reachable.
+    fn check_reachable(&self, cid: &Cid) -> Result<()> {
+        if !self.reachability_checks()
+            || self.reachable.contains(cid)
+            || cid.hash().code() == fvm_shared::IDENTITY_HASH
+            || matches!(
+                cid.codec(),
+                commcid::FIL_COMMITMENT_SEALED | commcid::FIL_COMMITMENT_UNSEALED
+            )
+        {
+            return Ok(());
+        }
+        if self.get_self()?.map_or(false, |state| state.state == *cid) {
+            return Ok(());
+        }
+        Err(syscall_error!(NotFound; "block {} isn't reachable", cid).into())
+    }
+
+    /// Validates Wasm for deployment (unless it's a builtin actor) and compiles it into the engine
+    /// under the given code CID.
+    #[cfg(feature = "m2-native")]
+    fn install_wasm(&mut self, code_id: &Cid, wasm: &[u8]) -> Result<()> {
+        let t = self
+            .call_manager
+            .charge_gas(self.call_manager.price_list().on_install_actor(wasm.len()))?;
+        let builtin = self
+            .call_manager
+            .machine()
+            .builtin_actors()
+            .id_by_code(code_id)
+            != 0;
+        if !builtin {
+            let invalid = |e: crate::engine::WasmValidationError| {
+                if e.is_limit_exceeded() {
+                    syscall_error!(LimitExceeded; "actor code exceeds limits: {}", e)
+                } else {
+                    syscall_error!(IllegalArgument; "actor code is invalid: {}", e)
+                }
+            };
+            // Charge for compilation before doing any real work, as when loading user code.
+            let network = &self.call_manager.context().network;
+            let stats = network.wasm_limits.check(wasm).map_err(invalid)?;
+            let _ = self.call_manager.charge_gas(
+                self.call_manager
+                    .price_list()
+                    .on_compile_actor(stats.size, stats.functions),
+            )?;
+            crate::engine::validate_wasm_for_deployment(wasm, network).map_err(invalid)?;
+        }
+        self.call_manager
+            .engine()
+            .prepare_wasm_bytecode(code_id, wasm)
+            .context("failed to install actor")
+            .or_illegal_argument()?;
+        if !builtin {
+            self.call_manager.record_code_compiled(*code_id);
+        }
+        t.stop();
+        Ok(())
+    }
+
+    /// Stores the return value of a send (if any) in the block registry.
+    fn put_invocation_result(&mut self, result: InvocationResult) -> Result<SendResult> {
+        Ok(match result {
+            InvocationResult {
+                exit_code,
+                value: Some(blk),
+            } => {
+                // Return values were validated (and their links paid for) when they were created.
+                self.mark_links_reachable(&blk).or_fatal()?;
+                let block_stat = blk.stat();
+                let block_id = self
+                    .blocks
+                    .put(blk)
+                    .or_fatal()
+                    .context("failed to store a valid return value")?;
+                SendResult {
+                    block_id,
+                    block_stat,
+                    exit_code,
+                }
+            }
+            InvocationResult {
+                exit_code,
+                value: None,
+            } => SendResult {
+                block_id: NO_DATA_BLOCK_ID,
+                block_stat: BlockStat { codec: 0, size: 0 },
+                exit_code,
+            },
+        })
+    }
+
+    /// Resolves a signer to its key (f1/f3) address. Key addresses are returned as-is, and from
+    /// NV21 any other address must resolve to an account actor, whose key address is read from its
+    /// state. The actor lookup and state load are charged as usual.
+    fn resolve_key_address(&self, signer: &Address) -> Result<Address> {
+        if matches!(signer.payload(), Payload::BLS(_) | Payload::Secp256k1(_)) {
+            return Ok(*signer);
+        }
+
+        // Earlier network versions only support key addresses.
+        if self.call_manager.context().network.network_version < NetworkVersion::V21 {
+            return Err(syscall_error!(IllegalArgument; "address protocol {} not supported", signer.protocol()).into());
+        }
+
+        let id = self
+            .call_manager
+            .resolve_address(signer)?
+            .ok_or_else(|| syscall_error!(NotFound; "signer {} not found", signer))?;
+        let actor = self
+            .call_manager
+            .get_actor(id)?
+            .ok_or_else(|| syscall_error!(NotFound; "signer {} not found", signer))?;
+        if !self
+            .call_manager
+            .machine()
+            .builtin_actors()
+            .is_account_actor(&actor.code)
+        {
+            return Err(
+                syscall_error!(IllegalArgument; "signer {} is not an account actor", signer).into(),
+            );
+        }
+
+        let _ = self
+            .call_manager
+            .charge_gas(self.call_manager.price_list().on_block_open_base())?;
+        let state = self
+            .call_manager
+            .get_block(&actor.state)?
+            .ok_or_else(|| anyhow!("missing account state: {}", actor.state))
+            .or_fatal()?;
+        let _ = self.call_manager.charge_gas(
+            self.call_manager
+                .price_list()
+                .on_block_open_per_byte(state.len()),
+        )?;
+        let state: crate::account_actor::State = fvm_ipld_encoding::from_slice(&state)
+            .context("failed to decode account actor state")
+            .or_fatal()?;
+        Ok(state.address)
+    }
 }
 
 impl<C> SelfOps for DefaultKernel<C>
@@ -138,12 +319,27 @@ where
         Ok(cid)
     }
 
+    fn self_code_cid(&self) -> Result<Cid> {
+        let t = self
+            .call_manager
+            .charge_gas(self.call_manager.price_list().on_get_actor_code_cid())?;
+
+        // Like the state root, this can fail during normal operations if the actor has been
+        // deleted.
+        t.record(Ok(self
+            .get_self()?
+            .context("code CID requested after actor deletion")
+            .or_error(ErrorNumber::IllegalOperation)?
+            .code))
+    }
+
     fn set_root(&mut self, new: Cid) -> Result<()> {
         if self.read_only {
             return Err(
                 syscall_error!(ReadOnly; "cannot update the state-root while read-only").into(),
             );
         }
+        self.check_reachable(&new)?;
         let mut state = self
             .call_manager
             .get_actor(self.actor_id)?
@@ -200,7 +396,7 @@ where
     C: CallManager,
 {
     fn block_open(&mut self, cid: &Cid) -> Result<(BlockId, BlockStat)> {
-        // TODO(M2): Check for reachability here.
+        self.check_reachable(cid)?;
 
         let _ = self
             .call_manager
@@ -210,11 +406,7 @@ where
 
         let data = self
             .call_manager
-            .blockstore()
-            .get(cid)
-            // TODO: This is really "super fatal". It means we failed to store state, and should
-            // probably abort the entire block.
-            .or_fatal()?
+            .get_block(cid)?
             .ok_or_else(|| anyhow!("missing state: {}", cid))
             // Missing state is a fatal error because it means we have a bug. Once we do
             // reachability checking (for user actors) we won't get here unless the block is known
@@ -229,9 +421,18 @@ where
                 .on_block_open_per_byte(block.size() as usize),
         )?;
 
+        // Blocks in the state-tree are always well-formed.
+        let links = self.mark_links_reachable(&block).or_fatal()?;
+        t.stop_with(start);
+
+        if links > 0 {
+            let _ = self
+                .call_manager
+                .charge_gas(self.call_manager.price_list().on_block_scan_links(links))?;
+        }
+
         let stat = block.stat();
         let id = self.blocks.put(block)?;
-        t.stop_with(start);
         Ok((id, stat))
     }
 
@@ -244,6 +445,31 @@ where
             .call_manager
             .charge_gas(self.call_manager.price_list().on_block_create(data.len()))?;
 
+        let max_links = self.call_manager.context().max_block_links;
+        if codec == DAG_CBOR && (self.reachability_checks() || max_links.is_some()) {
+            let mut links = Vec::new();
+            scan_dag_cbor_links(data, |link| {
+                links.push(link);
+                Ok(())
+            })
+            .or_error(ErrorNumber::Serialization)?;
+            if !links.is_empty() {
+                let _ = self.call_manager.charge_gas(
+                    self.call_manager
+                        .price_list()
+                        .on_block_scan_links(links.len()),
+                )?;
+            }
+            if let Some(max) = max_links {
+                if links.len() > max {
+                    return Err(syscall_error!(LimitExceeded; "blocks may not have more than {} links", max).into());
+                }
+            }
+            for link in &links {
+                self.check_reachable(link)?;
+            }
+        }
+
         t.record(Ok(self.blocks.put(Block::new(codec, data))?))
     }
 
@@ -267,13 +493,12 @@ where
             return Err(syscall_error!(IllegalCid; "invalid hash length: {}", hash_len).into());
         }
         let k = Cid::new_v1(block.codec(), hash.truncate(hash_len as u8));
-        // TODO(M2): Add the block to the reachable set.
-        self.call_manager
-            .blockstore()
-            .put_keyed(&k, block.data())
-            // TODO: This is really "super fatal". It means we failed to store state, and should
-            // probably abort the entire block.
-            .or_fatal()?;
+        self.call_manager.record_block_write()?;
+        if self.reachability_checks() {
+            self.reachable.insert(k);
+        }
+        // Buffered (and discarded if this call aborts), see `CallManager::put_block`.
+        self.call_manager.put_block(k, block.data())?;
         t.stop_with(start);
         Ok(k)
     }
@@ -356,6 +581,18 @@ where
         t.stop();
         Ok(ctx)
     }
+
+    fn msg_caller_code_cid(&self) -> Result<Cid> {
+        let t = self
+            .call_manager
+            .charge_gas(self.call_manager.price_list().on_get_actor_code_cid())?;
+
+        t.record(Ok(self
+            .call_manager
+            .get_actor(self.caller)?
+            .ok_or_else(|| syscall_error!(NotFound; "caller not found"))?
+            .code))
+    }
 }
 
 impl<C> SendOps for DefaultKernel<C>
@@ -398,32 +635,7 @@ where
         })?;
 
         // Store result and return.
-        Ok(match result {
-            InvocationResult {
-                exit_code,
-                value: Some(blk),
-            } => {
-                let block_stat = blk.stat();
-                let block_id = self
-                    .blocks
-                    .put(blk)
-                    .or_fatal()
-                    .context("failed to store a valid return value")?;
-                SendResult {
-                    block_id,
-                    block_stat,
-                    exit_code,
-                }
-            }
-            InvocationResult {
-                exit_code,
-                value: None,
-            } => SendResult {
-                block_id: NO_DATA_BLOCK_ID,
-                block_stat: BlockStat { codec: 0, size: 0 },
-                exit_code,
-            },
-        })
+        self.put_invocation_result(result)
     }
 }
 
@@ -456,15 +668,7 @@ where
                 .on_verify_signature(sig_type, plaintext.len()),
         )?;
 
-        // We only support key addresses (f1/f3). This change does not require a FIP, because no
-        // actors invoke this method with non-key addresses.
-        let signing_addr = match signer.payload() {
-            Payload::BLS(_) | Payload::Secp256k1(_) => *signer,
-            // Not a key address.
-            _ => {
-                return Err(syscall_error!(IllegalArgument; "address protocol {} not supported", signer.protocol()).into());
-            }
-        };
+        let signing_addr = self.resolve_key_address(signer)?;
 
         // Verify signature, catching errors. Signature verification can include some complicated
         // math.
@@ -473,6 +677,41 @@ where
         }))
     }
 
+    fn verify_aggregate_signature(
+        &self,
+        aggregate_sig: &[u8; BLS_SIG_LEN],
+        pub_keys: &[[u8; BLS_PUB_LEN]],
+        plaintexts: &[&[u8]],
+    ) -> Result<bool> {
+        // An aggregate of no signatures would "verify" trivially.
+        if pub_keys.is_empty() {
+            return Err(syscall_error!(IllegalArgument; "no signers").into());
+        }
+        if pub_keys.len() != plaintexts.len() {
+            return Err(syscall_error!(IllegalArgument;
+                "expected one plaintext per signer, got {} signers and {} plaintexts",
+                pub_keys.len(), plaintexts.len())
+            .into());
+        }
+
+        let t = self.call_manager.charge_gas(
+            self.call_manager
+                .price_list()
+                .on_verify_aggregate_signature(
+                    pub_keys.len(),
+                    plaintexts.iter().map(|p| p.len()).sum(),
+                ),
+        )?;
+
+        let sig = signature::Signature::new_bls(aggregate_sig.to_vec());
+        let pub_keys: Vec<&[u8]> = pub_keys.iter().map(|k| &k[..]).collect();
+        t.record(catch_and_log_panic("verifying aggregate signature", || {
+            Ok(signature::ops::verify_bls_aggregate(
+                plaintexts, &pub_keys, &sig,
+            ))
+        }))
+    }
+
     fn recover_secp_public_key(
         &self,
         hash: &[u8; SECP_SIG_MESSAGE_HASH_SIZE],
@@ -533,7 +772,14 @@ where
 
         // It's probably _fine_ to just let these turn into fatal errors, but seal verification is
         // pretty self contained, so catching panics here probably doesn't hurt.
-        t.record(catch_and_log_panic("verifying seal", || verify_seal(vi)))
+        //
+        // There are probably verification errors that should be fatal, but it's hard to tell so
+        // we stick with illegal argument. Worst case, _some_ node falls out of sync. Better than
+        // the network halting.
+        let externs = AssertUnwindSafe(self.call_manager.externs());
+        t.record(catch_and_log_panic("verifying seal", || {
+            externs.verify_seal(vi).or_illegal_argument()
+        }))
     }
 
     fn verify_post(&self, verify_info: &WindowPoStVerifyInfo) -> Result<bool> {
@@ -559,8 +805,24 @@ where
         }
 
         // This is especially important to catch as, otherwise, a bad "post" could be undisputable.
+        let externs = AssertUnwindSafe(self.call_manager.externs());
         t.record(catch_and_log_panic("verifying post", || {
-            verify_post(&verify_info)
+            externs.verify_post(&verify_info).or_illegal_argument()
+        }))
+    }
+
+    fn verify_winning_post(&self, verify_info: &WinningPoStVerifyInfo) -> Result<bool> {
+        let t = self.call_manager.charge_gas(
+            self.call_manager
+                .price_list()
+                .on_verify_winning_post(verify_info),
+        )?;
+
+        let externs = AssertUnwindSafe(self.call_manager.externs());
+        t.record(catch_and_log_panic("verifying winning post", || {
+            externs
+                .verify_winning_post(verify_info)
+                .or_illegal_argument()
         }))
     }
 
@@ -587,6 +849,25 @@ where
                 .or_illegal_argument(),
         )?;
 
+        // The extern checks the block signatures against the miner's worker key in the lookback
+        // state for the fault epoch, which can only exist at or before the current epoch. A fault
+        // from the future (or against a non-ID address) means the node is misbehaving. Faults are
+        // only validated from NV21.
+        let validate = self.call_manager.context().network_version >= NetworkVersion::V21;
+        if let Some(fault) = fault.as_ref().filter(|_| validate) {
+            let epoch = self.call_manager.context().epoch;
+            if fault.epoch > epoch {
+                return Err(syscall_error!(IllegalArgument;
+                    "consensus fault epoch {} is after the current epoch {}", fault.epoch, epoch)
+                .into());
+            }
+            if fault.target.id().is_err() {
+                return Err(syscall_error!(IllegalArgument;
+                    "consensus fault target {} is not an ID address", fault.target)
+                .into());
+            }
+        }
+
         Ok(fault)
     }
 
@@ -600,12 +881,13 @@ where
                 .charge_gas(self.call_manager.price_list().on_verify_seal(vi))?;
             items.push((vi, t));
         }
+        let externs = self.call_manager.externs();
         log::debug!("batch verify seals start");
         let out = items.par_drain(..)
             .with_min_len(vis.len() / *NUM_CPUS)
             .map(|(seal, timer)| {
                 let start = GasTimer::start();
-                let verify_seal_result = std::panic::catch_unwind(|| verify_seal(seal));
+                let verify_seal_result = std::panic::catch_unwind(AssertUnwindSafe(|| externs.verify_seal(seal)));
                 let ok = match verify_seal_result {
                     Ok(res) => {
                         match res {
@@ -647,8 +929,11 @@ where
                 .price_list()
                 .on_verify_aggregate_seals(aggregate),
         )?;
+        let externs = AssertUnwindSafe(self.call_manager.externs());
         t.record(catch_and_log_panic("verifying aggregate seals", || {
-            verify_aggregate_seals(aggregate)
+            externs
+                .verify_aggregate_seals(aggregate)
+                .or_illegal_argument()
         }))
     }
 
@@ -658,8 +943,9 @@ where
                 .price_list()
                 .on_verify_replica_update(replica),
         )?;
+        let externs = AssertUnwindSafe(self.call_manager.externs());
         t.record(catch_and_log_panic("verifying replica update", || {
-            verify_replica_update(replica)
+            externs.verify_replica_update(replica).or_illegal_argument()
         }))
     }
 }
@@ -680,6 +966,11 @@ where
         self.call_manager.gas_tracker().charge_gas(name, compute)
     }
 
+    fn record_gas_milestone(&self, name: &str) -> Result<()> {
+        self.call_manager.gas_tracker().record_milestone(name);
+        Ok(())
+    }
+
     fn price_list(&self) -> &PriceList {
         self.call_manager.price_list()
     }
@@ -746,12 +1037,56 @@ where
 
         let _ = self
             .call_manager
-            .charge_gas(self.call_manager.price_list().on_tipset_cid(offset > 1));
+            .charge_gas(self.call_manager.price_list().on_tipset_cid(offset > 1))?;
 
         self.call_manager.externs().get_tipset_cid(epoch).or_fatal()
     }
 }
 
+impl<C> DefaultKernel<C>
+where
+    C: CallManager,
+{
+    /// Validates a randomness request before asking the client for randomness, so that invalid
+    /// requests fail deterministically instead of depending on the client's errors.
+    fn check_randomness_request(
+        &self,
+        personalization: i64,
+        rand_epoch: ChainEpoch,
+        entropy: &[u8],
+    ) -> Result<()> {
+        let context = self.call_manager.context();
+
+        // Domain separation tags start at 1. Earlier network versions left this to the client.
+        if context.network.network_version >= NetworkVersion::V21 && personalization <= 0 {
+            return Err(syscall_error!(IllegalArgument; "invalid domain separation tag {}", personalization).into());
+        }
+
+        if let Some(max) = context.network.max_randomness_entropy {
+            if entropy.len() > max {
+                return Err(syscall_error!(LimitExceeded; "randomness entropy of {} bytes exceeds the maximum of {} bytes", entropy.len(), max).into());
+            }
+        }
+
+        let lookback = context.epoch.checked_sub(rand_epoch).ok_or_else(
+            || syscall_error!(IllegalArgument; "randomness epoch {} is out of range", rand_epoch),
+        )?;
+        if lookback < 0 {
+            return Err(
+                syscall_error!(IllegalArgument; "randomness epoch {} is in the future", rand_epoch)
+                    .into(),
+            );
+        }
+        if let Some(max) = context.network.max_randomness_lookback {
+            if lookback > max {
+                return Err(syscall_error!(LimitExceeded; "randomness epoch {} is too far in the past", rand_epoch).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
 impl<C> RandomnessOps for DefaultKernel<C>
 where
     C: CallManager,
@@ -768,8 +1103,7 @@ where
                 .on_get_randomness(entropy.len()),
         )?;
 
-        // TODO(M2): Check error code
-        // Specifically, lookback length?
+        self.check_randomness_request(personalization, rand_epoch, entropy)?;
         t.record(
             self.call_manager
                 .externs()
@@ -790,8 +1124,7 @@ where
                 .on_get_randomness(entropy.len()),
         )?;
 
-        // TODO(M2): Check error code
-        // Specifically, lookback length?
+        self.check_randomness_request(personalization, rand_epoch, entropy)?;
         t.record(
             self.call_manager
                 .externs()
@@ -896,20 +1229,39 @@ where
 
     #[cfg(feature = "m2-native")]
     fn install_actor(&mut self, code_id: Cid) -> Result<()> {
-        let start = GasTimer::start();
-        let size = self
+        // The code may have been written by this message, so it may not be in the blockstore yet.
+        let wasm = self
             .call_manager
-            .engine()
-            .preload(self.call_manager.blockstore(), &[code_id])
-            .context("failed to install actor")
+            .get_block(&code_id)?
+            .context("actor code not found")
             .or_illegal_argument()?;
+        self.install_wasm(&code_id, &wasm)
+    }
 
-        let t = self
-            .call_manager
-            .charge_gas(self.call_manager.price_list().on_install_actor(size))?;
-        t.stop_with(start);
+    #[cfg(feature = "m2-native")]
+    fn install_actor_code(&mut self, wasm: &[u8]) -> Result<Cid> {
+        if self.read_only {
+            return Err(syscall_error!(ReadOnly; "cannot install actors while read-only").into());
+        }
 
-        Ok(())
+        // Store the code like any other block, except that it's bounded by the module size limit
+        // instead of the block size limit.
+        let t = self.call_manager.charge_gas(
+            self.call_manager
+                .price_list()
+                .on_block_link(SupportedHashes::Blake2b256, wasm.len()),
+        )?;
+        let code_id = Cid::new_v1(IPLD_RAW, SupportedHashes::Blake2b256.digest(wasm));
+        self.install_wasm(&code_id, wasm)?;
+        self.call_manager.record_block_write()?;
+        if self.reachability_checks() {
+            self.reachable.insert(code_id);
+        }
+        // Buffered (and discarded if this call aborts), see `CallManager::put_block`.
+        self.call_manager.put_block(code_id, wasm)?;
+        t.stop();
+
+        Ok(code_id)
     }
 
     fn balance_of(&self, actor_id: ActorID) -> Result<TokenAmount> {
@@ -922,6 +1274,36 @@ where
             .balance)
     }
 
+    fn upgrade_actor(&mut self, new_code_cid: Cid, params_id: BlockId) -> Result<SendResult> {
+        if self.call_manager.context().network_version < NetworkVersion::V21 {
+            return Err(syscall_error!(Forbidden; "actors can't be upgraded before NV21").into());
+        }
+
+        if self.read_only {
+            return Err(syscall_error!(ReadOnly; "cannot upgrade actors when read-only").into());
+        }
+
+        // Load parameters.
+        let params = if params_id == NO_DATA_BLOCK_ID {
+            None
+        } else {
+            Some(self.blocks.get(params_id)?.clone())
+        };
+
+        // Make sure we can actually store the return block.
+        if self.blocks.is_full() {
+            return Err(syscall_error!(LimitExceeded; "cannot store return block").into());
+        }
+
+        // Upgrade, reverting the code change if the upgrade entrypoint fails.
+        let (caller, actor_id) = (self.caller, self.actor_id);
+        let result = self.call_manager.with_transaction(|cm| {
+            cm.upgrade_actor::<Self>(caller, actor_id, new_code_cid, params)
+        })?;
+
+        self.put_invocation_result(result)
+    }
+
     fn lookup_delegated_address(&self, actor_id: ActorID) -> Result<Option<Address>> {
         let t = self
             .call_manager
@@ -941,10 +1323,18 @@ where
         println!("{}", msg)
     }
 
+    fn log_at(&self, level: log::Level, msg: String) {
+        println!("[{}] {}", level, msg)
+    }
+
     fn debug_enabled(&self) -> bool {
         self.call_manager.context().actor_debugging
     }
 
+    fn call_stack(&self) -> Vec<ActorID> {
+        self.call_manager.call_stack().to_vec()
+    }
+
     fn store_artifact(&self, name: &str, data: &[u8]) -> Result<()> {
         // Ensure well formed artifact name
         {
@@ -994,6 +1384,10 @@ where
         }
         Ok(())
     }
+
+    fn record_syscall(&mut self, record: SyscallRecord) {
+        self.call_manager.record_syscall(record)
+    }
 }
 
 impl<C> LimiterOps for DefaultKernel<C>
@@ -1096,13 +1490,6 @@ fn validate_actor_event(evt: &ActorEvent) -> Result<()> {
     Ok(())
 }
 
-fn prover_id_from_u64(id: u64) -> ProverId {
-    let mut prover_id = ProverId::default();
-    let prover_bytes = Address::new_id(id).payload().to_raw_bytes();
-    prover_id[..prover_bytes.len()].copy_from_slice(&prover_bytes);
-    prover_id
-}
-
 fn get_required_padding(
     old_length: PaddedPieceSize,
     new_piece_length: PaddedPieceSize,
@@ -1125,208 +1512,6 @@ fn get_required_padding(
     (pad_pieces, PaddedPieceSize(sum))
 }
 
-fn to_fil_public_replica_infos(
-    src: &[SectorInfo],
-    typ: RegisteredPoStProof,
-) -> Result<BTreeMap<SectorId, PublicReplicaInfo>> {
-    let replicas = src
-        .iter()
-        .map::<core::result::Result<(SectorId, PublicReplicaInfo), String>, _>(
-            |sector_info: &SectorInfo| {
-                let commr = commcid::cid_to_replica_commitment_v1(&sector_info.sealed_cid)?;
-                if !check_valid_proof_type(typ, sector_info.proof) {
-                    return Err("invalid proof type".to_string());
-                }
-                let replica = PublicReplicaInfo::new(typ.try_into()?, commr);
-                Ok((SectorId::from(sector_info.sector_number), replica))
-            },
-        )
-        .collect::<core::result::Result<BTreeMap<SectorId, PublicReplicaInfo>, _>>()
-        .or_illegal_argument()?;
-    Ok(replicas)
-}
-
-fn check_valid_proof_type(post_type: RegisteredPoStProof, seal_type: RegisteredSealProof) -> bool {
-    let proof_type_v1p1 = seal_type
-        .registered_window_post_proof()
-        .unwrap_or(RegisteredPoStProof::Invalid(-1));
-    let proof_type_v1 = match proof_type_v1p1 {
-        RegisteredPoStProof::StackedDRGWindow2KiBV1P1 => {
-            RegisteredPoStProof::StackedDRGWindow2KiBV1
-        }
-        RegisteredPoStProof::StackedDRGWindow8MiBV1P1 => {
-            RegisteredPoStProof::StackedDRGWindow8MiBV1
-        }
-        RegisteredPoStProof::StackedDRGWindow512MiBV1P1 => {
-            RegisteredPoStProof::StackedDRGWindow512MiBV1
-        }
-        RegisteredPoStProof::StackedDRGWindow32GiBV1P1 => {
-            RegisteredPoStProof::StackedDRGWindow32GiBV1
-        }
-        RegisteredPoStProof::StackedDRGWindow64GiBV1P1 => {
-            RegisteredPoStProof::StackedDRGWindow64GiBV1
-        }
-        _ => {
-            return false;
-        }
-    };
-
-    proof_type_v1 == post_type || proof_type_v1p1 == post_type
-}
-
-fn verify_seal(vi: &SealVerifyInfo) -> Result<bool> {
-    let commr = commcid::cid_to_replica_commitment_v1(&vi.sealed_cid).or_illegal_argument()?;
-    let commd = commcid::cid_to_data_commitment_v1(&vi.unsealed_cid).or_illegal_argument()?;
-    let prover_id = prover_id_from_u64(vi.sector_id.miner);
-
-    proofs::seal::verify_seal(
-        vi.registered_proof
-            .try_into()
-            .or_illegal_argument()
-            .context(format_args!("invalid proof type {:?}", vi.registered_proof))?,
-        commr,
-        commd,
-        prover_id,
-        SectorId::from(vi.sector_id.number),
-        bytes_32(&vi.randomness.0),
-        bytes_32(&vi.interactive_randomness.0),
-        &vi.proof,
-    )
-    .or_illegal_argument()
-    // There are probably errors here that should be fatal, but it's hard to tell so I'm sticking
-    // with illegal argument for now.
-    //
-    // Worst case, _some_ node falls out of sync. Better than the network halting.
-    .context("failed to verify seal proof")
-}
-
-fn verify_post(verify_info: &WindowPoStVerifyInfo) -> Result<bool> {
-    let WindowPoStVerifyInfo {
-        ref proofs,
-        ref challenged_sectors,
-        prover,
-        ..
-    } = verify_info;
-
-    let Randomness(mut randomness) = verify_info.randomness.clone();
-
-    // Necessary to be valid bls12 381 element.
-    randomness[31] &= 0x3f;
-
-    let proof_type = proofs[0].post_proof;
-
-    for proof in proofs {
-        if proof.post_proof != proof_type {
-            return Err(
-                syscall_error!(IllegalArgument; "all proof types must be the same (found both {:?} and {:?})", proof_type, proof.post_proof)
-                    .into(),
-            );
-        }
-    }
-    // Convert sector info into public replica
-    let replicas = to_fil_public_replica_infos(challenged_sectors, proof_type)?;
-
-    // Convert PoSt proofs into proofs-api format
-    let proofs: Vec<(proofs::RegisteredPoStProof, _)> = proofs
-        .iter()
-        .map(|p| Ok((p.post_proof.try_into()?, p.proof_bytes.as_ref())))
-        .collect::<core::result::Result<_, String>>()
-        .or_illegal_argument()?;
-
-    // Generate prover bytes from ID
-    let prover_id = prover_id_from_u64(*prover);
-
-    // Verify Proof
-    proofs::post::verify_window_post(&bytes_32(&randomness), &proofs, &replicas, prover_id)
-        .or_illegal_argument()
-}
-
-fn verify_aggregate_seals(aggregate: &AggregateSealVerifyProofAndInfos) -> Result<bool> {
-    if aggregate.infos.is_empty() {
-        return Err(syscall_error!(IllegalArgument; "no seal verify infos").into());
-    }
-    let spt: proofs::RegisteredSealProof = aggregate.seal_proof.try_into().or_illegal_argument()?;
-    let prover_id = prover_id_from_u64(aggregate.miner);
-    struct AggregationInputs {
-        // replica
-        commr: [u8; 32],
-        // data
-        commd: [u8; 32],
-        sector_id: SectorId,
-        ticket: [u8; 32],
-        seed: [u8; 32],
-    }
-    let inputs: Vec<AggregationInputs> = aggregate
-        .infos
-        .iter()
-        .map(|info| {
-            let commr = commcid::cid_to_replica_commitment_v1(&info.sealed_cid)?;
-            let commd = commcid::cid_to_data_commitment_v1(&info.unsealed_cid)?;
-            Ok(AggregationInputs {
-                commr,
-                commd,
-                ticket: bytes_32(&info.randomness.0),
-                seed: bytes_32(&info.interactive_randomness.0),
-                sector_id: SectorId::from(info.sector_number),
-            })
-        })
-        .collect::<core::result::Result<Vec<_>, &'static str>>()
-        .or_illegal_argument()?;
-
-    let inp: Vec<Vec<_>> = inputs
-        .par_iter()
-        .map(|input| {
-            proofs::seal::get_seal_inputs(
-                spt,
-                input.commr,
-                input.commd,
-                prover_id,
-                input.sector_id,
-                input.ticket,
-                input.seed,
-            )
-        })
-        .try_reduce(Vec::new, |mut acc, current| {
-            acc.extend(current);
-            Ok(acc)
-        })
-        .or_illegal_argument()?;
-
-    let commrs: Vec<[u8; 32]> = inputs.iter().map(|input| input.commr).collect();
-    let seeds: Vec<[u8; 32]> = inputs.iter().map(|input| input.seed).collect();
-
-    proofs::seal::verify_aggregate_seal_commit_proofs(
-        spt,
-        aggregate.aggregate_proof.try_into().or_illegal_argument()?,
-        aggregate.proof.clone(),
-        &commrs,
-        &seeds,
-        inp,
-    )
-    .or_illegal_argument()
-}
-
-fn verify_replica_update(replica: &ReplicaUpdateInfo) -> Result<bool> {
-    let up: proofs::RegisteredUpdateProof =
-        replica.update_proof_type.try_into().or_illegal_argument()?;
-
-    let commr_old =
-        commcid::cid_to_replica_commitment_v1(&replica.old_sealed_cid).or_illegal_argument()?;
-    let commr_new =
-        commcid::cid_to_replica_commitment_v1(&replica.new_sealed_cid).or_illegal_argument()?;
-    let commd =
-        commcid::cid_to_data_commitment_v1(&replica.new_unsealed_cid).or_illegal_argument()?;
-
-    proofs::update::verify_empty_sector_update_proof(
-        up,
-        &replica.proof,
-        commr_old,
-        commr_new,
-        commd,
-    )
-    .or_illegal_argument()
-}
-
 fn compute_unsealed_sector_cid(
     proof_type: RegisteredSealProof,
     pieces: &[PieceInfo],
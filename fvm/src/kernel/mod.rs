@@ -6,7 +6,7 @@ use fvm_shared::address::Address;
 use fvm_shared::clock::ChainEpoch;
 use fvm_shared::consensus::ConsensusFault;
 use fvm_shared::crypto::signature::{
-    SignatureType, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
+    SignatureType, BLS_PUB_LEN, BLS_SIG_LEN, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
 };
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::error::ExitCode;
@@ -14,7 +14,7 @@ use fvm_shared::piece::PieceInfo;
 use fvm_shared::randomness::{Randomness, RANDOMNESS_LENGTH};
 use fvm_shared::sector::{
     AggregateSealVerifyProofAndInfos, RegisteredSealProof, ReplicaUpdateInfo, SealVerifyInfo,
-    WindowPoStVerifyInfo,
+    WindowPoStVerifyInfo, WinningPoStVerifyInfo,
 };
 use fvm_shared::sys::out::network::NetworkContext;
 use fvm_shared::sys::out::vm::MessageContext;
@@ -37,6 +37,8 @@ use crate::call_manager::CallManager;
 use crate::gas::{Gas, GasTimer, PriceList};
 use crate::machine::limiter::MemoryLimiter;
 use crate::machine::Machine;
+use crate::syscalls::SyscallLinker;
+use crate::trace::SyscallRecord;
 
 pub struct SendResult {
     pub block_id: BlockId,
@@ -97,11 +99,26 @@ pub trait Kernel:
 
     /// The kernel's underlying "machine".
     fn machine(&self) -> &<Self::CallManager as CallManager>::Machine;
+
+    /// Binds additional, network-specific syscalls into actor modules. By default, no custom
+    /// syscalls are bound.
+    ///
+    /// Custom syscalls must be bound into their own namespaces (see
+    /// [`SyscallLinker::namespace`]) and may charge gas through a hook (see
+    /// [`SyscallNamespace::bind_with_gas`][crate::syscalls::SyscallNamespace::bind_with_gas]).
+    fn bind_custom_syscalls(linker: &mut SyscallLinker<'_, Self>) -> anyhow::Result<()>
+    where
+        Self: Sized,
+    {
+        let _ = linker;
+        Ok(())
+    }
 }
 
 /// Network-related operations.
 pub trait NetworkOps {
-    /// Network information (epoch, version, etc.).
+    /// Network information (epoch, version, tipset timestamp, etc.), taken from the
+    /// [`MachineContext`](crate::machine::MachineContext).
     fn network_context(&self) -> Result<NetworkContext>;
 
     /// The CID of the tipset at the specified epoch.
@@ -112,6 +129,12 @@ pub trait NetworkOps {
 pub trait MessageOps {
     /// Message information.
     fn msg_context(&self) -> Result<MessageContext>;
+
+    /// Returns the code CID of the caller.
+    ///
+    /// This method will fail with [`NotFound`](fvm_shared::error::ErrorNumber::NotFound) if the
+    /// caller no longer exists (e.g., because it deleted itself before calling).
+    fn msg_caller_code_cid(&self) -> Result<Cid>;
 }
 
 /// The IPLD subset of the kernel.
@@ -152,6 +175,13 @@ pub trait SelfOps: IpldBlockOps {
     /// Get the state root.
     fn root(&self) -> Result<Cid>;
 
+    /// Get the code CID of the executing actor.
+    ///
+    /// This method will fail with
+    /// [`IllegalOperation`](fvm_shared::error::ErrorNumber::IllegalOperation) if the actor has
+    /// been deleted.
+    fn self_code_cid(&self) -> Result<Cid>;
+
     /// Update the state-root.
     ///
     /// This method will fail if the new state-root isn't reachable.
@@ -199,6 +229,12 @@ pub trait ActorOps {
     #[cfg(feature = "m2-native")]
     fn install_actor(&mut self, code_cid: Cid) -> Result<()>;
 
+    /// Validates the given Wasm for deployment (see
+    /// [`validate_wasm_for_deployment`](crate::engine::validate_wasm_for_deployment)), stores it
+    /// under its code CID, and installs it. Actors may then be created with the returned code CID.
+    #[cfg(feature = "m2-native")]
+    fn install_actor_code(&mut self, wasm: &[u8]) -> Result<Cid>;
+
     /// Returns the actor's "type" (if builitin) or 0 (if not).
     fn get_builtin_actor_type(&self, code_cid: &Cid) -> Result<u32>;
 
@@ -207,6 +243,13 @@ pub trait ActorOps {
 
     /// Returns the balance associated with an actor id
     fn balance_of(&self, actor_id: ActorID) -> Result<TokenAmount>;
+
+    /// Upgrades the calling actor to `new_code_cid` in-place, keeping its address, balance, and
+    /// state, then invokes the new code's `upgrade` entrypoint with the given parameters and
+    /// [`UpgradeInfo`](fvm_shared::upgrade::UpgradeInfo).
+    ///
+    /// The code change is reverted if the `upgrade` entrypoint fails.
+    fn upgrade_actor(&mut self, new_code_cid: Cid, params_id: BlockId) -> Result<SendResult>;
 }
 
 /// Operations to send messages to other actors.
@@ -247,6 +290,10 @@ pub trait GasOps {
     /// `name` provides information about gas charging point.
     fn charge_gas(&self, name: &str, compute: Gas) -> Result<GasTimer>;
 
+    /// Records a named milestone at the current gas used, for profiling. Milestones are only
+    /// recorded when gas breakdowns are enabled and don't consume any gas.
+    fn record_gas_milestone(&self, name: &str) -> Result<()>;
+
     /// Returns the currently active gas price list.
     fn price_list(&self) -> &PriceList;
 }
@@ -254,6 +301,10 @@ pub trait GasOps {
 /// Cryptographic primitives provided by the kernel.
 pub trait CryptoOps {
     /// Verifies that a signature is valid for an address and plaintext.
+    ///
+    /// From NV21, signers other than key (f1/f3) addresses are resolved to their account actor's
+    /// key address. Fails with `NotFound` if the signer doesn't exist, or `IllegalArgument` if it
+    /// isn't an account actor. Earlier network versions only accept key addresses.
     fn verify_signature(
         &self,
         sig_type: SignatureType,
@@ -262,17 +313,36 @@ pub trait CryptoOps {
         plaintext: &[u8],
     ) -> Result<bool>;
 
-    /// Given a message hash and its signature, recovers the public key of the signer.
+    /// Verifies a BLS aggregate signature, where the signer with the BLS public key `pub_keys[i]`
+    /// signed `plaintexts[i]`. The plaintexts must be distinct.
+    ///
+    /// Fails with `IllegalArgument` if there are no signers, or if the number of public keys and
+    /// plaintexts differ.
+    fn verify_aggregate_signature(
+        &self,
+        aggregate_sig: &[u8; BLS_SIG_LEN],
+        pub_keys: &[[u8; BLS_PUB_LEN]],
+        plaintexts: &[&[u8]],
+    ) -> Result<bool>;
+
+    /// Given a message hash and its signature, recovers the (uncompressed) public key of the
+    /// signer, ecrecover-style.
+    ///
+    /// The signature is the 64 byte `r || s` signature followed by the recovery ID. Fails with
+    /// `IllegalArgument` if the recovery ID or signature is malformed or no key could be
+    /// recovered. A fixed `on_recover_secp_public_key` gas charge applies regardless of success.
     fn recover_secp_public_key(
         &self,
         hash: &[u8; SECP_SIG_MESSAGE_HASH_SIZE],
         signature: &[u8; SECP_SIG_LEN],
     ) -> Result<[u8; SECP_PUB_LEN]>;
 
-    /// Hashes input `data_in` using with the specified hash function, writing the output to
-    /// `digest_out`, returning the size of the digest written to `digest_out`. If `digest_out` is
-    /// to small to fit the entire digest, it will be truncated. If too large, the leftover space
-    /// will not be overwritten.
+    /// Hashes `data` with the hash function identified by the multihash `code`, returning the
+    /// resulting multihash. Any of the [`SupportedHashes`] may be selected (blake2b-256/512,
+    /// sha2-256, keccak-256, and ripemd-160), each charged per the price list's hashing costs.
+    ///
+    /// Fails with `IllegalArgument` if the hash function isn't supported. The `crypto::hash`
+    /// syscall truncates the digest to fit the actor's output buffer.
     fn hash(&self, code: u64, data: &[u8]) -> Result<MultihashGeneric<64>>;
 
     /// Computes an unsealed sector CID (CommD) from its constituent piece CIDs (CommPs) and sizes.
@@ -288,6 +358,9 @@ pub trait CryptoOps {
     /// Verifies a window proof of spacetime.
     fn verify_post(&self, verify_info: &WindowPoStVerifyInfo) -> Result<bool>;
 
+    /// Verifies a winning proof of spacetime.
+    fn verify_winning_post(&self, verify_info: &WinningPoStVerifyInfo) -> Result<bool>;
+
     /// Verifies that two block headers provide proof of a consensus fault:
     /// - both headers mined by the same actor
     /// - headers are different
@@ -321,6 +394,12 @@ pub trait CryptoOps {
 }
 
 /// Randomness queries.
+///
+/// Requests with a non-positive domain separation tag (`personalization`) or an epoch in the
+/// future fail with `IllegalArgument`. Requests exceeding the network's
+/// [`max_randomness_lookback`](crate::machine::NetworkConfig::max_randomness_lookback) or
+/// [`max_randomness_entropy`](crate::machine::NetworkConfig::max_randomness_entropy) fail with
+/// `LimitExceeded`.
 pub trait RandomnessOps {
     /// Randomness returns a (pseudo)random byte array drawing from the latest
     /// ticket chain from a given epoch and incorporating requisite entropy.
@@ -348,12 +427,23 @@ pub trait DebugOps {
     /// Log a message.
     fn log(&self, msg: String);
 
+    /// Log a message at the given level.
+    fn log_at(&self, level: log::Level, msg: String);
+
     /// Returns whether debug mode is enabled.
     fn debug_enabled(&self) -> bool;
 
+    /// Returns the actors currently executing on the call stack, outermost first (ending with the
+    /// calling actor). See [`CallManager::call_stack`].
+    fn call_stack(&self) -> Vec<ActorID>;
+
     /// Store an artifact.
     /// Returns error on malformed name, returns Ok and logs the error on system/os errors.
     fn store_artifact(&self, name: &str, data: &[u8]) -> Result<()>;
+
+    /// Records a syscall made by the actor, if syscall recording is enabled (see
+    /// [`MachineContext::syscall_recording`](crate::machine::MachineContext::syscall_recording)).
+    fn record_syscall(&mut self, record: SyscallRecord);
 }
 
 /// Track and limit memory expansion.
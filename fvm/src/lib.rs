@@ -17,6 +17,8 @@ pub mod executor;
 pub mod externs;
 pub mod kernel;
 pub mod machine;
+pub mod metrics;
+pub mod migration;
 pub mod syscalls;
 
 pub mod gas;
@@ -65,15 +67,18 @@ mod test {
 
     use crate::call_manager::DefaultCallManager;
     use crate::engine::EnginePool;
-    use crate::externs::{Chain, Consensus, Externs, Rand};
+    use crate::externs::{Chain, Consensus, Externs, Rand, Verifier};
     use crate::machine::{DefaultMachine, Manifest, NetworkConfig};
     use crate::state_tree::StateTree;
     use crate::{executor, DefaultKernel};
 
-    struct DummyExterns;
+    #[derive(Clone)]
+    pub(crate) struct DummyExterns;
 
     impl Externs for DummyExterns {}
 
+    impl Verifier for DummyExterns {}
+
     impl Rand for DummyExterns {
         fn get_chain_randomness(
             &self,
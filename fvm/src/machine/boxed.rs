@@ -1,6 +1,7 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use cid::Cid;
+use fvm_ipld_blockstore::BlockstoreStats;
 
 use super::{Machine, MachineContext, Manifest};
 use crate::kernel::Result;
@@ -53,6 +54,11 @@ impl<M: Machine> Machine for Box<M> {
         (*self).into_store()
     }
 
+    #[inline(always)]
+    fn blockstore_stats(&self) -> Option<BlockstoreStats> {
+        (**self).blockstore_stats()
+    }
+
     #[inline(always)]
     fn machine_id(&self) -> &str {
         (**self).machine_id()
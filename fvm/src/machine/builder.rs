@@ -0,0 +1,268 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::version::NetworkVersion;
+use fvm_shared::IDENTITY_HASH;
+
+use super::default::{is_supported_version, load_manifest};
+use super::{DefaultMachine, MachineContext};
+use crate::externs::Externs;
+use crate::gas::price_list_by_network_version;
+use crate::state_tree::StateTree;
+
+/// An error encountered while validating the inputs to a [`DefaultMachine`].
+#[derive(Debug, thiserror::Error)]
+pub enum MachineBuildError {
+    #[error("unsupported network version: {0}")]
+    UnsupportedNetworkVersion(NetworkVersion),
+    #[error("invalid epoch: {0}")]
+    InvalidEpoch(ChainEpoch),
+    #[error("expected network version {expected} at epoch {epoch}, got {actual}")]
+    NetworkVersionMismatch {
+        epoch: ChainEpoch,
+        expected: NetworkVersion,
+        actual: NetworkVersion,
+    },
+    #[error("the price list doesn't match network version {0}")]
+    PriceListMismatch(NetworkVersion),
+    #[error("blockstore doesn't have the initial state-root {0}")]
+    MissingStateRoot(Cid),
+    #[error("failed to load the state-tree: {0}")]
+    InvalidStateTree(anyhow::Error),
+    #[error("failed to load the builtin actors manifest: {0}")]
+    InvalidManifest(anyhow::Error),
+    #[error("blockstore doesn't have the code for builtin actor {0}")]
+    MissingActorCode(Cid),
+    #[error("blockstore error: {0}")]
+    Blockstore(anyhow::Error),
+    #[error("failed to construct the machine: {0}")]
+    Other(anyhow::Error),
+}
+
+/// Validates the machine context and initial state before constructing a [`DefaultMachine`].
+///
+/// [`DefaultMachine::new`] only performs the checks it needs to get started, so problems like an
+/// incomplete actor bundle or a mismatched price list will otherwise only surface once messages are
+/// executed. The builder checks that:
+///
+/// 1. The network version is supported and, if an upgrade schedule is supplied, matches the epoch.
+/// 2. The price list is the one defined for the network version (unless custom price lists are
+///    explicitly allowed).
+/// 3. The initial state root is present in the blockstore and is a valid state-tree.
+/// 4. The builtin actors manifest can be loaded and the code for every builtin actor is present in
+///    the blockstore.
+pub struct MachineBuilder<B, E> {
+    context: MachineContext,
+    blockstore: B,
+    externs: E,
+    upgrade_schedule: Vec<(ChainEpoch, NetworkVersion)>,
+    allow_custom_price_list: bool,
+}
+
+impl<B, E> MachineBuilder<B, E>
+where
+    B: Blockstore + 'static,
+    E: Externs + 'static,
+{
+    /// Create a new builder for a machine with the given context, blockstore, and externs.
+    pub fn new(context: MachineContext, blockstore: B, externs: E) -> Self {
+        MachineBuilder {
+            context,
+            blockstore,
+            externs,
+            upgrade_schedule: Vec::new(),
+            allow_custom_price_list: false,
+        }
+    }
+
+    /// Check the network version against an upgrade schedule of `(epoch, network version)` pairs,
+    /// where each network version is active starting at the corresponding epoch.
+    pub fn upgrade_schedule(
+        mut self,
+        schedule: impl IntoIterator<Item = (ChainEpoch, NetworkVersion)>,
+    ) -> Self {
+        self.upgrade_schedule = schedule.into_iter().collect();
+        self.upgrade_schedule.sort_by_key(|(epoch, _)| *epoch);
+        self
+    }
+
+    /// Allow a price list other than the one defined for the network version (e.g., for testing).
+    pub fn allow_custom_price_list(mut self) -> Self {
+        self.allow_custom_price_list = true;
+        self
+    }
+
+    /// Validate the inputs and construct the machine.
+    pub fn build(self) -> Result<DefaultMachine<B, E>, MachineBuildError> {
+        self.validate()?;
+        DefaultMachine::new(&self.context, self.blockstore, self.externs)
+            .map_err(MachineBuildError::Other)
+    }
+
+    fn validate(&self) -> Result<(), MachineBuildError> {
+        let ctx = &self.context;
+        if !is_supported_version(ctx.network_version) {
+            return Err(MachineBuildError::UnsupportedNetworkVersion(
+                ctx.network_version,
+            ));
+        }
+
+        if ctx.epoch < 0 {
+            return Err(MachineBuildError::InvalidEpoch(ctx.epoch));
+        }
+
+        if let Some(&(_, expected)) = self
+            .upgrade_schedule
+            .iter()
+            .rev()
+            .find(|(epoch, _)| *epoch <= ctx.epoch)
+        {
+            if expected != ctx.network_version {
+                return Err(MachineBuildError::NetworkVersionMismatch {
+                    epoch: ctx.epoch,
+                    expected,
+                    actual: ctx.network_version,
+                });
+            }
+        }
+
+        if !self.allow_custom_price_list
+            && !std::ptr::eq(
+                ctx.price_list,
+                price_list_by_network_version(ctx.network_version),
+            )
+        {
+            return Err(MachineBuildError::PriceListMismatch(ctx.network_version));
+        }
+
+        if !self
+            .blockstore
+            .has(&ctx.initial_state_root)
+            .map_err(MachineBuildError::Blockstore)?
+        {
+            return Err(MachineBuildError::MissingStateRoot(ctx.initial_state_root));
+        }
+
+        let state_tree = StateTree::new_from_root_with_config(
+            &self.blockstore,
+            &ctx.initial_state_root,
+            ctx.network.state_tree_hamt.clone(),
+        )
+        .map_err(|e| MachineBuildError::InvalidStateTree(e.into()))?;
+
+        let manifest =
+            load_manifest(&state_tree, ctx).map_err(MachineBuildError::InvalidManifest)?;
+        for code in manifest.builtin_actor_codes() {
+            // Inline (identity) CIDs are never stored in the blockstore.
+            if code.hash().code() == IDENTITY_HASH {
+                continue;
+            }
+            if !self
+                .blockstore
+                .has(code)
+                .map_err(MachineBuildError::Blockstore)?
+            {
+                return Err(MachineBuildError::MissingActorCode(*code));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_ipld_blockstore::MemoryBlockstore;
+    use fvm_ipld_encoding::{CborStore, DAG_CBOR};
+    use fvm_shared::state::StateTreeVersion;
+    use multihash::{Code, MultihashDigest};
+
+    use super::*;
+    use crate::machine::{Manifest, NetworkConfig};
+    use crate::test::DummyExterns;
+
+    fn setup() -> (MemoryBlockstore, MachineContext) {
+        let mut st = StateTree::new(MemoryBlockstore::default(), StateTreeVersion::V5).unwrap();
+        let root = st.flush().unwrap();
+        let bs = st.into_store();
+
+        let manifest_cid = bs
+            .put_cbor(&Manifest::DUMMY_CODES, Code::Blake2b256)
+            .unwrap();
+        let actors_cid = bs.put_cbor(&(1, manifest_cid), Code::Blake2b256).unwrap();
+
+        let mc = NetworkConfig::new(NetworkVersion::V18)
+            .override_actors(actors_cid)
+            .for_epoch(10, 0, root);
+        (bs, mc)
+    }
+
+    #[test]
+    fn builds_valid_machine() {
+        let (bs, mc) = setup();
+        MachineBuilder::new(mc, bs, DummyExterns)
+            .upgrade_schedule([(0, NetworkVersion::V17), (5, NetworkVersion::V18)])
+            .build()
+            .unwrap();
+    }
+
+    #[test]
+    fn supports_registered_network_versions() {
+        // No other test may use this network version.
+        let nv = NetworkVersion::new(1001);
+        let (bs, mut mc) = setup();
+        mc.network.network_version = nv;
+        let err = MachineBuilder::new(mc.clone(), bs.clone(), DummyExterns)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, MachineBuildError::UnsupportedNetworkVersion(v) if v == nv));
+
+        crate::gas::register_price_list(
+            nv,
+            crate::gas::price_list_by_network_version(NetworkVersion::V21),
+        )
+        .unwrap();
+        let result = MachineBuilder::new(mc, bs, DummyExterns).build();
+        crate::gas::unregister_price_list(nv);
+        result.unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_inputs() {
+        let (bs, mc) = setup();
+        let err = MachineBuilder::new(mc, bs, DummyExterns)
+            .upgrade_schedule([(0, NetworkVersion::V18), (5, NetworkVersion::V19)])
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            MachineBuildError::NetworkVersionMismatch {
+                epoch: 10,
+                expected: NetworkVersion::V19,
+                actual: NetworkVersion::V18,
+            }
+        ));
+
+        let missing = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(b"missing"));
+
+        let (bs, mut mc) = setup();
+        mc.initial_state_root = missing;
+        let err = MachineBuilder::new(mc, bs, DummyExterns)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, MachineBuildError::MissingStateRoot(_)));
+
+        let (bs, mut mc) = setup();
+        mc.builtin_actors_override = Some(missing);
+        let err = MachineBuilder::new(mc, bs, DummyExterns)
+            .build()
+            .err()
+            .unwrap();
+        assert!(matches!(err, MachineBuildError::InvalidManifest(_)));
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Import and export of state snapshots as (CARv1) CAR files.
+use std::collections::HashSet;
+use std::io::{Read, Write};
+
+use anyhow::{anyhow, Context};
+use cid::Cid;
+use futures::executor::block_on;
+use futures::io::AllowStdIo;
+use fvm_ipld_blockstore::{scan_dag_cbor_links, Blockstore};
+use fvm_ipld_car::{load_car, CarHeader};
+use fvm_ipld_encoding::DAG_CBOR;
+use fvm_shared::commcid::{FIL_COMMITMENT_SEALED, FIL_COMMITMENT_UNSEALED};
+use fvm_shared::IDENTITY_HASH;
+
+/// Writes every block reachable from `root` to `writer` as a CAR file with `root` as its only root.
+///
+/// Blocks are written in depth-first order, starting with the root, and each block is written
+/// exactly once. Links are followed through DAG-CBOR blocks. Inline (identity) CIDs and piece
+/// commitments are not written, but every other linked block must be present in the blockstore.
+pub fn export_car<B, W>(blockstore: &B, root: &Cid, mut writer: W) -> anyhow::Result<()>
+where
+    B: Blockstore,
+    W: Write + Send,
+{
+    let mut walker = DagWalker {
+        blockstore,
+        stack: vec![*root],
+        seen: HashSet::new(),
+        error: None,
+    };
+    let mut writer = AllowStdIo::new(&mut writer);
+    block_on(
+        CarHeader::from(vec![*root])
+            .write_stream_async(&mut writer, &mut futures::stream::iter(&mut walker)),
+    )
+    .context("failed to write car file")?;
+    match walker.error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Loads all blocks from a CAR file into the blockstore, validating their CIDs, and returns the
+/// CAR file's roots.
+pub fn import_car<B, R>(blockstore: &B, mut reader: R) -> anyhow::Result<Vec<Cid>>
+where
+    B: Blockstore,
+    R: Read + Send,
+{
+    block_on(load_car(blockstore, AllowStdIo::new(&mut reader))).context("failed to load car file")
+}
+
+/// Walks a DAG in depth-first order, yielding each block. Stops (and records the error) on the first
+/// failure.
+struct DagWalker<'a, B> {
+    blockstore: &'a B,
+    stack: Vec<Cid>,
+    seen: HashSet<Cid>,
+    error: Option<anyhow::Error>,
+}
+
+impl<'a, B: Blockstore> DagWalker<'a, B> {
+    fn visit(&mut self, k: Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        if !self.seen.insert(k) {
+            return Ok(None);
+        }
+        if k.hash().code() == IDENTITY_HASH
+            || matches!(k.codec(), FIL_COMMITMENT_SEALED | FIL_COMMITMENT_UNSEALED)
+        {
+            return Ok(None);
+        }
+        let block = self
+            .blockstore
+            .get(&k)?
+            .ok_or_else(|| anyhow!("missing block {}", k))?;
+        if k.codec() == DAG_CBOR {
+            let start = self.stack.len();
+            scan_dag_cbor_links(&block, |link| {
+                self.stack.push(link);
+                Ok(())
+            })
+            .with_context(|| format!("failed to scan block {} for links", k))?;
+            // Visit links in the order in which they appear in the block.
+            self.stack[start..].reverse();
+        }
+        Ok(Some(block))
+    }
+}
+
+impl<'a, B: Blockstore> Iterator for DagWalker<'a, B> {
+    type Item = (Cid, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        while let Some(k) = self.stack.pop() {
+            match self.visit(k) {
+                Ok(Some(block)) => return Some((k, block)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.error = Some(e);
+                    return None;
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_ipld_blockstore::MemoryBlockstore;
+    use fvm_ipld_encoding::CborStore;
+    use multihash::Code;
+
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let bs = MemoryBlockstore::default();
+        let leaf = bs.put_cbor(&"leaf", Code::Blake2b256).unwrap();
+        let middle = bs.put_cbor(&(1u8, leaf), Code::Blake2b256).unwrap();
+        let root = bs.put_cbor(&(middle, leaf), Code::Blake2b256).unwrap();
+        let unreachable = bs.put_cbor(&"unreachable", Code::Blake2b256).unwrap();
+
+        let mut car = Vec::new();
+        export_car(&bs, &root, &mut car).unwrap();
+
+        let imported = MemoryBlockstore::default();
+        assert_eq!(import_car(&imported, &car[..]).unwrap(), vec![root]);
+        for k in [root, middle, leaf] {
+            assert_eq!(imported.get(&k).unwrap(), bs.get(&k).unwrap());
+        }
+        assert!(!imported.has(&unreachable).unwrap());
+    }
+
+    #[test]
+    fn missing_block() {
+        let bs = MemoryBlockstore::default();
+        let missing = MemoryBlockstore::default()
+            .put_cbor(&"missing", Code::Blake2b256)
+            .unwrap();
+        let root = bs.put_cbor(&(missing,), Code::Blake2b256).unwrap();
+
+        let mut car = Vec::new();
+        assert!(export_car(&bs, &root, &mut car).is_err());
+    }
+}
@@ -4,8 +4,8 @@ use std::ops::RangeInclusive;
 
 use anyhow::{anyhow, Context as _};
 use cid::Cid;
-use fvm_ipld_blockstore::{Block, Blockstore, Buffered};
-use fvm_ipld_encoding::{to_vec, CborStore, DAG_CBOR};
+use fvm_ipld_blockstore::{Block, Blockstore, BlockstoreStats, Buffered};
+use fvm_ipld_encoding::{to_vec, DAG_CBOR};
 use fvm_shared::version::NetworkVersion;
 use log::debug;
 use multihash::Code::Blake2b256;
@@ -20,6 +20,24 @@ use crate::state_tree::StateTree;
 use crate::system_actor::State as SystemActorState;
 use crate::EMPTY_ARR_CID;
 
+/// The network versions supported by this version of the FVM.
+#[cfg(not(feature = "hyperspace"))]
+pub const SUPPORTED_VERSIONS: RangeInclusive<NetworkVersion> =
+    NetworkVersion::V18..=NetworkVersion::V21;
+
+/// The network versions supported by this version of the FVM.
+#[cfg(feature = "hyperspace")]
+pub const SUPPORTED_VERSIONS: RangeInclusive<NetworkVersion> =
+    NetworkVersion::V18..=NetworkVersion::MAX;
+
+/// Returns true if this version of the FVM can run the network version: either one of the
+/// [`SUPPORTED_VERSIONS`], or a future version with a registered price list (see
+/// [`crate::gas::register_price_list`]).
+pub(super) fn is_supported_version(network_version: NetworkVersion) -> bool {
+    SUPPORTED_VERSIONS.contains(&network_version)
+        || crate::gas::has_registered_price_list(network_version)
+}
+
 lazy_static::lazy_static! {
     /// Pre-serialized block containing the empty array
     pub static ref EMPTY_ARRAY_BLOCK: Block<Vec<u8>> = {
@@ -59,20 +77,12 @@ where
     /// * `blockstore`: The underlying [blockstore][`Blockstore`] for reading/writing state.
     /// * `externs`: Client-provided ["external"][`Externs`] methods for accessing chain state.
     pub fn new(context: &MachineContext, blockstore: B, externs: E) -> anyhow::Result<Self> {
-        #[cfg(not(feature = "hyperspace"))]
-        const SUPPORTED_VERSIONS: RangeInclusive<NetworkVersion> =
-            NetworkVersion::V18..=NetworkVersion::V20;
-
-        #[cfg(feature = "hyperspace")]
-        const SUPPORTED_VERSIONS: RangeInclusive<NetworkVersion> =
-            NetworkVersion::V18..=NetworkVersion::MAX;
-
         debug!(
             "initializing a new machine, epoch={}, base_fee={}, nv={:?}, root={}",
             context.epoch, &context.base_fee, context.network_version, context.initial_state_root
         );
 
-        if !SUPPORTED_VERSIONS.contains(&context.network_version) {
+        if !is_supported_version(context.network_version) {
             return Err(anyhow!(
                 "unsupported network version: {}",
                 context.network_version
@@ -95,25 +105,16 @@ where
         // Create a new state tree from the supplied root.
         let state_tree = {
             let bstore = BufferedBlockstore::new(blockstore);
-            StateTree::new_from_root(bstore, &context.initial_state_root)?
+            let mut state_tree = StateTree::new_from_root_with_config(
+                bstore,
+                &context.initial_state_root,
+                context.network.state_tree_hamt.clone(),
+            )?;
+            state_tree.set_node_cache_size(context.network.state_tree_node_cache_size);
+            state_tree
         };
 
-        // Load the built-in actors manifest.
-        let (builtin_actors_cid, manifest_version) = match context.builtin_actors_override {
-            Some(manifest_cid) => {
-                let (version, cid): (u32, Cid) = state_tree
-                    .store()
-                    .get_cbor(&manifest_cid)?
-                    .context("failed to load actor manifest")?;
-                (cid, version)
-            }
-            None => {
-                let (state, _) = SystemActorState::load(&state_tree)?;
-                (state.builtin_actors, 1)
-            }
-        };
-        let builtin_actors =
-            Manifest::load(state_tree.store(), &builtin_actors_cid, manifest_version)?;
+        let builtin_actors = load_manifest(&state_tree, context)?;
 
         // 16 bytes is random _enough_
         let randomness: [u8; 16] = rand::random();
@@ -130,6 +131,19 @@ where
             ),
         })
     }
+
+    /// Restarts the machine with a new execution context (e.g., for the next epoch), reusing the
+    /// blockstore and externs. This is equivalent to constructing a new [`DefaultMachine`] from
+    /// the parts of this one.
+    ///
+    /// Any changes that haven't been flushed are discarded, so callers will usually
+    /// [`flush`](Machine::flush) the machine and start the new context from the returned state
+    /// root. The engine (and its module cache) lives outside the machine and can be reused by
+    /// constructing a new executor with the same [`EnginePool`](crate::engine::EnginePool).
+    pub fn restart(self, context: &MachineContext) -> anyhow::Result<Self> {
+        let blockstore = self.state_tree.into_store().into_inner();
+        DefaultMachine::new(context, blockstore, self.externs)
+    }
 }
 
 impl<B, E> Machine for DefaultMachine<B, E>
@@ -172,10 +186,19 @@ where
     /// constructed).
     fn flush(&mut self) -> Result<Cid> {
         let root = self.state_tree_mut().flush()?;
+        let before = self.blockstore().stats();
         self.blockstore().flush(&root).or_fatal()?;
+        let flushed = self.blockstore().stats().since(&before);
+        self.context()
+            .metrics
+            .blockstore_flushed(flushed.blocks_flushed, flushed.bytes_flushed);
         Ok(root)
     }
 
+    fn blockstore_stats(&self) -> Option<BlockstoreStats> {
+        Some(self.blockstore().stats())
+    }
+
     fn into_store(self) -> Self::Blockstore {
         self.state_tree.into_store()
     }
@@ -189,6 +212,21 @@ where
     }
 }
 
+/// Loads the built-in actors manifest, either from the override in the machine context or from the
+/// system actor's state.
+pub(super) fn load_manifest<B: Blockstore>(
+    state_tree: &StateTree<B>,
+    context: &MachineContext,
+) -> anyhow::Result<Manifest> {
+    match context.builtin_actors_override {
+        Some(bundle_root) => Manifest::load_bundle(state_tree.store(), &bundle_root),
+        None => {
+            let (state, _) = SystemActorState::load(state_tree)?;
+            Manifest::load(state_tree.store(), &state.builtin_actors, 1)
+        }
+    }
+}
+
 // Helper method that puts certain "empty" types in the blockstore.
 // These types are privileged by some parts of the system (eg. as the default actor state).
 fn put_empty_blocks<B: Blockstore>(blockstore: B) -> anyhow::Result<()> {
@@ -1,11 +1,16 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
 
 use anyhow::{anyhow, Context};
 use cid::Cid;
 use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::CborStore;
+use fvm_shared::version::NetworkVersion;
+
+use super::car::import_car;
+use super::NetworkConfig;
 
 const ACCOUNT_ACTOR_NAME: &str = "account";
 const INIT_ACTOR_NAME: &str = "init";
@@ -14,6 +19,14 @@ const PLACEHOLDER_ACTOR_NAME: &str = "placeholder";
 const EAM_ACTOR_NAME: &str = "eam";
 const ETHACCOUNT_ACTOR_NAME: &str = "ethaccount";
 
+/// The builtin actors invoked by (almost) every block.
+const HOT_ACTOR_NAMES: &[&str] = &[
+    SYSTEM_ACTOR_NAME,
+    ACCOUNT_ACTOR_NAME,
+    "storagemarket",
+    "storageminer",
+];
+
 /// A mapping of builtin actor CIDs to their respective types.
 pub struct Manifest {
     account_code: Cid,
@@ -25,6 +38,7 @@ pub struct Manifest {
 
     by_id: HashMap<u32, Cid>,
     by_code: HashMap<Cid, u32>,
+    by_name: HashMap<String, Cid>,
 }
 
 /// Create an "id CID" (for testing).
@@ -85,6 +99,15 @@ impl Manifest {
         Manifest::new(vec)
     }
 
+    /// Load a manifest from the blockstore, given the root CID of a builtin actors bundle (a
+    /// `(version, manifest)` tuple).
+    pub fn load_bundle<B: Blockstore>(bs: &B, bundle_root: &Cid) -> anyhow::Result<Manifest> {
+        let (version, manifest_cid): (u32, Cid) = bs
+            .get_cbor(bundle_root)?
+            .context("failed to load actor manifest")?;
+        Manifest::load(bs, &manifest_cid, version)
+    }
+
     /// Construct a new manifest from actor name/cid tuples.
     pub fn new(iter: impl IntoIterator<Item = (impl Into<String>, Cid)>) -> anyhow::Result<Self> {
         let mut by_name = HashMap::new();
@@ -133,6 +156,7 @@ impl Manifest {
             ethaccount_code,
             by_id,
             by_code,
+            by_name,
         })
     }
 
@@ -146,6 +170,21 @@ impl Manifest {
         self.by_code.get(code).copied().unwrap_or(0)
     }
 
+    /// Returns the code CID for a builtin actor, given the actor's name in the manifest (e.g.,
+    /// "storageminer").
+    pub fn code_by_name(&self, name: &str) -> Option<&Cid> {
+        self.by_name.get(name)
+    }
+
+    /// Returns the code CIDs of the builtin actors invoked by (almost) every block: the system,
+    /// account, storage market, and storage miner actors. Actors missing from the manifest are
+    /// skipped. See [`Machine::preload_modules`](super::Machine::preload_modules).
+    pub fn hot_actor_codes(&self) -> impl Iterator<Item = &Cid> {
+        HOT_ACTOR_NAMES
+            .iter()
+            .filter_map(|name| self.code_by_name(name))
+    }
+
     /// Returns true id the passed code CID is the account actor.
     pub fn is_account_actor(&self, cid: &Cid) -> bool {
         &self.account_code == cid
@@ -195,3 +234,146 @@ impl Manifest {
         &self.ethaccount_code
     }
 }
+
+/// The builtin actor bundles to use at each network version.
+///
+/// This allows a node to execute across network upgrades where the builtin actor code CIDs change
+/// by selecting the bundle for the network version when configuring the machine (see
+/// [`BuiltinActorBundles::configure`]). Each network version uses the bundle registered for the
+/// latest network version at or before it.
+#[derive(Clone, Debug, Default)]
+pub struct BuiltinActorBundles {
+    bundles: BTreeMap<NetworkVersion, Cid>,
+}
+
+impl BuiltinActorBundles {
+    /// Create an empty set of bundles.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the bundle with the given root CID (already in the blockstore) for the given
+    /// network version and later.
+    pub fn insert(&mut self, nv: NetworkVersion, bundle_root: Cid) -> &mut Self {
+        self.bundles.insert(nv, bundle_root);
+        self
+    }
+
+    /// Load a bundle CAR into the blockstore and register it for the given network version and
+    /// later. Returns the bundle's root CID.
+    ///
+    /// Fails if the CAR file doesn't have exactly one root, or the root isn't a valid manifest.
+    pub fn import_car<B, R>(&mut self, bs: &B, nv: NetworkVersion, reader: R) -> anyhow::Result<Cid>
+    where
+        B: Blockstore,
+        R: Read + Send,
+    {
+        let bundle_root = match &*import_car(bs, reader)? {
+            [root] => *root,
+            roots => {
+                return Err(anyhow!(
+                    "expected exactly one root CID in bundle, found {}",
+                    roots.len()
+                ))
+            }
+        };
+        Manifest::load_bundle(bs, &bundle_root)
+            .with_context(|| format!("invalid builtin actors bundle {}", bundle_root))?;
+        self.insert(nv, bundle_root);
+        Ok(bundle_root)
+    }
+
+    /// Returns the root CID of the bundle to use at the given network version, if any.
+    pub fn get(&self, nv: NetworkVersion) -> Option<&Cid> {
+        self.bundles.range(..=nv).next_back().map(|(_, root)| root)
+    }
+
+    /// Load the manifest of the bundle to use at the given network version.
+    pub fn load_manifest<B: Blockstore>(
+        &self,
+        bs: &B,
+        nv: NetworkVersion,
+    ) -> anyhow::Result<Manifest> {
+        let bundle_root = self
+            .get(nv)
+            .with_context(|| format!("no builtin actors bundle for network version {}", nv))?;
+        Manifest::load_bundle(bs, bundle_root)
+    }
+
+    /// Configure the network to use the bundle for its network version, if any.
+    pub fn configure(&self, nc: &mut NetworkConfig) {
+        if let Some(bundle_root) = self.get(nc.network_version) {
+            nc.override_actors(*bundle_root);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_ipld_blockstore::MemoryBlockstore;
+    use multihash::Code;
+
+    use super::*;
+    use crate::machine::export_car;
+
+    fn put_bundle(bs: &MemoryBlockstore) -> Cid {
+        let manifest = bs
+            .put_cbor(&Manifest::DUMMY_CODES, Code::Blake2b256)
+            .unwrap();
+        bs.put_cbor(&(1u32, manifest), Code::Blake2b256).unwrap()
+    }
+
+    #[test]
+    fn hot_actor_codes() {
+        let manifest = Manifest::dummy();
+        assert_eq!(
+            manifest.code_by_name("account"),
+            Some(manifest.get_account_code())
+        );
+        assert_eq!(manifest.code_by_name("storageminer"), None);
+        // The dummy manifest has no market or miner actors.
+        assert_eq!(
+            manifest.hot_actor_codes().collect::<Vec<_>>(),
+            [manifest.get_system_code(), manifest.get_account_code()]
+        );
+    }
+
+    #[test]
+    fn bundles_by_network_version() {
+        let bs = MemoryBlockstore::default();
+        let v1 = put_bundle(&bs);
+
+        let mut car = Vec::new();
+        export_car(&bs, &v1, &mut car).unwrap();
+
+        let mut bundles = BuiltinActorBundles::new();
+        let imported = MemoryBlockstore::default();
+        assert_eq!(
+            bundles
+                .import_car(&imported, NetworkVersion::V18, &car[..])
+                .unwrap(),
+            v1
+        );
+
+        assert_eq!(bundles.get(NetworkVersion::V17), None);
+        assert_eq!(bundles.get(NetworkVersion::V18), Some(&v1));
+        assert_eq!(bundles.get(NetworkVersion::V19), Some(&v1));
+
+        let v2 = Cid::default();
+        bundles.insert(NetworkVersion::V19, v2);
+        assert_eq!(bundles.get(NetworkVersion::V18), Some(&v1));
+        assert_eq!(bundles.get(NetworkVersion::V20), Some(&v2));
+
+        let manifest = bundles
+            .load_manifest(&imported, NetworkVersion::V18)
+            .unwrap();
+        assert_eq!(manifest.code_by_id(1), Some(&Manifest::DUMMY_CODES[0].1));
+        assert!(bundles
+            .load_manifest(&imported, NetworkVersion::V17)
+            .is_err());
+
+        let mut nc = NetworkConfig::new(NetworkVersion::V18);
+        bundles.configure(&mut nc);
+        assert_eq!(nc.builtin_actors_override, Some(v1));
+    }
+}
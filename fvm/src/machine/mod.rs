@@ -1,35 +1,53 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+use std::sync::Arc;
+use std::time::Duration;
+
 use cid::Cid;
 use derive_more::{Deref, DerefMut};
-use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_blockstore::{Blockstore, BlockstoreStats};
+use fvm_ipld_hamt::Config as HamtConfig;
 use fvm_shared::clock::ChainEpoch;
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::version::NetworkVersion;
 use fvm_shared::ActorID;
 use num_traits::Zero;
 
+use crate::call_manager::ReentrancyPolicy;
+use crate::engine::{Engine, WasmFeatures, WasmLimits};
 use crate::externs::Externs;
-use crate::gas::{price_list_by_network_version, PriceList};
+use crate::gas::{price_list_by_network_version, DefaultFeePolicy, FeePolicy, PriceList};
 use crate::kernel::Result;
+use crate::metrics::{Metrics, NoopMetrics};
 use crate::state_tree::StateTree;
+use crate::syscall_error;
 
+mod builder;
+mod car;
 mod default;
 
-pub use default::DefaultMachine;
+pub use builder::{MachineBuildError, MachineBuilder};
+pub use car::{export_car, import_car};
+pub use default::{DefaultMachine, SUPPORTED_VERSIONS};
 use fvm_shared::chainid::ChainID;
 
 pub mod limiter;
 mod manifest;
 
-pub use manifest::Manifest;
+pub use manifest::{BuiltinActorBundles, Manifest};
 
 use self::limiter::MemoryLimiter;
 
 mod boxed;
+mod parallel;
+
+pub use parallel::{BatchMessage, ParallelExecutor};
 
 pub const REWARD_ACTOR_ID: ActorID = 2;
 
+/// Distinguished actor invoked by the system at the end of every epoch.
+pub const CRON_ACTOR_ID: ActorID = 3;
+
 /// Distinguished Account actor that is the destination of all burnt funds.
 pub const BURNT_FUNDS_ACTOR_ID: ActorID = 99;
 
@@ -73,6 +91,61 @@ pub trait Machine: 'static {
         self.state_tree_mut().flush()
     }
 
+    /// Deletes the actor with the given ID from the state tree, transferring its remaining balance
+    /// to `beneficiary`.
+    ///
+    /// This operates directly on the state tree and isn't metered. Actors delete themselves via the
+    /// (metered) `self::self_destruct` syscall instead.
+    fn delete_actor(&mut self, id: ActorID, beneficiary: ActorID) -> Result<()> {
+        let actor = self
+            .state_tree()
+            .get_actor(id)?
+            .ok_or_else(|| syscall_error!(NotFound; "actor {} not found", id))?;
+        if !actor.balance.is_zero() {
+            if beneficiary == id {
+                return Err(syscall_error!(Forbidden; "benefactor cannot be beneficiary").into());
+            }
+            let mut beneficiary_actor = self
+                .state_tree()
+                .get_actor(beneficiary)?
+                .ok_or_else(|| syscall_error!(NotFound; "beneficiary {} not found", beneficiary))?;
+            beneficiary_actor.deposit_funds(&actor.balance);
+            self.state_tree_mut()
+                .set_actor(beneficiary, beneficiary_actor);
+        }
+        self.state_tree_mut().delete_actor(id);
+        Ok(())
+    }
+
+    /// Writes the state reachable from `root` (usually a state root returned by
+    /// [`flush`](Machine::flush)) to `writer` as a CAR file. See [`export_car`].
+    ///
+    /// Use [`import_car`] to load the exported state into a blockstore before constructing a
+    /// machine on top of it.
+    fn export_state_car<W>(&self, root: &Cid, writer: W) -> anyhow::Result<()>
+    where
+        Self: Sized,
+        W: std::io::Write + Send,
+    {
+        export_car(self.blockstore(), root, writer)
+    }
+
+    /// Compiles the actors with the given code CIDs (loaded from the machine's blockstore) and pins
+    /// them in the engine's module cache so they're never evicted. See [`Engine::preload_pinned`].
+    ///
+    /// Call this at startup with frequently invoked actors (e.g.,
+    /// [`Manifest::hot_actor_codes`]) so the first blocks validated don't pay for compiling them.
+    ///
+    /// Returns the total original byte size of the modules.
+    fn preload_modules(&self, engine: &Engine, cids: &[Cid]) -> anyhow::Result<usize> {
+        engine.preload_pinned(self.blockstore(), cids)
+    }
+
+    /// Returns the I/O statistics for the machine's blockstore, if tracked.
+    fn blockstore_stats(&self) -> Option<BlockstoreStats> {
+        None
+    }
+
     /// Consumes the machine and returns the owned blockstore.
     fn into_store(self) -> Self::Blockstore;
 
@@ -95,11 +168,19 @@ pub struct NetworkConfig {
     /// DEFAULT: 0 (Invalid)
     pub chain_id: ChainID,
 
-    /// The maximum call depth.
+    /// The maximum call depth. Sends that would exceed this depth fail with a `LimitExceeded`
+    /// syscall error (or `SYS_LIMIT_EXCEEDED` if it's the top-level call).
     ///
     /// DEFAULT: 1024
     pub max_call_depth: u32,
 
+    /// The maximum number of sends (including plain value transfers and the top-level call) a
+    /// single message may make. Sends past this limit fail just like sends exceeding the maximum
+    /// call depth.
+    ///
+    /// DEFAULT: `None` (unlimited)
+    pub max_sends_per_message: Option<u64>,
+
     /// The maximum number of elements on wasm stack
     /// DEFAULT: 64Ki (512KiB of u64 elements)
     pub max_wasm_stack: u32,
@@ -121,6 +202,52 @@ pub struct NetworkConfig {
     /// DEFAULT: 1MiB
     pub max_block_size: usize,
 
+    /// The maximum number of links a (DAG-CBOR) block created by an actor may contain. Creating a
+    /// block with more links fails with a `LimitExceeded` syscall error.
+    ///
+    /// DEFAULT: `None` (unlimited)
+    pub max_block_links: Option<usize>,
+
+    /// The maximum number of blocks actors may write (link) during a single message's execution.
+    /// Writes past this limit fail with a `LimitExceeded` syscall error. Blocks written by calls
+    /// that are reverted don't count towards the limit.
+    ///
+    /// DEFAULT: `None` (unlimited)
+    pub max_blocks_written_per_message: Option<u64>,
+
+    /// The maximum size of the value returned by a single call, in bytes. Calls returning larger
+    /// values fail with `SYS_LIMIT_EXCEEDED` (reverting their changes).
+    ///
+    /// DEFAULT: `None` (only bounded by the maximum block size)
+    pub max_return_size: Option<u32>,
+
+    /// The maximum total size of the values returned by all calls made while executing a single
+    /// message (including calls that fail or are reverted), in bytes. This bounds the memory
+    /// retained by return values in deep call chains. Calls returning values past this limit fail
+    /// just like calls exceeding [`NetworkConfig::max_return_size`].
+    ///
+    /// DEFAULT: `None` (unlimited)
+    pub max_return_bytes_per_message: Option<u64>,
+
+    /// The maximum number of epochs actors may look back when drawing randomness (from either the
+    /// ticket chain or the beacon). Drawing randomness from further back fails with a
+    /// `LimitExceeded` syscall error.
+    ///
+    /// DEFAULT: `None` (unlimited)
+    pub max_randomness_lookback: Option<ChainEpoch>,
+
+    /// The maximum amount of entropy actors may mix into randomness, in bytes. Drawing randomness
+    /// with more entropy fails with a `LimitExceeded` syscall error.
+    ///
+    /// DEFAULT: `None` (only bounded by the actor's memory)
+    pub max_randomness_entropy: Option<usize>,
+
+    /// What to do when an actor is called while it's already executing further up the call stack.
+    /// Denying reentrant calls makes them fail with a `Forbidden` syscall error.
+    ///
+    /// DEFAULT: [`ReentrancyPolicy::Allow`]
+    pub reentrancy_policy: ReentrancyPolicy,
+
     /// An override for builtin-actors. If specified, this should be the CID of a builtin-actors
     /// "manifest".
     ///
@@ -137,8 +264,72 @@ pub struct NetworkConfig {
     /// DEFAULT: The price-list for the current network version.
     pub price_list: &'static PriceList,
 
+    /// The fee policy, determining how gas fees are split between burning, the miner, and the
+    /// sender.
+    ///
+    /// DEFAULT: [`DefaultFeePolicy`] (the mainnet rules).
+    pub fee_policy: &'static dyn FeePolicy,
+
+    /// The configuration of the state tree's actors HAMT. Anything other than the default produces
+    /// state roots incompatible with Filecoin networks.
+    ///
+    /// DEFAULT: A bit width of 5, with the default HAMT settings otherwise.
+    pub state_tree_hamt: HamtConfig,
+
+    /// The maximum number of decoded state tree HAMT nodes a machine keeps between flushes (see
+    /// [`StateTree::set_node_cache_size`](crate::state_tree::StateTree::set_node_cache_size)).
+    /// This doesn't affect consensus.
+    ///
+    /// DEFAULT: unbounded
+    pub state_tree_node_cache_size: usize,
+
+    /// Restrict the blocks actors may open, link to, and set as their state root to those
+    /// "reachable" from their current state root, the parameters they received, the return
+    /// values of their sends, and the blocks they've linked within the current call.
+    ///
+    /// DEFAULT: `false`
+    pub reachability_checks: bool,
+
     /// Actor redirects for debug execution
     pub actor_redirect: Vec<(Cid, Cid)>,
+
+    /// Meter Wasm execution with wasmtime's native fuel instead of injecting gas accounting code
+    /// into actors. Fuel is converted to gas at the price list's fuel exchange rate, so this
+    /// changes gas usage and is consensus-critical.
+    ///
+    /// DEFAULT: `false`
+    pub fuel_metering: bool,
+
+    /// Require a deterministic configuration: creating an engine fails if options with
+    /// host-dependent behavior (currently, [`NetworkConfig::execution_timeout`]) are set. This
+    /// must be enabled for all consensus-critical execution. Wasm execution itself is always
+    /// deterministic (NaNs are canonicalized, and threads and relaxed SIMD are disabled)
+    /// regardless.
+    ///
+    /// DEFAULT: `true`
+    pub deterministic: bool,
+
+    /// The optional Wasm features actors may use. This is consensus-critical.
+    ///
+    /// DEFAULT: The features enabled in the current network version (see
+    /// [`WasmFeatures::for_network_version`]).
+    pub wasm_features: WasmFeatures,
+
+    /// Limits on the size, imports, and functions of user-deployed Wasm modules, checked before
+    /// they're compiled. This is consensus-critical.
+    ///
+    /// DEFAULT: The limits in the current network version (see
+    /// [`WasmLimits::for_network_version`]).
+    pub wasm_limits: WasmLimits,
+
+    /// Bound the wall-clock time a single message may spend executing actor code. Messages that
+    /// exceed this limit fail with a fatal error. Wall-clock time isn't deterministic, so this is
+    /// only intended to protect against runaway actors when execution isn't otherwise bounded
+    /// (e.g., when debugging or replaying messages) and must not be used for consensus-critical
+    /// execution. Requires disabling [`NetworkConfig::deterministic`].
+    ///
+    /// DEFAULT: `None`
+    pub execution_timeout: Option<Duration>,
 }
 
 impl NetworkConfig {
@@ -148,14 +339,34 @@ impl NetworkConfig {
             chain_id: ChainID::from(0u64),
             network_version,
             max_call_depth: 1024,
+            max_sends_per_message: None,
             max_wasm_stack: 2048,
             max_inst_memory_bytes: 512 * (1 << 20),
             max_memory_bytes: 2 * (1 << 30),
             actor_debugging: false,
             builtin_actors_override: None,
             price_list: price_list_by_network_version(network_version),
+            fee_policy: &DefaultFeePolicy,
+            state_tree_hamt: HamtConfig {
+                bit_width: fvm_shared::HAMT_BIT_WIDTH,
+                ..Default::default()
+            },
+            state_tree_node_cache_size: usize::MAX,
+            reachability_checks: false,
             actor_redirect: vec![],
             max_block_size: 1 << 20,
+            max_block_links: None,
+            max_blocks_written_per_message: None,
+            max_return_size: None,
+            max_return_bytes_per_message: None,
+            max_randomness_lookback: None,
+            max_randomness_entropy: None,
+            reentrancy_policy: ReentrancyPolicy::Allow,
+            fuel_metering: false,
+            deterministic: true,
+            wasm_features: WasmFeatures::for_network_version(network_version),
+            wasm_limits: WasmLimits::for_network_version(network_version),
+            execution_timeout: None,
         }
     }
 
@@ -166,6 +377,160 @@ impl NetworkConfig {
         self
     }
 
+    /// Enable reachability checks (see [`NetworkConfig::reachability_checks`]). This is a
+    /// consensus-critical option, so it should only be enabled for local testing or as a
+    /// network-wide parameter.
+    pub fn enable_reachability_checks(&mut self) -> &mut Self {
+        self.reachability_checks = true;
+        self
+    }
+
+    /// Meter Wasm execution with wasmtime fuel instead of instrumentation. This is a
+    /// consensus-critical option (affects gas usage) so it should only be enabled for local testing
+    /// or as a network-wide parameter.
+    pub fn enable_fuel_metering(&mut self) -> &mut Self {
+        self.fuel_metering = true;
+        self
+    }
+
+    /// Override the Wasm features actors may use (see [`NetworkConfig::wasm_features`]). This is a
+    /// consensus-critical option, so it should only be changed for local testing or as a
+    /// network-wide parameter.
+    pub fn wasm_features(&mut self, features: WasmFeatures) -> &mut Self {
+        self.wasm_features = features;
+        self
+    }
+
+    /// Override the limits on user-deployed Wasm modules (see [`NetworkConfig::wasm_limits`]).
+    /// This is a consensus-critical option, so it should only be changed for local testing or as a
+    /// network-wide parameter.
+    pub fn wasm_limits(&mut self, limits: WasmLimits) -> &mut Self {
+        self.wasm_limits = limits;
+        self
+    }
+
+    /// Set the maximum call depth. This is a consensus-critical option, so it should only be
+    /// changed for local testing or as a network-wide parameter.
+    pub fn max_call_depth(&mut self, depth: u32) -> &mut Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Limit the number of sends a single message may make. This is a consensus-critical option,
+    /// so it should only be set for local testing or as a network-wide parameter.
+    pub fn max_sends_per_message(&mut self, sends: u64) -> &mut Self {
+        self.max_sends_per_message = Some(sends);
+        self
+    }
+
+    /// Limit the number of links in blocks created by actors. This is a consensus-critical option,
+    /// so it should only be set for local testing or as a network-wide parameter.
+    pub fn max_block_links(&mut self, links: usize) -> &mut Self {
+        self.max_block_links = Some(links);
+        self
+    }
+
+    /// Limit the number of blocks actors may write during a single message's execution. This is a
+    /// consensus-critical option, so it should only be set for local testing or as a network-wide
+    /// parameter.
+    pub fn max_blocks_written_per_message(&mut self, blocks: u64) -> &mut Self {
+        self.max_blocks_written_per_message = Some(blocks);
+        self
+    }
+
+    /// Limit the size of the value returned by a single call. This is a consensus-critical option,
+    /// so it should only be set for local testing or as a network-wide parameter.
+    pub fn max_return_size(&mut self, bytes: u32) -> &mut Self {
+        self.max_return_size = Some(bytes);
+        self
+    }
+
+    /// Limit the total size of the values returned by all calls made while executing a single
+    /// message. This is a consensus-critical option, so it should only be set for local testing or
+    /// as a network-wide parameter.
+    pub fn max_return_bytes_per_message(&mut self, bytes: u64) -> &mut Self {
+        self.max_return_bytes_per_message = Some(bytes);
+        self
+    }
+
+    /// Limit how far back actors may draw randomness from. This is a consensus-critical option, so
+    /// it should only be set for local testing or as a network-wide parameter.
+    pub fn max_randomness_lookback(&mut self, epochs: ChainEpoch) -> &mut Self {
+        self.max_randomness_lookback = Some(epochs);
+        self
+    }
+
+    /// Limit the entropy actors may mix into randomness. This is a consensus-critical option, so it
+    /// should only be set for local testing or as a network-wide parameter.
+    pub fn max_randomness_entropy(&mut self, bytes: usize) -> &mut Self {
+        self.max_randomness_entropy = Some(bytes);
+        self
+    }
+
+    /// Set the [`ReentrancyPolicy`]. Anything other than [`ReentrancyPolicy::Deny`] doesn't affect
+    /// execution, but denying reentrant calls is consensus-critical, so it should only be set for
+    /// local testing or as a network-wide parameter.
+    pub fn reentrancy_policy(&mut self, policy: ReentrancyPolicy) -> &mut Self {
+        self.reentrancy_policy = policy;
+        self
+    }
+
+    /// Set the maximum number of elements on the wasm stack. This is a consensus-critical option,
+    /// so it should only be changed for local testing or as a network-wide parameter.
+    pub fn max_wasm_stack(&mut self, elements: u32) -> &mut Self {
+        self.max_wasm_stack = elements;
+        self
+    }
+
+    /// Set the maximum size of each Wasm instance's memory, in bytes. This must be a multiple of
+    /// the Wasm page size (64KiB). Growing an instance's memory past this limit fails the
+    /// `memory.grow` instruction.
+    ///
+    /// This is a consensus-critical option, so it should only be changed for local testing or as a
+    /// network-wide parameter.
+    pub fn max_inst_memory_bytes(&mut self, bytes: u64) -> &mut Self {
+        self.max_inst_memory_bytes = bytes;
+        self
+    }
+
+    /// Set the maximum memory used across all Wasm instances (and tables) during a message's
+    /// execution, in bytes. Memory growth is charged gas as it happens, but this limit bounds the
+    /// host memory a single message can use, regardless of its gas limit.
+    ///
+    /// This is a consensus-critical option, so it should only be changed for local testing or as a
+    /// network-wide parameter.
+    pub fn max_memory_bytes(&mut self, bytes: u64) -> &mut Self {
+        self.max_memory_bytes = bytes;
+        self
+    }
+
+    /// Set the fee policy. This is a consensus-critical option, so it should only be changed for
+    /// local testing or as a network-wide parameter.
+    pub fn fee_policy(&mut self, policy: &'static dyn FeePolicy) -> &mut Self {
+        self.fee_policy = policy;
+        self
+    }
+
+    /// Set the configuration of the state tree's actors HAMT (e.g., its bit width). This changes
+    /// the format of the state tree, so it should only be changed for local testing.
+    pub fn state_tree_hamt(&mut self, config: HamtConfig) -> &mut Self {
+        self.state_tree_hamt = config;
+        self
+    }
+
+    /// Set the maximum number of decoded state tree HAMT nodes to keep between flushes.
+    pub fn state_tree_node_cache_size(&mut self, nodes: usize) -> &mut Self {
+        self.state_tree_node_cache_size = nodes;
+        self
+    }
+
+    /// Bound the wall-clock time a single message may spend executing actor code. See
+    /// [`NetworkConfig::execution_timeout`].
+    pub fn execution_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.execution_timeout = Some(timeout);
+        self
+    }
+
     /// Override actors with the specific manifest. This is primarily useful for testing, or
     /// networks prior to NV16 (where the actor's "manifest" isn't specified on-chain).
     pub fn override_actors(&mut self, manifest: Cid) -> &mut Self {
@@ -194,6 +559,10 @@ impl NetworkConfig {
             initial_state_root: initial_state,
             circ_supply: fvm_shared::TOTAL_FILECOIN.clone(),
             tracing: false,
+            gas_breakdown: false,
+            syscall_recording: false,
+            invariant_checks: false,
+            metrics: Arc::new(NoopMetrics),
         }
     }
 
@@ -240,6 +609,29 @@ pub struct MachineContext {
     /// Whether or not to produce execution traces in the returned result.
     /// Not consensus-critical, but has a performance impact.
     pub tracing: bool,
+
+    /// Whether or not to produce a breakdown of gas usage by category in the returned result.
+    /// Not consensus-critical, but has a (small) performance impact.
+    pub gas_breakdown: bool,
+
+    /// Whether or not to record every syscall's arguments and result (see
+    /// [`SyscallRecord`](crate::trace::SyscallRecord)) in the returned execution trace, e.g., for
+    /// differential fuzzing between implementations. Not consensus-critical, but has a significant
+    /// performance impact.
+    pub syscall_recording: bool,
+
+    /// Whether or not to check that every message conserves the total FIL supply and leaves no
+    /// actor with a negative balance. On violation, the machine is poisoned and message execution
+    /// fails with an [`InvariantViolation`](crate::executor::InvariantViolation) describing the
+    /// problem. Not consensus-critical, but walks the entire state tree before and after every
+    /// message so it should only be enabled when testing.
+    pub invariant_checks: bool,
+
+    /// The sink into which operational metrics (messages applied, syscalls, etc.) are recorded.
+    /// Not consensus-critical.
+    ///
+    /// DEFAULT: [`NoopMetrics`]
+    pub metrics: Arc<dyn Metrics>,
 }
 
 impl MachineContext {
@@ -260,4 +652,28 @@ impl MachineContext {
         self.tracing = true;
         self
     }
+
+    /// Enable gas breakdowns. [`MachineContext::gas_breakdown`].
+    pub fn enable_gas_breakdown(&mut self) -> &mut Self {
+        self.gas_breakdown = true;
+        self
+    }
+
+    /// Enable syscall recording. [`MachineContext::syscall_recording`].
+    pub fn enable_syscall_recording(&mut self) -> &mut Self {
+        self.syscall_recording = true;
+        self
+    }
+
+    /// Enable value transfer invariant checks. [`MachineContext::invariant_checks`].
+    pub fn enable_invariant_checks(&mut self) -> &mut Self {
+        self.invariant_checks = true;
+        self
+    }
+
+    /// Set [`MachineContext::metrics`].
+    pub fn set_metrics(&mut self, metrics: Arc<dyn Metrics>) -> &mut Self {
+        self.metrics = metrics;
+        self
+    }
 }
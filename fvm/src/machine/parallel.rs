@@ -0,0 +1,443 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::marker::PhantomData;
+
+use anyhow::Context as _;
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use fvm_shared::ActorID;
+
+use super::{DefaultMachine, Machine, MachineContext, BURNT_FUNDS_ACTOR_ID, REWARD_ACTOR_ID};
+use crate::call_manager::{CallManager, DefaultCallManager};
+use crate::engine::EnginePool;
+use crate::executor::{ApplyKind, ApplyRet, DefaultExecutor, Executor, EXEC_POOL};
+use crate::externs::Externs;
+use crate::kernel::Kernel;
+use crate::state_tree::{ActorState, StateTree};
+use crate::DefaultKernel;
+
+/// Actors that receive gas fees from (almost) every message. Deposits into these actors commute,
+/// so they're merged by summing balance changes instead of being treated as conflicts. But any
+/// other access (e.g., an actor reading their balance) would observe the deposits made by earlier
+/// messages, so it conflicts with every group depositing into them.
+const FEE_SINK_ACTORS: &[ActorID] = &[REWARD_ACTOR_ID, BURNT_FUNDS_ACTOR_ID];
+
+/// A message to be applied by the [`ParallelExecutor`], along with its [`ApplyKind`] and raw
+/// (on-chain) length.
+pub type BatchMessage = (Message, ApplyKind, usize);
+
+/// An executor that applies a batch of messages concurrently, while producing the same results as
+/// applying them one at a time, in order.
+///
+/// Messages are initially grouped by sender and receiver address, and each group is applied on its
+/// own machine (on top of the same initial state root) on a separate thread. Once applied, the
+/// actors read and written by each group are compared and any groups that turn out to conflict are
+/// merged and re-applied, until all remaining groups are independent. Finally, the state-tree
+/// writes of all groups are merged (in message order) into a single new state root.
+///
+/// The blockstore `B` is cloned for each group, so all clones must share the same underlying
+/// storage (e.g., an `Arc` around a thread-safe store).
+pub struct ParallelExecutor<B, E, K = DefaultKernel<DefaultCallManager<DefaultMachine<B, E>>>> {
+    engine_pool: EnginePool,
+    context: MachineContext,
+    blockstore: B,
+    externs: E,
+    _kernel: PhantomData<fn() -> K>,
+}
+
+/// The actors accessed by a group of messages.
+#[derive(Default, Debug, PartialEq, Eq)]
+struct Footprint {
+    /// All actors read or written by the group.
+    accessed: BTreeSet<ActorID>,
+    /// All actors written by the group, along with their new state (`None` if deleted).
+    written: BTreeMap<ActorID, Option<ActorState>>,
+    /// Funds deposited (by the executor) into fee-sink actors that weren't otherwise accessed.
+    deposits: BTreeMap<ActorID, TokenAmount>,
+}
+
+impl Footprint {
+    /// Returns true if either footprint writes or deposits into an actor accessed by the other.
+    fn conflicts_with(&self, other: &Footprint) -> bool {
+        let writes_to = |a: &Footprint, b: &Footprint| {
+            a.written
+                .keys()
+                .chain(a.deposits.keys())
+                .any(|id| b.accessed.contains(id))
+        };
+        writes_to(self, other) || writes_to(other, self)
+    }
+}
+
+/// The result of applying a group of messages.
+struct GroupOutcome {
+    rets: Vec<ApplyRet>,
+    footprint: Footprint,
+}
+
+impl<B, E, K> ParallelExecutor<B, E, K>
+where
+    B: Blockstore + Clone + Send + 'static,
+    E: Externs + Clone + Send + 'static,
+    K: Kernel,
+    K::CallManager: CallManager<Machine = DefaultMachine<B, E>>,
+{
+    /// Create a new [`ParallelExecutor`]. Messages will be applied on top of the context's
+    /// `initial_state_root`.
+    pub fn new(
+        engine_pool: EnginePool,
+        context: MachineContext,
+        blockstore: B,
+        externs: E,
+    ) -> Self {
+        Self {
+            engine_pool,
+            context,
+            blockstore,
+            externs,
+            _kernel: PhantomData,
+        }
+    }
+
+    /// Returns the current state root. This is updated after every batch.
+    pub fn state_root(&self) -> Cid {
+        self.context.initial_state_root
+    }
+
+    /// Apply a batch of messages, returning their results in the order in which they were
+    /// specified. The state root is only updated if the entire batch is successfully applied.
+    pub fn execute_messages(&mut self, msgs: Vec<BatchMessage>) -> anyhow::Result<Vec<ApplyRet>> {
+        let mut groups = group_by_address(&msgs);
+        let mut outcomes: Vec<Option<GroupOutcome>> = groups.iter().map(|_| None).collect();
+
+        loop {
+            self.apply_groups(&msgs, &groups, &mut outcomes)?;
+
+            let footprints: Vec<&Footprint> = outcomes
+                .iter()
+                .map(|o| &o.as_ref().expect("all groups applied").footprint)
+                .collect();
+            let merged = merge_conflicting(&footprints);
+            if merged.len() == groups.len() {
+                break;
+            }
+
+            // Merge the conflicting groups and forget their outcomes so they're re-applied. Groups
+            // that weren't merged with anything keep their outcomes.
+            let mut new_groups = Vec::with_capacity(merged.len());
+            let mut new_outcomes = Vec::with_capacity(merged.len());
+            for members in merged {
+                if let [idx] = members[..] {
+                    new_groups.push(std::mem::take(&mut groups[idx]));
+                    new_outcomes.push(outcomes[idx].take());
+                } else {
+                    let mut group: Vec<usize> = members
+                        .into_iter()
+                        .flat_map(|idx| std::mem::take(&mut groups[idx]))
+                        .collect();
+                    group.sort_unstable();
+                    new_groups.push(group);
+                    new_outcomes.push(None);
+                }
+            }
+            groups = new_groups;
+            outcomes = new_outcomes;
+        }
+
+        // All remaining groups are independent, so we can merge their writes in any order. We
+        // merge in group order to keep things simple.
+        let mut state_tree = StateTree::new_from_root_with_config(
+            &self.blockstore,
+            &self.context.initial_state_root,
+            self.context.network.state_tree_hamt.clone(),
+        )?;
+        let mut rets: Vec<Option<ApplyRet>> = msgs.iter().map(|_| None).collect();
+        for (group, outcome) in groups.iter().zip(outcomes) {
+            let GroupOutcome {
+                rets: group_rets,
+                footprint,
+            } = outcome.expect("all groups applied");
+            for (id, actor) in footprint.written {
+                match actor {
+                    Some(actor) => state_tree.set_actor(id, actor),
+                    None => state_tree.delete_actor(id),
+                }
+            }
+            for (id, amount) in footprint.deposits {
+                state_tree.mutate_actor(id, |actor| {
+                    actor.deposit_funds(&amount);
+                    Ok(())
+                })?;
+            }
+            for (&idx, ret) in group.iter().zip(group_rets) {
+                rets[idx] = Some(ret);
+            }
+        }
+        self.context.initial_state_root = state_tree.flush()?;
+
+        Ok(rets
+            .into_iter()
+            .map(|r| r.expect("all messages applied"))
+            .collect())
+    }
+
+    /// Apply all groups that don't yet have an outcome, concurrently, on top of the current state
+    /// root.
+    fn apply_groups(
+        &self,
+        msgs: &[BatchMessage],
+        groups: &[Vec<usize>],
+        outcomes: &mut [Option<GroupOutcome>],
+    ) -> anyhow::Result<()> {
+        let mut results: Vec<anyhow::Result<()>> = groups.iter().map(|_| Ok(())).collect();
+
+        EXEC_POOL.scoped(|scope| {
+            for ((group, outcome), result) in groups
+                .iter()
+                .zip(outcomes.iter_mut())
+                .zip(results.iter_mut())
+                .filter(|((_, outcome), _)| outcome.is_none())
+            {
+                let group_msgs: Vec<BatchMessage> =
+                    group.iter().map(|&idx| msgs[idx].clone()).collect();
+                let engine_pool = self.engine_pool.clone();
+                let context = &self.context;
+                let blockstore = self.blockstore.clone();
+                let externs = self.externs.clone();
+                scope.execute(move || {
+                    *result =
+                        Self::apply_group(engine_pool, context, blockstore, externs, group_msgs)
+                            .map(|o| *outcome = Some(o));
+                });
+            }
+        });
+
+        results.into_iter().collect()
+    }
+
+    /// Apply a single group of messages, in order, on a fresh machine.
+    fn apply_group(
+        engine_pool: EnginePool,
+        context: &MachineContext,
+        blockstore: B,
+        externs: E,
+        msgs: Vec<BatchMessage>,
+    ) -> anyhow::Result<GroupOutcome> {
+        let machine = DefaultMachine::new(context, blockstore, externs)?;
+        let mut executor = DefaultExecutor::<K>::new(engine_pool, machine)?;
+        // Distinguishes fee deposits (made by the executor) from accesses made by actors.
+        executor.state_tree_mut().record_transaction_accesses();
+
+        let rets = msgs
+            .into_iter()
+            .map(|(msg, apply_kind, raw_length)| {
+                executor.execute_message(msg, apply_kind, raw_length)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let footprint = {
+            let state_tree = executor.state_tree();
+            let base = StateTree::new_from_root_with_config(
+                state_tree.store(),
+                &context.initial_state_root,
+                state_tree.hamt_config().clone(),
+            )?;
+            let actor_accesses = state_tree.transaction_accesses();
+            let mut footprint = Footprint::default();
+            let mut fee_sinks = Vec::new();
+            state_tree.for_each_cached_actor(|id, modified| {
+                if let Some(actor) = modified {
+                    if FEE_SINK_ACTORS.contains(&id) && !actor_accesses.contains(&id) {
+                        fee_sinks.push((id, actor.cloned()));
+                        return;
+                    }
+                    footprint.written.insert(id, actor.cloned());
+                }
+                footprint.accessed.insert(id);
+            });
+            for (id, actor) in fee_sinks {
+                let before = base.get_actor(id)?;
+                match (&before, &actor) {
+                    (Some(before), Some(after))
+                        if after.balance >= before.balance
+                            && ActorState {
+                                balance: before.balance.clone(),
+                                ..after.clone()
+                            } == *before =>
+                    {
+                        footprint
+                            .deposits
+                            .insert(id, &after.balance - &before.balance);
+                    }
+                    _ => {
+                        footprint.accessed.insert(id);
+                        footprint.written.insert(id, actor);
+                    }
+                }
+            }
+            footprint
+        };
+
+        // Write the group's state to the underlying blockstore so it can be merged.
+        executor
+            .flush()
+            .context("failed to flush parallel execution group")?;
+
+        Ok(GroupOutcome { rets, footprint })
+    }
+}
+
+/// Group messages such that messages sharing a sender or receiver address end up in the same
+/// group. Groups are ordered by their first message and message indices within each group are
+/// sorted.
+fn group_by_address(msgs: &[BatchMessage]) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..msgs.len()).collect();
+    let mut first_use: HashMap<Address, usize> = HashMap::new();
+    for (idx, (msg, _, _)) in msgs.iter().enumerate() {
+        for addr in [msg.from, msg.to] {
+            let other = *first_use.entry(addr).or_insert(idx);
+            union(&mut parents, idx, other);
+        }
+    }
+    collect_sets(&mut parents)
+}
+
+/// Repeatedly merge conflicting footprints, returning the sets of (indices of) footprints that
+/// must be applied together. Sets are ordered by their first member.
+fn merge_conflicting(footprints: &[&Footprint]) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..footprints.len()).collect();
+    for (i, a) in footprints.iter().enumerate() {
+        for (j, b) in footprints.iter().enumerate().skip(i + 1) {
+            if a.conflicts_with(b) {
+                union(&mut parents, i, j);
+            }
+        }
+    }
+    collect_sets(&mut parents)
+}
+
+fn find(parents: &mut [usize], mut idx: usize) -> usize {
+    while parents[idx] != idx {
+        parents[idx] = parents[parents[idx]];
+        idx = parents[idx];
+    }
+    idx
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    // Always keep the smallest index as the root so that sets are ordered deterministically.
+    parents[a.max(b)] = a.min(b);
+}
+
+fn collect_sets(parents: &mut [usize]) -> Vec<Vec<usize>> {
+    let mut sets: Vec<Vec<usize>> = Vec::new();
+    let mut set_of_root = HashMap::new();
+    for idx in 0..parents.len() {
+        let root = find(parents, idx);
+        let set = *set_of_root.entry(root).or_insert_with(|| {
+            sets.push(Vec::new());
+            sets.len() - 1
+        });
+        sets[set].push(idx);
+    }
+    sets
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_ipld_encoding::RawBytes;
+    use fvm_shared::address::Address;
+    use fvm_shared::econ::TokenAmount;
+    use fvm_shared::message::Message;
+
+    use super::*;
+
+    fn msg(from: u64, to: u64) -> BatchMessage {
+        (
+            Message {
+                version: 0,
+                from: Address::new_id(from),
+                to: Address::new_id(to),
+                sequence: 0,
+                value: TokenAmount::default(),
+                method_num: 0,
+                params: RawBytes::default(),
+                gas_limit: 1,
+                gas_fee_cap: TokenAmount::default(),
+                gas_premium: TokenAmount::default(),
+            },
+            ApplyKind::Explicit,
+            0,
+        )
+    }
+
+    fn footprint(accessed: &[ActorID], written: &[ActorID]) -> Footprint {
+        Footprint {
+            accessed: accessed.iter().chain(written).copied().collect(),
+            written: written.iter().map(|&id| (id, None)).collect(),
+            deposits: Default::default(),
+        }
+    }
+
+    #[test]
+    fn groups_by_address() {
+        let msgs = vec![
+            msg(100, 200),
+            msg(101, 201),
+            msg(102, 200),
+            msg(103, 203),
+            msg(201, 104),
+        ];
+        assert_eq!(
+            group_by_address(&msgs),
+            vec![vec![0, 2], vec![1, 4], vec![3]]
+        );
+    }
+
+    #[test]
+    fn merges_conflicts() {
+        let fps = [
+            footprint(&[1], &[100]),
+            footprint(&[1], &[101]),
+            footprint(&[100], &[102]),
+            footprint(&[], &[103, 1]),
+        ];
+        let fps: Vec<_> = fps.iter().collect();
+        // 0 & 2 conflict on 100, 3 conflicts with both 0 and 1 on 1.
+        assert_eq!(merge_conflicting(&fps), vec![vec![0, 1, 2, 3]]);
+
+        let fps = [
+            footprint(&[1], &[100]),
+            footprint(&[1], &[101]),
+            footprint(&[100], &[102]),
+        ];
+        let fps: Vec<_> = fps.iter().collect();
+        assert_eq!(merge_conflicting(&fps), vec![vec![0, 2], vec![1]]);
+    }
+
+    #[test]
+    fn deposits_conflict_with_accesses() {
+        let deposit = Footprint {
+            deposits: [(BURNT_FUNDS_ACTOR_ID, TokenAmount::from_atto(1))]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        // Deposits commute with each other.
+        let fps = [&deposit, &deposit];
+        assert_eq!(merge_conflicting(&fps), vec![vec![0], vec![1]]);
+
+        // But not with reads (or writes).
+        let read = footprint(&[BURNT_FUNDS_ACTOR_ID], &[]);
+        let write = footprint(&[], &[BURNT_FUNDS_ACTOR_ID]);
+        let other = footprint(&[], &[100]);
+        let fps = [&deposit, &other, &read, &deposit, &write];
+        assert_eq!(merge_conflicting(&fps), vec![vec![0, 2, 3, 4], vec![1]]);
+    }
+}
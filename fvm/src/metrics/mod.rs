@@ -0,0 +1,40 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Operational metrics recorded by the machine, call manager, and engine.
+//!
+//! Metrics are recorded into a [`Metrics`] sink configured with
+//! [`MachineContext::set_metrics`](crate::machine::MachineContext::set_metrics) (and, for wasm
+//! compilation, [`MultiEngine::with_metrics`](crate::engine::MultiEngine::with_metrics)). By
+//! default, nothing is recorded.
+use std::fmt::Debug;
+use std::time::Duration;
+
+mod prometheus;
+
+pub use prometheus::PrometheusMetrics;
+
+/// A sink for operational metrics. Every method defaults to doing nothing, so implementations only
+/// need to override the metrics they're interested in.
+///
+/// Metrics are recorded synchronously during execution, so implementations should be cheap (e.g.,
+/// atomic counters).
+pub trait Metrics: Debug + Send + Sync {
+    /// Records that a message was applied, along with the gas it used.
+    fn message_applied(&self, _gas_used: u64) {}
+
+    /// Records that an actor's wasm module was compiled, along with how long it took.
+    fn module_compiled(&self, _duration: Duration) {}
+
+    /// Records a syscall invocation.
+    fn syscall(&self, _module: &'static str, _name: &'static str) {}
+
+    /// Records that the machine flushed its state, along with the number of blocks and bytes
+    /// written to the underlying blockstore.
+    fn blockstore_flushed(&self, _blocks: u64, _bytes: u64) {}
+}
+
+/// A [`Metrics`] sink that discards everything.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
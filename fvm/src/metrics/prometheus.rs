@@ -0,0 +1,165 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::Metrics;
+use crate::syscalls::SYSCALLS;
+
+/// A [`Metrics`] sink that accumulates counters and renders them in the Prometheus text exposition
+/// format (see [`PrometheusMetrics::render`]), e.g., for serving from a `/metrics` endpoint.
+///
+/// All counters (including one per syscall) are registered up-front, so recording a metric never
+/// takes a lock or allocates.
+#[derive(Debug)]
+pub struct PrometheusMetrics {
+    messages_applied: AtomicU64,
+    gas_used: AtomicU64,
+    modules_compiled: AtomicU64,
+    compile_nanos: AtomicU64,
+    blocks_flushed: AtomicU64,
+    bytes_flushed: AtomicU64,
+    /// Syscall invocation counters, sorted by module and name.
+    syscalls: Vec<((&'static str, &'static str), AtomicU64)>,
+}
+
+impl PrometheusMetrics {
+    /// Creates a new sink with all counters set to zero.
+    pub fn new() -> Self {
+        let mut syscalls: Vec<_> = SYSCALLS
+            .iter()
+            .flat_map(|(module, names)| names.iter().map(move |name| (*module, *name)))
+            .map(|syscall| (syscall, AtomicU64::new(0)))
+            .collect();
+        syscalls.sort_by_key(|(syscall, _)| *syscall);
+        PrometheusMetrics {
+            messages_applied: Default::default(),
+            gas_used: Default::default(),
+            modules_compiled: Default::default(),
+            compile_nanos: Default::default(),
+            blocks_flushed: Default::default(),
+            bytes_flushed: Default::default(),
+            syscalls,
+        }
+    }
+
+    /// Renders the accumulated counters in the Prometheus text exposition format. All metric
+    /// names are prefixed with `fvm_`.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let mut counter = |name: &str, help: &str, value: &dyn std::fmt::Display| {
+            let _ = writeln!(out, "# HELP fvm_{name} {help}");
+            let _ = writeln!(out, "# TYPE fvm_{name} counter");
+            let _ = writeln!(out, "fvm_{name} {value}");
+        };
+        counter(
+            "messages_applied_total",
+            "Messages applied.",
+            &self.messages_applied.load(Ordering::Relaxed),
+        );
+        counter(
+            "gas_used_total",
+            "Gas used by applied messages.",
+            &self.gas_used.load(Ordering::Relaxed),
+        );
+        counter(
+            "wasm_modules_compiled_total",
+            "Actor wasm modules compiled.",
+            &self.modules_compiled.load(Ordering::Relaxed),
+        );
+        counter(
+            "wasm_compile_seconds_total",
+            "Time spent compiling actor wasm modules.",
+            &Duration::from_nanos(self.compile_nanos.load(Ordering::Relaxed)).as_secs_f64(),
+        );
+        counter(
+            "blockstore_flushed_blocks_total",
+            "Blocks flushed to the underlying blockstore.",
+            &self.blocks_flushed.load(Ordering::Relaxed),
+        );
+        counter(
+            "blockstore_flushed_bytes_total",
+            "Bytes flushed to the underlying blockstore.",
+            &self.bytes_flushed.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(out, "# HELP fvm_syscalls_total Syscall invocations.");
+        let _ = writeln!(out, "# TYPE fvm_syscalls_total counter");
+        for ((module, name), count) in &self.syscalls {
+            let _ = writeln!(
+                out,
+                "fvm_syscalls_total{{module=\"{module}\",name=\"{name}\"}} {}",
+                count.load(Ordering::Relaxed)
+            );
+        }
+        out
+    }
+}
+
+impl Default for PrometheusMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics for PrometheusMetrics {
+    fn message_applied(&self, gas_used: u64) {
+        self.messages_applied.fetch_add(1, Ordering::Relaxed);
+        self.gas_used.fetch_add(gas_used, Ordering::Relaxed);
+    }
+
+    fn module_compiled(&self, duration: Duration) {
+        self.modules_compiled.fetch_add(1, Ordering::Relaxed);
+        self.compile_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn syscall(&self, module: &'static str, name: &'static str) {
+        // Only the syscalls in the table can be bound, so there's always a counter.
+        if let Ok(i) = self
+            .syscalls
+            .binary_search_by_key(&(module, name), |(syscall, _)| *syscall)
+        {
+            self.syscalls[i].1.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn blockstore_flushed(&self, blocks: u64, bytes: u64) {
+        self.blocks_flushed.fetch_add(blocks, Ordering::Relaxed);
+        self.bytes_flushed.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render() {
+        let metrics = PrometheusMetrics::new();
+        metrics.message_applied(10);
+        metrics.message_applied(5);
+        metrics.module_compiled(Duration::from_millis(500));
+        metrics.syscall("ipld", "block_open");
+        metrics.syscall("ipld", "block_open");
+        metrics.blockstore_flushed(2, 100);
+
+        let out = metrics.render();
+        for line in [
+            "fvm_messages_applied_total 2",
+            "fvm_gas_used_total 15",
+            "fvm_wasm_modules_compiled_total 1",
+            "fvm_wasm_compile_seconds_total 0.5",
+            "fvm_blockstore_flushed_blocks_total 2",
+            "fvm_blockstore_flushed_bytes_total 100",
+            "fvm_syscalls_total{module=\"ipld\",name=\"block_open\"} 2",
+            "fvm_syscalls_total{module=\"ipld\",name=\"block_read\"} 0",
+        ] {
+            assert!(
+                out.lines().any(|l| l == line),
+                "missing {line:?} in:\n{out}"
+            );
+        }
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use cid::Cid;
+
+/// A thread-safe cache of intermediate migration results, shared between all actor migrations in a
+/// [`Migration`](super::Migration).
+///
+/// Migrations of large actors (e.g., miners) can take a long time. To avoid doing all that work at
+/// the upgrade epoch, a client can run a [pre-migration](super::Migration::premigrate) on some
+/// state shortly before the upgrade, and then run the real migration with the same cache. Actor
+/// migrations should cache their results keyed on their inputs (e.g., by
+/// [`MigrationCache::key`]) so that work done for actors that haven't changed in the meantime
+/// can be reused.
+#[derive(Debug, Default)]
+pub struct MigrationCache {
+    entries: RwLock<HashMap<String, Cid>>,
+}
+
+impl MigrationCache {
+    /// Creates an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a cache key for the result of migrating the object with the given CID, namespaced by
+    /// `prefix` (e.g., the name of the migration and the kind of object).
+    pub fn key(prefix: &str, cid: &Cid) -> String {
+        format!("{}-{}", prefix, cid)
+    }
+
+    /// Returns the cached value for the given key, if any.
+    pub fn get(&self, key: &str) -> Option<Cid> {
+        self.entries.read().unwrap().get(key).copied()
+    }
+
+    /// Caches a value for the given key, replacing any existing value.
+    pub fn insert(&self, key: impl Into<String>, value: Cid) {
+        self.entries.write().unwrap().insert(key.into(), value);
+    }
+
+    /// Returns the cached value for the given key, computing (and caching) it with `f` if it isn't
+    /// cached yet. The cache isn't locked while `f` runs, so `f` may be called more than once for
+    /// the same key if called concurrently.
+    pub fn get_or_try_insert_with<F>(&self, key: impl Into<String>, f: F) -> anyhow::Result<Cid>
+    where
+        F: FnOnce() -> anyhow::Result<Cid>,
+    {
+        let key = key.into();
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        let value = f()?;
+        self.insert(key, value);
+        Ok(value)
+    }
+
+    /// Returns the number of cached values.
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    /// Returns true if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
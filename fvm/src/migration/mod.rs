@@ -0,0 +1,319 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! State migrations between network versions.
+//!
+//! Network upgrades frequently change the code of (some) builtin actors, and sometimes the layout
+//! of their state. A [`Migration`] rewrites every actor in a state tree by applying the
+//! [`StateMigration`] registered for that actor's code, in parallel, producing a new state root.
+//! Actors whose code has no registered migration are left untouched.
+mod cache;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::Context as _;
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_hamt::Config as HamtConfig;
+use fvm_shared::ActorID;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+pub use self::cache::MigrationCache;
+use crate::state_tree::{ActorState, StateTree};
+
+/// The input to a [`StateMigration`].
+#[derive(Debug)]
+pub struct MigrationInput<'a> {
+    /// The ID of the actor being migrated.
+    pub id: ActorID,
+    /// The actor, before migration.
+    pub actor: &'a ActorState,
+    /// The cache shared by all actor migrations.
+    pub cache: &'a MigrationCache,
+}
+
+/// The result of a [`StateMigration`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MigrationOutput {
+    /// The actor's new code CID.
+    pub code: Cid,
+    /// The actor's new state root.
+    pub state: Cid,
+}
+
+/// Migrates the state of actors with a specific code CID.
+///
+/// Migrations are run concurrently (for different actors) and must be deterministic. Expensive
+/// migrations should cache their results in [`MigrationInput::cache`] so they can be
+/// [pre-migrated](Migration::premigrate).
+pub trait StateMigration<B>: Send + Sync {
+    /// Migrates a single actor, writing any new state to the blockstore.
+    fn migrate_state(
+        &self,
+        store: &B,
+        input: MigrationInput<'_>,
+    ) -> anyhow::Result<MigrationOutput>;
+}
+
+/// A [`StateMigration`] that changes an actor's code without touching its state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeMigration {
+    /// The actor's new code CID.
+    pub new_code: Cid,
+}
+
+impl<B> StateMigration<B> for CodeMigration {
+    fn migrate_state(
+        &self,
+        _store: &B,
+        input: MigrationInput<'_>,
+    ) -> anyhow::Result<MigrationOutput> {
+        Ok(MigrationOutput {
+            code: self.new_code,
+            state: input.actor.state,
+        })
+    }
+}
+
+/// A state migration, made up of one [`StateMigration`] per (old) actor code CID.
+pub struct Migration<B> {
+    migrations: HashMap<Cid, Arc<dyn StateMigration<B>>>,
+    workers: usize,
+    hamt_config: HamtConfig,
+}
+
+impl<B> Default for Migration<B> {
+    fn default() -> Self {
+        Self {
+            migrations: HashMap::new(),
+            workers: num_cpus::get(),
+            hamt_config: HamtConfig {
+                bit_width: fvm_shared::HAMT_BIT_WIDTH,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl<B> Migration<B>
+where
+    B: Blockstore + Send + Sync,
+{
+    /// Creates a migration that doesn't migrate any actors.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Migrates actors with the code `old_code` with the given migration. Replaces any migration
+    /// previously registered for the same code.
+    pub fn add_migration(
+        &mut self,
+        old_code: Cid,
+        migration: impl StateMigration<B> + 'static,
+    ) -> &mut Self {
+        self.migrations.insert(old_code, Arc::new(migration));
+        self
+    }
+
+    /// Changes the code of all actors with the code `old_code` to `new_code`, leaving their state
+    /// as-is. See [`CodeMigration`].
+    pub fn add_code_migration(&mut self, old_code: Cid, new_code: Cid) -> &mut Self {
+        self.add_migration(old_code, CodeMigration { new_code })
+    }
+
+    /// Sets the number of worker threads used to migrate actors.
+    ///
+    /// Default: the number of CPUs.
+    pub fn set_workers(&mut self, workers: usize) -> &mut Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets the HAMT configuration of the state tree. This must match the network's
+    /// [`NetworkConfig::state_tree_hamt`](crate::machine::NetworkConfig::state_tree_hamt).
+    ///
+    /// Default: A bit width of 5, with the default HAMT settings otherwise.
+    pub fn set_hamt_config(&mut self, hamt_config: HamtConfig) -> &mut Self {
+        self.hamt_config = hamt_config;
+        self
+    }
+
+    /// Migrates the state tree rooted at `state_root`, returning the new state root.
+    pub fn run(&self, store: &B, state_root: &Cid, cache: &MigrationCache) -> anyhow::Result<Cid> {
+        let mut state_tree =
+            StateTree::new_from_root_with_config(store, state_root, self.hamt_config.clone())?;
+        for (id, actor) in self.migrate_actors(store, &state_tree, cache)? {
+            state_tree.set_actor(id, actor);
+        }
+        Ok(state_tree.flush()?)
+    }
+
+    /// Runs all actor migrations on the state tree rooted at `state_root` to populate the cache,
+    /// without writing a new state tree. The real migration should then be [run](Self::run) with
+    /// the same cache.
+    pub fn premigrate(
+        &self,
+        store: &B,
+        state_root: &Cid,
+        cache: &MigrationCache,
+    ) -> anyhow::Result<()> {
+        let state_tree =
+            StateTree::new_from_root_with_config(store, state_root, self.hamt_config.clone())?;
+        self.migrate_actors(store, &state_tree, cache)?;
+        Ok(())
+    }
+
+    /// Migrates all actors in the state tree, returning those that changed.
+    fn migrate_actors(
+        &self,
+        store: &B,
+        state_tree: &StateTree<&B>,
+        cache: &MigrationCache,
+    ) -> anyhow::Result<Vec<(ActorID, ActorState)>> {
+        let mut jobs = Vec::new();
+        state_tree.for_each(|addr, actor| {
+            if let Some(migration) = self.migrations.get(&actor.code) {
+                jobs.push((addr.id()?, actor.clone(), migration.clone()));
+            }
+            Ok(())
+        })?;
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.workers)
+            .thread_name(|id| format!("fvm-migration-{}", id))
+            .build()
+            .context("failed to create migration worker pool")?;
+        pool.install(|| {
+            jobs.into_par_iter()
+                .filter_map(|(id, mut actor, migration)| {
+                    let input = MigrationInput {
+                        id,
+                        actor: &actor,
+                        cache,
+                    };
+                    let output = match migration
+                        .migrate_state(store, input)
+                        .with_context(|| format!("failed to migrate actor {}", id))
+                    {
+                        Ok(output) => output,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    if output.code == actor.code && output.state == actor.state {
+                        return None;
+                    }
+                    actor.code = output.code;
+                    actor.state = output.state;
+                    Some(Ok((id, actor)))
+                })
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use fvm_ipld_blockstore::MemoryBlockstore;
+    use fvm_ipld_encoding::CborStore;
+    use fvm_shared::econ::TokenAmount;
+    use fvm_shared::state::StateTreeVersion;
+    use multihash::{Code, MultihashDigest};
+    use num_traits::Zero;
+
+    use super::*;
+
+    fn code(name: &str) -> Cid {
+        Cid::new_v1(
+            fvm_shared::IPLD_RAW,
+            Code::Blake2b256.digest(name.as_bytes()),
+        )
+    }
+
+    /// Doubles the (integer) state of an actor, counting how often it actually does any work.
+    #[derive(Default)]
+    struct Doubler(Arc<AtomicUsize>);
+
+    impl StateMigration<MemoryBlockstore> for Doubler {
+        fn migrate_state(
+            &self,
+            store: &MemoryBlockstore,
+            input: MigrationInput<'_>,
+        ) -> anyhow::Result<MigrationOutput> {
+            let key = MigrationCache::key("doubler", &input.actor.state);
+            let state = input.cache.get_or_try_insert_with(key, || {
+                self.0.fetch_add(1, Ordering::Relaxed);
+                let n: u64 = store
+                    .get_cbor(&input.actor.state)?
+                    .context("missing state")?;
+                store.put_cbor(&(n * 2), Code::Blake2b256)
+            })?;
+            Ok(MigrationOutput {
+                code: code("doubler/v2"),
+                state,
+            })
+        }
+    }
+
+    #[test]
+    fn migrate() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let set = |tree: &mut StateTree<_>, id, code, n: u64| {
+            let state = store.put_cbor(&n, Code::Blake2b256).unwrap();
+            tree.set_actor(
+                id,
+                ActorState::new(code, state, TokenAmount::zero(), 0, None),
+            );
+        };
+        for id in 100..110 {
+            set(&mut tree, id, code("doubler/v1"), id);
+        }
+        set(&mut tree, 200, code("other/v1"), 1);
+        set(&mut tree, 300, code("untouched"), 1);
+        let pre_root = tree.flush().unwrap();
+
+        // One more actor gets created before the upgrade.
+        set(&mut tree, 110, code("doubler/v1"), 110);
+        let root = tree.flush().unwrap();
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut migration = Migration::new();
+        migration
+            .add_migration(code("doubler/v1"), Doubler(calls.clone()))
+            .add_code_migration(code("other/v1"), code("other/v2"))
+            .set_workers(4);
+
+        let cache = MigrationCache::new();
+        migration.premigrate(&store, &pre_root, &cache).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 10);
+        assert_eq!(cache.len(), 10);
+
+        // Only the new actor needs to be migrated from scratch.
+        let new_root = migration.run(&store, &root, &cache).unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), 11);
+
+        let tree = StateTree::new_from_root(&store, &new_root).unwrap();
+        for id in 100..111 {
+            let actor = tree.get_actor(id).unwrap().unwrap();
+            assert_eq!(actor.code, code("doubler/v2"));
+            assert_eq!(store.get_cbor::<u64>(&actor.state).unwrap(), Some(id * 2));
+        }
+        let other = tree.get_actor(200).unwrap().unwrap();
+        assert_eq!(other.code, code("other/v2"));
+        assert_eq!(store.get_cbor::<u64>(&other.state).unwrap(), Some(1));
+        assert_eq!(
+            tree.get_actor(300).unwrap().unwrap().code,
+            code("untouched")
+        );
+
+        // Migrating is deterministic, regardless of the number of workers or the cache.
+        migration.set_workers(1);
+        assert_eq!(
+            migration
+                .run(&store, &root, &MigrationCache::new())
+                .unwrap(),
+            new_root
+        );
+    }
+}
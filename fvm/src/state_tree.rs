@@ -2,14 +2,15 @@
 // Copyright 2019-2022 ChainSafe Systems
 // SPDX-License-Identifier: Apache-2.0, MIT
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use anyhow::{anyhow, Context as _};
 use cid::{multihash, Cid};
 use fvm_ipld_blockstore::Blockstore;
 use fvm_ipld_encoding::tuple::*;
 use fvm_ipld_encoding::CborStore;
-use fvm_ipld_hamt::Hamt;
+use fvm_ipld_hamt::{Change, Config as HamtConfig, Hamt};
 use fvm_shared::address::{Address, Payload};
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::state::{StateInfo0, StateRoot, StateTreeVersion};
@@ -19,25 +20,109 @@ use num_traits::Zero;
 use quickcheck::Arbitrary;
 
 use crate::history_map::HistoryMap;
-use crate::init_actor::State as InitActorState;
+use crate::init_actor::{State as InitActorState, INIT_ACTOR_ID};
 use crate::kernel::{ClassifyResult, ExecutionError, Result};
 use crate::{syscall_error, EMPTY_ARR_CID};
 
 /// State tree implementation using hamt. This structure is not threadsafe and should only be used
 /// in sync contexts.
+///
+/// Actors are loaded lazily: opening a state tree only loads the root HAMT node, and each actor
+/// (along with the HAMT nodes on its path) is only loaded the first time it's looked up.
+/// Modifications are kept in a copy-on-write overlay (the actor cache) and are only written back
+/// into the HAMT on [`StateTree::flush`], so a tipset that touches a small fraction of actors only
+/// loads (and rewrites) that fraction of the tree. Actors that were modified but end up back
+/// in their original state don't produce any new blocks.
 pub struct StateTree<S> {
-    hamt: Hamt<S, ActorState>,
+    hamt: Hamt<NodeStore<S>, ActorState>,
+    /// The configuration of the actors HAMT.
+    hamt_config: HamtConfig,
+    /// The maximum number of decoded HAMT nodes to keep across flushes.
+    node_cache_size: usize,
 
     version: StateTreeVersion,
     info: Option<Cid>,
 
-    /// An actor-state cache that internally keeps an undo history.
+    /// An actor-state cache that internally keeps an undo history. The cache lives as long as the
+    /// state tree (i.e., across all messages applied by a machine) and is updated on writes, so
+    /// actors are only decoded the first time they're looked up.
     actor_cache: RefCell<HistoryMap<ActorID, ActorCacheEntry>>,
-    /// An actor-address cache that internally keeps an undo history.
+    /// An actor-address cache that internally keeps an undo history. It's invalidated whenever the
+    /// init actor's state is replaced (except when registering new addresses).
     resolve_cache: RefCell<HistoryMap<Address, ActorID>>,
+    /// Actor cache hit/miss counters.
+    actor_stats: Cell<CacheStats>,
+    /// Address resolution cache hit/miss counters.
+    resolve_stats: Cell<CacheStats>,
     /// Snapshot layers. Each layer contains points in the actor/resolve cache histories to which
-    /// said caches will be reverted on revert.
+    /// said caches will be reverted on revert, along with the blocks written in the layer (see
+    /// [`StateTree::put_block`]).
     layers: Vec<StateSnapLayer>,
+    /// Actors accessed inside of transactions, if being recorded (see
+    /// [`StateTree::record_transaction_accesses`]).
+    transaction_accesses: RefCell<Option<BTreeSet<ActorID>>>,
+}
+
+/// Statistics on one of the caches of a [`StateTree`]. See [`StateTree::actor_cache_stats`] and
+/// [`StateTree::resolve_cache_stats`].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CacheStats {
+    /// Number of lookups served from the cache.
+    pub hits: u64,
+    /// Number of lookups that had to load state (whether or not anything was found).
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// The fraction of lookups served from the cache, or 0 if there haven't been any lookups.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+/// The actors HAMT configuration used by all Filecoin networks (state tree versions 3 and up use
+/// a bit width of 5).
+fn default_hamt_config() -> HamtConfig {
+    HamtConfig {
+        bit_width: HAMT_BIT_WIDTH,
+        ..Default::default()
+    }
+}
+
+/// The store backing the actors HAMT. It counts the HAMT nodes loaded since the HAMT's root was
+/// last (re)loaded, all of which the HAMT keeps decoded (see [`StateTree::set_node_cache_size`]).
+struct NodeStore<S> {
+    store: S,
+    loaded: Cell<usize>,
+}
+
+impl<S> NodeStore<S> {
+    fn new(store: S) -> Self {
+        NodeStore {
+            store,
+            loaded: Cell::new(0),
+        }
+    }
+}
+
+impl<S: Blockstore> Blockstore for NodeStore<S> {
+    fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        self.loaded.set(self.loaded.get() + 1);
+        self.store.get(k)
+    }
+
+    fn has(&self, k: &Cid) -> anyhow::Result<bool> {
+        self.store.has(k)
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+        self.store.put_keyed(k, block)
+    }
 }
 
 /// An entry in the actor cache.
@@ -49,12 +134,51 @@ struct ActorCacheEntry {
     actor: Option<ActorState>,
 }
 
+/// A handle to a point in the state tree's history, returned by [`StateTree::snapshot`].
+///
+/// Snapshots must be either reverted or committed, in the reverse order in which they were taken.
+/// Reverting or committing a snapshot also reverts/commits all snapshots (and transactions) taken
+/// after it.
+#[must_use]
+#[derive(Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    /// The number of layers that existed before this snapshot was taken.
+    depth: usize,
+}
+
 /// State snap shot layer.
 struct StateSnapLayer {
     /// The actor-cache height at which this snapshot was taken.
     actor_cache_height: usize,
     /// The resolve-cache height at which this snapshot was taken.
     resolve_cache_height: usize,
+    /// The blocks written since this snapshot was taken.
+    blocks: HashMap<Cid, Vec<u8>>,
+}
+
+/// A view of the state tree's store that includes the blocks buffered by the current transactions
+/// (see [`StateTree::put_block`]). Writes go straight to the underlying store.
+pub(crate) struct BufferedStore<'a, S> {
+    layers: &'a [StateSnapLayer],
+    store: &'a S,
+}
+
+impl<S: Blockstore> Blockstore for BufferedStore<'_, S> {
+    fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        match self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.blocks.get(k))
+        {
+            Some(data) => Ok(Some(data.clone())),
+            None => self.store.get(k),
+        }
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+        self.store.put_keyed(k, block)
+    }
 }
 
 impl<S> StateTree<S>
@@ -62,6 +186,17 @@ where
     S: Blockstore,
 {
     pub fn new(store: S, version: StateTreeVersion) -> Result<Self> {
+        Self::new_with_config(store, version, default_hamt_config())
+    }
+
+    /// Creates a new state tree whose actors HAMT uses the given configuration. Only the default
+    /// configuration (see [`StateTree::new`]) is valid on Filecoin networks; anything else
+    /// produces incompatible state roots.
+    pub fn new_with_config(
+        store: S,
+        version: StateTreeVersion,
+        hamt_config: HamtConfig,
+    ) -> Result<Self> {
         let info = match version {
             StateTreeVersion::V0
             | StateTreeVersion::V1
@@ -82,20 +217,33 @@ where
             }
         };
 
-        // Both V3 and V4 use bitwidt=5.
-        let hamt = Hamt::new_with_bit_width(store, HAMT_BIT_WIDTH);
+        let hamt = Hamt::new_with_config(NodeStore::new(store), hamt_config.clone());
         Ok(Self {
             hamt,
+            hamt_config,
+            node_cache_size: usize::MAX,
             version,
             info,
             actor_cache: Default::default(),
             resolve_cache: Default::default(),
+            actor_stats: Default::default(),
+            resolve_stats: Default::default(),
             layers: Vec::new(),
+            transaction_accesses: Default::default(),
         })
     }
 
     /// Constructor for a hamt state tree given an IPLD store
     pub fn new_from_root(store: S, c: &Cid) -> Result<Self> {
+        Self::new_from_root_with_config(store, c, default_hamt_config())
+    }
+
+    /// Loads a state tree whose actors HAMT uses the given configuration. See
+    /// [`StateTree::new_with_config`].
+    ///
+    /// Decoded HAMT nodes are cached by the state tree, so repeated lookups of the same actor
+    /// only decode the nodes on its path once.
+    pub fn new_from_root_with_config(store: S, c: &Cid, hamt_config: HamtConfig) -> Result<Self> {
         // Try to load state root, if versioned
         let (version, info, actors) = match store.get_cbor(c) {
             Ok(Some(StateRoot {
@@ -129,25 +277,80 @@ where
             ))),
 
             StateTreeVersion::V5 => {
-                let hamt = Hamt::load_with_bit_width(&actors, store, HAMT_BIT_WIDTH)
-                    .context("failed to load state tree")
-                    .or_fatal()?;
+                let hamt =
+                    Hamt::load_with_config(&actors, NodeStore::new(store), hamt_config.clone())
+                        .context("failed to load state tree")
+                        .or_fatal()?;
 
                 Ok(Self {
                     hamt,
+                    hamt_config,
+                    node_cache_size: usize::MAX,
                     version,
                     info,
                     actor_cache: Default::default(),
                     resolve_cache: Default::default(),
+                    actor_stats: Default::default(),
+                    resolve_stats: Default::default(),
                     layers: Vec::new(),
+                    transaction_accesses: Default::default(),
                 })
             }
         }
     }
 
+    /// Returns the configuration of the actors HAMT.
+    pub fn hamt_config(&self) -> &HamtConfig {
+        &self.hamt_config
+    }
+
+    /// Sets the maximum number of decoded HAMT nodes to keep. The HAMT keeps every node it loads
+    /// decoded, so repeated lookups of actors never re-decode the nodes on their paths; once more
+    /// than `nodes` nodes have been loaded, the decoded nodes are dropped on the next
+    /// [`StateTree::flush`]. This bounds the memory used by long-lived state trees (e.g., a
+    /// machine's over a tipset touching many actors) at the cost of decoding those nodes again.
+    ///
+    /// DEFAULT: unbounded
+    pub fn set_node_cache_size(&mut self, nodes: usize) {
+        self.node_cache_size = nodes;
+    }
+
+    /// Returns the number of HAMT nodes loaded (and kept decoded) since the decoded nodes were last
+    /// dropped.
+    pub fn cached_nodes(&self) -> usize {
+        self.hamt.store().loaded.get()
+    }
+
     /// Retrieve store reference to modify db.
     pub fn store(&self) -> &S {
-        self.hamt.store()
+        &self.hamt.store().store
+    }
+
+    /// Returns a view of the store that includes the blocks buffered by [`StateTree::put_block`].
+    pub(crate) fn buffered_store(&self) -> BufferedStore<'_, S> {
+        BufferedStore {
+            layers: &self.layers,
+            store: self.store(),
+        }
+    }
+
+    /// Writes a block. Within a transaction, the block is buffered until the outermost transaction
+    /// ends and is discarded if any enclosing transaction is reverted. Outside of a transaction,
+    /// the block is written to the store immediately.
+    pub fn put_block(&mut self, k: Cid, data: &[u8]) -> Result<()> {
+        match self.layers.last_mut() {
+            Some(layer) => {
+                layer.blocks.insert(k, data.to_vec());
+                Ok(())
+            }
+            None => self.store().put_keyed(&k, data).or_fatal(),
+        }
+    }
+
+    /// Reads a block, including blocks buffered by [`StateTree::put_block`] that haven't been written
+    /// to the store yet.
+    pub fn get_block(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        self.buffered_store().get(k).or_fatal()
     }
 
     /// Get actor state from an address. Will be resolved to ID address.
@@ -162,10 +365,14 @@ where
 
     /// Get actor state from an actor ID.
     pub fn get_actor(&self, id: ActorID) -> Result<Option<ActorState>> {
-        self.actor_cache
+        self.record_access(id);
+        let mut hit = true;
+        let actor = self
+            .actor_cache
             .borrow_mut()
             .get_or_try_insert_with(id, || {
                 // It's not cached/dirty, so we look it up and cache it.
+                hit = false;
                 let key = Address::new_id(id).to_bytes();
                 Ok(ActorCacheEntry {
                     dirty: false,
@@ -177,12 +384,30 @@ where
                         .cloned(),
                 })
             })
-            .map(|ActorCacheEntry { actor, .. }| actor.clone())
+            .map(|ActorCacheEntry { actor, .. }| actor.clone())?;
+
+        let mut stats = self.actor_stats.get();
+        if hit {
+            stats.hits += 1;
+        } else {
+            stats.misses += 1;
+        }
+        self.actor_stats.set(stats);
+        Ok(actor)
     }
 
     /// Set actor state with an actor ID.
     pub fn set_actor(&mut self, id: ActorID, actor: ActorState) {
-        self.actor_cache.borrow_mut().insert(
+        if id == INIT_ACTOR_ID {
+            self.invalidate_resolve_cache(Some(&actor.state));
+        }
+        self.cache_actor(id, actor)
+    }
+
+    /// Records an actor in the actor cache, without invalidating the resolve cache.
+    fn cache_actor(&mut self, id: ActorID, actor: ActorState) {
+        self.record_access(id);
+        self.actor_cache.get_mut().insert(
             id,
             ActorCacheEntry {
                 actor: Some(actor),
@@ -197,13 +422,18 @@ where
             return Ok(Some(id));
         }
 
+        let mut stats = self.resolve_stats.get();
         if let Some(&res_address) = self.resolve_cache.borrow().get(addr) {
+            stats.hits += 1;
+            self.resolve_stats.set(stats);
             return Ok(Some(res_address));
         }
+        stats.misses += 1;
+        self.resolve_stats.set(stats);
 
         let (state, _) = InitActorState::load(self)?;
 
-        let a = match state.resolve_address(self.store(), addr)? {
+        let a = match state.resolve_address(self.buffered_store(), addr)? {
             Some(a) => a,
             None => return Ok(None),
         };
@@ -213,8 +443,42 @@ where
         Ok(Some(a))
     }
 
+    /// Returns statistics on the address resolution cache. Only lookups of non-ID addresses are
+    /// counted.
+    pub fn resolve_cache_stats(&self) -> CacheStats {
+        self.resolve_stats.get()
+    }
+
+    /// Returns statistics on the actor cache.
+    pub fn actor_cache_stats(&self) -> CacheStats {
+        self.actor_stats.get()
+    }
+
+    /// Invalidates the address resolution cache if the init actor's state is about to change to
+    /// `new_state` (or the init actor is about to be deleted, if `None`).
+    fn invalidate_resolve_cache(&mut self, new_state: Option<&Cid>) {
+        let resolve_cache = self.resolve_cache.get_mut();
+        if resolve_cache.is_empty() {
+            return;
+        }
+        // Addresses are only resolved through the init actor, so it's always cached when the
+        // resolve cache is non-empty.
+        let unchanged = matches!(
+            (self.actor_cache.get_mut().get(&INIT_ACTOR_ID), new_state),
+            (Some(ActorCacheEntry { actor: Some(init), .. }), Some(new_state))
+                if &init.state == new_state
+        );
+        if !unchanged {
+            resolve_cache.clear();
+        }
+    }
+
     /// Delete actor identified by the supplied ID.
     pub fn delete_actor(&mut self, id: ActorID) {
+        if id == INIT_ACTOR_ID {
+            self.invalidate_resolve_cache(None);
+        }
+        self.record_access(id);
         // Record that we've deleted the actor.
         self.actor_cache.borrow_mut().insert(
             id,
@@ -263,7 +527,7 @@ where
     pub fn register_new_address(&mut self, addr: &Address) -> Result<ActorID> {
         let (mut state, mut actor) = InitActorState::load(self)?;
 
-        let new_id = state.map_address_to_new_id(self.store(), addr)?;
+        let new_id = state.map_address_to_new_id(self.buffered_store(), addr)?;
 
         // Set state for init actor in store and update root Cid
         actor.state = self
@@ -271,8 +535,9 @@ where
             .put_cbor(&state, multihash::Code::Blake2b256)
             .or_fatal()?;
 
-        self.set_actor(crate::init_actor::INIT_ACTOR_ID, actor);
-        self.resolve_cache.borrow_mut().insert(*addr, new_id);
+        // Registering an address only adds a new mapping, so existing cache entries remain valid.
+        self.cache_actor(INIT_ACTOR_ID, actor);
+        self.resolve_cache.get_mut().insert(*addr, new_id);
 
         Ok(new_id)
     }
@@ -282,6 +547,7 @@ where
         self.layers.push(StateSnapLayer {
             actor_cache_height: self.actor_cache.get_mut().history_len(),
             resolve_cache_height: self.resolve_cache.get_mut().history_len(),
+            blocks: HashMap::new(),
         })
     }
 
@@ -299,6 +565,17 @@ where
             self.resolve_cache
                 .get_mut()
                 .rollback(layer.resolve_cache_height);
+        } else {
+            // Keep the blocks written in the layer, writing them to the store once the last
+            // transaction ends.
+            match self.layers.last_mut() {
+                Some(parent) => parent.blocks.extend(layer.blocks),
+                None => self
+                    .store()
+                    .put_many_keyed(layer.blocks)
+                    .context("failed to write buffered blocks")
+                    .or_fatal()?,
+            }
         }
         // When we end the last transaction, discard the undo history.
         if !self.in_transaction() {
@@ -308,6 +585,41 @@ where
         Ok(())
     }
 
+    /// Take a snapshot of the current state. Any changes made after this call can be undone by
+    /// passing the returned [`Snapshot`] to [`StateTree::revert_to_snapshot`], or kept by passing
+    /// it to [`StateTree::commit_snapshot`].
+    ///
+    /// Snapshots are implemented as transactions, so the state tree can't be flushed while a
+    /// snapshot is outstanding.
+    pub fn snapshot(&mut self) -> Snapshot {
+        let depth = self.layers.len();
+        self.begin_transaction();
+        Snapshot { depth }
+    }
+
+    /// Revert all changes made since the snapshot was taken.
+    pub fn revert_to_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        self.end_snapshot(snapshot, true)
+    }
+
+    /// Keep all changes made since the snapshot was taken, discarding the snapshot.
+    pub fn commit_snapshot(&mut self, snapshot: Snapshot) -> Result<()> {
+        self.end_snapshot(snapshot, false)
+    }
+
+    fn end_snapshot(&mut self, snapshot: Snapshot, revert: bool) -> Result<()> {
+        if self.layers.len() <= snapshot.depth {
+            return Err(ExecutionError::Fatal(anyhow!(
+                "snapshot at depth {} has already been committed or reverted",
+                snapshot.depth
+            )));
+        }
+        while self.layers.len() > snapshot.depth {
+            self.end_transaction(revert)?;
+        }
+        Ok(())
+    }
+
     /// Returns true if we're inside of a transaction.
     pub fn in_transaction(&self) -> bool {
         !self.layers.is_empty()
@@ -340,6 +652,12 @@ where
 
         let root = self.hamt.flush().or_fatal()?;
 
+        // Every node is clean now, so the decoded nodes can be dropped by reloading the root.
+        if self.cached_nodes() > self.node_cache_size {
+            self.hamt.set_root(&root).or_fatal()?;
+            self.hamt.store().loaded.set(0);
+        }
+
         match self.version {
             StateTreeVersion::V0 => Ok(root),
             _ => {
@@ -360,9 +678,89 @@ where
         }
     }
 
+    /// Calls `f` for every actor that has been loaded into or written to the actor cache. Modified
+    /// actors are passed along with their pending state (`None` if the actor has been deleted),
+    /// while actors that have only been read are passed `None` as the second argument.
+    ///
+    /// Note: the actor cache is not cleared on flush, so this reports every actor accessed since
+    /// the state tree was constructed, but only actors modified since the last flush.
+    pub(crate) fn for_each_cached_actor<F>(&self, mut f: F)
+    where
+        F: FnMut(ActorID, Option<Option<&ActorState>>),
+    {
+        for (&id, entry) in self.actor_cache.borrow().iter() {
+            f(id, entry.dirty.then_some(entry.actor.as_ref()))
+        }
+    }
+
+    /// Starts recording the actors read or written inside of transactions, i.e., by actors (as
+    /// opposed to by the executor, before and after invoking them). Accesses are recorded even if
+    /// the transaction is later reverted.
+    pub(crate) fn record_transaction_accesses(&mut self) {
+        self.transaction_accesses
+            .get_mut()
+            .get_or_insert_with(Default::default);
+    }
+
+    /// Returns the actors accessed inside of transactions since
+    /// [`StateTree::record_transaction_accesses`] was called.
+    pub(crate) fn transaction_accesses(&self) -> BTreeSet<ActorID> {
+        self.transaction_accesses
+            .borrow()
+            .clone()
+            .unwrap_or_default()
+    }
+
+    fn record_access(&self, id: ActorID) {
+        if self.in_transaction() {
+            if let Some(ids) = self.transaction_accesses.borrow_mut().as_mut() {
+                ids.insert(id);
+            }
+        }
+    }
+
     /// Consumes this StateTree and returns the Blockstore it owns via the HAMT.
     pub fn into_store(self) -> S {
-        self.hamt.into_store()
+        self.hamt.into_store().store
+    }
+
+    /// Returns the actors that were added, modified, or deleted to get from this state tree to the
+    /// state tree rooted at `other_root` (loaded from this state tree's store), keyed by actor ID.
+    ///
+    /// Parts of the actors HAMT shared by both state trees are skipped, so diffing similar state
+    /// trees (e.g., before and after a message) is cheap. Only flushed state is compared, so this
+    /// fails if the state tree has pending changes.
+    pub fn diff(&self, other_root: &Cid) -> anyhow::Result<BTreeMap<ActorID, ActorChange>> {
+        if self
+            .actor_cache
+            .borrow()
+            .iter()
+            .any(|(_, entry)| entry.dirty)
+        {
+            return Err(anyhow!("cannot diff a state tree with unflushed changes"));
+        }
+        let other = StateTree::new_from_root_with_config(
+            self.store(),
+            other_root,
+            self.hamt_config.clone(),
+        )
+        .map_err(anyhow::Error::from)
+        .context("failed to load state tree to diff against")?;
+        self.hamt
+            .diff(&other.hamt)?
+            .into_iter()
+            .map(|change| -> anyhow::Result<_> {
+                let (key, change) = match change {
+                    Change::Added { key, value } => (key, ActorChange::Added(value)),
+                    Change::Removed { key, value } => (key, ActorChange::Deleted(value)),
+                    Change::Modified { key, before, after } => {
+                        (key, ActorChange::Modified { before, after })
+                    }
+                };
+                let id = Address::from_bytes(&key.0)?.id()?;
+                Ok((id, change))
+            })
+            .collect()
     }
 
     pub fn for_each<F>(&self, mut f: F) -> anyhow::Result<()>
@@ -377,6 +775,21 @@ where
     }
 }
 
+/// A change to a single actor between two state trees, as returned by [`StateTree::diff`].
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ActorChange {
+    /// The actor was created.
+    Added(ActorState),
+    /// The actor was modified. The actor's state head changed if `before.state` differs from
+    /// `after.state`.
+    Modified {
+        before: ActorState,
+        after: ActorState,
+    },
+    /// The actor was deleted.
+    Deleted(ActorState),
+}
+
 /// State of all actor implementations.
 #[derive(PartialEq, Eq, Clone, Debug, Serialize_tuple, Deserialize_tuple)]
 pub struct ActorState {
@@ -539,9 +952,10 @@ pub mod json {
 #[cfg(test)]
 mod tests {
     use cid::multihash::Code::Blake2b256;
-    use cid::multihash::Multihash;
+    use cid::multihash::{Multihash, MultihashDigest};
     use cid::Cid;
-    use fvm_ipld_blockstore::MemoryBlockstore;
+    use fvm_ipld_blockstore::tracking::TrackingBlockstore;
+    use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
     use fvm_ipld_encoding::{CborStore, DAG_CBOR};
     use fvm_shared::address::{Address, SECP_PUB_LEN};
     use fvm_shared::econ::TokenAmount;
@@ -551,7 +965,7 @@ mod tests {
 
     use crate::init_actor;
     use crate::init_actor::INIT_ACTOR_ID;
-    use crate::state_tree::{ActorState, StateTree};
+    use crate::state_tree::{ActorChange, ActorState, CacheStats, StateTree};
 
     lazy_static! {
         pub static ref DUMMY_ACCOUNT_ACTOR_CODE_ID: Cid = Cid::new_v1(
@@ -588,6 +1002,62 @@ mod tests {
         assert_eq!(tree.get_actor(actor_id).unwrap().unwrap(), act_a);
     }
 
+    #[test]
+    fn actor_cache_stats() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, None);
+        tree.set_actor(1, actor);
+        let root = tree.flush().unwrap();
+
+        // The cache survives flushes.
+        tree.get_actor(1).unwrap();
+        assert_eq!(tree.actor_cache_stats(), CacheStats { hits: 1, misses: 0 });
+
+        let tree = StateTree::new_from_root(&store, &root).unwrap();
+        tree.get_actor(1).unwrap();
+        tree.get_actor(1).unwrap();
+        tree.get_actor(2).unwrap();
+        tree.get_actor(2).unwrap();
+        let stats = tree.actor_cache_stats();
+        assert_eq!(stats, CacheStats { hits: 2, misses: 2 });
+        assert_eq!(stats.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn lazy_copy_on_write() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = |seq| ActorState::new(empty_cid(), empty_cid(), Default::default(), seq, None);
+        for id in 100..1100 {
+            tree.set_actor(id, actor(0));
+        }
+        let root = tree.flush().unwrap();
+
+        // Loading the tree doesn't load any actors.
+        let store = TrackingBlockstore::new(&store);
+        let mut tree = StateTree::new_from_root(&store, &root).unwrap();
+        let opened = *store.stats.borrow();
+        assert_eq!(
+            opened.r, 2,
+            "expected to only load the state root & root HAMT node"
+        );
+
+        // Looking up an actor only loads the nodes on its path.
+        assert_eq!(tree.get_actor(500).unwrap(), Some(actor(0)));
+        let looked_up = *store.stats.borrow();
+        assert!(looked_up.r - opened.r <= 3, "{:?}", looked_up);
+
+        // Writes are buffered until flush, and writes that end up restoring the original state
+        // don't write anything.
+        tree.set_actor(500, actor(1));
+        tree.set_actor(500, actor(0));
+        assert_eq!(*store.stats.borrow(), looked_up);
+        assert_eq!(tree.flush().unwrap(), root);
+        // Only the (identical) state root object is re-written.
+        assert_eq!(store.stats.borrow().w, looked_up.w + 1);
+    }
+
     #[test]
     fn delete_actor() {
         let store = MemoryBlockstore::default();
@@ -601,6 +1071,94 @@ mod tests {
         assert_eq!(tree.get_actor(actor_id).unwrap(), None);
     }
 
+    #[test]
+    fn hamt_config() {
+        let store = MemoryBlockstore::default();
+        let config = fvm_ipld_hamt::Config {
+            bit_width: 8,
+            ..Default::default()
+        };
+        let actor = ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, None);
+        let mut tree =
+            StateTree::new_with_config(&store, StateTreeVersion::V5, config.clone()).unwrap();
+        tree.set_actor(1, actor.clone());
+        let root = tree.flush().unwrap();
+
+        let tree = StateTree::new_from_root_with_config(&store, &root, config).unwrap();
+        assert_eq!(tree.hamt_config().bit_width, 8);
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor));
+    }
+
+    #[test]
+    fn node_cache_size() {
+        let store = MemoryBlockstore::default();
+        let actor = ActorState::new(empty_cid(), empty_cid(), Default::default(), 1, None);
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        // Enough actors for the root node to link to child nodes.
+        for id in 0..100 {
+            tree.set_actor(id, actor.clone());
+        }
+        let root = tree.flush().unwrap();
+
+        let mut tree = StateTree::new_from_root(&store, &root).unwrap();
+        tree.set_node_cache_size(1);
+        let mut loaded = tree.cached_nodes();
+        for id in 0..100 {
+            tree.get_actor(id).unwrap();
+        }
+        assert!(tree.cached_nodes() > loaded);
+
+        // Looking actors up again doesn't load (or decode) any nodes.
+        loaded = tree.cached_nodes();
+        tree.actor_cache.get_mut().clear();
+        for id in 0..100 {
+            tree.get_actor(id).unwrap();
+        }
+        assert_eq!(tree.cached_nodes(), loaded);
+
+        // Flushing drops the decoded nodes once there are too many.
+        assert_eq!(tree.flush().unwrap(), root);
+        assert_eq!(tree.cached_nodes(), 0);
+        tree.actor_cache.get_mut().clear();
+        assert_eq!(tree.get_actor(0).unwrap(), Some(actor));
+        assert!(tree.cached_nodes() > 0);
+    }
+
+    #[test]
+    fn diff() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let actor = |seq| ActorState::new(empty_cid(), empty_cid(), Default::default(), seq, None);
+        for id in 100..200 {
+            tree.set_actor(id, actor(0));
+        }
+        let before = tree.flush().unwrap();
+
+        tree.delete_actor(100);
+        tree.set_actor(101, actor(1));
+        tree.set_actor(102, actor(0));
+        tree.set_actor(200, actor(0));
+        assert!(tree.diff(&before).is_err());
+        let after = tree.flush().unwrap();
+
+        let tree = StateTree::new_from_root(&store, &before).unwrap();
+        assert!(tree.diff(&before).unwrap().is_empty());
+        assert_eq!(
+            tree.diff(&after).unwrap().into_iter().collect::<Vec<_>>(),
+            vec![
+                (100, ActorChange::Deleted(actor(0))),
+                (
+                    101,
+                    ActorChange::Modified {
+                        before: actor(0),
+                        after: actor(1)
+                    }
+                ),
+                (200, ActorChange::Added(actor(0))),
+            ]
+        );
+    }
+
     #[test]
     fn get_set_non_id() {
         let store = MemoryBlockstore::default();
@@ -649,6 +1207,145 @@ mod tests {
         assert_eq!(assigned_addr, 100);
     }
 
+    #[test]
+    fn delegated_addresses() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let init_state = init_actor::State::new_test(&store);
+        let state_cid = tree.store().put_cbor(&init_state, Blake2b256).unwrap();
+        tree.set_actor(
+            INIT_ACTOR_ID,
+            ActorState::new(
+                *DUMMY_INIT_ACTOR_CODE_ID,
+                state_cid,
+                Default::default(),
+                1,
+                None,
+            ),
+        );
+
+        let f4 = Address::new_delegated(10, b"foobar").unwrap();
+        let id = tree.register_new_address(&f4).unwrap();
+        tree.set_actor(
+            id,
+            ActorState::new_empty(*DUMMY_INIT_ACTOR_CODE_ID, Some(f4)),
+        );
+
+        assert_eq!(tree.lookup_id(&f4).unwrap(), Some(id));
+        assert_eq!(
+            tree.lookup_id(&Address::new_delegated(10, b"other").unwrap())
+                .unwrap(),
+            None
+        );
+
+        // Resolution survives a flush and reload.
+        let root = tree.flush().unwrap();
+        let tree = StateTree::new_from_root(&store, &root).unwrap();
+        assert_eq!(tree.lookup_id(&f4).unwrap(), Some(id));
+        assert_eq!(
+            tree.get_actor(id).unwrap().unwrap().delegated_address,
+            Some(f4)
+        );
+    }
+
+    #[test]
+    fn resolve_cache() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+        let empty_init = tree
+            .store()
+            .put_cbor(&init_actor::State::new_test(&store), Blake2b256)
+            .unwrap();
+        let init_actor = |state| {
+            ActorState::new(
+                *DUMMY_INIT_ACTOR_CODE_ID,
+                state,
+                Default::default(),
+                1,
+                None,
+            )
+        };
+        tree.set_actor(INIT_ACTOR_ID, init_actor(empty_init));
+
+        let addr = Address::new_secp256k1(&[2; SECP_PUB_LEN]).unwrap();
+        let id = tree.register_new_address(&addr).unwrap();
+        assert_eq!(tree.lookup_id(&addr).unwrap(), Some(id));
+        assert_eq!(tree.lookup_id(&Address::new_id(id)).unwrap(), Some(id));
+        assert_eq!(
+            tree.resolve_cache_stats(),
+            CacheStats { hits: 1, misses: 0 }
+        );
+
+        // Updating the init actor without touching its state keeps the cache.
+        tree.mutate_actor(INIT_ACTOR_ID, |actor| {
+            actor.sequence += 1;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(tree.lookup_id(&addr).unwrap(), Some(id));
+        assert_eq!(
+            tree.resolve_cache_stats(),
+            CacheStats { hits: 2, misses: 0 }
+        );
+
+        // Replacing the init actor's state invalidates the cache, until reverted.
+        tree.begin_transaction();
+        tree.set_actor(INIT_ACTOR_ID, init_actor(empty_init));
+        assert_eq!(tree.lookup_id(&addr).unwrap(), None);
+        tree.end_transaction(true).unwrap();
+        assert_eq!(tree.lookup_id(&addr).unwrap(), Some(id));
+
+        let stats = tree.resolve_cache_stats();
+        assert_eq!(stats, CacheStats { hits: 3, misses: 1 });
+        assert_eq!(stats.hit_rate(), 0.75);
+    }
+
+    #[test]
+    fn buffered_blocks() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        // Write the init actor's state as an actor would, inside a transaction.
+        let init_state = fvm_ipld_encoding::to_vec(&init_actor::State::new_test(&store)).unwrap();
+        let init_cid = Cid::new_v1(DAG_CBOR, Blake2b256.digest(&init_state));
+        tree.begin_transaction();
+        tree.begin_transaction();
+        tree.put_block(init_cid, &init_state).unwrap();
+        tree.set_actor(
+            INIT_ACTOR_ID,
+            ActorState::new(
+                *DUMMY_INIT_ACTOR_CODE_ID,
+                init_cid,
+                Default::default(),
+                1,
+                None,
+            ),
+        );
+        tree.end_transaction(false).unwrap();
+
+        // The state tree can use the buffered state before it's written to the store.
+        assert!(!store.has(&init_cid).unwrap());
+        assert_eq!(tree.get_block(&init_cid).unwrap(), Some(init_state.clone()));
+        let addr = Address::new_secp256k1(&[3; SECP_PUB_LEN]).unwrap();
+        let id = tree.register_new_address(&addr).unwrap();
+        tree.resolve_cache.get_mut().clear();
+        assert_eq!(tree.lookup_id(&addr).unwrap(), Some(id));
+
+        // Ending the outermost transaction writes the buffered blocks.
+        tree.end_transaction(false).unwrap();
+        assert!(store.has(&init_cid).unwrap());
+
+        // Blocks written in reverted transactions are discarded.
+        let data = b"reverted";
+        let cid = Cid::new_v1(IPLD_RAW, Blake2b256.digest(data));
+        tree.begin_transaction();
+        tree.put_block(cid, data).unwrap();
+        assert_eq!(tree.get_block(&cid).unwrap(), Some(data.to_vec()));
+        tree.end_transaction(true).unwrap();
+        assert_eq!(tree.get_block(&cid).unwrap(), None);
+        assert!(!store.has(&cid).unwrap());
+    }
+
     #[test]
     fn test_transactions() {
         let store = MemoryBlockstore::default();
@@ -749,6 +1446,52 @@ mod tests {
         assert_eq!(tree.get_actor(actor_id).unwrap(), None);
     }
 
+    #[test]
+    fn revert_snapshot() {
+        let store = MemoryBlockstore::default();
+        let mut tree = StateTree::new(&store, StateTreeVersion::V5).unwrap();
+
+        let actor = |sequence| {
+            ActorState::new(
+                *DUMMY_ACCOUNT_ACTOR_CODE_ID,
+                *DUMMY_ACCOUNT_ACTOR_CODE_ID,
+                TokenAmount::from_atto(55),
+                sequence,
+                None,
+            )
+        };
+
+        tree.set_actor(1, actor(1));
+
+        let first = tree.snapshot();
+        tree.set_actor(1, actor(2));
+        tree.set_actor(2, actor(1));
+
+        // Nested snapshots and transactions are reverted along with the outer snapshot.
+        let second = tree.snapshot();
+        tree.set_actor(3, actor(1));
+        tree.begin_transaction();
+        tree.delete_actor(1);
+
+        // Can't flush while a snapshot is outstanding.
+        assert!(tree.flush().unwrap_err().is_fatal());
+
+        tree.revert_to_snapshot(first).unwrap();
+        assert_eq!(tree.get_actor(1).unwrap(), Some(actor(1)));
+        assert_eq!(tree.get_actor(2).unwrap(), None);
+        assert_eq!(tree.get_actor(3).unwrap(), None);
+        assert!(!tree.in_transaction());
+
+        // The nested snapshot is gone.
+        assert!(tree.commit_snapshot(second).unwrap_err().is_fatal());
+
+        let third = tree.snapshot();
+        tree.set_actor(2, actor(1));
+        tree.commit_snapshot(third).unwrap();
+        tree.flush().unwrap();
+        assert_eq!(tree.get_actor(2).unwrap(), Some(actor(1)));
+    }
+
     #[test]
     fn unsupported_versions() {
         let unsupported = vec![
@@ -1,10 +1,12 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use anyhow::{anyhow, Context as _};
+use fvm_shared::error::ExitCode;
 use fvm_shared::{sys, ActorID};
 
+use super::error::{Abort, ControlFlow};
 use super::Context;
-use crate::kernel::{ClassifyResult, Result};
+use crate::kernel::{ClassifyResult, Result, SendResult};
 use crate::{syscall_error, Kernel};
 
 pub fn resolve_address(
@@ -12,7 +14,7 @@ pub fn resolve_address(
     addr_off: u32, // Address
     addr_len: u32,
 ) -> Result<u64> {
-    let addr = context.memory.read_address(addr_off, addr_len)?;
+    let addr = context.read_address(addr_off, addr_len)?;
     let actor_id = context.kernel.resolve_address(&addr)?;
     Ok(actor_id)
 }
@@ -23,14 +25,19 @@ pub fn lookup_delegated_address(
     obuf_off: u32,
     obuf_len: u32,
 ) -> Result<u32> {
-    let obuf = context.memory.try_slice_mut(obuf_off, obuf_len)?;
+    context.memory.check_bounds(obuf_off, obuf_len)?;
     match context.kernel.lookup_delegated_address(actor_id)? {
         Some(address) => {
             let address = address.to_bytes();
-            obuf.get_mut(..address.len())
-                .ok_or_else(
-                    || syscall_error!(BufferTooSmall; "address output buffer is too small"),
-                )?
+            if address.len() > obuf_len as usize {
+                return Err(
+                    syscall_error!(BufferTooSmall; "address output buffer is too small").into(),
+                );
+            }
+            context.charge_copy_out(address.len())?;
+            context
+                .memory
+                .try_slice_mut(obuf_off, address.len() as u32)?
                 .copy_from_slice(&address);
             Ok(address.len() as u32)
         }
@@ -39,7 +46,7 @@ pub fn lookup_delegated_address(
 }
 
 pub fn get_actor_code_cid(
-    context: Context<'_, impl Kernel>,
+    mut context: Context<'_, impl Kernel>,
     actor_id: u64,
     obuf_off: u32, // Cid
     obuf_len: u32,
@@ -49,7 +56,7 @@ pub fn get_actor_code_cid(
 
     let typ = context.kernel.get_actor_code_cid(actor_id)?;
 
-    context.memory.write_cid(&typ, obuf_off, obuf_len)
+    context.write_cid(&typ, obuf_off, obuf_len)
 }
 
 /// Generates a new actor address, and writes it into the supplied output buffer.
@@ -62,7 +69,7 @@ pub fn next_actor_address(
     obuf_len: u32,
 ) -> Result<u32> {
     // Check bounds first.
-    let obuf = context.memory.try_slice_mut(obuf_off, obuf_len)?;
+    context.memory.check_bounds(obuf_off, obuf_len)?;
 
     // Then make sure we can actually put the return result somewhere before we do anything else.
     const EXPECTED_LEN: u32 = fvm_shared::address::PAYLOAD_HASH_LEN as u32 + 1;
@@ -86,7 +93,11 @@ pub fn next_actor_address(
         return Err(anyhow!("created {} byte actor address", len)).or_fatal();
     }
 
-    obuf[..len].copy_from_slice(bytes.as_slice());
+    context.charge_copy_out(len)?;
+    context
+        .memory
+        .try_slice_mut(obuf_off, len as u32)?
+        .copy_from_slice(bytes.as_slice());
     Ok(len as u32)
 }
 
@@ -97,13 +108,9 @@ pub fn create_actor(
     delegated_addr_off: u32,
     delegated_addr_len: u32,
 ) -> Result<()> {
-    let typ = context.memory.read_cid(typ_off)?;
+    let typ = context.read_cid(typ_off)?;
     let addr = (delegated_addr_len > 0)
-        .then(|| {
-            context
-                .memory
-                .read_address(delegated_addr_off, delegated_addr_len)
-        })
+        .then(|| context.read_address(delegated_addr_off, delegated_addr_len))
         .transpose()?;
 
     context.kernel.create_actor(typ, actor_id, addr)
@@ -113,12 +120,12 @@ pub fn get_builtin_actor_type(
     context: Context<'_, impl Kernel>,
     code_cid_off: u32, // Cid
 ) -> Result<i32> {
-    let cid = context.memory.read_cid(code_cid_off)?;
+    let cid = context.read_cid(code_cid_off)?;
     Ok(context.kernel.get_builtin_actor_type(&cid)? as i32)
 }
 
 pub fn get_code_cid_for_type(
-    context: Context<'_, impl Kernel>,
+    mut context: Context<'_, impl Kernel>,
     typ: i32,
     obuf_off: u32, // Cid
     obuf_len: u32,
@@ -126,7 +133,7 @@ pub fn get_code_cid_for_type(
     context.memory.check_bounds(obuf_off, obuf_len)?;
 
     let k = context.kernel.get_code_cid_for_type(typ as u32)?;
-    context.memory.write_cid(&k, obuf_off, obuf_len)
+    context.write_cid(&k, obuf_off, obuf_len)
 }
 
 #[cfg(feature = "m2-native")]
@@ -134,10 +141,64 @@ pub fn install_actor(
     context: Context<'_, impl Kernel>,
     typ_off: u32, // Cid
 ) -> Result<()> {
-    let typ = context.memory.read_cid(typ_off)?;
+    let typ = context.read_cid(typ_off)?;
     context.kernel.install_actor(typ)
 }
 
+#[cfg(feature = "m2-native")]
+pub fn install_actor_code(
+    mut context: Context<'_, impl Kernel>,
+    wasm_off: u32,
+    wasm_len: u32,
+    obuf_off: u32, // Cid
+    obuf_len: u32,
+) -> Result<u32> {
+    context.memory.check_bounds(obuf_off, obuf_len)?;
+
+    let wasm = context.memory.try_slice(wasm_off, wasm_len)?;
+    let k = context.kernel.install_actor_code(wasm)?;
+    context.write_cid(&k, obuf_off, obuf_len)
+}
+
+/// Upgrades the calling actor to new code, invoking the new code's `upgrade` entrypoint.
+///
+/// If the upgrade succeeds, the calling actor's invocation ends immediately (with the upgrade's
+/// return value) as the old code must not keep running. Otherwise, the upgrade is reverted and the
+/// exit code of the `upgrade` entrypoint is returned to the actor as if it had sent a message.
+pub fn upgrade_actor(
+    context: Context<'_, impl Kernel>,
+    new_code_cid_off: u32, // Cid
+    params_id: u32,
+) -> ControlFlow<sys::out::send::Send> {
+    let new_code_cid = match context.read_cid(new_code_cid_off) {
+        Ok(cid) => cid,
+        Err(e) => return e.into(),
+    };
+
+    match context.kernel.upgrade_actor(new_code_cid, params_id) {
+        Ok(SendResult {
+            block_id,
+            exit_code,
+            ..
+        }) if exit_code.is_success() => ControlFlow::Abort(Abort::Exit(
+            ExitCode::OK,
+            String::from("actor upgraded"),
+            block_id,
+        )),
+        Ok(SendResult {
+            block_id,
+            block_stat,
+            exit_code,
+        }) => ControlFlow::Return(sys::out::send::Send {
+            exit_code: exit_code.value(),
+            return_id: block_id,
+            return_codec: block_stat.codec,
+            return_size: block_stat.size,
+        }),
+        Err(e) => e.into(),
+    }
+}
+
 pub fn balance_of(context: Context<'_, impl Kernel>, actor_id: u64) -> Result<sys::TokenAmount> {
     let balance = context.kernel.balance_of(actor_id)?;
     balance
@@ -1,22 +1,29 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use std::mem;
+use std::sync::Arc;
 
-use fvm_shared::error::ErrorNumber;
+use fvm_shared::error::{ErrorNumber, ExitCode};
 use fvm_shared::sys::SyscallSafe;
 use wasmtime::{Caller, Linker, WasmTy};
 
 use super::context::Memory;
-use super::error::Abort;
+use super::error::{Abort, ControlFlow};
 use super::{charge_for_exec, update_gas_available, Context, InvocationData};
 use crate::call_manager::backtrace;
+use crate::gas::Gas;
 use crate::kernel::{self, ExecutionError, Kernel, SyscallError};
+use crate::machine::Machine;
+use crate::trace::{SyscallOutcome, SyscallRecord};
 
 /// Binds syscalls to a linker, converting the returned error according to the syscall convention:
 ///
 /// 1. If the error is a syscall error, it's returned as the first return value.
 /// 2. If the error is a fatal error, a Trap is returned.
-pub(super) trait BindSyscall<Args, Ret, Func> {
+pub trait BindSyscall<Args, Ret, Func> {
+    /// The kernel type the syscall is bound for.
+    type Kernel;
+
     /// Bind a syscall to the linker.
     ///
     /// 1. The return type will be automatically adjusted to return `Result<u32, Trap>` where
@@ -46,9 +53,24 @@ pub(super) trait BindSyscall<Args, Ret, Func> {
         module: &'static str,
         name: &'static str,
         syscall: Func,
+    ) -> anyhow::Result<&mut Self> {
+        self.bind_with_gas_hook(module, name, None, syscall)
+    }
+
+    /// Bind a syscall to the linker, like [`BindSyscall::bind`], additionally charging the gas
+    /// computed by the specified hook (if any) before invoking the syscall.
+    fn bind_with_gas_hook(
+        &mut self,
+        module: &'static str,
+        name: &'static str,
+        gas_hook: Option<GasHook<Self::Kernel>>,
+        syscall: Func,
     ) -> anyhow::Result<&mut Self>;
 }
 
+/// A hook computing the gas to charge for a syscall invocation, along with the name of the charge.
+pub type GasHook<K> = (String, Arc<dyn Fn(&K) -> Gas + Send + Sync>);
+
 /// The helper trait used by `BindSyscall` to convert kernel results with execution errors into
 /// results that can be handled by wasmtime. See the documentation on `BindSyscall` for details.
 #[doc(hidden)]
@@ -86,6 +108,100 @@ where
     }
 }
 
+// Implementations for syscalls that may end the calling actor's invocation.
+impl<T> IntoSyscallResult for ControlFlow<T>
+where
+    T: SyscallSafe,
+{
+    type Value = T;
+    fn into(self) -> Result<Result<Self::Value, SyscallError>, Abort> {
+        match self {
+            ControlFlow::Return(value) => Ok(Ok(value)),
+            ControlFlow::Error(err) => Ok(Err(err)),
+            ControlFlow::Abort(abort) => Err(abort),
+        }
+    }
+}
+
+/// A syscall argument type that can be recorded (see
+/// [`MachineContext::syscall_recording`](crate::machine::MachineContext::syscall_recording)).
+#[doc(hidden)]
+pub trait SyscallArg: Copy {
+    /// Returns the argument widened (or reinterpreted) to 64 bits.
+    fn to_u64(self) -> u64;
+}
+
+macro_rules! impl_syscall_arg {
+    ($($t:ty => |$v:ident| $e:expr),* $(,)?) => {
+        $(impl SyscallArg for $t {
+            fn to_u64(self) -> u64 {
+                let $v = self;
+                $e
+            }
+        })*
+    };
+}
+
+impl_syscall_arg! {
+    u32 => |v| v as u64,
+    u64 => |v| v,
+    i32 => |v| v as u32 as u64,
+    i64 => |v| v as u64,
+    f32 => |v| v.to_bits() as u64,
+    f64 => |v| v.to_bits(),
+}
+
+/// Returns the in-memory representation of a syscall's return value, for recording.
+fn value_bytes<T: SyscallSafe>(value: &T) -> Vec<u8> {
+    // SAFETY: syscall return values are plain-old-data (that's what `SyscallSafe` asserts).
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, mem::size_of::<T>()) }
+        .to_vec()
+}
+
+/// Returns the ranges of memory that differ between `before` and `after`, as `(offset, bytes)`.
+fn memory_writes(before: &[u8], after: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let mut writes = Vec::new();
+    let mut i = 0;
+    while i < after.len() {
+        if before.get(i) == Some(&after[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < after.len() && before.get(i) != Some(&after[i]) {
+            i += 1;
+        }
+        writes.push((start as u32, after[start..i].to_vec()));
+    }
+    writes
+}
+
+/// Builds the record of a syscall from its arguments, the actor's memory before and after the
+/// syscall (but before the return value is written), and its outcome.
+fn syscall_record<T>(
+    module: &'static str,
+    name: &'static str,
+    (args, before): (Vec<u64>, Vec<u8>),
+    after: &[u8],
+    out: &Result<Result<T, SyscallError>, Abort>,
+    value: impl FnOnce(&T) -> Vec<u8>,
+) -> SyscallRecord {
+    let result = match out {
+        Ok(Ok(v)) => SyscallOutcome::Ok(value(v)),
+        Ok(Err(err)) => SyscallOutcome::Error(err.1),
+        Err(Abort::Exit(code, ..)) => SyscallOutcome::Abort(*code),
+        Err(Abort::OutOfGas) => SyscallOutcome::Abort(ExitCode::SYS_OUT_OF_GAS),
+        Err(Abort::Fatal(_)) => SyscallOutcome::Abort(ExitCode::SYS_ASSERTION_FAILED),
+    };
+    SyscallRecord {
+        module,
+        name,
+        args,
+        result,
+        writes: memory_writes(&before, after),
+    }
+}
+
 fn memory_and_data<'a, K: Kernel>(
     caller: &'a mut Caller<'_, InvocationData<K>>,
 ) -> (&'a mut Memory, &'a mut InvocationData<K>) {
@@ -103,6 +219,17 @@ macro_rules! charge_syscall_gas {
     };
 }
 
+macro_rules! charge_hook_gas {
+    ($kernel:expr, $hook:expr) => {
+        if let Some((charge_name, hook)) = $hook {
+            let gas = hook(&$kernel);
+            let _ = $kernel
+                .charge_gas(charge_name, gas)
+                .map_err(Abort::from_error_as_fatal)?;
+        }
+    };
+}
+
 // Unfortunately, we can't implement this for _all_ functions. So we implement it for functions of up to 6 arguments.
 macro_rules! impl_bind_syscalls {
     ($($t:ident)*) => {
@@ -112,12 +239,15 @@ macro_rules! impl_bind_syscalls {
             K: Kernel,
             Func: Fn(Context<'_, K> $(, $t)*) -> Ret + Send + Sync + 'static,
             Ret: IntoSyscallResult,
-           $($t: WasmTy+SyscallSafe,)*
+           $($t: WasmTy+SyscallSafe+SyscallArg,)*
         {
-            fn bind(
+            type Kernel = K;
+
+            fn bind_with_gas_hook(
                 &mut self,
                 module: &'static str,
                 name: &'static str,
+                gas_hook: Option<GasHook<K>>,
                 syscall: Func,
             ) -> anyhow::Result<&mut Self> {
                 if mem::size_of::<Ret::Value>() == 0 {
@@ -126,11 +256,19 @@ macro_rules! impl_bind_syscalls {
                         charge_for_exec(&mut caller)?;
 
                         let (mut memory, mut data) = memory_and_data(&mut caller);
+                        data.kernel.machine().context().metrics.syscall(module, name);
+                        let recording = data.kernel.machine().context().syscall_recording.then(|| (vec![$($t.to_u64()),*], memory.to_vec()));
                         charge_syscall_gas!(data.kernel);
+                        charge_hook_gas!(data.kernel, &gas_hook);
 
                         let ctx = Context{kernel: &mut data.kernel, memory: &mut memory};
                         let out = syscall(ctx $(, $t)*).into();
 
+                        if let Some(recording) = recording {
+                            let record = syscall_record(module, name, recording, &memory[..], &out, |_| Vec::new());
+                            data.kernel.record_syscall(record);
+                        }
+
                         let result = match out {
                             Ok(Ok(_)) => {
                                 log::trace!("syscall {}::{}: ok", module, name);
@@ -156,18 +294,35 @@ macro_rules! impl_bind_syscalls {
                         charge_for_exec(&mut caller)?;
 
                         let (mut memory, mut data) = memory_and_data(&mut caller);
+                        data.kernel.machine().context().metrics.syscall(module, name);
+                        let recording = data.kernel.machine().context().syscall_recording.then(|| (vec![$($t.to_u64()),*], memory.to_vec()));
                         charge_syscall_gas!(data.kernel);
 
                         // We need to check to make sure we can store the return value _before_ we do anything.
                         if (ret as u64) > (memory.len() as u64)
                             || memory.len() - (ret as usize) < mem::size_of::<Ret::Value>() {
                             let code = ErrorNumber::IllegalArgument;
-                            data.last_error = Some(backtrace::Cause::from_syscall(module, name, SyscallError(format!("no space for return value"), code)));
+                            let err = SyscallError(format!("no space for return value"), code);
+                            if let Some(recording) = recording {
+                                let out: Result<Result<(), SyscallError>, Abort> = Ok(Err(err.clone()));
+                                let record = syscall_record(module, name, recording, &memory[..], &out, |_| Vec::new());
+                                data.kernel.record_syscall(record);
+                            }
+                            data.last_error = Some(backtrace::Cause::from_syscall(module, name, err));
                             return Ok(code as u32);
                         }
 
+                        charge_hook_gas!(data.kernel, &gas_hook);
+
                         let ctx = Context{kernel: &mut data.kernel, memory: &mut memory};
-                        let result = match syscall(ctx $(, $t)*).into() {
+                        let out = syscall(ctx $(, $t)*).into();
+
+                        if let Some(recording) = recording {
+                            let record = syscall_record(module, name, recording, &memory[..], &out, value_bytes);
+                            data.kernel.record_syscall(record);
+                        }
+
+                        let result = match out {
                             Ok(Ok(value)) => {
                                 log::trace!("syscall {}::{}: ok", module, name);
                                 unsafe { *(memory.as_mut_ptr().offset(ret as isize) as *mut Ret::Value) = value };
@@ -9,9 +9,11 @@ use fvm_ipld_encoding::from_slice;
 use fvm_shared::address::Address;
 use fvm_shared::error::ErrorNumber;
 use fvm_shared::MAX_CID_LEN;
+use num_traits::Zero;
 use serde::de::DeserializeOwned;
 
-use crate::kernel::{ClassifyResult, Context as _, Result};
+use crate::gas::{GasCharge, GasTimer};
+use crate::kernel::{ClassifyResult, Context as _, Kernel, Result};
 use crate::syscall_error;
 
 pub struct Context<'a, K> {
@@ -19,6 +21,63 @@ pub struct Context<'a, K> {
     pub memory: &'a mut Memory,
 }
 
+/// Gas-metered copies across the wasm boundary. Syscalls should copy values in and out of the
+/// actor's memory through these methods (or charge for the copy with [`Context::charge_copy_in`]
+/// and [`Context::charge_copy_out`]) rather than through [`Memory`] directly, charging once per
+/// buffer.
+///
+/// Copies are only priced from NV21, and copies made by debug syscalls are never charged as
+/// they're only made when debugging is enabled on the node.
+impl<K: Kernel> Context<'_, K> {
+    /// Charges for copying `len` bytes out of the actor's memory.
+    pub fn charge_copy_in(&self, len: usize) -> Result<()> {
+        self.charge_copy(self.kernel.price_list().on_memory_copy_in(len))
+    }
+
+    /// Charges for copying `len` bytes into the actor's memory.
+    pub fn charge_copy_out(&self, len: usize) -> Result<()> {
+        self.charge_copy(self.kernel.price_list().on_memory_copy_out(len))
+    }
+
+    fn charge_copy(&self, charge: GasCharge) -> Result<()> {
+        // Don't record free copies (before NV21) in gas traces.
+        if charge.total().is_zero() {
+            return Ok(());
+        }
+        self.kernel
+            .charge_gas(&charge.name, charge.compute_gas)
+            .map(GasTimer::stop)
+    }
+
+    /// Reads a CID out of the actor's memory. See [`Memory::read_cid`].
+    pub fn read_cid(&self, offset: u32) -> Result<Cid> {
+        let cid = self.memory.read_cid(offset)?;
+        self.charge_copy_in(cid.encoded_len())?;
+        Ok(cid)
+    }
+
+    /// Writes a CID into the actor's memory. See [`Memory::write_cid`].
+    pub fn write_cid(&mut self, k: &Cid, offset: u32, len: u32) -> Result<u32> {
+        self.memory.check_bounds(offset, len)?;
+        self.charge_copy_out(k.encoded_len())?;
+        self.memory.write_cid(k, offset, len)
+    }
+
+    /// Reads an address out of the actor's memory. See [`Memory::read_address`].
+    pub fn read_address(&self, offset: u32, len: u32) -> Result<Address> {
+        self.memory.check_bounds(offset, len)?;
+        self.charge_copy_in(len as usize)?;
+        self.memory.read_address(offset, len)
+    }
+
+    /// Decodes a CBOR value out of the actor's memory. See [`Memory::read_cbor`].
+    pub fn read_cbor<T: DeserializeOwned>(&self, offset: u32, len: u32) -> Result<T> {
+        self.memory.check_bounds(offset, len)?;
+        self.charge_copy_in(len as usize)?;
+        self.memory.read_cbor(offset, len)
+    }
+}
+
 #[repr(transparent)]
 pub struct Memory([u8]);
 
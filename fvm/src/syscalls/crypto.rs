@@ -4,12 +4,12 @@ use std::cmp;
 
 use anyhow::{anyhow, Context as _};
 use fvm_shared::crypto::signature::{
-    SignatureType, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
+    SignatureType, BLS_PUB_LEN, BLS_SIG_LEN, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
 };
 use fvm_shared::piece::PieceInfo;
 use fvm_shared::sector::{
     AggregateSealVerifyProofAndInfos, RegisteredSealProof, ReplicaUpdateInfo, SealVerifyInfo,
-    WindowPoStVerifyInfo,
+    WindowPoStVerifyInfo, WinningPoStVerifyInfo,
 };
 use fvm_shared::sys;
 use num_traits::FromPrimitive;
@@ -38,7 +38,7 @@ pub fn verify_signature(
         .with_context(|| format!("unknown signature type {}", sig_type))
         .or_illegal_argument()?;
     let sig_bytes = context.memory.try_slice(sig_off, sig_len)?;
-    let addr = context.memory.read_address(addr_off, addr_len)?;
+    let addr = context.read_address(addr_off, addr_len)?;
     let plaintext = context.memory.try_slice(plaintext_off, plaintext_len)?;
 
     context
@@ -47,6 +47,73 @@ pub fn verify_signature(
         .map(|v| if v { 0 } else { -1 })
 }
 
+/// Verifies a BLS aggregate signature over `num_signers` plaintexts, one per signer.
+///
+/// - The aggregate signature is read from `sig_off`.
+/// - The signers' BLS public keys are read from `pub_keys_off` (`num_signers` keys, back to back).
+/// - The plaintexts are read from `plaintexts_off` (back to back), with their lengths read from
+///   `plaintext_lens_off` (`num_signers` little-endian `u32`s).
+///
+/// The return i32 indicates the status code of the verification:
+///  - 0: verification ok.
+///  - -1: verification failed.
+pub fn verify_aggregate_signature(
+    context: Context<'_, impl Kernel>,
+    num_signers: u32,
+    sig_off: u32,
+    pub_keys_off: u32,
+    plaintexts_off: u32,
+    plaintext_lens_off: u32,
+) -> Result<i32> {
+    let sig: [u8; BLS_SIG_LEN] = context
+        .memory
+        .try_slice(sig_off, BLS_SIG_LEN as u32)?
+        .try_into()
+        .or_illegal_argument()?;
+
+    let pub_keys_len = num_signers
+        .checked_mul(BLS_PUB_LEN as u32)
+        .context("too many signers")
+        .or_illegal_argument()?;
+    let pub_keys: Vec<[u8; BLS_PUB_LEN]> = context
+        .memory
+        .try_slice(pub_keys_off, pub_keys_len)?
+        .chunks_exact(BLS_PUB_LEN)
+        .map(|k| k.try_into().expect("chunk has the size of a public key"))
+        .collect();
+
+    let lens_len = num_signers
+        .checked_mul(4)
+        .context("too many signers")
+        .or_illegal_argument()?;
+    let plaintext_lens: Vec<u32> = context
+        .memory
+        .try_slice(plaintext_lens_off, lens_len)?
+        .chunks_exact(4)
+        .map(|l| u32::from_le_bytes(l.try_into().expect("chunk has the size of a u32")))
+        .collect();
+
+    let total_len = plaintext_lens
+        .iter()
+        .try_fold(0u32, |total, &len| total.checked_add(len))
+        .context("plaintexts too large")
+        .or_illegal_argument()?;
+    let mut data = context.memory.try_slice(plaintexts_off, total_len)?;
+    let plaintexts: Vec<&[u8]> = plaintext_lens
+        .iter()
+        .map(|&len| {
+            let (plaintext, rest) = data.split_at(len as usize);
+            data = rest;
+            plaintext
+        })
+        .collect();
+
+    context
+        .kernel
+        .verify_aggregate_signature(&sig, &pub_keys, &plaintexts)
+        .map(|v| if v { 0 } else { -1 })
+}
+
 pub fn recover_secp_public_key(
     context: Context<'_, impl Kernel>,
     hash_off: u32,
@@ -89,8 +156,9 @@ pub fn hash(
     };
 
     // Then copy the result.
+    let length = cmp::min(digest_len as usize, digest.digest().len());
+    context.charge_copy_out(length)?;
     let digest_out = context.memory.try_slice_mut(digest_off, digest_len)?;
-    let length = cmp::min(digest_out.len(), digest.digest().len());
     digest_out[..length].copy_from_slice(&digest.digest()[..length]);
     Ok(length as u32)
 }
@@ -100,7 +168,7 @@ pub fn hash(
 ///
 /// Writes the CID in the provided output buffer.
 pub fn compute_unsealed_sector_cid(
-    context: Context<'_, impl Kernel>,
+    mut context: Context<'_, impl Kernel>,
     proof_type: i64, // RegisteredSealProof,
     pieces_off: u32, // [PieceInfo]
     pieces_len: u32,
@@ -112,7 +180,7 @@ pub fn compute_unsealed_sector_cid(
     if let RegisteredSealProof::Invalid(invalid) = typ {
         return Err(syscall_error!(IllegalArgument; "invalid proof type {}", invalid).into());
     }
-    let pieces: Vec<PieceInfo> = context.memory.read_cbor(pieces_off, pieces_len)?;
+    let pieces: Vec<PieceInfo> = context.read_cbor(pieces_off, pieces_len)?;
     context.memory.check_bounds(cid_off, cid_len)?;
 
     // Compute
@@ -121,7 +189,7 @@ pub fn compute_unsealed_sector_cid(
         .compute_unsealed_sector_cid(typ, pieces.as_slice())?;
 
     // REturn
-    context.memory.write_cid(&cid, cid_off, cid_len)
+    context.write_cid(&cid, cid_off, cid_len)
 }
 
 /// Verifies a sector seal proof.
@@ -134,9 +202,7 @@ pub fn verify_seal(
     info_off: u32, // SealVerifyInfo
     info_len: u32,
 ) -> Result<i32> {
-    let info = context
-        .memory
-        .read_cbor::<SealVerifyInfo>(info_off, info_len)?;
+    let info = context.read_cbor::<SealVerifyInfo>(info_off, info_len)?;
     context
         .kernel
         .verify_seal(&info)
@@ -153,15 +219,30 @@ pub fn verify_post(
     info_off: u32, // WindowPoStVerifyInfo,
     info_len: u32,
 ) -> Result<i32> {
-    let info = context
-        .memory
-        .read_cbor::<WindowPoStVerifyInfo>(info_off, info_len)?;
+    let info = context.read_cbor::<WindowPoStVerifyInfo>(info_off, info_len)?;
     context
         .kernel
         .verify_post(&info)
         .map(|v| if v { 0 } else { -1 })
 }
 
+/// Verifies a winning proof of spacetime.
+///
+/// The return i32 indicates the status code of the verification:
+///  - 0: verification ok.
+///  - -1: verification failed.
+pub fn verify_winning_post(
+    context: Context<'_, impl Kernel>,
+    info_off: u32, // WinningPoStVerifyInfo,
+    info_len: u32,
+) -> Result<i32> {
+    let info = context.read_cbor::<WinningPoStVerifyInfo>(info_off, info_len)?;
+    context
+        .kernel
+        .verify_winning_post(&info)
+        .map(|v| if v { 0 } else { -1 })
+}
+
 /// Verifies that two block headers provide proof of a consensus fault:
 /// - both headers mined by the same actor
 /// - headers are different
@@ -215,9 +296,7 @@ pub fn verify_aggregate_seals(
     agg_off: u32, // AggregateSealVerifyProofAndInfos
     agg_len: u32,
 ) -> Result<i32> {
-    let info = context
-        .memory
-        .read_cbor::<AggregateSealVerifyProofAndInfos>(agg_off, agg_len)?;
+    let info = context.read_cbor::<AggregateSealVerifyProofAndInfos>(agg_off, agg_len)?;
     context
         .kernel
         .verify_aggregate_seals(&info)
@@ -232,9 +311,7 @@ pub fn verify_replica_update(
     rep_off: u32, // ReplicaUpdateInfo
     rep_len: u32,
 ) -> Result<i32> {
-    let info = context
-        .memory
-        .read_cbor::<ReplicaUpdateInfo>(rep_off, rep_len)?;
+    let info = context.read_cbor::<ReplicaUpdateInfo>(rep_off, rep_len)?;
     context
         .kernel
         .verify_replica_update(&info)
@@ -252,12 +329,10 @@ pub fn batch_verify_seals(
     result_off: u32,
 ) -> Result<()> {
     // Check and decode params.
-    let batch = context
-        .memory
-        .read_cbor::<Vec<SealVerifyInfo>>(batch_off, batch_len)?;
-    let output = context
+    let batch = context.read_cbor::<Vec<SealVerifyInfo>>(batch_off, batch_len)?;
+    context
         .memory
-        .try_slice_mut(result_off, batch.len() as u32)?;
+        .check_bounds(result_off, batch.len() as u32)?;
 
     // Execute.
     let result = context.kernel.batch_verify_seals(&batch)?;
@@ -273,6 +348,10 @@ pub fn batch_verify_seals(
     }
 
     // Return.
+    context.charge_copy_out(result.len())?;
+    let output = context
+        .memory
+        .try_slice_mut(result_off, batch.len() as u32)?;
     unsafe {
         output.copy_from_slice(&*(&*result as *const [bool] as *const [u8]));
     }
@@ -0,0 +1,131 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use wasmtime::Linker;
+
+use super::bind::BindSyscall;
+use super::InvocationData;
+use crate::gas::Gas;
+use crate::Kernel;
+
+/// Syscall namespaces (wasm import modules) used by the builtin syscalls. Custom syscalls may not
+/// be bound into these namespaces.
+pub const RESERVED_NAMESPACES: &[&str] = &[
+    "vm", "network", "ipld", "self", "actor", "crypto", "event", "rand", "gas", "send", "debug",
+];
+
+/// A handle for binding additional, network-specific syscalls into actor modules.
+///
+/// Custom syscalls are bound by implementing [`Kernel::bind_custom_syscalls`] on a custom kernel.
+/// They're invoked following the same convention as the builtin syscalls, and are charged the
+/// same base syscall gas.
+pub struct SyscallLinker<'a, K> {
+    linker: &'a mut Linker<InvocationData<K>>,
+}
+
+impl<'a, K: Kernel> SyscallLinker<'a, K> {
+    pub(crate) fn new(linker: &'a mut Linker<InvocationData<K>>) -> Self {
+        SyscallLinker { linker }
+    }
+
+    /// Returns a handle for binding syscalls into the given namespace. Fails if the namespace is
+    /// empty or reserved by the builtin syscalls.
+    pub fn namespace(
+        &mut self,
+        namespace: &'static str,
+    ) -> anyhow::Result<SyscallNamespace<'_, K>> {
+        if namespace.is_empty() {
+            return Err(anyhow!("syscall namespace must not be empty"));
+        }
+        if RESERVED_NAMESPACES.contains(&namespace) {
+            return Err(anyhow!("syscall namespace {} is reserved", namespace));
+        }
+        Ok(SyscallNamespace {
+            linker: &mut *self.linker,
+            namespace,
+        })
+    }
+}
+
+/// A handle for binding custom syscalls into a single namespace. See [`SyscallLinker`].
+pub struct SyscallNamespace<'a, K> {
+    linker: &'a mut Linker<InvocationData<K>>,
+    namespace: &'static str,
+}
+
+impl<'a, K: Kernel> SyscallNamespace<'a, K> {
+    /// Bind a syscall into this namespace, charging only the base syscall gas.
+    pub fn bind<Args, Ret, Func>(
+        &mut self,
+        name: &'static str,
+        syscall: Func,
+    ) -> anyhow::Result<&mut Self>
+    where
+        Linker<InvocationData<K>>: BindSyscall<Args, Ret, Func, Kernel = K>,
+    {
+        self.linker.bind(self.namespace, name, syscall)?;
+        Ok(self)
+    }
+
+    /// Bind a syscall into this namespace, additionally charging the gas returned by `gas` before
+    /// each invocation. The charge is named `<namespace>::<name>`.
+    pub fn bind_with_gas<Args, Ret, Func>(
+        &mut self,
+        name: &'static str,
+        gas: impl Fn(&K) -> Gas + Send + Sync + 'static,
+        syscall: Func,
+    ) -> anyhow::Result<&mut Self>
+    where
+        Linker<InvocationData<K>>: BindSyscall<Args, Ret, Func, Kernel = K>,
+    {
+        let charge_name = format!("{}::{}", self.namespace, name);
+        self.linker.bind_with_gas_hook(
+            self.namespace,
+            name,
+            Some((charge_name, Arc::new(gas))),
+            syscall,
+        )?;
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_ipld_blockstore::MemoryBlockstore;
+
+    use super::*;
+    use crate::call_manager::DefaultCallManager;
+    use crate::kernel;
+    use crate::machine::DefaultMachine;
+    use crate::syscalls::Context;
+    use crate::test::DummyExterns;
+    use crate::DefaultKernel;
+
+    type TestKernel =
+        DefaultKernel<DefaultCallManager<DefaultMachine<MemoryBlockstore, DummyExterns>>>;
+
+    fn answer(_: Context<'_, TestKernel>) -> kernel::Result<u32> {
+        Ok(42)
+    }
+
+    #[test]
+    fn namespaces() {
+        let engine = wasmtime::Engine::default();
+        let mut linker = Linker::new(&engine);
+        let mut syscalls = SyscallLinker::<TestKernel>::new(&mut linker);
+
+        for ns in RESERVED_NAMESPACES.iter().copied().chain([""]) {
+            assert!(syscalls.namespace(ns).is_err());
+        }
+
+        syscalls
+            .namespace("mynet")
+            .unwrap()
+            .bind("answer", answer)
+            .unwrap()
+            .bind_with_gas("priced_answer", |_| Gas::new(10), answer)
+            .unwrap();
+    }
+}
@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0, MIT
 use crate::kernel::{ClassifyResult, Result};
 use crate::syscalls::context::Context;
-use crate::Kernel;
+use crate::{syscall_error, Kernel};
 
 pub fn log(context: Context<'_, impl Kernel>, msg_off: u32, msg_len: u32) -> Result<()> {
     // No-op if disabled.
@@ -16,6 +16,31 @@ pub fn log(context: Context<'_, impl Kernel>, msg_off: u32, msg_len: u32) -> Res
     Ok(())
 }
 
+pub fn log_at(
+    context: Context<'_, impl Kernel>,
+    level: u32,
+    msg_off: u32,
+    msg_len: u32,
+) -> Result<()> {
+    // No-op if disabled.
+    if !context.kernel.debug_enabled() {
+        return Ok(());
+    }
+
+    let level = match level {
+        1 => log::Level::Error,
+        2 => log::Level::Warn,
+        3 => log::Level::Info,
+        4 => log::Level::Debug,
+        5 => log::Level::Trace,
+        _ => return Err(syscall_error!(IllegalArgument; "invalid log level {}", level).into()),
+    };
+    let msg = context.memory.try_slice(msg_off, msg_len)?;
+    let msg = String::from_utf8(msg.to_owned()).or_illegal_argument()?;
+    context.kernel.log_at(level, msg);
+    Ok(())
+}
+
 pub fn enabled(context: Context<'_, impl Kernel>) -> Result<i32> {
     Ok(if context.kernel.debug_enabled() {
         0
@@ -24,6 +49,27 @@ pub fn enabled(context: Context<'_, impl Kernel>) -> Result<i32> {
     })
 }
 
+/// Writes the IDs of the actors on the call stack (outermost first, ending with the caller) into
+/// the output buffer as little-endian u64s, returning the number of IDs written. Returns 0 without
+/// writing anything if debugging is disabled.
+pub fn call_stack(context: Context<'_, impl Kernel>, obuf_off: u32, obuf_len: u32) -> Result<u32> {
+    let obuf = context.memory.try_slice_mut(obuf_off, obuf_len)?;
+
+    // No-op if disabled.
+    if !context.kernel.debug_enabled() {
+        return Ok(0);
+    }
+
+    let stack = context.kernel.call_stack();
+    let obuf = obuf
+        .get_mut(..stack.len() * 8)
+        .ok_or_else(|| syscall_error!(BufferTooSmall; "call stack output buffer is too small"))?;
+    for (chunk, id) in obuf.chunks_exact_mut(8).zip(&stack) {
+        chunk.copy_from_slice(&id.to_le_bytes());
+    }
+    Ok(stack.len() as u32)
+}
+
 pub fn store_artifact(
     context: Context<'_, impl Kernel>,
     name_off: u32,
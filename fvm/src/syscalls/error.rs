@@ -6,7 +6,7 @@ use fvm_shared::error::ExitCode;
 use wasmtime::Trap;
 
 use crate::call_manager::NO_DATA_BLOCK_ID;
-use crate::kernel::{BlockId, ExecutionError};
+use crate::kernel::{BlockId, ExecutionError, SyscallError};
 
 /// Represents an actor "abort".
 #[derive(Debug, thiserror::Error)]
@@ -50,6 +50,27 @@ impl Abort {
     }
 }
 
+/// The result of a syscall that may either return to the calling actor (with a value or an error)
+/// or end the calling actor's invocation.
+pub enum ControlFlow<T> {
+    /// Return the value to the actor.
+    Return(T),
+    /// Return the error to the actor.
+    Error(SyscallError),
+    /// Abort the actor's invocation.
+    Abort(Abort),
+}
+
+impl<T> From<ExecutionError> for ControlFlow<T> {
+    fn from(e: ExecutionError) -> Self {
+        match e {
+            ExecutionError::Syscall(err) => ControlFlow::Error(err),
+            ExecutionError::OutOfGas => ControlFlow::Abort(Abort::OutOfGas),
+            ExecutionError::Fatal(err) => ControlFlow::Abort(Abort::Fatal(err)),
+        }
+    }
+}
+
 /// Unwraps a trap error from an actor into an "abort".
 impl From<anyhow::Error> for Abort {
     fn from(e: anyhow::Error) -> Self {
@@ -75,6 +96,10 @@ impl From<anyhow::Error> for Abort {
                     trap.to_string(),
                     NO_DATA_BLOCK_ID,
                 ),
+                // Only reachable when metering execution with fuel.
+                Trap::OutOfFuel => Abort::OutOfGas,
+                // Only reachable when an execution timeout is configured.
+                Trap::Interrupt => Abort::Fatal(anyhow!("message execution timed out")),
                 _ => Abort::Fatal(anyhow!("unexpected wasmtime trap: {}", trap)),
             };
         };
@@ -25,3 +25,9 @@ pub fn charge_gas(
 pub fn available(context: Context<'_, impl Kernel>) -> Result<u64> {
     Ok(context.kernel.gas_available().round_down())
 }
+
+pub fn milestone(context: Context<'_, impl Kernel>, name_off: u32, name_len: u32) -> Result<()> {
+    let name =
+        str::from_utf8(context.memory.try_slice(name_off, name_len)?).or_illegal_argument()?;
+    context.kernel.record_gas_milestone(name)
+}
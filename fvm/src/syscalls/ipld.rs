@@ -7,7 +7,7 @@ use crate::kernel::Result;
 use crate::Kernel;
 
 pub fn block_open(context: Context<'_, impl Kernel>, cid: u32) -> Result<sys::out::ipld::IpldOpen> {
-    let cid = context.memory.read_cid(cid)?;
+    let cid = context.read_cid(cid)?;
     let (id, stat) = context.kernel.block_open(&cid)?;
     Ok(sys::out::ipld::IpldOpen {
         id,
@@ -27,7 +27,7 @@ pub fn block_create(
 }
 
 pub fn block_link(
-    context: Context<'_, impl Kernel>,
+    mut context: Context<'_, impl Kernel>,
     id: u32,
     hash_fun: u64,
     hash_len: u32,
@@ -41,7 +41,7 @@ pub fn block_link(
     let cid = context.kernel.block_link(id, hash_fun, hash_len)?;
 
     // Return
-    context.memory.write_cid(&cid, cid_off, cid_len)
+    context.write_cid(&cid, cid_off, cid_len)
 }
 
 pub fn block_read(
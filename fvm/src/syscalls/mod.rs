@@ -1,8 +1,9 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use anyhow::{anyhow, Context as _};
+use fvm_shared::version::NetworkVersion;
 use num_traits::Zero;
-use wasmtime::{AsContextMut, ExternType, Global, Linker, Memory, Module, Val};
+use wasmtime::{AsContextMut, ExternType, Global, Linker, Memory, Module, StoreContextMut, Val};
 
 use crate::call_manager::backtrace;
 use crate::gas::{Gas, GasInstant, GasTimer};
@@ -16,6 +17,7 @@ mod actor;
 mod bind;
 mod context;
 mod crypto;
+mod custom;
 mod debug;
 mod event;
 mod gas;
@@ -26,7 +28,9 @@ mod send;
 mod sself;
 mod vm;
 
-pub(self) use context::Context;
+pub use bind::{BindSyscall, GasHook};
+pub use context::Context;
+pub use custom::{SyscallLinker, SyscallNamespace, RESERVED_NAMESPACES};
 
 /// Invocation data attached to a wasm "store" and available to the syscall binding.
 pub struct InvocationData<K> {
@@ -47,6 +51,11 @@ pub struct InvocationData<K> {
     /// `last_milligas_available`.
     pub last_gas_available: Gas,
 
+    /// The last-set fuel, if execution is metered with wasmtime fuel instead of instrumentation.
+    /// When `charge_for_exec` is called, we charge for the fuel consumed since then, at the price
+    /// list's fuel exchange rate.
+    pub last_fuel_available: Option<u64>,
+
     /// The total size of the memory used by the execution the last time we charged gas for it.
     pub last_memory_bytes: usize,
 
@@ -79,6 +88,14 @@ pub fn update_gas_available(
         .set(&mut ctx, Val::I64(avail_milligas))
         .map_err(|e| Abort::Fatal(anyhow!("failed to set available gas global: {}", e)))?;
 
+    // If we're metering with fuel, convert the available gas into fuel.
+    if ctx.data().last_fuel_available.is_some() {
+        let rate = fuel_exchange_rate(ctx.data());
+        let fuel = avail_gas.as_milligas() / rate.as_milligas();
+        set_fuel(&mut ctx, fuel)?;
+        ctx.data_mut().last_fuel_available = Some(fuel);
+    }
+
     // Finally, update the last-seen values. We'll use these values in `charge_for_exec` below.
     let data = ctx.data_mut();
     data.last_gas_available = avail_gas;
@@ -93,6 +110,10 @@ pub fn charge_for_exec<K: Kernel>(
     ctx: &mut impl AsContextMut<Data = InvocationData<K>>,
 ) -> Result<(), Abort> {
     let mut ctx = ctx.as_context_mut();
+    if ctx.data().last_fuel_available.is_some() {
+        return charge_for_fuel(&mut ctx);
+    }
+
     let global = ctx.data_mut().avail_gas_global;
 
     // Get the remaining milligas. This will go _negative_ if we run out.
@@ -160,6 +181,110 @@ pub fn charge_for_exec<K: Kernel>(
     Ok(())
 }
 
+/// Returns the price of one unit of fuel (at least one milligas).
+fn fuel_exchange_rate<K: Kernel>(data: &InvocationData<K>) -> Gas {
+    data.kernel
+        .price_list()
+        .wasm_rules
+        .fuel_exchange_rate
+        .max(Gas::from_milligas(1))
+}
+
+/// Sets the fuel available to the Wasm module to exactly `fuel`.
+fn set_fuel<K: Kernel>(
+    ctx: &mut StoreContextMut<'_, InvocationData<K>>,
+    fuel: u64,
+) -> Result<(), Abort> {
+    let remaining = ctx
+        .consume_fuel(0)
+        .context("failed to get remaining fuel")
+        .map_err(Abort::Fatal)?;
+    if remaining > fuel {
+        ctx.consume_fuel(remaining - fuel)
+            .context("failed to consume fuel")
+            .map_err(Abort::Fatal)?;
+    } else {
+        ctx.add_fuel(fuel - remaining)
+            .context("failed to add fuel")
+            .map_err(Abort::Fatal)?;
+    }
+    Ok(())
+}
+
+/// The fuel-metering equivalent of [`charge_for_exec`]. We charge for the fuel consumed since the
+/// last charge, plus whatever the bulk memory instrumentation (see [`crate::gas::FuelRules`])
+/// deducted from the gas global.
+///
+/// Running out of fuel traps, so the fuel consumed never exceeds the available gas. Exhausting the
+/// fuel exactly isn't an error in itself (the next charge will fail); a fuel-exhaustion trap is
+/// handled by [`charge_for_fuel_exhaustion`].
+fn charge_for_fuel<K: Kernel>(
+    ctx: &mut StoreContextMut<'_, InvocationData<K>>,
+) -> Result<(), Abort> {
+    let remaining = ctx
+        .consume_fuel(0)
+        .context("failed to get remaining fuel")
+        .map_err(Abort::Fatal)?;
+
+    let global = ctx.data_mut().avail_gas_global;
+    let milligas_available_wasm = global
+        .get(&mut *ctx)
+        .i64()
+        .context("failed to get wasm gas")
+        .map_err(Abort::Fatal)?;
+
+    let data = ctx.data_mut();
+    let rate = fuel_exchange_rate(data);
+    let last_fuel = data.last_fuel_available.unwrap_or_default();
+
+    // The instrumented bulk memory charges may take the global negative, in which case we charge
+    // for more than the remaining gas so that we actually run out.
+    let bulk_memory_gas_charge = if milligas_available_wasm < 0 {
+        data.last_gas_available + Gas::from_milligas(milligas_available_wasm.abs_diff(0))
+    } else {
+        data.last_gas_available - Gas::from_milligas(milligas_available_wasm as u64)
+    };
+    let exec_gas_charge = rate * last_fuel.saturating_sub(remaining) + bulk_memory_gas_charge;
+
+    let t = data
+        .kernel
+        .charge_gas("wasm_exec", exec_gas_charge)
+        .map_err(Abort::from_error_as_fatal)?;
+    t.stop_with(data.last_charge_time);
+
+    // Fuel doesn't account for memory growth, so we have to charge for it separately.
+    let memory_bytes = data.kernel.limiter_mut().memory_used();
+    let memory_delta_bytes = memory_bytes.saturating_sub(data.last_memory_bytes);
+    let memory_gas_charge = data.kernel.price_list().grow_memory_gas(memory_delta_bytes);
+    if !memory_gas_charge.is_zero() {
+        let _ = data
+            .kernel
+            .charge_gas("wasm_memory_grow", memory_gas_charge)
+            .map_err(Abort::from_error_as_fatal)?;
+    }
+    data.last_fuel_available = Some(remaining);
+
+    Ok(())
+}
+
+/// Charges for any uncharged execution after the Wasm module trapped because it ran out of fuel,
+/// then for all remaining gas so that the invocation actually runs out of gas.
+pub fn charge_for_fuel_exhaustion<K: Kernel>(
+    ctx: &mut impl AsContextMut<Data = InvocationData<K>>,
+) -> Result<(), Abort> {
+    let mut ctx = ctx.as_context_mut();
+    charge_for_fuel(&mut ctx)?;
+
+    // Less than one unit of fuel's worth of gas may be left over, so charge for just over it.
+    let data = ctx.data_mut();
+    let remaining_gas = data.kernel.gas_available() + Gas::from_milligas(1);
+    let _ = data
+        .kernel
+        .charge_gas("wasm_exec", remaining_gas)
+        .map_err(Abort::from_error_as_fatal)?;
+    Ok(())
+}
+
 /// Charge for the initial memory and tables before a Wasm module is instantiated.
 ///
 /// The Wasm instrumentation machinery via [fvm_wasm_instrument::gas_metering::MemoryGrowCost]
@@ -231,106 +356,135 @@ fn min_table_elements(module: &Module) -> Option<u32> {
     }
 }
 
-use self::bind::BindSyscall;
 use self::error::Abort;
 
-// Binds the syscall handlers so they can handle invocations
-// from the actor code.
-pub fn bind_syscalls(
-    linker: &mut Linker<InvocationData<impl Kernel + 'static>>,
-) -> anyhow::Result<()> {
-    linker.bind("vm", "exit", vm::exit)?;
-    linker.bind("vm", "message_context", vm::message_context)?;
-
-    linker.bind(
-        "network",
-        "total_fil_circ_supply",
-        network::total_fil_circ_supply,
-    )?;
-    linker.bind("network", "context", network::context)?;
-    linker.bind("network", "tipset_cid", network::tipset_cid)?;
-
-    linker.bind("ipld", "block_open", ipld::block_open)?;
-    linker.bind("ipld", "block_create", ipld::block_create)?;
-    linker.bind("ipld", "block_read", ipld::block_read)?;
-    linker.bind("ipld", "block_stat", ipld::block_stat)?;
-    linker.bind("ipld", "block_link", ipld::block_link)?;
-
-    linker.bind("self", "root", sself::root)?;
-    linker.bind("self", "set_root", sself::set_root)?;
-    linker.bind("self", "current_balance", sself::current_balance)?;
-    linker.bind("self", "self_destruct", sself::self_destruct)?;
-
-    linker.bind("actor", "resolve_address", actor::resolve_address)?;
-    linker.bind(
-        "actor",
-        "lookup_delegated_address",
-        actor::lookup_delegated_address,
-    )?;
-    linker.bind("actor", "get_actor_code_cid", actor::get_actor_code_cid)?;
-    linker.bind("actor", "next_actor_address", actor::next_actor_address)?;
-    linker.bind("actor", "create_actor", actor::create_actor)?;
-    linker.bind(
-        "actor",
-        "get_builtin_actor_type",
-        actor::get_builtin_actor_type,
-    )?;
-    linker.bind(
-        "actor",
-        "get_code_cid_for_type",
-        actor::get_code_cid_for_type,
-    )?;
-    linker.bind("actor", "balance_of", actor::balance_of)?;
-
-    // Only wire this syscall when M2 native is enabled.
-    #[cfg(feature = "m2-native")]
-    linker.bind("actor", "install_actor", actor::install_actor)?;
-
-    linker.bind("crypto", "verify_signature", crypto::verify_signature)?;
-    linker.bind(
-        "crypto",
-        "recover_secp_public_key",
-        crypto::recover_secp_public_key,
-    )?;
-    linker.bind("crypto", "hash", crypto::hash)?;
-    linker.bind("crypto", "verify_seal", crypto::verify_seal)?;
-    linker.bind("crypto", "verify_post", crypto::verify_post)?;
-    linker.bind(
-        "crypto",
-        "compute_unsealed_sector_cid",
-        crypto::compute_unsealed_sector_cid,
-    )?;
-    linker.bind(
-        "crypto",
-        "verify_consensus_fault",
-        crypto::verify_consensus_fault,
-    )?;
-    linker.bind(
-        "crypto",
-        "verify_aggregate_seals",
-        crypto::verify_aggregate_seals,
-    )?;
-    linker.bind(
-        "crypto",
-        "verify_replica_update",
-        crypto::verify_replica_update,
-    )?;
-    linker.bind("crypto", "batch_verify_seals", crypto::batch_verify_seals)?;
-
-    linker.bind("event", "emit_event", event::emit_event)?;
-
-    linker.bind("rand", "get_chain_randomness", rand::get_chain_randomness)?;
-    linker.bind("rand", "get_beacon_randomness", rand::get_beacon_randomness)?;
-
-    linker.bind("gas", "charge", gas::charge_gas)?;
-    linker.bind("gas", "available", gas::available)?;
+/// Declares the syscalls from a single table of `module: [name => handler]` entries, generating
+/// both [`bind_syscalls`] and [`SYSCALLS`] so they can't get out of sync. Attributes on an entry
+/// (e.g., `#[cfg(...)]`) only apply to its binding.
+macro_rules! syscalls {
+    ($($module:literal: [$($(#[$attr:meta])* $name:literal => $handler:path),* $(,)?]),* $(,)?) => {
+        /// Binds the syscall handlers so they can handle invocations from the actor code. Syscalls
+        /// introduced by a network upgrade are only bound from that network version (see
+        /// [`is_syscall_available`]).
+        pub fn bind_syscalls(
+            linker: &mut Linker<InvocationData<impl Kernel + 'static>>,
+            network_version: NetworkVersion,
+        ) -> anyhow::Result<()> {
+            $($(
+                $(#[$attr])*
+                if is_syscall_available($module, $name, network_version) {
+                    linker.bind($module, $name, $handler)?;
+                }
+            )*)*
+            Ok(())
+        }
+
+        /// The syscalls bound by [`bind_syscalls`], by module. These are the only imports
+        /// user-deployed actors may declare (see
+        /// [`validate_wasm_for_deployment`](crate::engine::validate_wasm_for_deployment)).
+        ///
+        /// This includes the `actor::install_actor*` syscalls even though they're only bound with
+        /// M2 native, as actors can't be deployed without them.
+        pub(crate) const SYSCALLS: &[(&str, &[&str])] = &[$(($module, &[$($name),*])),*];
+    };
+}
 
+syscalls! {
+    "vm": [
+        "exit" => vm::exit,
+        "message_context" => vm::message_context,
+        "caller_code_cid" => vm::caller_code_cid,
+    ],
+    "network": [
+        "total_fil_circ_supply" => network::total_fil_circ_supply,
+        "context" => network::context,
+        "tipset_cid" => network::tipset_cid,
+    ],
+    "ipld": [
+        "block_open" => ipld::block_open,
+        "block_create" => ipld::block_create,
+        "block_read" => ipld::block_read,
+        "block_stat" => ipld::block_stat,
+        "block_link" => ipld::block_link,
+    ],
+    "self": [
+        "root" => sself::root,
+        "code_cid" => sself::code_cid,
+        "set_root" => sself::set_root,
+        "current_balance" => sself::current_balance,
+        "self_destruct" => sself::self_destruct,
+    ],
+    "actor": [
+        "resolve_address" => actor::resolve_address,
+        "lookup_delegated_address" => actor::lookup_delegated_address,
+        "get_actor_code_cid" => actor::get_actor_code_cid,
+        "next_actor_address" => actor::next_actor_address,
+        "create_actor" => actor::create_actor,
+        "get_builtin_actor_type" => actor::get_builtin_actor_type,
+        "get_code_cid_for_type" => actor::get_code_cid_for_type,
+        "balance_of" => actor::balance_of,
+        "upgrade_actor" => actor::upgrade_actor,
+        // Only wire these syscalls when M2 native is enabled.
+        #[cfg(feature = "m2-native")]
+        "install_actor" => actor::install_actor,
+        #[cfg(feature = "m2-native")]
+        "install_actor_code" => actor::install_actor_code,
+    ],
+    "crypto": [
+        "verify_signature" => crypto::verify_signature,
+        "recover_secp_public_key" => crypto::recover_secp_public_key,
+        "verify_aggregate_signature" => crypto::verify_aggregate_signature,
+        "hash" => crypto::hash,
+        "verify_seal" => crypto::verify_seal,
+        "verify_post" => crypto::verify_post,
+        "verify_winning_post" => crypto::verify_winning_post,
+        "compute_unsealed_sector_cid" => crypto::compute_unsealed_sector_cid,
+        "verify_consensus_fault" => crypto::verify_consensus_fault,
+        "verify_aggregate_seals" => crypto::verify_aggregate_seals,
+        "verify_replica_update" => crypto::verify_replica_update,
+        "batch_verify_seals" => crypto::batch_verify_seals,
+    ],
+    "event": [
+        "emit_event" => event::emit_event,
+    ],
+    "rand": [
+        "get_chain_randomness" => rand::get_chain_randomness,
+        "get_beacon_randomness" => rand::get_beacon_randomness,
+    ],
+    "gas": [
+        "charge" => gas::charge_gas,
+        "available" => gas::available,
+        "milestone" => gas::milestone,
+    ],
     // Ok, this singled-out syscall should probably be in another category.
-    linker.bind("send", "send", send::send)?;
-
-    linker.bind("debug", "log", debug::log)?;
-    linker.bind("debug", "enabled", debug::enabled)?;
-    linker.bind("debug", "store_artifact", debug::store_artifact)?;
+    "send": [
+        "send" => send::send,
+    ],
+    "debug": [
+        "log" => debug::log,
+        "log_at" => debug::log_at,
+        "enabled" => debug::enabled,
+        "store_artifact" => debug::store_artifact,
+        "call_stack" => debug::call_stack,
+    ],
+}
 
-    Ok(())
+/// Returns true if the syscall is available at the network version. Syscalls listed in
+/// [`SYSCALLS`] are available from the network version that introduced them.
+pub(crate) fn is_syscall_available(
+    module: &str,
+    name: &str,
+    network_version: NetworkVersion,
+) -> bool {
+    match (module, name) {
+        ("actor", "upgrade_actor")
+        | ("crypto", "verify_aggregate_signature")
+        | ("crypto", "verify_winning_post")
+        | ("vm", "caller_code_cid")
+        | ("self", "code_cid")
+        | ("gas", "milestone")
+        | ("debug", "log_at")
+        | ("debug", "call_stack") => network_version >= NetworkVersion::V21,
+        _ => true,
+    }
 }
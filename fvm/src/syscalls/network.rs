@@ -22,7 +22,7 @@ pub fn context(context: Context<'_, impl Kernel>) -> crate::kernel::Result<Netwo
 }
 
 pub fn tipset_cid(
-    context: Context<'_, impl Kernel>,
+    mut context: Context<'_, impl Kernel>,
     epoch: i64,
     obuf_off: u32,
     obuf_len: u32,
@@ -31,5 +31,5 @@ pub fn tipset_cid(
     context.memory.check_bounds(obuf_off, obuf_len)?;
 
     let cid = context.kernel.tipset_cid(epoch)?;
-    context.memory.write_cid(&cid, obuf_off, obuf_len)
+    context.write_cid(&cid, obuf_off, obuf_len)
 }
@@ -11,20 +11,40 @@ use crate::kernel::{ClassifyResult, Kernel, Result};
 /// The returned u32 represents the _actual_ length of the CID. If the supplied
 /// buffer is smaller, no value will have been written. The caller must retry
 /// with a larger buffer.
-pub fn root(context: Context<'_, impl Kernel>, obuf_off: u32, obuf_len: u32) -> Result<u32> {
+pub fn root(mut context: Context<'_, impl Kernel>, obuf_off: u32, obuf_len: u32) -> Result<u32> {
     context.memory.check_bounds(obuf_off, obuf_len)?;
 
     let root = context.kernel.root()?;
 
-    context.memory.write_cid(&root, obuf_off, obuf_len)
+    context.write_cid(&root, obuf_off, obuf_len)
 }
 
+/// Returns the code CID of the actor by writing it in the specified buffer.
+///
+/// The returned u32 represents the _actual_ length of the CID. If the supplied
+/// buffer is smaller, no value will have been written. The caller must retry
+/// with a larger buffer.
+pub fn code_cid(
+    mut context: Context<'_, impl Kernel>,
+    obuf_off: u32,
+    obuf_len: u32,
+) -> Result<u32> {
+    context.memory.check_bounds(obuf_off, obuf_len)?;
+
+    let cid = context.kernel.self_code_cid()?;
+
+    context.write_cid(&cid, obuf_off, obuf_len)
+}
+
+/// Sets the root CID of the actor's state. The CID must be reachable (i.e., created or opened by
+/// the actor within the current call).
 pub fn set_root(context: Context<'_, impl Kernel>, cid_off: u32) -> Result<()> {
-    let cid = context.memory.read_cid(cid_off)?;
+    let cid = context.read_cid(cid_off)?;
     context.kernel.set_root(cid)?;
     Ok(())
 }
 
+/// Returns the actor's current balance.
 pub fn current_balance(context: Context<'_, impl Kernel>) -> Result<sys::TokenAmount> {
     let balance = context.kernel.current_balance()?;
     balance
@@ -33,12 +53,14 @@ pub fn current_balance(context: Context<'_, impl Kernel>) -> Result<sys::TokenAm
         .or_fatal()
 }
 
+/// Deletes the actor, transferring its remaining balance to the beneficiary at the specified
+/// address.
 pub fn self_destruct(
     context: Context<'_, impl Kernel>,
     addr_off: u32,
     addr_len: u32,
 ) -> Result<()> {
-    let addr = context.memory.read_address(addr_off, addr_len)?;
+    let addr = context.read_address(addr_off, addr_len)?;
     context.kernel.self_destruct(&addr)?;
     Ok(())
 }
@@ -63,3 +63,20 @@ pub fn exit(
 pub fn message_context(context: Context<'_, impl Kernel>) -> crate::kernel::Result<MessageContext> {
     context.kernel.msg_context()
 }
+
+/// Returns the code CID of the caller by writing it in the specified buffer.
+///
+/// The returned u32 represents the _actual_ length of the CID. If the supplied
+/// buffer is smaller, no value will have been written. The caller must retry
+/// with a larger buffer.
+pub fn caller_code_cid(
+    mut context: Context<'_, impl Kernel>,
+    obuf_off: u32,
+    obuf_len: u32,
+) -> crate::kernel::Result<u32> {
+    context.memory.check_bounds(obuf_off, obuf_len)?;
+
+    let cid = context.kernel.msg_caller_code_cid()?;
+
+    context.write_cid(&cid, obuf_off, obuf_len)
+}
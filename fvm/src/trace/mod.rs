@@ -3,12 +3,19 @@
 use fvm_ipld_encoding::ipld_block::IpldBlock;
 use fvm_shared::address::Address;
 use fvm_shared::econ::TokenAmount;
-use fvm_shared::error::ExitCode;
+use fvm_shared::error::{ErrorNumber, ExitCode};
 use fvm_shared::{ActorID, MethodNum};
 
-use crate::gas::GasCharge;
+use crate::gas::{Gas, GasCharge};
 use crate::kernel::SyscallError;
 
+mod replay;
+mod spans;
+pub use replay::{replay_syscalls, ReplayOutcome};
+#[cfg(feature = "opentelemetry")]
+pub use spans::otel;
+pub use spans::{call_spans, CallSpan};
+
 /// Execution Trace, only for informational and debugging purposes.
 pub type ExecutionTrace = Vec<ExecutionEvent>;
 
@@ -26,6 +33,46 @@ pub enum ExecutionEvent {
         params: Option<IpldBlock>,
         value: TokenAmount,
     },
-    CallReturn(ExitCode, Option<IpldBlock>),
+    /// The return of a call, along with the total gas used by the call (including any nested
+    /// calls).
+    CallReturn(ExitCode, Option<IpldBlock>, Gas),
     CallError(SyscallError),
+    /// A syscall made by an actor. Only recorded when syscall recording is enabled (see
+    /// [`MachineContext::syscall_recording`](crate::machine::MachineContext::syscall_recording)).
+    Syscall(SyscallRecord),
+}
+
+/// The inputs and outputs of a single syscall, for differential testing and replay (see
+/// [`replay_syscalls`]).
+///
+/// Along with the syscall's direct arguments and outcome, the memory written by the syscall
+/// through pointer arguments (e.g., output buffers) is recorded. Memory read by the syscall isn't
+/// recorded separately: it's determined by the actor's code and the outputs of the syscalls it
+/// made before.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyscallRecord {
+    /// The syscall's module (namespace).
+    pub module: &'static str,
+    /// The syscall's name.
+    pub name: &'static str,
+    /// The syscall's arguments, excluding the return-value pointer, each widened to 64 bits.
+    pub args: Vec<u64>,
+    /// The syscall's outcome.
+    pub result: SyscallOutcome,
+    /// The ranges of the actor's memory written by the syscall, as `(offset, bytes)`, not
+    /// including the return value.
+    pub writes: Vec<(u32, Vec<u8>)>,
+}
+
+/// The outcome of a recorded syscall.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyscallOutcome {
+    /// The syscall returned a value (in its in-memory representation, empty if the syscall
+    /// returns nothing).
+    Ok(Vec<u8>),
+    /// The syscall returned an error to the actor.
+    Error(ErrorNumber),
+    /// The syscall aborted the actor's invocation with the given exit code (e.g., `vm::exit`, or
+    /// running out of gas).
+    Abort(ExitCode),
 }
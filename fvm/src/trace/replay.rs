@@ -0,0 +1,177 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::collections::VecDeque;
+
+use anyhow::{anyhow, Context as _};
+use fvm_shared::error::ExitCode;
+use wasmtime::{Caller, Engine, Extern, ExternType, Linker, Module, Store, Val};
+
+use super::{SyscallOutcome, SyscallRecord};
+
+/// The outcome of replaying an actor invocation (see [`replay_syscalls`]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplayOutcome {
+    /// The actor's `invoke` entrypoint returned the given block ID.
+    Returned(u32),
+    /// A replayed syscall aborted the invocation with the given exit code.
+    Aborted(ExitCode),
+}
+
+/// The state of a replay: the syscalls left to replay, and the exit code of the syscall that
+/// aborted the invocation (if any).
+struct Replay {
+    records: VecDeque<SyscallRecord>,
+    aborted: Option<ExitCode>,
+}
+
+/// Re-runs an actor invocation against the syscalls recorded from a previous run (see
+/// [`MachineContext::syscall_recording`](crate::machine::MachineContext::syscall_recording)),
+/// without a kernel or machine.
+///
+/// The actor's (uninstrumented) `wasm` module is invoked with `params_id`, and every syscall it
+/// makes is served from the next record: the record must be for the same syscall with the same
+/// arguments, its memory writes are applied, and its outcome is returned to the actor. This makes
+/// it possible to check that an actor (or a different build or implementation of it) behaves
+/// exactly as it did when the syscalls were recorded.
+///
+/// Fails if the actor makes a syscall other than the next recorded one or doesn't make all the
+/// recorded syscalls, or if it traps.
+pub fn replay_syscalls(
+    wasm: &[u8],
+    params_id: u32,
+    records: impl IntoIterator<Item = SyscallRecord>,
+) -> anyhow::Result<ReplayOutcome> {
+    let engine = Engine::default();
+    let module = Module::new(&engine, wasm).context("failed to compile actor")?;
+    let mut store = Store::new(
+        &engine,
+        Replay {
+            records: records.into_iter().collect(),
+            aborted: None,
+        },
+    );
+
+    let mut linker = Linker::new(&engine);
+    for import in module.imports() {
+        let ty = match import.ty() {
+            ExternType::Func(ty) => ty,
+            _ => {
+                return Err(anyhow!(
+                    "unexpected non-function import {}::{}",
+                    import.module(),
+                    import.name()
+                ))
+            }
+        };
+        let (module_name, name) = (import.module().to_owned(), import.name().to_owned());
+        linker.func_new(
+            import.module(),
+            import.name(),
+            ty,
+            move |caller, params, results| {
+                replay_syscall(caller, &module_name, &name, params, results)
+            },
+        )?;
+    }
+
+    let instance = linker.instantiate(&mut store, &module)?;
+    let invoke = instance.get_typed_func::<u32, u32>(&mut store, "invoke")?;
+    let outcome = match invoke.call(&mut store, params_id) {
+        Ok(block_id) => ReplayOutcome::Returned(block_id),
+        Err(e) => match store.data().aborted {
+            Some(code) => ReplayOutcome::Aborted(code),
+            None => return Err(e),
+        },
+    };
+
+    let remaining = store.data().records.len();
+    if remaining > 0 {
+        return Err(anyhow!("{} recorded syscalls weren't replayed", remaining));
+    }
+    Ok(outcome)
+}
+
+/// Serves a single syscall from the next record.
+fn replay_syscall(
+    mut caller: Caller<'_, Replay>,
+    module: &str,
+    name: &str,
+    params: &[Val],
+    results: &mut [Val],
+) -> anyhow::Result<()> {
+    let record = caller
+        .data_mut()
+        .records
+        .pop_front()
+        .ok_or_else(|| anyhow!("unexpected syscall {}::{}", module, name))?;
+    if (record.module, record.name) != (module, name) {
+        return Err(anyhow!(
+            "expected syscall {}::{}, got {}::{}",
+            record.module,
+            record.name,
+            module,
+            name
+        ));
+    }
+
+    // Syscalls returning a value take a return-value pointer as their first argument.
+    let (ret, args) = if params.len() == record.args.len() + 1 {
+        (Some(params[0].unwrap_i32() as u32), &params[1..])
+    } else {
+        (None, params)
+    };
+    let args = args
+        .iter()
+        .map(|arg| match *arg {
+            Val::I32(v) => Ok(v as u32 as u64),
+            Val::I64(v) => Ok(v as u64),
+            Val::F32(bits) => Ok(bits as u64),
+            Val::F64(bits) => Ok(bits),
+            _ => Err(anyhow!("unexpected syscall argument type")),
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if args != record.args {
+        return Err(anyhow!(
+            "syscall {}::{} called with {:?}, recorded with {:?}",
+            module,
+            name,
+            args,
+            record.args
+        ));
+    }
+
+    let memory = match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => memory,
+        _ => return Err(anyhow!("actor has no memory export")),
+    };
+    let data = memory.data_mut(&mut caller);
+    let mut write = |offset: u32, bytes: &[u8]| {
+        data.get_mut(offset as usize..)
+            .and_then(|data| data.get_mut(..bytes.len()))
+            .ok_or_else(|| anyhow!("recorded write at {} is out of bounds", offset))
+            .map(|out| out.copy_from_slice(bytes))
+    };
+    for (offset, bytes) in &record.writes {
+        write(*offset, bytes)?;
+    }
+
+    match record.result {
+        SyscallOutcome::Ok(value) => {
+            if let Some(ret) = ret {
+                write(ret, &value)?;
+            }
+            results[0] = Val::I32(0);
+        }
+        SyscallOutcome::Error(code) => results[0] = Val::I32(code as u32 as i32),
+        SyscallOutcome::Abort(code) => {
+            caller.data_mut().aborted = Some(code);
+            return Err(anyhow!(
+                "syscall {}::{} aborted with {}",
+                module,
+                name,
+                code
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,337 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use std::time::Duration;
+
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::{ActorID, MethodNum};
+
+use super::{ExecutionEvent, ExecutionTrace};
+use crate::gas::Gas;
+use crate::kernel::SyscallError;
+
+/// A single call (send) reconstructed from an execution trace, for export to distributed tracing
+/// systems. See [`call_spans`].
+#[derive(Clone, Debug)]
+pub struct CallSpan {
+    /// The index of the calling span, or `None` for the top-level call.
+    pub parent: Option<usize>,
+    /// The time at which the call started, relative to the start of the message.
+    pub start: Duration,
+    /// The time spent in the call, including nested calls.
+    pub duration: Duration,
+    pub from: ActorID,
+    pub to: Address,
+    pub method: MethodNum,
+    pub value: TokenAmount,
+    /// The call's exit code, or `None` if it failed with a syscall error (see `error`) or never
+    /// returned.
+    pub exit_code: Option<ExitCode>,
+    /// The syscall error the call failed with, if any.
+    pub error: Option<SyscallError>,
+    /// The gas used by the call, including nested calls.
+    pub gas_used: Gas,
+}
+
+/// Reconstructs the (nested) calls made while applying a message from its execution trace, in the
+/// order in which they were made.
+///
+/// Execution traces don't record wall-clock times, so timings are derived from the execution time
+/// recorded on each gas charge, which covers nearly all time spent executing a message.
+pub fn call_spans(trace: &ExecutionTrace) -> Vec<CallSpan> {
+    let mut spans: Vec<CallSpan> = Vec::new();
+    let mut stack: Vec<usize> = Vec::new();
+    let mut now = Duration::ZERO;
+    for event in trace {
+        match event {
+            ExecutionEvent::GasCharge(charge) => {
+                if let Some(elapsed) = charge.elapsed.get() {
+                    now += *elapsed;
+                }
+            }
+            ExecutionEvent::Call {
+                from,
+                to,
+                method,
+                value,
+                ..
+            } => {
+                stack.push(spans.len());
+                spans.push(CallSpan {
+                    parent: stack.iter().rev().nth(1).copied(),
+                    start: now,
+                    duration: Duration::ZERO,
+                    from: *from,
+                    to: *to,
+                    method: *method,
+                    value: value.clone(),
+                    exit_code: None,
+                    error: None,
+                    gas_used: Gas::default(),
+                });
+            }
+            ExecutionEvent::CallReturn(exit_code, _, gas_used) => {
+                if let Some(span) = stack.pop().map(|i| &mut spans[i]) {
+                    span.duration = now - span.start;
+                    span.exit_code = Some(*exit_code);
+                    span.gas_used = *gas_used;
+                }
+            }
+            ExecutionEvent::CallError(error) => {
+                if let Some(span) = stack.pop().map(|i| &mut spans[i]) {
+                    span.duration = now - span.start;
+                    span.error = Some(error.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    // Close any calls that never returned (e.g., because the message ran out of gas).
+    for i in stack {
+        spans[i].duration = now - spans[i].start;
+    }
+    spans
+}
+
+/// Export of execution traces as [OpenTelemetry](https://opentelemetry.io) spans.
+///
+/// Any OpenTelemetry exporter can be used, e.g., `opentelemetry-zipkin` or `opentelemetry-jaeger`
+/// to visualize slow messages and tipsets in a standard tracing UI.
+#[cfg(feature = "opentelemetry")]
+pub mod otel {
+    use std::time::SystemTime;
+
+    use opentelemetry::trace::{Span, TraceContextExt, Tracer};
+    use opentelemetry::{Context, KeyValue};
+
+    use super::call_spans;
+    use crate::executor::ApplyRet;
+
+    /// Emits a span for a message and one (nested) span for each call it made, with gas and exit
+    /// code attributes. The message span starts at `start` and is a child of `parent` (e.g.,
+    /// a span for the tipset being applied).
+    ///
+    /// Requires tracing to be enabled (see
+    /// [`MachineContext::enable_tracing`](crate::machine::MachineContext::enable_tracing)); gas
+    /// charged outside of calls (e.g., for message inclusion) isn't attributed to a call span.
+    pub fn export_message<T: Tracer>(
+        tracer: &T,
+        parent: &Context,
+        name: &'static str,
+        ret: &ApplyRet,
+        start: SystemTime,
+    ) {
+        let spans = call_spans(&ret.exec_trace);
+        let end = start
+            + spans
+                .iter()
+                .map(|s| s.start + s.duration)
+                .max()
+                .unwrap_or_default();
+
+        let message_cx = parent.with_span(
+            tracer
+                .span_builder(name)
+                .with_start_time(start)
+                .with_attributes(vec![
+                    KeyValue::new("fvm.exit_code", ret.msg_receipt.exit_code.value() as i64),
+                    KeyValue::new("fvm.gas_used", ret.msg_receipt.gas_used as i64),
+                    KeyValue::new("fvm.gas_burned", ret.gas_burned as i64),
+                ])
+                .start_with_context(tracer, parent),
+        );
+
+        let mut contexts: Vec<Context> = Vec::with_capacity(spans.len());
+        for span in &spans {
+            let parent_cx = span.parent.map_or(&message_cx, |i| &contexts[i]);
+            let mut attributes = vec![
+                KeyValue::new("fvm.from", span.from as i64),
+                KeyValue::new("fvm.to", span.to.to_string()),
+                KeyValue::new("fvm.method", span.method as i64),
+                KeyValue::new("fvm.value", span.value.to_string()),
+                KeyValue::new("fvm.gas_used", span.gas_used.round_up() as i64),
+            ];
+            if let Some(exit_code) = span.exit_code {
+                attributes.push(KeyValue::new("fvm.exit_code", exit_code.value() as i64));
+            }
+            if let Some(error) = &span.error {
+                attributes.push(KeyValue::new("fvm.error", error.to_string()));
+            }
+            let otel_span = tracer
+                .span_builder(format!("{}::{}", span.to, span.method))
+                .with_start_time(start + span.start)
+                .with_attributes(attributes)
+                .start_with_context(tracer, parent_cx);
+            let cx = parent_cx.with_span(otel_span);
+            contexts.push(cx);
+        }
+
+        // End the spans innermost-first.
+        for (span, cx) in spans.iter().zip(&contexts).rev() {
+            cx.span()
+                .end_with_timestamp(start + span.start + span.duration);
+        }
+        message_cx.span().end_with_timestamp(end);
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::sync::{Arc, Mutex};
+
+        use futures::future::BoxFuture;
+        use fvm_shared::address::Address;
+        use fvm_shared::econ::TokenAmount;
+        use fvm_shared::error::ExitCode;
+        use fvm_shared::receipt::Receipt;
+        use opentelemetry::trace::TracerProvider as _;
+        use opentelemetry::{Key, Value};
+        use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+        use opentelemetry_sdk::trace::TracerProvider;
+
+        use super::*;
+        use crate::gas::Gas;
+        use crate::trace::ExecutionEvent;
+
+        /// An exporter collecting spans in memory.
+        #[derive(Clone, Debug, Default)]
+        struct MemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+        impl SpanExporter for MemoryExporter {
+            fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+                self.0.lock().unwrap().extend(batch);
+                Box::pin(std::future::ready(Ok(())))
+            }
+        }
+
+        fn call(to: u64) -> ExecutionEvent {
+            ExecutionEvent::Call {
+                from: 1,
+                to: Address::new_id(to),
+                method: 2,
+                params: None,
+                value: TokenAmount::from_atto(3),
+            }
+        }
+
+        #[test]
+        fn export_message() {
+            let ret = ApplyRet {
+                msg_receipt: Receipt {
+                    exit_code: ExitCode::USR_ASSERTION_FAILED,
+                    return_data: Default::default(),
+                    gas_used: 20,
+                    events_root: None,
+                },
+                penalty: Default::default(),
+                miner_tip: Default::default(),
+                base_fee_burn: Default::default(),
+                over_estimation_burn: Default::default(),
+                refund: Default::default(),
+                gas_refund: 0,
+                gas_burned: 0,
+                failure_info: None,
+                failure: None,
+                exec_trace: vec![
+                    call(100),
+                    call(101),
+                    ExecutionEvent::CallReturn(ExitCode::OK, None, Gas::new(5)),
+                    ExecutionEvent::CallReturn(ExitCode::USR_ASSERTION_FAILED, None, Gas::new(10)),
+                ],
+                events: vec![],
+                gas_breakdown: None,
+                blockstore_stats: None,
+            };
+
+            let exporter = MemoryExporter::default();
+            let provider = TracerProvider::builder()
+                .with_simple_exporter(exporter.clone())
+                .build();
+            let tracer = provider.tracer("fvm");
+            super::export_message(
+                &tracer,
+                &Context::new(),
+                "message",
+                &ret,
+                SystemTime::UNIX_EPOCH,
+            );
+            // Shutting down the provider waits for all spans to be exported.
+            drop(tracer);
+            drop(provider);
+
+            let spans = exporter.0.lock().unwrap();
+            assert_eq!(spans.len(), 3);
+            let span = |name: &str| spans.iter().find(|s| s.name == name).unwrap();
+            let (message, outer, inner) = (span("message"), span("f0100::2"), span("f0101::2"));
+
+            // Calls are nested under the message, in the same trace.
+            assert_eq!(outer.parent_span_id, message.span_context.span_id());
+            assert_eq!(inner.parent_span_id, outer.span_context.span_id());
+            assert_eq!(
+                inner.span_context.trace_id(),
+                message.span_context.trace_id()
+            );
+
+            let attr =
+                |span: &SpanData, key: &'static str| span.attributes.get(&Key::new(key)).cloned();
+            assert_eq!(attr(message, "fvm.gas_used"), Some(Value::I64(20)));
+            assert_eq!(
+                attr(outer, "fvm.exit_code"),
+                Some(Value::I64(ExitCode::USR_ASSERTION_FAILED.value() as i64))
+            );
+            assert_eq!(attr(outer, "fvm.gas_used"), Some(Value::I64(10)));
+            assert_eq!(attr(inner, "fvm.exit_code"), Some(Value::I64(0)));
+            assert_eq!(attr(inner, "fvm.to"), Some(Value::from("f0101")));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(to: u64) -> ExecutionEvent {
+        ExecutionEvent::Call {
+            from: 1,
+            to: Address::new_id(to),
+            method: 2,
+            params: None,
+            value: TokenAmount::from_atto(3),
+        }
+    }
+
+    #[test]
+    fn nested_calls() {
+        let trace = vec![
+            call(100),
+            call(101),
+            ExecutionEvent::CallReturn(ExitCode::OK, None, Gas::new(5)),
+            call(102),
+            ExecutionEvent::CallError(SyscallError::new(
+                fvm_shared::error::ErrorNumber::Forbidden,
+                "nope",
+            )),
+            ExecutionEvent::CallReturn(ExitCode::USR_ASSERTION_FAILED, None, Gas::new(10)),
+            call(103),
+        ];
+        let spans = call_spans(&trace);
+        assert_eq!(spans.len(), 4);
+
+        assert_eq!(spans[0].parent, None);
+        assert_eq!(spans[0].to, Address::new_id(100));
+        assert_eq!(spans[0].exit_code, Some(ExitCode::USR_ASSERTION_FAILED));
+        assert_eq!(spans[0].gas_used, Gas::new(10));
+
+        assert_eq!(spans[1].parent, Some(0));
+        assert_eq!(spans[1].exit_code, Some(ExitCode::OK));
+        assert_eq!(spans[1].gas_used, Gas::new(5));
+
+        assert_eq!(spans[2].parent, Some(0));
+        assert_eq!(spans[2].exit_code, None);
+        assert!(spans[2].error.is_some());
+
+        assert_eq!(spans[3].parent, None);
+        assert_eq!(spans[3].exit_code, None);
+        assert!(spans[3].error.is_none());
+    }
+}
@@ -434,6 +434,151 @@ mod ipld {
     }
 }
 
+mod reachability {
+    use cid::Cid;
+    use fvm::kernel::{IpldBlockOps, SelfOps};
+    use fvm::machine::Machine;
+    use fvm::state_tree::ActorState;
+    use fvm::EMPTY_ARR_CID;
+    use fvm_ipld_blockstore::Blockstore;
+    use fvm_ipld_encoding::{to_vec, DAG_CBOR};
+    use fvm_shared::ActorID;
+    use multihash::MultihashDigest;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    const ACTOR: ActorID = 101;
+
+    /// The CIDs of the blocks set up by [`build_kernel`].
+    struct Blocks {
+        /// The actor's state root, linking to `leaf`.
+        root: Cid,
+        /// A block only reachable through the state root.
+        leaf: Cid,
+        /// A block in the blockstore that isn't reachable from the actor's state.
+        unrelated: Cid,
+    }
+
+    fn put(call_manager: &DummyCallManager, data: &[u8]) -> Cid {
+        let cid = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(data));
+        call_manager
+            .machine
+            .blockstore()
+            .put_keyed(&cid, data)
+            .unwrap();
+        cid
+    }
+
+    fn build_kernel(reachability_checks: bool) -> (TestingKernel, Blocks) {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        if reachability_checks {
+            call_manager.machine.ctx.enable_reachability_checks();
+        }
+
+        let leaf = put(&call_manager, &to_vec("leaf").unwrap());
+        let unrelated = put(&call_manager, &to_vec("unrelated").unwrap());
+        let root = put(&call_manager, &to_vec(&vec![leaf]).unwrap());
+        call_manager.machine.state_tree_mut().set_actor(
+            ACTOR,
+            ActorState::new(*EMPTY_ARR_CID, root, Zero::zero(), 0, None),
+        );
+
+        let kern = TestingKernel::new(
+            call_manager,
+            BlockRegistry::default(),
+            100,
+            ACTOR,
+            0,
+            Zero::zero(),
+            false,
+        );
+        (
+            kern,
+            Blocks {
+                root,
+                leaf,
+                unrelated,
+            },
+        )
+    }
+
+    #[test]
+    fn disabled() -> anyhow::Result<()> {
+        let (mut kern, blocks) = build_kernel(false);
+
+        // Anything in the blockstore may be opened or set as the state root.
+        kern.block_open(&blocks.unrelated)?;
+        kern.set_root(blocks.leaf)?;
+        Ok(())
+    }
+
+    #[test]
+    fn open() -> anyhow::Result<()> {
+        let (mut kern, blocks) = build_kernel(true);
+
+        expect_syscall_err!(NotFound, kern.block_open(&blocks.unrelated));
+        // Not until the state root has been opened.
+        expect_syscall_err!(NotFound, kern.block_open(&blocks.leaf));
+
+        kern.block_open(&blocks.root)?;
+        kern.block_open(&blocks.leaf)?;
+        expect_syscall_err!(NotFound, kern.block_open(&blocks.unrelated));
+        Ok(())
+    }
+
+    #[test]
+    fn set_root() -> anyhow::Result<()> {
+        let (mut kern, blocks) = build_kernel(true);
+
+        expect_syscall_err!(NotFound, kern.set_root(blocks.unrelated));
+        expect_syscall_err!(NotFound, kern.set_root(blocks.leaf));
+
+        // Reachable once linked from an opened block.
+        kern.block_open(&blocks.root)?;
+        kern.set_root(blocks.leaf)?;
+
+        // As are blocks linked by the actor, but they may only link to reachable blocks.
+        expect_syscall_err!(
+            NotFound,
+            kern.block_create(DAG_CBOR, &to_vec(&vec![blocks.unrelated])?)
+        );
+        let id = kern.block_create(DAG_CBOR, &to_vec(&vec![blocks.leaf])?)?;
+        let new_root = kern.block_link(id, Code::Blake2b256.into(), 32)?;
+        kern.set_root(new_root)?;
+
+        let (call_manager, _) = kern.into_inner();
+        let state = call_manager.machine.state_tree().get_actor(ACTOR)?.unwrap();
+        assert_eq!(state.state, new_root);
+        Ok(())
+    }
+
+    #[test]
+    fn scan_gas() -> anyhow::Result<()> {
+        let (mut kern, blocks) = build_kernel(true);
+
+        let block = to_vec(&vec![blocks.leaf, blocks.leaf])?;
+        kern.block_open(&blocks.root)?;
+        kern.block_create(DAG_CBOR, &block)?;
+
+        let (call_manager, _) = kern.into_inner();
+        let price_list = &call_manager.machine.context().price_list;
+        let root_len = call_manager
+            .machine
+            .blockstore()
+            .get(&blocks.root)?
+            .unwrap()
+            .len();
+        let expected = price_list.on_block_open_base().total()
+            + price_list.on_block_open_per_byte(root_len).total()
+            + price_list.on_block_scan_links(1).total()
+            + price_list.on_block_create(block.len()).total()
+            + price_list.on_block_scan_links(2).total();
+        assert_eq!(call_manager.gas_tracker.gas_used(), expected);
+        Ok(())
+    }
+}
+
 mod gas {
     use fvm::gas::*;
     use fvm::kernel::GasOps;
@@ -528,3 +673,364 @@ mod gas {
         Ok(())
     }
 }
+
+mod network {
+    use fvm::kernel::NetworkOps;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    #[test]
+    fn context() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        call_manager.machine.ctx.epoch = 1234;
+        call_manager.machine.ctx.timestamp = 1_680_000_000;
+
+        let kern = TestingKernel::new(
+            call_manager,
+            BlockRegistry::default(),
+            0,
+            0,
+            0,
+            Zero::zero(),
+            false,
+        );
+
+        // The tipset timestamp is passed through from the machine context as-is.
+        let ctx = kern.network_context()?;
+        assert_eq!(ctx.epoch, 1234);
+        assert_eq!(ctx.timestamp, 1_680_000_000);
+        assert_eq!(ctx.network_version, STUB_NETWORK_VER);
+
+        Ok(())
+    }
+}
+
+mod randomness {
+    use fvm::kernel::RandomnessOps;
+    use fvm_shared::clock::ChainEpoch;
+    use fvm_shared::version::NetworkVersion;
+
+    use super::*;
+
+    #[test]
+    fn bounds() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        call_manager.machine.ctx.epoch = 1000;
+        call_manager.machine.ctx.network.network_version = NetworkVersion::V21;
+        call_manager
+            .machine
+            .ctx
+            .network
+            .max_randomness_lookback(100);
+        call_manager.machine.ctx.network.max_randomness_entropy(4);
+
+        let kern = TestingKernel::new(
+            call_manager,
+            BlockRegistry::default(),
+            0,
+            0,
+            0,
+            Zero::zero(),
+            false,
+        );
+
+        // Anything from the current epoch back to the maximum lookback is fine.
+        kern.get_randomness_from_tickets(1, 1000, b"abcd")?;
+        kern.get_randomness_from_beacon(1, 900, &[])?;
+
+        // But the kernel rejects invalid requests without consulting the client.
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.get_randomness_from_tickets(0, 1000, &[])
+        );
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.get_randomness_from_beacon(-1, 1000, &[])
+        );
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.get_randomness_from_tickets(1, 1001, &[])
+        );
+        expect_syscall_err!(LimitExceeded, kern.get_randomness_from_beacon(1, 899, &[]));
+        expect_syscall_err!(
+            LimitExceeded,
+            kern.get_randomness_from_tickets(1, 1000, b"abcde")
+        );
+        // Lookbacks that don't fit in an epoch are rejected rather than overflowing.
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.get_randomness_from_beacon(1, ChainEpoch::MIN, &[])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn domain_separation_tags_unchecked_before_nv21() -> anyhow::Result<()> {
+        let (call_manager, _) = dummy::DummyCallManager::new_stub();
+        let kern = TestingKernel::new(
+            call_manager,
+            BlockRegistry::default(),
+            0,
+            0,
+            0,
+            Zero::zero(),
+            false,
+        );
+
+        kern.get_randomness_from_tickets(0, 0, &[])?;
+        kern.get_randomness_from_beacon(-1, 0, &[])?;
+        Ok(())
+    }
+}
+
+mod consensus_fault {
+    use fvm::kernel::CryptoOps;
+    use fvm_shared::address::Address;
+    use fvm_shared::version::NetworkVersion;
+
+    use super::*;
+
+    fn build_kernel(nv: NetworkVersion) -> TestingKernel {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        call_manager.machine.ctx.epoch = 1000;
+        call_manager.machine.ctx.network.network_version = nv;
+        TestingKernel::new(
+            call_manager,
+            BlockRegistry::default(),
+            0,
+            0,
+            0,
+            Zero::zero(),
+            false,
+        )
+    }
+
+    #[test]
+    fn validated_from_nv21() -> anyhow::Result<()> {
+        let miner = Address::new_id(1000).to_bytes();
+        let robust = Address::new_actor(b"miner").to_bytes();
+        let epoch = |epoch: i64| epoch.to_be_bytes();
+
+        let kern = build_kernel(NetworkVersion::V21);
+        let fault = kern.verify_consensus_fault(&epoch(1000), &miner, &[])?;
+        assert_eq!(fault.map(|f| f.epoch), Some(1000));
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.verify_consensus_fault(&epoch(1001), &miner, &[])
+        );
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.verify_consensus_fault(&epoch(1000), &robust, &[])
+        );
+
+        // Faults reported by the externs were accepted as-is before NV21.
+        let kern = build_kernel(NetworkVersion::V20);
+        assert!(kern
+            .verify_consensus_fault(&epoch(1001), &miner, &[])?
+            .is_some());
+        assert!(kern
+            .verify_consensus_fault(&epoch(1000), &robust, &[])?
+            .is_some());
+
+        Ok(())
+    }
+}
+
+mod aggregate_signature {
+    use fvm::kernel::CryptoOps;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    use super::*;
+
+    /// Returns the aggregate signature of the messages, each signed by a new key, and the keys.
+    fn sign(messages: &[&[u8]]) -> ([u8; 96], Vec<[u8; 48]>) {
+        let mut rng = StdRng::seed_from_u64(0);
+        let keys: Vec<_> = messages
+            .iter()
+            .map(|_| bls_signatures::PrivateKey::generate(&mut rng))
+            .collect();
+        let sigs: Vec<_> = keys
+            .iter()
+            .zip(messages)
+            .map(|(key, msg)| key.sign(msg))
+            .collect();
+        let aggregate =
+            bls_signatures::Serialize::as_bytes(&bls_signatures::aggregate(&sigs).unwrap())
+                .try_into()
+                .unwrap();
+        let pub_keys = keys
+            .iter()
+            .map(|key| {
+                bls_signatures::Serialize::as_bytes(&key.public_key())
+                    .try_into()
+                    .unwrap()
+            })
+            .collect();
+        (aggregate, pub_keys)
+    }
+
+    #[test]
+    fn valid() -> anyhow::Result<()> {
+        let (kern, _) = build_inspecting_test()?;
+        let messages: &[&[u8]] = &[b"foo", b"bar", b"baz"];
+        let (sig, pub_keys) = sign(messages);
+        assert!(kern.verify_aggregate_signature(&sig, &pub_keys, messages)?);
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() -> anyhow::Result<()> {
+        let (kern, _) = build_inspecting_test()?;
+        let messages: &[&[u8]] = &[b"foo", b"bar"];
+        let (sig, pub_keys) = sign(messages);
+
+        // Wrong message.
+        assert!(!kern.verify_aggregate_signature(&sig, &pub_keys, &[b"foo", b"baz"])?);
+        // Signers swapped.
+        let swapped = [pub_keys[1], pub_keys[0]];
+        assert!(!kern.verify_aggregate_signature(&sig, &swapped, messages)?);
+        // Not a signature.
+        assert!(!kern.verify_aggregate_signature(&[0; 96], &pub_keys, messages)?);
+        Ok(())
+    }
+
+    #[test]
+    fn empty() -> anyhow::Result<()> {
+        let (kern, _) = build_inspecting_test()?;
+        let (sig, _) = sign(&[b"foo"]);
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.verify_aggregate_signature(&sig, &[], &[])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn mismatched_lengths() -> anyhow::Result<()> {
+        let (kern, _) = build_inspecting_test()?;
+        let messages: &[&[u8]] = &[b"foo", b"bar"];
+        let (sig, pub_keys) = sign(messages);
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.verify_aggregate_signature(&sig, &pub_keys, &messages[..1])
+        );
+        expect_syscall_err!(
+            IllegalArgument,
+            kern.verify_aggregate_signature(&sig, &pub_keys[..1], messages)
+        );
+        Ok(())
+    }
+}
+
+mod code_cid {
+    use cid::Cid;
+    use fvm::kernel::{MessageOps, SelfOps};
+    use fvm::machine::Machine;
+    use fvm::state_tree::ActorState;
+    use multihash::MultihashDigest;
+    use pretty_assertions::assert_eq;
+
+    use super::*;
+
+    fn code(name: &[u8]) -> Cid {
+        Cid::new_v1(fvm_ipld_encoding::IPLD_RAW, Code::Blake2b256.digest(name))
+    }
+
+    #[test]
+    fn caller_and_self() -> anyhow::Result<()> {
+        let (mut call_manager, _) = dummy::DummyCallManager::new_stub();
+        let state_tree = call_manager.machine.state_tree_mut();
+        state_tree.set_actor(100, ActorState::new_empty(code(b"caller"), None));
+        state_tree.set_actor(101, ActorState::new_empty(code(b"receiver"), None));
+
+        let kern = TestingKernel::new(
+            call_manager,
+            BlockRegistry::default(),
+            100,
+            101,
+            0,
+            Zero::zero(),
+            false,
+        );
+
+        assert_eq!(kern.msg_caller_code_cid()?, code(b"caller"));
+        assert_eq!(kern.self_code_cid()?, code(b"receiver"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn missing_actors() -> anyhow::Result<()> {
+        let (call_manager, _) = dummy::DummyCallManager::new_stub();
+        let kern = TestingKernel::new(
+            call_manager,
+            BlockRegistry::default(),
+            100,
+            101,
+            0,
+            Zero::zero(),
+            false,
+        );
+
+        expect_syscall_err!(NotFound, kern.msg_caller_code_cid());
+        expect_syscall_err!(IllegalOperation, kern.self_code_cid());
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "m2-native")]
+mod install_actor_code {
+    use cid::Cid;
+    use fvm::kernel::ActorOps;
+    use fvm::machine::Machine;
+    use fvm_ipld_blockstore::Blockstore;
+    use multihash::MultihashDigest;
+
+    use super::*;
+
+    fn build_kernel(read_only: bool) -> TestingKernel {
+        let (call_manager, _) = dummy::DummyCallManager::new_stub();
+        TestingKernel::new(
+            call_manager,
+            BlockRegistry::default(),
+            100,
+            101,
+            0,
+            Zero::zero(),
+            read_only,
+        )
+    }
+
+    #[test]
+    fn rejects_invalid_code() -> anyhow::Result<()> {
+        // (module (import "env" "foo" (func)))
+        const FOREIGN_IMPORT: &[u8] = b"\0asm\x01\0\0\0\
+            \x01\x04\x01\x60\x00\x00\
+            \x02\x0b\x01\x03env\x03foo\x00\x00";
+
+        let mut kern = build_kernel(false);
+        expect_syscall_err!(IllegalArgument, kern.install_actor_code(b"not wasm"));
+        expect_syscall_err!(IllegalArgument, kern.install_actor_code(FOREIGN_IMPORT));
+
+        // Nothing was stored.
+        let (call_manager, _) = kern.into_inner();
+        let code = Cid::new_v1(
+            fvm_ipld_encoding::IPLD_RAW,
+            Code::Blake2b256.digest(FOREIGN_IMPORT),
+        );
+        assert!(!call_manager.machine.blockstore().has(&code)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn read_only() -> anyhow::Result<()> {
+        let mut kern = build_kernel(true);
+        expect_syscall_err!(ReadOnly, kern.install_actor_code(b"\0asm\x01\0\0\0"));
+        Ok(())
+    }
+}
@@ -8,16 +8,19 @@ use anyhow::Context;
 use cid::Cid;
 use fvm::call_manager::{Backtrace, CallManager, FinishRet, InvocationResult};
 use fvm::engine::Engine;
-use fvm::externs::{Chain, Consensus, Externs, Rand};
+use fvm::externs::{Chain, Consensus, Externs, Rand, Verifier};
 use fvm::gas::{Gas, GasCharge, GasTimer, GasTracker};
+use fvm::kernel::ClassifyResult;
 use fvm::machine::limiter::MemoryLimiter;
 use fvm::machine::{Machine, MachineContext, Manifest, NetworkConfig};
 use fvm::state_tree::StateTree;
+use fvm::trace::SyscallRecord;
 use fvm::{kernel, Kernel};
 use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
 use fvm_ipld_encoding::{CborStore, DAG_CBOR};
 use fvm_shared::address::Address;
 use fvm_shared::bigint::Zero;
+use fvm_shared::consensus::{ConsensusFault, ConsensusFaultType};
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::event::StampedEvent;
 use fvm_shared::state::StateTreeVersion;
@@ -32,6 +35,8 @@ pub struct DummyExterns;
 
 impl Externs for DummyExterns {}
 
+impl Verifier for DummyExterns {}
+
 impl Rand for DummyExterns {
     fn get_chain_randomness(
         &self,
@@ -39,7 +44,7 @@ impl Rand for DummyExterns {
         _round: fvm_shared::clock::ChainEpoch,
         _entropy: &[u8],
     ) -> anyhow::Result<[u8; 32]> {
-        todo!()
+        Ok([0; 32])
     }
 
     fn get_beacon_randomness(
@@ -48,19 +53,28 @@ impl Rand for DummyExterns {
         _round: fvm_shared::clock::ChainEpoch,
         _entropy: &[u8],
     ) -> anyhow::Result<[u8; 32]> {
-        todo!()
+        Ok([0; 32])
     }
 }
 
 impl Consensus for DummyExterns {
     fn verify_consensus_fault(
         &self,
-        _h1: &[u8],
-        _h2: &[u8],
+        h1: &[u8],
+        h2: &[u8],
         _extra: &[u8],
-    ) -> anyhow::Result<(Option<fvm_shared::consensus::ConsensusFault>, i64)> {
-        // consensus is always valid for tests :)
-        anyhow::Result::Ok((None, 0))
+    ) -> anyhow::Result<(Option<ConsensusFault>, i64)> {
+        // consensus is always valid for tests :), unless a test asks for a fault by passing its
+        // epoch (as big-endian bytes) and target address as the headers.
+        let fault = match <[u8; 8]>::try_from(h1) {
+            Ok(epoch) => Some(ConsensusFault {
+                target: Address::from_bytes(h2)?,
+                epoch: i64::from_be_bytes(epoch),
+                fault_type: ConsensusFaultType::DoubleForkMining,
+            }),
+            Err(_) => None,
+        };
+        anyhow::Result::Ok((fault, 0))
     }
 }
 
@@ -292,6 +306,16 @@ impl CallManager for DummyCallManager {
         todo!()
     }
 
+    fn upgrade_actor<K: Kernel<CallManager = Self>>(
+        &mut self,
+        _caller: fvm_shared::ActorID,
+        _actor_id: fvm_shared::ActorID,
+        _new_code_cid: Cid,
+        _params: Option<kernel::Block>,
+    ) -> kernel::Result<InvocationResult> {
+        todo!()
+    }
+
     fn with_transaction(
         &mut self,
         _f: impl FnOnce(&mut Self) -> kernel::Result<InvocationResult>,
@@ -304,6 +328,7 @@ impl CallManager for DummyCallManager {
         (
             Ok(FinishRet {
                 gas_used: 0,
+                gas_refunded: 0,
                 backtrace: Backtrace {
                     frames: Vec::new(),
                     cause: None,
@@ -311,6 +336,7 @@ impl CallManager for DummyCallManager {
                 exec_trace: Vec::new(),
                 events: Vec::new(),
                 events_root: None,
+                gas_breakdown: None,
             }),
             self.machine,
         )
@@ -366,6 +392,27 @@ impl CallManager for DummyCallManager {
         todo!()
     }
 
+    fn call_stack(&self) -> &[ActorID] {
+        todo!()
+    }
+
+    fn record_block_write(&mut self) -> fvm::kernel::Result<()> {
+        Ok(())
+    }
+
+    fn put_block(&mut self, k: Cid, data: &[u8]) -> fvm::kernel::Result<()> {
+        self.machine.blockstore().put_keyed(&k, data).or_fatal()
+    }
+
+    fn get_block(&self, k: &Cid) -> fvm::kernel::Result<Option<Vec<u8>>> {
+        self.machine.blockstore().get(k).or_fatal()
+    }
+
+    #[cfg(feature = "m2-native")]
+    fn record_code_compiled(&mut self, _code: Cid) {}
+
+    fn record_syscall(&mut self, _record: SyscallRecord) {}
+
     fn limiter_mut(&mut self) -> &mut <Self::Machine as Machine>::Limiter {
         &mut self.limits
     }
@@ -0,0 +1,319 @@
+// Copyright 2021-2023 Protocol Labs
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use anyhow::{anyhow, Result};
+use cid::Cid;
+
+use crate::links::scan_for_links;
+use crate::{Blockstore, Buffered};
+
+// Multicodecs we need to know about in order to traverse links. These are defined here because this
+// crate sits below the encoding crate (and `fvm_shared`).
+const CBOR: u64 = 0x51;
+const DAG_CBOR: u64 = 0x71;
+const FIL_COMMITMENT_UNSEALED: u64 = 0xf101;
+const FIL_COMMITMENT_SEALED: u64 = 0xf102;
+
+/// I/O statistics for a [`BufferedBlockstore`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockstoreStats {
+    /// Number of block reads (`get` and `has`).
+    pub reads: u64,
+    /// Number of block reads served from the write buffer.
+    pub buffer_hits: u64,
+    /// Number of block writes (into the write buffer).
+    pub writes: u64,
+    /// Number of bytes read.
+    pub bytes_read: u64,
+    /// Number of bytes written (into the write buffer).
+    pub bytes_written: u64,
+    /// Number of blocks written to the underlying store by [`Buffered::flush`]. Buffered blocks
+    /// that aren't reachable from the flushed root are never written.
+    pub blocks_flushed: u64,
+    /// Number of bytes written to the underlying store by [`Buffered::flush`].
+    pub bytes_flushed: u64,
+}
+
+impl BlockstoreStats {
+    /// Returns the statistics accumulated since `earlier` was recorded.
+    pub fn since(&self, earlier: &BlockstoreStats) -> BlockstoreStats {
+        BlockstoreStats {
+            reads: self.reads - earlier.reads,
+            buffer_hits: self.buffer_hits - earlier.buffer_hits,
+            writes: self.writes - earlier.writes,
+            bytes_read: self.bytes_read - earlier.bytes_read,
+            bytes_written: self.bytes_written - earlier.bytes_written,
+            blocks_flushed: self.blocks_flushed - earlier.blocks_flushed,
+            bytes_flushed: self.bytes_flushed - earlier.bytes_flushed,
+        }
+    }
+
+    /// Returns the fraction of reads served from the write buffer, or 0 if there were no reads.
+    pub fn buffer_hit_rate(&self) -> f64 {
+        if self.reads == 0 {
+            0.0
+        } else {
+            self.buffer_hits as f64 / self.reads as f64
+        }
+    }
+}
+
+/// Wrapper around `Blockstore` to limit and have control over when values are written.
+///
+/// All writes are buffered in memory until [`Buffered::flush`] is called with a root CID, at which
+/// point only the blocks reachable from that root are written to the underlying store. Anything
+/// else (e.g., state from reverted executions) is never persisted.
+///
+/// This type is not threadsafe and can only be used in synchronous contexts.
+#[derive(Debug)]
+pub struct BufferedBlockstore<BS> {
+    base: BS,
+    write: RefCell<HashMap<Cid, Vec<u8>>>,
+    stats: Cell<BlockstoreStats>,
+}
+
+impl<BS> BufferedBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    pub fn new(base: BS) -> Self {
+        Self {
+            base,
+            write: Default::default(),
+            stats: Default::default(),
+        }
+    }
+
+    pub fn into_inner(self) -> BS {
+        self.base
+    }
+
+    /// Returns the I/O statistics accumulated since this blockstore was created.
+    pub fn stats(&self) -> BlockstoreStats {
+        self.stats.get()
+    }
+
+    fn record(&self, f: impl FnOnce(&mut BlockstoreStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+}
+
+impl<BS> Buffered for BufferedBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    /// Flushes the buffered cache based on the root node.
+    /// This will recursively traverse the cache and write all data connected by links to this
+    /// root Cid. Calling flush will not reset the write buffer.
+    fn flush(&self, root: &Cid) -> Result<()> {
+        let mut buffer = Vec::new();
+        let s = self.write.borrow();
+        copy_rec(&s, *root, &mut buffer)?;
+
+        let bytes = buffer.iter().map(|(_, b)| b.len() as u64).sum::<u64>();
+        let blocks = buffer.len() as u64;
+        self.base.put_many_keyed(buffer)?;
+        self.record(|stats| {
+            stats.blocks_flushed += blocks;
+            stats.bytes_flushed += bytes;
+        });
+
+        Ok(())
+    }
+}
+
+/// Copies the IPLD DAG under `root` from the cache to the base store.
+fn copy_rec<'a>(
+    cache: &'a HashMap<Cid, Vec<u8>>,
+    root: Cid,
+    buffer: &mut Vec<(Cid, &'a [u8])>,
+) -> Result<()> {
+    const DAG_RAW: u64 = 0x55;
+    const BLAKE2B_256: u64 = 0xb220;
+    const BLAKE2B_LEN: u8 = 32;
+    const IDENTITY: u64 = 0x0;
+
+    // Differences from lotus (vm.Copy):
+    // 1. We assume that if we don't have a block in our buffer, it must already be in the client
+    //    and don't check. This should only happen if the client is missing state.
+    // 2. We always write-back new blocks, even if the client already has them. We haven't noticed a
+    //    perf impact.
+
+    // TODO(M2): Make this not cbor specific.
+    // TODO(M2): Allow CBOR (not just DAG_CBOR).
+    match (root.codec(), root.hash().code(), root.hash().size()) {
+        // Allow non-truncated blake2b-256 raw/cbor (code/state)
+        (DAG_RAW | DAG_CBOR | CBOR, BLAKE2B_256, BLAKE2B_LEN) => (),
+        // Ignore raw identity cids (fake code cids)
+        (DAG_RAW, IDENTITY, _) => return Ok(()),
+        // Copy links from cbor identity cids.
+        // We shouldn't be creating these at the moment, but lotus' vm.Copy supports them.
+        (DAG_CBOR, IDENTITY, _) => {
+            return scan_for_links(&mut Cursor::new(root.hash().digest()), |link| {
+                copy_rec(cache, link, buffer)
+            })
+        }
+        // Ignore commitments (not even going to check the hash function.
+        (FIL_COMMITMENT_UNSEALED | FIL_COMMITMENT_SEALED, _, _) => return Ok(()),
+        // Fail on anything else. We usually want to continue on error, but there's really no going
+        // back from here.
+        (codec, hash, length) => {
+            return Err(anyhow!(
+                "cid {root} has unexpected codec ({codec}), hash ({hash}), or length ({length})"
+            ))
+        }
+    }
+
+    // If we don't have the block, we assume it's already in the datastore.
+    //
+    // The alternative would be to check if it's in the datastore, but that's likely even more
+    // expensive. And there wouldn't be much we could do at that point but abort the block.
+    let block = match cache.get(&root) {
+        Some(blk) => blk,
+        None => return Ok(()),
+    };
+
+    // At the moment, we only expect dag-cbor and raw.
+    // In M2, we'll need to copy explicitly.
+    if root.codec() == DAG_CBOR {
+        // TODO(M2): Make this non-recursive.
+        scan_for_links(&mut Cursor::new(block), |link| {
+            copy_rec(cache, link, buffer)
+        })?;
+    }
+
+    // Finally, push the block. We do this _last_ so that we always include write before parents.
+    buffer.push((root, block));
+
+    Ok(())
+}
+
+impl<BS> Blockstore for BufferedBlockstore<BS>
+where
+    BS: Blockstore,
+{
+    fn get(&self, cid: &Cid) -> Result<Option<Vec<u8>>> {
+        let (data, hit) = match self.write.borrow().get(cid) {
+            Some(data) => (Some(data.clone()), true),
+            None => (self.base.get(cid)?, false),
+        };
+        self.record(|stats| {
+            stats.reads += 1;
+            stats.buffer_hits += hit as u64;
+            stats.bytes_read += data.as_ref().map_or(0, |d| d.len() as u64);
+        });
+        Ok(data)
+    }
+
+    fn put_keyed(&self, cid: &Cid, buf: &[u8]) -> Result<()> {
+        self.write.borrow_mut().insert(*cid, Vec::from(buf));
+        self.record(|stats| {
+            stats.writes += 1;
+            stats.bytes_written += buf.len() as u64;
+        });
+        Ok(())
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        let hit = self.write.borrow().contains_key(k);
+        self.record(|stats| {
+            stats.reads += 1;
+            stats.buffer_hits += hit as u64;
+        });
+        if hit {
+            Ok(true)
+        } else {
+            Ok(self.base.has(k)?)
+        }
+    }
+
+    fn get_many<'a, I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        // Serve what we can from the write buffer, and fetch the rest from the base store in one
+        // batch.
+        let mut stats = self.stats.get();
+        let mut misses = Vec::new();
+        let mut blocks: Vec<_> = {
+            let write = self.write.borrow();
+            keys.into_iter()
+                .enumerate()
+                .map(|(i, k)| {
+                    let data = write.get(k).cloned();
+                    if data.is_none() {
+                        misses.push((i, k));
+                    }
+                    data
+                })
+                .collect()
+        };
+        let fetched = self.base.get_many(misses.iter().map(|(_, k)| *k))?;
+        for ((i, _), data) in misses.iter().zip(fetched) {
+            blocks[*i] = data;
+        }
+        stats.reads += blocks.len() as u64;
+        stats.buffer_hits += (blocks.len() - misses.len()) as u64;
+        stats.bytes_read += blocks.iter().flatten().map(|d| d.len() as u64).sum::<u64>();
+        self.stats.set(stats);
+        Ok(blocks)
+    }
+
+    fn has_many<'a, I>(&self, keys: I) -> Result<Vec<bool>>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        let mut misses = Vec::new();
+        let mut found: Vec<_> = {
+            let write = self.write.borrow();
+            keys.into_iter()
+                .enumerate()
+                .map(|(i, k)| {
+                    let hit = write.contains_key(k);
+                    if !hit {
+                        misses.push((i, k));
+                    }
+                    hit
+                })
+                .collect()
+        };
+        let base_found = self.base.has_many(misses.iter().map(|(_, k)| *k))?;
+        for ((i, _), hit) in misses.iter().zip(base_found) {
+            found[*i] = hit;
+        }
+        let hits = (found.len() - misses.len()) as u64;
+        let reads = found.len() as u64;
+        self.record(|stats| {
+            stats.reads += reads;
+            stats.buffer_hits += hits;
+        });
+        Ok(found)
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        let mut stats = self.stats.get();
+        self.write
+            .borrow_mut()
+            .extend(blocks.into_iter().map(|(k, v)| {
+                stats.writes += 1;
+                stats.bytes_written += v.as_ref().len() as u64;
+                (k, v.as_ref().into())
+            }));
+        self.stats.set(stats);
+        Ok(())
+    }
+}
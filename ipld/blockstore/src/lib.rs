@@ -9,11 +9,27 @@ use cid::{multihash, Cid};
 pub mod tracking;
 
 mod memory;
-pub use memory::MemoryBlockstore;
+pub use memory::{CodecStats, MemoryBlockstore};
 
 mod block;
 pub use block::*;
 
+mod buffered;
+pub use buffered::{BlockstoreStats, BufferedBlockstore};
+
+mod links;
+pub use links::scan_dag_cbor_links;
+
+#[cfg(feature = "rocksdb")]
+mod rocksdb;
+#[cfg(feature = "rocksdb")]
+pub use self::rocksdb::RocksDbBlockstore;
+
+#[cfg(feature = "sled")]
+mod sled;
+#[cfg(feature = "sled")]
+pub use self::sled::SledBlockstore;
+
 /// An IPLD blockstore suitable for injection into the FVM.
 ///
 /// The cgo blockstore adapter implements this trait.
@@ -34,6 +50,29 @@ pub trait Blockstore {
         Ok(self.get(k)?.is_some())
     }
 
+    /// Bulk-get blocks from the blockstore, returning one entry per key (in order).
+    ///
+    /// By default, this defers to get.
+    fn get_many<'a, I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        keys.into_iter().map(|k| self.get(k)).collect()
+    }
+
+    /// Checks if the blockstore has each of the specified blocks, returning one entry per key (in
+    /// order).
+    ///
+    /// By default, this defers to has.
+    fn has_many<'a, I>(&self, keys: I) -> Result<Vec<bool>>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        keys.into_iter().map(|k| self.has(k)).collect()
+    }
+
     /// Puts the block into the blockstore, computing the hash with the specified multicodec.
     ///
     /// By default, this defers to put.
@@ -84,7 +123,9 @@ pub trait Blockstore {
     }
 }
 
+/// A blockstore that buffers writes until explicitly flushed.
 pub trait Buffered: Blockstore {
+    /// Writes all buffered blocks reachable from `root` to the underlying store.
     fn flush(&self, root: &Cid) -> Result<()>;
 }
 
@@ -105,6 +146,22 @@ macro_rules! impl_blockstore {
                     (**self).has(k)
                 }
 
+                fn get_many<'a, I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>>
+                where
+                    Self: Sized,
+                    I: IntoIterator<Item = &'a Cid>,
+                {
+                    (**self).get_many(keys)
+                }
+
+                fn has_many<'a, I>(&self, keys: I) -> Result<Vec<bool>>
+                where
+                    Self: Sized,
+                    I: IntoIterator<Item = &'a Cid>,
+                {
+                    (**self).has_many(keys)
+                }
+
                 fn put<D>(&self, mh_code: multihash::Code, block: &Block<D>) -> Result<Cid>
                 where
                     Self: Sized,
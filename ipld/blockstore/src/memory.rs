@@ -1,16 +1,33 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use cid::multihash::{Code, MultihashDigest};
 use cid::Cid;
 
 use super::Blockstore;
 
+const IDENTITY: u64 = 0x0;
+
+/// The number of blocks (and bytes) of a single codec in a [`MemoryBlockstore`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CodecStats {
+    /// Number of blocks.
+    pub blocks: u64,
+    /// Total size of the blocks, in bytes.
+    pub bytes: u64,
+}
+
+/// An in-memory blockstore.
+///
+/// Cloning a `MemoryBlockstore` copies all of its blocks, so the clone is an independent snapshot
+/// of the original.
 #[derive(Debug, Default, Clone)]
 pub struct MemoryBlockstore {
     blocks: RefCell<HashMap<Cid, Vec<u8>>>,
+    validate: bool,
 }
 
 impl MemoryBlockstore {
@@ -18,10 +35,57 @@ impl MemoryBlockstore {
         Self::default()
     }
 
+    /// Creates a blockstore that checks that every block put into it matches its CID, returning an
+    /// error otherwise. Blocks must be hashed with a supported multihash (or be inlined in an
+    /// identity CID).
+    pub fn new_validating() -> Self {
+        Self {
+            validate: true,
+            ..Self::default()
+        }
+    }
+
     /// Copy all blocks from this blockstore into the target blockstore.
     pub fn copy_to(&self, other: &impl Blockstore) -> Result<()> {
         other.put_many_keyed(self.blocks.borrow().iter().map(|(&k, v)| (k, v)))
     }
+
+    /// Returns the number of blocks in the blockstore.
+    pub fn len(&self) -> usize {
+        self.blocks.borrow().len()
+    }
+
+    /// Returns true if the blockstore is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of blocks (and bytes) stored, by codec.
+    pub fn stats(&self) -> BTreeMap<u64, CodecStats> {
+        let mut stats = BTreeMap::<u64, CodecStats>::new();
+        for (k, v) in self.blocks.borrow().iter() {
+            let entry = stats.entry(k.codec()).or_default();
+            entry.blocks += 1;
+            entry.bytes += v.len() as u64;
+        }
+        stats
+    }
+}
+
+/// Checks that the block matches the multihash in its CID.
+fn validate_block(k: &Cid, block: &[u8]) -> Result<()> {
+    let hash = k.hash();
+    let valid = if hash.code() == IDENTITY {
+        hash.digest() == block
+    } else {
+        let code = Code::try_from(hash.code())
+            .map_err(|e| anyhow!("cid {k} uses an unsupported multihash: {e}"))?;
+        code.digest(block) == *hash
+    };
+    if !valid {
+        return Err(anyhow!("block doesn't match its cid {k}"));
+    }
+    Ok(())
 }
 
 impl Blockstore for MemoryBlockstore {
@@ -34,7 +98,84 @@ impl Blockstore for MemoryBlockstore {
     }
 
     fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        if self.validate {
+            validate_block(k, block)?;
+        }
         self.blocks.borrow_mut().insert(*k, block.into());
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use cid::multihash::Multihash;
+
+    use super::*;
+    use crate::Block;
+
+    const RAW: u64 = 0x55;
+    const DAG_CBOR: u64 = 0x71;
+
+    #[test]
+    fn validating_store() {
+        let bs = MemoryBlockstore::new_validating();
+        let cid = bs
+            .put(Code::Blake2b256, &Block::new(RAW, b"foobar"))
+            .unwrap();
+        assert_eq!(bs.get(&cid).unwrap().as_deref(), Some(&b"foobar"[..]));
+
+        // The data must match the hash...
+        bs.put_keyed(&cid, b"baz").unwrap_err();
+
+        // ...including for identity hashes.
+        let inline = Cid::new_v1(RAW, Multihash::wrap(IDENTITY, b"baz").unwrap());
+        bs.put_keyed(&inline, b"baz").unwrap();
+        bs.put_keyed(&inline, b"foobar").unwrap_err();
+        assert_eq!(bs.len(), 2);
+
+        // The default store doesn't check anything.
+        MemoryBlockstore::new().put_keyed(&cid, b"baz").unwrap();
+    }
+
+    #[test]
+    fn stats_and_snapshots() {
+        let bs = MemoryBlockstore::new();
+        assert!(bs.is_empty());
+        bs.put(Code::Blake2b256, &Block::new(RAW, b"abc")).unwrap();
+        bs.put(Code::Blake2b256, &Block::new(RAW, b"de")).unwrap();
+        bs.put(Code::Blake2b256, &Block::new(DAG_CBOR, [0x80]))
+            .unwrap();
+
+        let snapshot = bs.clone();
+        bs.put(Code::Blake2b256, &Block::new(RAW, b"f")).unwrap();
+
+        assert_eq!(
+            snapshot.stats(),
+            BTreeMap::from([
+                (
+                    RAW,
+                    CodecStats {
+                        blocks: 2,
+                        bytes: 5
+                    }
+                ),
+                (
+                    DAG_CBOR,
+                    CodecStats {
+                        blocks: 1,
+                        bytes: 1
+                    }
+                ),
+            ])
+        );
+        assert_eq!(
+            bs.stats()[&RAW],
+            CodecStats {
+                blocks: 3,
+                bytes: 6
+            }
+        );
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(bs.len(), 4);
+    }
+}
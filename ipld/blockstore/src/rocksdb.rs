@@ -0,0 +1,194 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! A [`Blockstore`] backed by RocksDB. Requires the `rocksdb` feature.
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use cid::Cid;
+use rocksdb::{ColumnFamily, Options, WriteBatch, DB};
+
+use crate::Blockstore;
+
+/// A [`Blockstore`] backed by a RocksDB database, optionally storing its blocks in a dedicated
+/// column family.
+///
+/// Blocks are keyed by their full (binary) CID, so blocks with the same multihash but different
+/// codecs are stored separately. Bulk puts are written in a single atomic [`WriteBatch`], and bulk
+/// gets use RocksDB's `multi_get`.
+pub struct RocksDbBlockstore {
+    db: DB,
+    column: Option<String>,
+}
+
+impl RocksDbBlockstore {
+    /// Opens (or creates) the database at `path`, storing blocks in the default column family.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        Ok(Self {
+            db: DB::open(&opts, path)?,
+            column: None,
+        })
+    }
+
+    /// Opens (or creates) the database at `path`, storing blocks in the column family `column`
+    /// (which is created if missing).
+    ///
+    /// Only the default column family and `column` are opened, so this can't be used to open a
+    /// database with other column families. Use [`RocksDbBlockstore::from_db`] instead.
+    pub fn open_column(path: impl AsRef<Path>, column: &str) -> Result<Self> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+        Ok(Self {
+            db: DB::open_cf(&opts, path, [column])?,
+            column: Some(column.into()),
+        })
+    }
+
+    /// Wraps an already open database, storing blocks in the column family `column` (or in the
+    /// default column family if `None`). The column family must already exist.
+    pub fn from_db(db: DB, column: Option<&str>) -> Result<Self> {
+        if let Some(column) = column {
+            if db.cf_handle(column).is_none() {
+                return Err(anyhow!("column family {column} doesn't exist"));
+            }
+        }
+        Ok(Self {
+            db,
+            column: column.map(Into::into),
+        })
+    }
+
+    /// Returns the underlying database.
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    /// Unwraps the underlying database.
+    pub fn into_db(self) -> DB {
+        self.db
+    }
+
+    fn cf(&self) -> Option<&ColumnFamily> {
+        // The column family was checked (or created) on construction, and we never drop it.
+        self.column.as_ref().map(|column| {
+            self.db
+                .cf_handle(column)
+                .expect("blockstore column family was dropped")
+        })
+    }
+}
+
+impl Blockstore for RocksDbBlockstore {
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        let key = k.to_bytes();
+        Ok(match self.cf() {
+            Some(cf) => self.db.get_cf(cf, key)?,
+            None => self.db.get(key)?,
+        })
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        let key = k.to_bytes();
+        match self.cf() {
+            Some(cf) => self.db.put_cf(cf, key, block)?,
+            None => self.db.put(key, block)?,
+        }
+        Ok(())
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        let key = k.to_bytes();
+        let found = match self.cf() {
+            Some(cf) => self.db.get_pinned_cf(cf, key)?,
+            None => self.db.get_pinned(key)?,
+        };
+        Ok(found.is_some())
+    }
+
+    fn get_many<'a, I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        let keys = keys.into_iter().map(Cid::to_bytes);
+        let results = match self.cf() {
+            Some(cf) => self.db.multi_get_cf(keys.map(|k| (cf, k))),
+            None => self.db.multi_get(keys),
+        };
+        results
+            .into_iter()
+            .map(|r| r.map_err(anyhow::Error::from))
+            .collect()
+    }
+
+    fn has_many<'a, I>(&self, keys: I) -> Result<Vec<bool>>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        Ok(self
+            .get_many(keys)?
+            .into_iter()
+            .map(|b| b.is_some())
+            .collect())
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        let mut batch = WriteBatch::default();
+        let cf = self.cf();
+        for (k, block) in blocks {
+            match cf {
+                Some(cf) => batch.put_cf(cf, k.to_bytes(), block),
+                None => batch.put(k.to_bytes(), block),
+            }
+        }
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cid::multihash::Code;
+
+    use super::*;
+    use crate::Block;
+
+    #[test]
+    fn roundtrip() {
+        let path = std::env::temp_dir().join(format!("fvm-rocksdb-test-{}", std::process::id()));
+        for column in [None, Some("blocks")] {
+            let bs = match column {
+                Some(column) => RocksDbBlockstore::open_column(&path, column).unwrap(),
+                None => RocksDbBlockstore::open(&path).unwrap(),
+            };
+            let blocks: Vec<_> = (0u8..10).map(|i| Block::new(0x55, vec![i])).collect();
+            bs.put_many(blocks.iter().map(|b| (Code::Blake2b256, b.into())))
+                .unwrap();
+
+            let missing = Block::new(0x55, vec![10]).cid(Code::Blake2b256);
+            let mut keys: Vec<_> = blocks.iter().map(|b| b.cid(Code::Blake2b256)).collect();
+            keys.push(missing);
+
+            let found = bs.get_many(&keys).unwrap();
+            assert_eq!(found.len(), 11);
+            assert_eq!(found[3].as_deref(), Some(&[3u8][..]));
+            assert_eq!(found[10], None);
+            assert!(bs.has(&keys[0]).unwrap());
+            assert!(!bs.has(&missing).unwrap());
+            assert_eq!(
+                bs.has_many(&keys).unwrap().iter().filter(|&&b| b).count(),
+                10
+            );
+            drop(bs);
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+    }
+}
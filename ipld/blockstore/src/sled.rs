@@ -0,0 +1,108 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! A [`Blockstore`] backed by sled. Requires the `sled` feature.
+use std::path::Path;
+
+use anyhow::Result;
+use cid::Cid;
+use sled::{Batch, Db, Tree};
+
+use crate::Blockstore;
+
+/// A [`Blockstore`] backed by a sled tree (either a database's default tree or a named tree).
+///
+/// Blocks are keyed by their full (binary) CID, so blocks with the same multihash but different
+/// codecs are stored separately. Bulk puts are applied to the tree as a single atomic [`Batch`].
+#[derive(Debug, Clone)]
+pub struct SledBlockstore {
+    tree: Tree,
+}
+
+impl SledBlockstore {
+    /// Opens (or creates) the database at `path`, storing blocks in its default tree.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Self::from_db(&sled::open(path)?))
+    }
+
+    /// Opens (or creates) the database at `path`, storing blocks in the tree named `tree`.
+    pub fn open_tree(path: impl AsRef<Path>, tree: &str) -> Result<Self> {
+        Ok(Self::new(sled::open(path)?.open_tree(tree)?))
+    }
+
+    /// Stores blocks in the default tree of an already open database.
+    pub fn from_db(db: &Db) -> Self {
+        Self::new((**db).clone())
+    }
+
+    /// Stores blocks in the given tree.
+    pub fn new(tree: Tree) -> Self {
+        Self { tree }
+    }
+
+    /// Returns the underlying tree.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+}
+
+impl Blockstore for SledBlockstore {
+    fn get(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        Ok(self.tree.get(k.to_bytes())?.map(|v| v.to_vec()))
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> Result<()> {
+        self.tree.insert(k.to_bytes(), block)?;
+        Ok(())
+    }
+
+    fn has(&self, k: &Cid) -> Result<bool> {
+        Ok(self.tree.contains_key(k.to_bytes())?)
+    }
+
+    fn put_many_keyed<D, I>(&self, blocks: I) -> Result<()>
+    where
+        Self: Sized,
+        D: AsRef<[u8]>,
+        I: IntoIterator<Item = (Cid, D)>,
+    {
+        let mut batch = Batch::default();
+        for (k, block) in blocks {
+            batch.insert(k.to_bytes(), block.as_ref());
+        }
+        self.tree.apply_batch(batch)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cid::multihash::Code;
+
+    use super::*;
+    use crate::Block;
+
+    #[test]
+    fn roundtrip() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        for bs in [
+            SledBlockstore::from_db(&db),
+            SledBlockstore::new(db.open_tree("blocks").unwrap()),
+        ] {
+            let blocks: Vec<_> = (0u8..10).map(|i| Block::new(0x55, vec![i])).collect();
+            bs.put_many(blocks.iter().map(|b| (Code::Blake2b256, b.into())))
+                .unwrap();
+
+            let missing = Block::new(0x55, vec![10]).cid(Code::Blake2b256);
+            let mut keys: Vec<_> = blocks.iter().map(|b| b.cid(Code::Blake2b256)).collect();
+            keys.push(missing);
+
+            let found = bs.get_many(&keys).unwrap();
+            assert_eq!(found[3].as_deref(), Some(&[3u8][..]));
+            assert_eq!(found[10], None);
+            assert_eq!(
+                bs.has_many(&keys).unwrap().iter().filter(|&&b| b).count(),
+                10
+            );
+        }
+    }
+}
@@ -62,6 +62,32 @@ where
         self.base.has(cid)
     }
 
+    fn get_many<'a, I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        let mut stats = self.stats.borrow_mut();
+        let blocks = self.base.get_many(keys)?;
+        for block in &blocks {
+            stats.r += 1;
+            if let Some(bytes) = block {
+                stats.br += bytes.len();
+            }
+        }
+        Ok(blocks)
+    }
+
+    fn has_many<'a, I>(&self, keys: I) -> Result<Vec<bool>>
+    where
+        Self: Sized,
+        I: IntoIterator<Item = &'a Cid>,
+    {
+        let found = self.base.has_many(keys)?;
+        self.stats.borrow_mut().r += found.len();
+        Ok(found)
+    }
+
     fn put<D>(&self, code: Code, block: &Block<D>) -> Result<Cid>
     where
         D: AsRef<[u8]>,
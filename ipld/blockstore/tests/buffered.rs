@@ -0,0 +1,158 @@
+// Copyright 2021-2023 Protocol Labs
+// Copyright 2019-2022 ChainSafe Systems
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+use cid::multihash::{Code, Multihash};
+use cid::Cid;
+use fvm_ipld_blockstore::{
+    Blockstore, BlockstoreStats, Buffered, BufferedBlockstore, MemoryBlockstore,
+};
+use fvm_ipld_encoding::CborStore;
+use fvm_shared::{commcid, IDENTITY_HASH};
+use serde::{Deserialize, Serialize};
+
+const RAW: u64 = 0x55;
+
+#[test]
+fn basic_buffered_store() {
+    let mem = MemoryBlockstore::default();
+    let buf_store = BufferedBlockstore::new(&mem);
+
+    let cid = buf_store.put_cbor(&8u8, Code::Blake2b256).unwrap();
+    assert_eq!(mem.get_cbor::<u8>(&cid).unwrap(), None);
+    assert_eq!(buf_store.get_cbor::<u8>(&cid).unwrap(), Some(8));
+
+    buf_store.flush(&cid).unwrap();
+    assert_eq!(buf_store.get_cbor::<u8>(&cid).unwrap(), Some(8));
+    assert_eq!(mem.get_cbor::<u8>(&cid).unwrap(), Some(8));
+}
+
+#[test]
+fn buffered_store_stats() {
+    let mem = MemoryBlockstore::default();
+    let persisted = mem.put_cbor(&1u8, Code::Blake2b256).unwrap();
+    let buf_store = BufferedBlockstore::new(&mem);
+
+    let buffered = buf_store.put_cbor(&2u8, Code::Blake2b256).unwrap();
+    let before = buf_store.stats();
+    assert_eq!(before.writes, 1);
+    assert_eq!(before.bytes_written, 1);
+
+    assert_eq!(buf_store.get_cbor::<u8>(&buffered).unwrap(), Some(2));
+    assert_eq!(buf_store.get_cbor::<u8>(&persisted).unwrap(), Some(1));
+    assert!(buf_store.has(&persisted).unwrap());
+
+    let stats = buf_store.stats().since(&before);
+    assert_eq!(
+        stats,
+        BlockstoreStats {
+            reads: 3,
+            buffer_hits: 1,
+            writes: 0,
+            bytes_read: 2,
+            bytes_written: 0,
+            blocks_flushed: 0,
+            bytes_flushed: 0,
+        }
+    );
+    assert!((stats.buffer_hit_rate() - 1.0 / 3.0).abs() < f64::EPSILON);
+}
+
+#[test]
+fn buffered_store_bulk_reads() {
+    let missing = MemoryBlockstore::default()
+        .put_cbor(&3u8, Code::Blake2b256)
+        .unwrap();
+    let mem = MemoryBlockstore::default();
+    let persisted = mem.put_cbor(&1u8, Code::Blake2b256).unwrap();
+    let buf_store = BufferedBlockstore::new(&mem);
+    let buffered = buf_store.put_cbor(&2u8, Code::Blake2b256).unwrap();
+    let before = buf_store.stats();
+
+    // Results come back in key order, whether they're buffered, persisted, or missing.
+    let keys = [missing, buffered, persisted];
+    assert_eq!(
+        buf_store.get_many(&keys).unwrap(),
+        vec![None, Some(vec![2]), Some(vec![1])]
+    );
+    assert_eq!(buf_store.has_many(&keys).unwrap(), vec![false, true, true]);
+
+    let stats = buf_store.stats().since(&before);
+    assert_eq!(stats.reads, 6);
+    assert_eq!(stats.buffer_hits, 2);
+    assert_eq!(stats.bytes_read, 2);
+}
+
+#[test]
+fn buffered_store_with_links() {
+    let mem = MemoryBlockstore::default();
+    let buf_store = BufferedBlockstore::new(&mem);
+    let str_val = String::from("value");
+    let value = 8u8;
+    let arr_cid = buf_store
+        .put_cbor(&(str_val.clone(), value), Code::Blake2b256)
+        .unwrap();
+    let identity_cid = Cid::new_v1(RAW, Multihash::wrap(IDENTITY_HASH, &[0]).unwrap());
+
+    // Create map to insert into store
+    let sealed_comm_cid = commcid::commitment_to_cid(
+        commcid::FIL_COMMITMENT_SEALED,
+        commcid::POSEIDON_BLS12_381_A1_FC1,
+        &[7u8; 32],
+    )
+    .unwrap();
+    let unsealed_comm_cid = commcid::commitment_to_cid(
+        commcid::FIL_COMMITMENT_UNSEALED,
+        commcid::SHA2_256_TRUNC254_PADDED,
+        &[5u8; 32],
+    )
+    .unwrap();
+    #[derive(Deserialize, Serialize, PartialEq, Eq, Debug)]
+    struct TestObject {
+        array: Cid,
+        sealed: Cid,
+        unsealed: Cid,
+        identity: Cid,
+        value: String,
+    }
+    let obj = TestObject {
+        array: arr_cid,
+        sealed: sealed_comm_cid,
+        unsealed: unsealed_comm_cid,
+        identity: identity_cid,
+        value: str_val.clone(),
+    };
+    let obj_cid = buf_store.put_cbor(&obj, Code::Blake2b256).unwrap();
+
+    let root_cid = buf_store
+        .put_cbor(&(obj_cid, 1u8), Code::Blake2b256)
+        .unwrap();
+
+    // Make sure a block not connected to the root does not get written
+    let unconnected = buf_store.put_cbor(&27u8, Code::Blake2b256).unwrap();
+
+    assert_eq!(mem.get_cbor::<TestObject>(&obj_cid).unwrap(), None);
+    assert_eq!(mem.get_cbor::<(Cid, u8)>(&root_cid).unwrap(), None);
+    assert_eq!(mem.get_cbor::<(String, u8)>(&arr_cid).unwrap(), None);
+    assert_eq!(buf_store.get_cbor::<u8>(&unconnected).unwrap(), Some(27u8));
+
+    // Flush and assert changes
+    buf_store.flush(&root_cid).unwrap();
+    assert_eq!(
+        mem.get_cbor::<(String, u8)>(&arr_cid).unwrap(),
+        Some((str_val, value))
+    );
+    assert_eq!(mem.get_cbor::<TestObject>(&obj_cid).unwrap(), Some(obj));
+    assert_eq!(
+        mem.get_cbor::<(Cid, u8)>(&root_cid).unwrap(),
+        Some((obj_cid, 1)),
+    );
+    assert_eq!(buf_store.get_cbor::<u8>(&identity_cid).unwrap(), None);
+    assert_eq!(buf_store.get(&unsealed_comm_cid).unwrap(), None);
+    assert_eq!(buf_store.get(&sealed_comm_cid).unwrap(), None);
+    assert_eq!(mem.get_cbor::<u8>(&unconnected).unwrap(), None);
+
+    // Only the three reachable blocks were written.
+    assert_eq!(buf_store.stats().blocks_flushed, 3);
+    assert!(buf_store.stats().bytes_flushed < buf_store.stats().bytes_written);
+}
@@ -0,0 +1,57 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+
+/// A single difference between two HAMTs, as returned by [`Hamt::diff`](crate::Hamt::diff).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<K, V> {
+    /// The key is only present in the new HAMT.
+    Added { key: K, value: V },
+    /// The key is only present in the old HAMT.
+    Removed { key: K, value: V },
+    /// The key is present in both HAMTs, but with different values.
+    Modified { key: K, before: V, after: V },
+}
+
+impl<K, V> Change<K, V> {
+    /// Returns the key that changed.
+    pub fn key(&self) -> &K {
+        match self {
+            Change::Added { key, .. }
+            | Change::Removed { key, .. }
+            | Change::Modified { key, .. } => key,
+        }
+    }
+}
+
+/// Appends the changes between two sets of key-value pairs that hash to the same position in
+/// the HAMT. These sets are small (they're either buckets or collapsed sub-trees), so a quadratic
+/// comparison is fine.
+pub(crate) fn diff_entries<K, V>(
+    before: Vec<(K, V)>,
+    mut after: Vec<(K, V)>,
+    changes: &mut Vec<Change<K, V>>,
+) where
+    K: PartialEq,
+    V: PartialEq,
+{
+    for (key, value) in before {
+        match after.iter().position(|(k, _)| *k == key) {
+            Some(i) => {
+                let (_, new) = after.swap_remove(i);
+                if new != value {
+                    changes.push(Change::Modified {
+                        key,
+                        before: value,
+                        after: new,
+                    });
+                }
+            }
+            None => changes.push(Change::Removed { key, value }),
+        }
+    }
+    changes.extend(
+        after
+            .into_iter()
+            .map(|(key, value)| Change::Added { key, value }),
+    );
+}
@@ -15,7 +15,7 @@ use serde::{Serialize, Serializer};
 
 use crate::hash_bits::HashBits;
 use crate::node::Node;
-use crate::{Config, Error, Hash, HashAlgorithm, Sha256};
+use crate::{Change, Config, Error, Hash, HashAlgorithm, Sha256};
 
 /// Implementation of the HAMT data structure for IPLD.
 ///
@@ -431,6 +431,61 @@ where
         }
     }
 
+    /// Returns the changes between this HAMT and `other`, i.e., the keys that were added, removed,
+    /// or modified to get from this HAMT to `other`.
+    ///
+    /// The two HAMTs are walked in lock-step and sub-trees that are identical in both (i.e.,
+    /// flushed to the same CID) are skipped, so the cost is proportional to the size of the
+    /// difference rather than the size of the HAMTs. Both HAMTs must have the same bit width.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use fvm_ipld_hamt::{Change, Hamt};
+    ///
+    /// let store = fvm_ipld_blockstore::MemoryBlockstore::default();
+    ///
+    /// let mut before: Hamt<_, u64, u64> = Hamt::new(&store);
+    /// before.set(1, 1).unwrap();
+    /// before.set(2, 2).unwrap();
+    /// let root = before.flush().unwrap();
+    ///
+    /// let mut after: Hamt<_, u64, u64> = Hamt::load(&root, &store).unwrap();
+    /// after.set(2, 3).unwrap();
+    /// after.set(4, 4).unwrap();
+    /// after.delete(&1).unwrap();
+    ///
+    /// let mut changes = before.diff(&after).unwrap();
+    /// changes.sort_by_key(|c| *c.key());
+    /// assert_eq!(
+    ///     changes,
+    ///     vec![
+    ///         Change::Removed { key: 1, value: 1 },
+    ///         Change::Modified { key: 2, before: 2, after: 3 },
+    ///         Change::Added { key: 4, value: 4 },
+    ///     ]
+    /// );
+    /// ```
+    pub fn diff<BS2>(&self, other: &Hamt<BS2, V, K, H>) -> Result<Vec<Change<K, V>>, Error>
+    where
+        K: Clone,
+        V: Clone + PartialEq,
+        BS2: Blockstore,
+    {
+        if self.conf.bit_width != other.conf.bit_width {
+            return Err("cannot diff HAMTs with different bit widths".into());
+        }
+        let mut changes = Vec::new();
+        self.root.diff(
+            &self.store,
+            &other.root,
+            &other.store,
+            &self.conf,
+            &mut changes,
+        )?;
+        Ok(changes)
+    }
+
     /// Consumes this HAMT and returns the Blockstore it owns.
     pub fn into_store(self) -> BS {
         self.store
@@ -14,6 +14,7 @@ use serde::de::DeserializeOwned;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::bitfield::Bitfield;
+use super::diff::{diff_entries, Change};
 use super::hash_bits::HashBits;
 use super::pointer::Pointer;
 use super::{Error, Hash, HashAlgorithm, KeyValuePair};
@@ -174,6 +175,109 @@ where
         Ok(())
     }
 
+    /// Appends the changes between this node and `other` to `changes`. Sub-trees linked by the
+    /// same CID in both nodes are skipped without being loaded.
+    pub(crate) fn diff<S, S2>(
+        &self,
+        store: &S,
+        other: &Self,
+        other_store: &S2,
+        conf: &Config,
+        changes: &mut Vec<Change<K, V>>,
+    ) -> Result<(), Error>
+    where
+        K: Clone,
+        V: Clone + PartialEq,
+        S: Blockstore,
+        S2: Blockstore,
+    {
+        for idx in 0..(1u32 << conf.bit_width) {
+            let before = self
+                .bitfield
+                .test_bit(idx)
+                .then(|| self.get_child(self.index_for_bit_pos(idx)));
+            let after = other
+                .bitfield
+                .test_bit(idx)
+                .then(|| other.get_child(other.index_for_bit_pos(idx)));
+            match (before, after) {
+                (None, None) => continue,
+                (Some(Pointer::Link { cid: a, .. }), Some(Pointer::Link { cid: b, .. }))
+                    if a == b =>
+                {
+                    continue
+                }
+                (Some(a), Some(b)) => {
+                    if let (Some(a), Some(b)) = (
+                        Self::load_child(a, store)?,
+                        Self::load_child(b, other_store)?,
+                    ) {
+                        a.diff(store, b, other_store, conf, changes)?;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            // At least one side is a bucket (or missing), so the other side can only hold a few
+            // entries.
+            diff_entries(
+                Self::child_entries(before, store)?,
+                Self::child_entries(after, other_store)?,
+                changes,
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the node behind a pointer, loading (and caching) it if necessary, or `None` if the
+    /// pointer is a bucket of values.
+    fn load_child<'a, S: Blockstore>(
+        child: &'a Pointer<K, V, H>,
+        store: &S,
+    ) -> Result<Option<&'a Self>, Error> {
+        match child {
+            Pointer::Link { cid, cache } => {
+                if let Some(cached_node) = cache.get() {
+                    return Ok(Some(cached_node));
+                }
+                let node: Box<Node<K, V, H>> = store
+                    .get_cbor(cid)?
+                    .ok_or_else(|| Error::CidNotFound(cid.to_string()))?;
+                // Intentionally ignoring error, cache will always be the same.
+                Ok(Some(cache.get_or_init(|| node)))
+            }
+            Pointer::Dirty(node) => Ok(Some(node)),
+            Pointer::Values(_) => Ok(None),
+        }
+    }
+
+    /// Collects all key-value pairs under a (possibly missing) pointer.
+    fn child_entries<S: Blockstore>(
+        child: Option<&Pointer<K, V, H>>,
+        store: &S,
+    ) -> Result<Vec<(K, V)>, Error>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut entries = Vec::new();
+        match child {
+            None => {}
+            Some(Pointer::Values(kvs)) => {
+                entries.extend(kvs.iter().map(|kv| (kv.0.clone(), kv.1.clone())));
+            }
+            Some(child) => {
+                if let Some(node) = Self::load_child(child, store)? {
+                    node.for_each(store, &mut |k: &K, v: &V| {
+                        entries.push((k.clone(), v.clone()));
+                        Ok(())
+                    })?;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
     pub(crate) fn for_each_ranged<Q: ?Sized, S, F>(
         &self,
         store: &S,
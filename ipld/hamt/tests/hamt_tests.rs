@@ -13,7 +13,7 @@ use fvm_ipld_encoding::strict_bytes::ByteBuf;
 use fvm_ipld_encoding::CborStore;
 #[cfg(feature = "identity")]
 use fvm_ipld_hamt::Identity;
-use fvm_ipld_hamt::{BytesKey, Config, Error, Hamt, Hash};
+use fvm_ipld_hamt::{BytesKey, Change, Config, Error, Hamt, Hash};
 use multihash::Code;
 use quickcheck::Arbitrary;
 use rand::seq::SliceRandom;
@@ -548,6 +548,69 @@ fn for_each_ranged(factory: HamtFactory, stats: Option<BSStats>, mut cids: CidCh
     }
 }
 
+fn diff(factory: HamtFactory) {
+    let mem = MemoryBlockstore::default();
+    let store = TrackingBlockstore::new(&mem);
+
+    let mut before: Hamt<_, u64, u64> = factory.new(&store);
+    for i in 0..200 {
+        before.set(i, i).unwrap();
+    }
+    let c = before.flush().unwrap();
+
+    // Identical HAMTs don't need to load any nodes beyond the roots.
+    let before: Hamt<_, u64, u64> = factory.load(&c, &store).unwrap();
+    let mut after: Hamt<_, u64, u64> = factory.load(&c, &store).unwrap();
+    let reads = store.stats.borrow().r;
+    assert!(before.diff(&after).unwrap().is_empty());
+    assert_eq!(store.stats.borrow().r, reads);
+
+    let mut expected = Vec::new();
+    for i in 0..10 {
+        after.delete(&i).unwrap();
+        expected.push(Change::Removed { key: i, value: i });
+    }
+    for i in 10..20 {
+        after.set(i, i + 1).unwrap();
+        expected.push(Change::Modified {
+            key: i,
+            before: i,
+            after: i + 1,
+        });
+    }
+    // Setting a key to its current value isn't a change.
+    after.set(20, 20).unwrap();
+    for i in 200..210 {
+        after.set(i, i).unwrap();
+        expected.push(Change::Added { key: i, value: i });
+    }
+
+    // Diff against the unflushed HAMT, then against the flushed one.
+    for _ in 0..2 {
+        let mut changes = before.diff(&after).unwrap();
+        changes.sort_by_key(|c| *c.key());
+        assert_eq!(changes, expected);
+        after.flush().unwrap();
+    }
+
+    // And the other way around.
+    let mut changes = after.diff(&before).unwrap();
+    changes.sort_by_key(|c| *c.key());
+    let reversed: Vec<_> = expected
+        .into_iter()
+        .map(|c| match c {
+            Change::Added { key, value } => Change::Removed { key, value },
+            Change::Removed { key, value } => Change::Added { key, value },
+            Change::Modified { key, before, after } => Change::Modified {
+                key,
+                before: after,
+                after: before,
+            },
+        })
+        .collect();
+    assert_eq!(changes, reversed);
+}
+
 #[cfg(feature = "identity")]
 fn add_and_remove_keys(
     bit_width: u32,
@@ -996,6 +1059,11 @@ mod test_default {
         super::for_each_ranged(HamtFactory::default(), Some(stats), cids);
     }
 
+    #[test]
+    fn diff() {
+        super::diff(HamtFactory::default())
+    }
+
     #[test]
     fn clean_child_ordering() {
         #[rustfmt::skip]
@@ -1091,6 +1159,11 @@ macro_rules! test_hamt_mod {
                 super::clean_child_ordering($factory, None, CidChecker::empty())
             }
 
+            #[test]
+            fn diff() {
+                super::diff($factory)
+            }
+
             #[quickcheck]
             fn prop_cid_indep_of_insert_order(
                 kvs: UniqueKeyValuePairs<u8, i64>,
@@ -4,13 +4,14 @@ use core::option::Option;
 use std::ptr; // no_std
 
 use cid::Cid;
+use fvm_ipld_encoding::ipld_block::IpldBlock;
 use fvm_shared::address::{Address, Payload, MAX_ADDRESS_LEN};
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::error::ErrorNumber;
-use fvm_shared::{ActorID, MAX_CID_LEN};
+use fvm_shared::{ActorID, Response, MAX_CID_LEN};
 use log::error;
 
-use crate::{sys, SyscallResult};
+use crate::{sys, SyscallResult, NO_DATA_BLOCK_ID};
 
 /// Resolves the ID address of an actor. Returns `None` if the address cannot be resolved.
 /// Successfully resolving an address doesn't necessarily mean the actor exists (e.g., if the
@@ -81,6 +82,11 @@ pub fn get_actor_code_cid(addr: &Address) -> Option<Cid> {
 }
 
 /// Generates a new actor address for an actor deployed by the calling actor.
+///
+/// The address is a "robust" (f2) address derived from the origin of the message, the message's
+/// nonce, and the number of actors created so far in the message's execution. It's predictable
+/// before the actor is created and stays the same until the next call to
+/// [`create_actor`], so it can be registered with the init actor before creating the actor.
 pub fn next_actor_address() -> Address {
     let mut buf = [0u8; MAX_ADDRESS_LEN];
     unsafe {
@@ -115,6 +121,42 @@ pub fn install_actor(code_cid: &Cid) -> SyscallResult<()> {
     unsafe { sys::actor::install_actor(cid.as_ptr()) }
 }
 
+/// Validates, stores, and installs Wasm actor code, returning its code CID. Actors may then be
+/// created with this code CID.
+#[cfg(feature = "m2-native")]
+pub fn install_actor_code(wasm: &[u8]) -> SyscallResult<Cid> {
+    let mut buf = [0u8; MAX_CID_LEN];
+    unsafe {
+        let len = sys::actor::install_actor_code(
+            wasm.as_ptr(),
+            wasm.len() as u32,
+            buf.as_mut_ptr(),
+            MAX_CID_LEN as u32,
+        )?;
+        Ok(Cid::read_bytes(&buf[..len as usize]).expect("invalid cid returned"))
+    }
+}
+
+/// Upgrades the calling actor to the specified code in-place, keeping its address, balance, and
+/// state, and invokes the new code's `upgrade` entrypoint with the given parameters.
+///
+/// If the upgrade succeeds, this function never returns: the calling actor's invocation ends with
+/// the return value of the `upgrade` entrypoint. Otherwise, the upgrade is reverted and this
+/// returns the `upgrade` entrypoint's exit code and return value, just like [`send`](crate::send::send).
+///
+/// Only available from NV21.
+pub fn upgrade_actor(new_code_cid: &Cid, params: Option<IpldBlock>) -> SyscallResult<Response> {
+    let cid = new_code_cid.to_bytes();
+    unsafe {
+        let params_id = match params {
+            Some(p) => sys::ipld::block_create(p.codec, p.data.as_ptr(), p.data.len() as u32)?,
+            None => NO_DATA_BLOCK_ID,
+        };
+        let send = sys::actor::upgrade_actor(cid.as_ptr(), params_id)?;
+        crate::send::read_response(send)
+    }
+}
+
 /// Determines whether the supplied CodeCID belongs to a built-in actor type,
 /// and to which.
 pub fn get_builtin_actor_type(code_cid: &Cid) -> Option<i32> {
@@ -132,6 +174,15 @@ pub fn get_builtin_actor_type(code_cid: &Cid) -> Option<i32> {
     }
 }
 
+/// Determines whether the actor at the specified address is a built-in actor and, if so, of which
+/// type. Returns `None` if the actor doesn't exist or isn't a built-in actor.
+///
+/// This is a convenience wrapper around [`get_actor_code_cid`] and [`get_builtin_actor_type`] for
+/// checking the type of a counterparty (e.g., the caller).
+pub fn resolve_builtin_actor_type(addr: &Address) -> Option<i32> {
+    get_builtin_actor_type(&get_actor_code_cid(addr)?)
+}
+
 /// Returns the CodeCID for a built-in actor type. Aborts with IllegalArgument
 /// if the supplied type is invalid.
 pub fn get_code_cid_for_type(typ: i32) -> Cid {
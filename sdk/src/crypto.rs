@@ -6,12 +6,13 @@ use fvm_shared::address::Address;
 use fvm_shared::consensus::ConsensusFault;
 use fvm_shared::crypto::hash::SupportedHashes;
 use fvm_shared::crypto::signature::{
-    Signature, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
+    Signature, BLS_PUB_LEN, BLS_SIG_LEN, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
 };
+use fvm_shared::error::ErrorNumber;
 use fvm_shared::piece::PieceInfo;
 use fvm_shared::sector::{
     AggregateSealVerifyProofAndInfos, RegisteredSealProof, ReplicaUpdateInfo, SealVerifyInfo,
-    WindowPoStVerifyInfo,
+    WindowPoStVerifyInfo, WinningPoStVerifyInfo,
 };
 use fvm_shared::MAX_CID_LEN;
 use num_traits::FromPrimitive;
@@ -20,7 +21,8 @@ use crate::{status_code_to_bool, sys, SyscallResult};
 
 /// Verifies that a signature is valid for an address and plaintext.
 ///
-/// NOTE: This only supports f1 and f3 addresses.
+/// The signer may be a key (f1/f3) address or, from NV21, any other address of an account actor, in
+/// which case the signature is checked against the account's key address.
 pub fn verify_signature(
     signature: &Signature,
     signer: &Address,
@@ -43,6 +45,30 @@ pub fn verify_signature(
     }
 }
 
+/// Verifies a BLS aggregate signature, where the signer with the public key `pub_keys[i]` signed
+/// `plaintexts[i]`. The plaintexts must be distinct.
+pub fn verify_aggregate_signature(
+    aggregate_sig: &[u8; BLS_SIG_LEN],
+    pub_keys: &[[u8; BLS_PUB_LEN]],
+    plaintexts: &[&[u8]],
+) -> SyscallResult<bool> {
+    if pub_keys.len() != plaintexts.len() {
+        return Err(ErrorNumber::IllegalArgument);
+    }
+    let plaintext_lens: Vec<u32> = plaintexts.iter().map(|p| p.len() as u32).collect();
+    let plaintexts = plaintexts.concat();
+    unsafe {
+        sys::crypto::verify_aggregate_signature(
+            pub_keys.len() as u32,
+            aggregate_sig.as_ptr(),
+            pub_keys.as_ptr(),
+            plaintexts.as_ptr(),
+            plaintext_lens.as_ptr(),
+        )
+        .map(status_code_to_bool)
+    }
+}
+
 /// Recovers the signer public key from the message hash and signature.
 pub fn recover_secp_public_key(
     hash: &[u8; SECP_SIG_MESSAGE_HASH_SIZE],
@@ -144,6 +170,14 @@ pub fn verify_post(info: &WindowPoStVerifyInfo) -> SyscallResult<bool> {
     unsafe { sys::crypto::verify_post(info.as_ptr(), info.len() as u32).map(status_code_to_bool) }
 }
 
+/// Verifies a winning proof of spacetime.
+pub fn verify_winning_post(info: &WinningPoStVerifyInfo) -> SyscallResult<bool> {
+    let info = to_vec(info).expect("failed to marshal PoSt verification input");
+    unsafe {
+        sys::crypto::verify_winning_post(info.as_ptr(), info.len() as u32).map(status_code_to_bool)
+    }
+}
+
 /// Verifies that two block headers provide proof of a consensus fault:
 /// - both headers mined by the same actor
 /// - headers are different
@@ -1,5 +1,7 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+use fvm_shared::error::ErrorNumber;
+use fvm_shared::ActorID;
 use lazy_static::lazy_static;
 use log::LevelFilter;
 
@@ -17,6 +19,18 @@ pub fn log(msg: String) {
         sys::debug::log(msg.as_ptr(), msg.len() as u32).unwrap();
     }
 }
+/// Logs a message on the node at the given level.
+///
+/// This requires a node supporting the `debug::log_at` syscall: actors calling it can't be
+/// deployed on older nodes.
+#[inline]
+pub fn log_at(level: log::Level, msg: impl AsRef<str>) {
+    let msg = msg.as_ref();
+    unsafe {
+        sys::debug::log_at(level as u32, msg.as_ptr(), msg.len() as u32).unwrap();
+    }
+}
+
 /// Initialize logging if debugging is enabled.
 #[inline(always)]
 pub fn init_logging() {
@@ -41,6 +55,27 @@ pub fn store_artifact(name: impl AsRef<str>, data: impl AsRef<[u8]>) {
     }
 }
 
+/// Returns the IDs of the actors currently executing on the call stack, outermost first (ending with
+/// the calling actor). Returns an empty list if debug mode is disabled.
+pub fn call_stack() -> Vec<ActorID> {
+    let mut buf = vec![0u8; 64 * 8];
+    loop {
+        match unsafe { sys::debug::call_stack(buf.as_mut_ptr(), buf.len() as u32) } {
+            Ok(n) => {
+                return buf[..n as usize * 8]
+                    .chunks_exact(8)
+                    .map(|id| ActorID::from_le_bytes(id.try_into().unwrap()))
+                    .collect()
+            }
+            Err(ErrorNumber::BufferTooSmall) => {
+                let len = buf.len() * 2;
+                buf.resize(len, 0);
+            }
+            Err(e) => panic!("unexpected error from debug::call_stack syscall: {}", e),
+        }
+    }
+}
+
 /// Returns whether debug mode is enabled.
 #[inline(always)]
 pub fn enabled() -> bool {
@@ -58,6 +93,11 @@ impl log::Log for Logger {
 
     fn log(&self, record: &log::Record) {
         if enabled() {
+            // Passing the level to the node requires the `debug::log_at` syscall, which older
+            // nodes don't provide, so it's opt-in.
+            #[cfg(feature = "log-levels")]
+            log_at(record.level(), record.args().to_string());
+            #[cfg(not(feature = "log-levels"))]
             log(format!("[{}] {}", record.level(), record.args()));
         }
     }
@@ -9,6 +9,15 @@ pub fn charge(name: &str, compute: u64) {
         .expect("failed to charge gas")
 }
 
+/// Returns the gas remaining for the current call, bounded by the gas limit of any enclosing
+/// send. Actors can use this to bound "best-effort" work by the remaining gas.
 pub fn available() -> u64 {
     unsafe { sys::gas::available() }.expect("failed to check available gas")
 }
+
+/// Record a named milestone at the current gas used, for profiling.
+pub fn milestone(name: &str) {
+    unsafe { sys::gas::milestone(name.as_ptr(), name.len() as u32) }
+        // can only happen if name isn't utf8, memory corruption, etc.
+        .expect("failed to record gas milestone")
+}
@@ -2,11 +2,13 @@
 // SPDX-License-Identifier: Apache-2.0, MIT
 use std::convert::TryInto;
 
+use cid::Cid;
 use fvm_ipld_encoding::ipld_block::IpldBlock;
 use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ErrorNumber;
 use fvm_shared::sys::out::vm::MessageContext;
 use fvm_shared::sys::BlockId;
-use fvm_shared::{ActorID, MethodNum};
+use fvm_shared::{ActorID, MethodNum, MAX_CID_LEN};
 
 use crate::{sys, SyscallResult, NO_DATA_BLOCK_ID};
 
@@ -30,6 +32,21 @@ pub fn caller() -> ActorID {
     MESSAGE_CONTEXT.caller
 }
 
+/// Returns the code CID of the caller, or `None` if the caller no longer exists (e.g., because
+/// it deleted itself before calling).
+pub fn caller_code_cid() -> Option<Cid> {
+    let mut buf = [0u8; MAX_CID_LEN];
+    unsafe {
+        match sys::vm::caller_code_cid(buf.as_mut_ptr(), buf.len() as u32) {
+            Ok(len) => Some(
+                Cid::read_bytes(&buf[..len as usize]).expect("runtime returned an invalid CID"),
+            ),
+            Err(ErrorNumber::NotFound) => None,
+            Err(e) => panic!("unexpected error from `vm::caller_code_cid` syscall: {}", e),
+        }
+    }
+}
+
 /// Returns the ID address of the origin
 #[inline(always)]
 pub fn origin() -> ActorID {
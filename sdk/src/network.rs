@@ -20,22 +20,29 @@ lazy_static::lazy_static! {
     };
 }
 
+/// Returns the chain ID of the network.
 pub fn chain_id() -> ChainID {
     NETWORK_CONTEXT.chain_id.into()
 }
 
+/// Returns the current epoch.
 pub fn curr_epoch() -> ChainEpoch {
     NETWORK_CONTEXT.epoch
 }
 
+/// Returns the current network version.
 pub fn version() -> NetworkVersion {
     NETWORK_CONTEXT.network_version
 }
 
+/// Returns the base fee of the tipset in which the current message is being executed. This is
+/// read from the network context, so it doesn't require a separate syscall.
 pub fn base_fee() -> TokenAmount {
     NETWORK_CONTEXT.base_fee.into()
 }
 
+/// Returns the circulating supply of FIL at the current epoch, as supplied to the FVM by the
+/// client. Unlike the rest of the network context, this is queried from the FVM on every call.
 pub fn total_fil_circ_supply() -> TokenAmount {
     unsafe {
         sys::network::total_fil_circ_supply()
@@ -33,12 +33,7 @@ pub fn send(
         };
 
         // Perform the syscall to send the message.
-        let fvm_shared::sys::out::send::Send {
-            exit_code,
-            return_id,
-            return_codec,
-            return_size,
-        } = sys::send::send(
+        let send = sys::send::send(
             recipient.as_ptr(),
             recipient.len() as u32,
             method,
@@ -50,25 +45,38 @@ pub fn send(
         )?;
 
         // Process the result.
-        let exit_code = ExitCode::new(exit_code);
-        let return_data = if return_id == NO_DATA_BLOCK_ID {
-            None
-        } else {
-            // Allocate a buffer to read the return data.
-            let mut bytes = vec![0; return_size as usize];
+        read_response(send)
+    }
+}
 
-            // Now read the return data.
-            let unread = sys::ipld::block_read(return_id, 0, bytes.as_mut_ptr(), return_size)?;
-            assert_eq!(0, unread);
-            Some(IpldBlock {
-                codec: return_codec,
-                data: bytes.to_vec(),
-            })
-        };
+/// Converts the result of a send (or similar) syscall into a [`Response`], reading the return
+/// data (if any).
+pub(crate) fn read_response(send: sys::send::Send) -> SyscallResult<Response> {
+    let sys::send::Send {
+        exit_code,
+        return_id,
+        return_codec,
+        return_size,
+    } = send;
+    let exit_code = ExitCode::new(exit_code);
+    let return_data = if return_id == NO_DATA_BLOCK_ID {
+        None
+    } else {
+        // Allocate a buffer to read the return data.
+        let mut bytes = vec![0; return_size as usize];
 
-        Ok(Response {
-            exit_code,
-            return_data,
+        // Now read the return data.
+        let unread =
+            unsafe { sys::ipld::block_read(return_id, 0, bytes.as_mut_ptr(), return_size)? };
+        assert_eq!(0, unread);
+        Some(IpldBlock {
+            codec: return_codec,
+            data: bytes.to_vec(),
         })
-    }
+    };
+
+    Ok(Response {
+        exit_code,
+        return_data,
+    })
 }
@@ -23,12 +23,26 @@ pub fn root() -> Result<Cid, StateReadError> {
     }
 }
 
+/// Returns the code CID of the calling actor. Fails if the actor has been deleted.
+pub fn code_cid() -> Result<Cid, StateReadError> {
+    let mut buf = [0u8; MAX_CID_LEN];
+    unsafe {
+        let len = sys::sself::code_cid(buf.as_mut_ptr(), buf.len() as u32).map_err(|e| match e {
+            ErrorNumber::IllegalOperation => StateReadError,
+            e => panic!("unexpected error from `self::code_cid` syscall: {}", e),
+        })? as usize;
+
+        Ok(Cid::read_bytes(&buf[..len]).expect("runtime returned an invalid CID"))
+    }
+}
+
 /// Set the actor's state-tree root.
 ///
 /// Fails if:
 ///
 /// - The new root is not in the actor's "reachable" set.
-/// - Fails if the actor has been deleted.
+/// - The actor has been deleted.
+/// - The actor is executing in read-only mode.
 pub fn set_root(cid: &Cid) -> Result<(), StateUpdateError> {
     let mut buf = [0u8; MAX_CID_LEN];
     cid.write_bytes(&mut buf[..])
@@ -133,6 +133,66 @@ super::fvm_syscalls! {
     #[cfg(feature = "m2-native")]
     pub fn install_actor(cid_off: *const u8) -> Result<()>;
 
+    /// Validates and stores Wasm actor code, and installs it. Actors may then be created with
+    /// the returned code CID.
+    ///
+    /// # Arguments
+    ///
+    /// - `wasm_off` and `wasm_len` specify the location and length of the Wasm module.
+    /// - `obuf_off` and `obuf_len` specify the location and length of a byte buffer into which the
+    ///   FVM will write the code CID.
+    ///
+    /// # Returns
+    ///
+    /// The length of the code CID.
+    ///
+    /// # Errors
+    ///
+    /// | Error               | Reason                                                           |
+    /// |---------------------|------------------------------------------------------------------|
+    /// | [`IllegalArgument`] | if the module is invalid, or the output buffer is too small      |
+    /// | [`LimitExceeded`]   | if the module exceeds the network's Wasm limits                  |
+    /// | [`ReadOnly`]        | if the actor is executing in read-only mode                      |
+    #[cfg(feature = "m2-native")]
+    pub fn install_actor_code(
+        wasm_off: *const u8,
+        wasm_len: u32,
+        obuf_off: *mut u8,
+        obuf_len: u32,
+    ) -> Result<u32>;
+
+    /// Upgrades the calling actor to new code in-place, keeping its address, balance, and state,
+    /// and invokes the new code's `upgrade` entrypoint.
+    ///
+    /// The `upgrade` entrypoint is called with the parameters block and an [`UpgradeInfo`] block.
+    /// If it succeeds, the calling actor's invocation ends immediately with the upgrade's return
+    /// value (this syscall doesn't return). Otherwise, the upgrade is reverted and the result is
+    /// returned as if the actor had sent a message.
+    ///
+    /// This syscall is only available from NV21.
+    ///
+    /// [`UpgradeInfo`]: fvm_shared::upgrade::UpgradeInfo
+    ///
+    /// # Arguments
+    ///
+    /// - `new_code_cid_off` is the offset of the new code CID.
+    /// - `params` is the IPLD block handle of the upgrade parameters.
+    ///
+    /// # Errors
+    ///
+    /// | Error               | Reason                                                 |
+    /// |---------------------|--------------------------------------------------------|
+    /// | [`NotFound`]        | the new code doesn't exist or can't be deployed        |
+    /// | [`InvalidHandle`]   | the parameters block wasn't found                      |
+    /// | [`ReadOnly`]        | the actor is executing in read-only mode               |
+    /// | [`Forbidden`]       | the actor has been re-entered and appears on the stack |
+    /// | [`LimitExceeded`]   | the recursion limit has been reached                   |
+    /// | [`IllegalArgument`] | the new code CID is invalid                            |
+    pub fn upgrade_actor(
+        new_code_cid_off: *const u8,
+        params: u32,
+    ) -> Result<super::send::Send>;
+
     /// Gets the balance of the specified actor.
     ///
     /// # Arguments
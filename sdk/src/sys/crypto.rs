@@ -2,7 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0, MIT
 //! Syscalls for cryptographic operations.
 
-use fvm_shared::crypto::signature::SECP_PUB_LEN;
+use fvm_shared::crypto::signature::{BLS_PUB_LEN, SECP_PUB_LEN};
 #[doc(inline)]
 pub use fvm_shared::sys::out::crypto::*;
 
@@ -28,6 +28,8 @@ super::fvm_syscalls! {
     /// | Error               | Reason                                               |
     /// |---------------------|------------------------------------------------------|
     /// | [`IllegalArgument`] | signature, address, or plaintext buffers are invalid |
+    /// | [`IllegalArgument`] | the signer isn't a key address or an account actor   |
+    /// | [`NotFound`]        | the signer doesn't exist                             |
     pub fn verify_signature(
         sig_type: u32,
         sig_off: *const u8,
@@ -38,6 +40,33 @@ super::fvm_syscalls! {
         plaintext_len: u32,
     ) -> Result<i32>;
 
+    /// Verifies a BLS aggregate signature, where each signer signed one (distinct) plaintext.
+    ///
+    /// Returns 0 on success, or -1 if the signature fails to validate.
+    ///
+    /// # Arguments
+    ///
+    /// - `num_signers` is the number of signers (and plaintexts), which must be non-zero.
+    /// - `sig_off` specifies the location of the 96-byte aggregate signature.
+    /// - `pub_keys_off` specifies the location of `num_signers` 48-byte BLS public keys.
+    /// - `plaintexts_off` specifies the location of the concatenated plaintexts, where the `i`th
+    ///   plaintext was signed by the `i`th signer.
+    /// - `plaintext_lens_off` specifies the location of `num_signers` `u32` plaintext lengths.
+    ///
+    /// # Errors
+    ///
+    /// | Error               | Reason                                                  |
+    /// |---------------------|---------------------------------------------------------|
+    /// | [`IllegalArgument`] | signature, public key, or plaintext buffers are invalid |
+    /// | [`IllegalArgument`] | there are no signers                                    |
+    pub fn verify_aggregate_signature(
+        num_signers: u32,
+        sig_off: *const u8,
+        pub_keys_off: *const [u8; BLS_PUB_LEN],
+        plaintexts_off: *const u8,
+        plaintext_lens_off: *const u32,
+    ) -> Result<i32>;
+
     /// Recovers the signer public key from a signed message hash and its signature.
     ///
     /// Returns the public key in uncompressed 65 bytes form.
@@ -143,6 +172,22 @@ super::fvm_syscalls! {
     /// | [`IllegalArgument`] | an argument is malformed |
     pub fn verify_post(info_off: *const u8, info_len: u32) -> Result<i32>;
 
+    /// Verifies a winning proof of spacetime.
+    ///
+    /// Returns 0 to indicate that the proof was valid, -1 otherwise.
+    ///
+    /// # Arguments
+    ///
+    /// `info_off` and `info_len` specify the location and length of a cbor-encoded
+    /// [`WinningPoStVerifyInfo`][fvm_shared::sector::WinningPoStVerifyInfo] in tuple representation.
+    ///
+    /// # Errors
+    ///
+    /// | Error               | Reason                   |
+    /// |---------------------|--------------------------|
+    /// | [`IllegalArgument`] | an argument is malformed |
+    pub fn verify_winning_post(info_off: *const u8, info_len: u32) -> Result<i32>;
+
     /// Verifies that two block headers provide proof of a consensus fault.
     ///
     /// Returns a 0 status if a consensus fault was recognized, along with the
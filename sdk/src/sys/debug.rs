@@ -2,6 +2,10 @@
 // SPDX-License-Identifier: Apache-2.0, MIT
 //! Syscalls for debugging.
 
+// for documentation links
+#[cfg(doc)]
+use crate::sys::ErrorNumber::*;
+
 super::fvm_syscalls! {
     module = "debug";
 
@@ -12,6 +16,28 @@ super::fvm_syscalls! {
     /// Logs a message on the node.
     pub fn log(message: *const u8, message_len: u32) -> Result<()>;
 
+    /// Logs a message on the node at the given level: 1 (error), 2 (warn), 3 (info), 4 (debug),
+    /// or 5 (trace). This is a no-op if debug mode is disabled.
+    ///
+    /// # Errors
+    ///
+    /// | Error               | Reason                                          |
+    /// |---------------------|-------------------------------------------------|
+    /// | [`IllegalArgument`] | the level is invalid or the message isn't UTF-8 |
+    pub fn log_at(level: u32, message: *const u8, message_len: u32) -> Result<()>;
+
     /// Save data as a debug artifact on the node.
     pub fn store_artifact(name_off: *const u8, name_len: u32, data_off: *const u8, data_len: u32) -> Result<()>;
+
+    /// Writes the IDs of the actors currently executing on the call stack (outermost first, ending
+    /// with the caller) into the output buffer as little-endian u64s, returning the number of IDs
+    /// written. This is a no-op returning 0 if debug mode is disabled.
+    ///
+    /// # Errors
+    ///
+    /// | Error               | Reason                                          |
+    /// |---------------------|-------------------------------------------------|
+    /// | [`IllegalArgument`] | the output buffer isn't valid                   |
+    /// | [`BufferTooSmall`]  | the output buffer can't hold the call stack     |
+    pub fn call_stack(obuf_off: *mut u8, obuf_len: u32) -> Result<u32>;
 }
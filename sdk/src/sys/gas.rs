@@ -26,4 +26,19 @@ super::fvm_syscalls! {
 
     /// Returns the amount of gas remaining.
     pub fn available() -> Result<u64>;
+
+    /// Records a named milestone at the current gas used, for profiling. Milestones are reported
+    /// in the message's gas breakdown (when enabled) and don't consume any gas beyond the cost of
+    /// the syscall itself.
+    ///
+    /// # Arguments
+    ///
+    /// - `name_off` and `name_len` specify the location and length of the milestone's name.
+    ///
+    /// # Errors
+    ///
+    /// | Error               | Reason               |
+    /// |---------------------|----------------------|
+    /// | [`IllegalArgument`] | invalid name buffer. |
+    pub fn milestone(name_off: *const u8, name_len: u32) -> Result<()>;
 }
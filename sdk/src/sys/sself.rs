@@ -27,6 +27,24 @@ super::fvm_syscalls! {
     /// | [`BufferTooSmall`]  | if the output buffer isn't large enough to fit the CID |
     pub fn root(cid: *mut u8, cid_max_len: u32) -> Result<u32>;
 
+    /// Gets the code CID of the calling actor.
+    ///
+    /// Returns the size of the CID.
+    ///
+    /// # Arguments
+    ///
+    /// - `cid` is the location in memory where the code CID will be written.
+    /// - `max_cid_len` is length of the output CID buffer.
+    ///
+    /// # Errors
+    ///
+    /// | Error                | Reason                                                 |
+    /// |----------------------|--------------------------------------------------------|
+    /// | [`IllegalOperation`] | actor has been deleted                                 |
+    /// | [`IllegalArgument`]  | if the passed buffer isn't valid, in memory, etc.      |
+    /// | [`BufferTooSmall`]   | if the output buffer isn't large enough to fit the CID |
+    pub fn code_cid(cid: *mut u8, cid_max_len: u32) -> Result<u32>;
+
     /// Sets the root CID for the calling actor. The new root must be in the reachable set.
     ///
     /// # Arguments
@@ -5,6 +5,10 @@
 #[doc(inline)]
 pub use fvm_shared::sys::out::vm::MessageContext;
 
+// for documentation links
+#[cfg(doc)]
+use crate::sys::ErrorNumber::*;
+
 super::fvm_syscalls! {
     module = "vm";
 
@@ -36,4 +40,22 @@ super::fvm_syscalls! {
     ///
     /// None
     pub fn message_context() -> Result<MessageContext>;
+
+    /// Gets the code CID of the caller.
+    ///
+    /// Returns the size of the CID.
+    ///
+    /// # Arguments
+    ///
+    /// - `obuf_off` is the location in memory where the code CID will be written.
+    /// - `obuf_len` is length of the output CID buffer.
+    ///
+    /// # Errors
+    ///
+    /// | Error               | Reason                                                 |
+    /// |---------------------|--------------------------------------------------------|
+    /// | [`NotFound`]        | the caller no longer exists                            |
+    /// | [`IllegalArgument`] | if the passed buffer isn't valid, in memory, etc.      |
+    /// | [`BufferTooSmall`]  | if the output buffer isn't large enough to fit the CID |
+    pub fn caller_code_cid(obuf_off: *mut u8, obuf_len: u32) -> Result<u32>;
 }
@@ -77,7 +77,10 @@ impl ExitCode {
     pub const SYS_ASSERTION_FAILED: ExitCode = ExitCode::new(10);
     /// The actor returned a block handle that doesn't exist
     pub const SYS_MISSING_RETURN: ExitCode = ExitCode::new(11);
-    // pub const SYS_RESERVED_12: ExitCode = ExitCode::new(12);
+    /// Message execution exceeded a system limit (e.g., the maximum call depth or the maximum
+    /// number of sends) before the receiver could be invoked. Before NV21, this was reported as
+    /// `SYS_ASSERTION_FAILED`.
+    pub const SYS_LIMIT_EXCEEDED: ExitCode = ExitCode::new(12);
     // pub const SYS_RESERVED_13: ExitCode = ExitCode::new(13);
     // pub const SYS_RESERVED_14: ExitCode = ExitCode::new(14);
     // pub const SYS_RESERVED_15: ExitCode = ExitCode::new(15);
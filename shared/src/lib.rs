@@ -29,6 +29,7 @@ pub mod sector;
 pub mod smooth;
 pub mod state;
 pub mod sys;
+pub mod upgrade;
 pub mod version;
 
 use econ::TokenAmount;
@@ -108,6 +109,9 @@ pub type MethodNum = u64;
 pub const METHOD_SEND: MethodNum = 0;
 /// Base actor constructor method.
 pub const METHOD_CONSTRUCTOR: MethodNum = 1;
+/// The method number invoked (via the `upgrade` entrypoint) when an actor's code is upgraded
+/// in-place.
+pub const METHOD_UPGRADE: MethodNum = 932083;
 
 /// The outcome of a `Send`, covering its ExitCode and optional return data
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -3,6 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0, MIT
 
 use cid::Cid;
+use fvm_ipld_encoding::de::DeserializeOwned;
 use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
 use fvm_ipld_encoding::RawBytes;
 
@@ -19,3 +20,36 @@ pub struct Receipt {
     /// CBOR NULL value on the wire).
     pub events_root: Option<Cid>, // Amt<Event>
 }
+
+impl Receipt {
+    /// Decodes the (CBOR) return data into a typed value, returning `None` if the message returned
+    /// nothing. This doesn't check the exit code.
+    pub fn decode_return<T: DeserializeOwned>(
+        &self,
+    ) -> Result<Option<T>, fvm_ipld_encoding::Error> {
+        if self.return_data.is_empty() {
+            return Ok(None);
+        }
+        self.return_data.deserialize().map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_return() {
+        let mut receipt = Receipt {
+            exit_code: ExitCode::OK,
+            return_data: RawBytes::default(),
+            gas_used: 0,
+            events_root: None,
+        };
+        assert_eq!(receipt.decode_return::<u64>().unwrap(), None);
+
+        receipt.return_data = RawBytes::serialize(42u64).unwrap();
+        assert_eq!(receipt.decode_return::<u64>().unwrap(), Some(42));
+        assert!(receipt.decode_return::<String>().is_err());
+    }
+}
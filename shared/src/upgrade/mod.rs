@@ -0,0 +1,12 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use cid::Cid;
+use serde_tuple::*;
+
+/// Information passed to an actor's `upgrade` entrypoint when its code is upgraded in-place (see
+/// the `actor::upgrade_actor` syscall).
+#[derive(Serialize_tuple, Deserialize_tuple, PartialEq, Eq, Clone, Copy, Debug)]
+pub struct UpgradeInfo {
+    /// The actor's code CID before the upgrade.
+    pub old_code_cid: Cid,
+}
@@ -55,6 +55,8 @@ impl NetworkVersion {
     pub const V19: Self = Self(19);
     /// Thunder (builtin-actors v11)
     pub const V20: Self = Self(20);
+    /// Watermelon (builtin-actors v12)
+    pub const V21: Self = Self(21);
 
     pub const MAX: Self = Self(u32::MAX);
 
@@ -0,0 +1,110 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Replays message test vectors (in the Filecoin test-vector JSON format) through the FVM and
+//! reports any receipt or state root that differs from the vector's postconditions.
+//!
+//! Usage: `fvm-replay <vector.json|directory>...`
+use std::path::Path;
+use std::{env, process};
+
+use colored::*;
+use fvm::engine::MultiEngine;
+use fvm_conformance_tests::driver::{is_runnable, run_variant, VariantResult};
+use fvm_conformance_tests::report;
+use fvm_conformance_tests::vector::MessageVector;
+use walkdir::WalkDir;
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.is_empty() {
+        println!("Usage: fvm-replay <vector.json|directory>...");
+        process::exit(1)
+    }
+
+    let engines = MultiEngine::new(1);
+    let (mut succeeded, mut failed, mut skipped) = (0, 0, 0);
+    for path in paths {
+        for entry in WalkDir::new(&path) {
+            let entry = entry.unwrap_or_else(|err| {
+                println!("Error walking {path}: {err}");
+                process::exit(1)
+            });
+            if !is_runnable(&entry) {
+                continue;
+            }
+            for res in replay(entry.path(), &engines) {
+                match res {
+                    VariantResult::Ok { id } => {
+                        report!("OK".on_green(), entry.path().display(), id);
+                        succeeded += 1;
+                    }
+                    VariantResult::Failed { reason, id } => {
+                        report!("FAIL".white().on_red(), entry.path().display(), id);
+                        println!("\t|> reason: {:#}", reason);
+                        failed += 1;
+                    }
+                    VariantResult::Skipped { reason, id } => {
+                        report!("SKIP".on_yellow(), entry.path().display(), id);
+                        println!("\t|> reason: {}", reason);
+                        skipped += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    println!(
+        "{}",
+        format!(
+            "replay result: {}/{} variants matched ({} skipped)",
+            succeeded,
+            failed + succeeded,
+            skipped,
+        )
+        .bold()
+    );
+    if failed > 0 {
+        process::exit(1)
+    }
+}
+
+/// Replays every variant of the vector at `path`, checking receipts and the final state root.
+fn replay(path: &Path, engines: &MultiEngine) -> Vec<VariantResult> {
+    let vector = match MessageVector::from_file(path) {
+        Ok(vector) => vector,
+        Err(reason) => {
+            return vec![VariantResult::Failed {
+                reason: reason.context("failed to load vector"),
+                id: "*".into(),
+            }]
+        }
+    };
+    if !vector.is_supported() {
+        return vec![VariantResult::Skipped {
+            reason: "selector not supported".into(),
+            id: "*".into(),
+        }];
+    }
+    let bs = match async_std::task::block_on(vector.seed_blockstore()) {
+        Ok((bs, _)) => bs,
+        Err(reason) => {
+            return vec![VariantResult::Failed {
+                reason: reason.context("failed to load the vector's state"),
+                id: "*".into(),
+            }]
+        }
+    };
+    vector
+        .preconditions
+        .variants
+        .iter()
+        .map(|variant| {
+            run_variant(bs.clone(), &vector, variant, engines, true, None, None).unwrap_or_else(
+                |reason| VariantResult::Failed {
+                    reason,
+                    id: variant.id.clone(),
+                },
+            )
+        })
+        .collect()
+}
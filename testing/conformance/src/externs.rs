@@ -1,6 +1,6 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
-use fvm::externs::{Chain, Consensus, Externs, Rand};
+use fvm::externs::{Chain, Consensus, Externs, Rand, Verifier};
 use fvm_shared::clock::ChainEpoch;
 use fvm_shared::consensus::ConsensusFault;
 
@@ -24,6 +24,8 @@ impl TestExterns {
 
 impl Externs for TestExterns {}
 
+impl Verifier for TestExterns {}
+
 impl Rand for TestExterns {
     fn get_chain_randomness(
         &self,
@@ -12,13 +12,14 @@ use fvm::kernel::*;
 use fvm::machine::limiter::MemoryLimiter;
 use fvm::machine::{DefaultMachine, Machine, MachineContext, Manifest, NetworkConfig};
 use fvm::state_tree::{ActorState, StateTree};
+use fvm::trace::SyscallRecord;
 use fvm::DefaultKernel;
-use fvm_ipld_blockstore::MemoryBlockstore;
+use fvm_ipld_blockstore::{BlockstoreStats, MemoryBlockstore};
 use fvm_shared::address::Address;
 use fvm_shared::clock::ChainEpoch;
 use fvm_shared::consensus::ConsensusFault;
 use fvm_shared::crypto::signature::{
-    SignatureType, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
+    SignatureType, BLS_PUB_LEN, BLS_SIG_LEN, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
 };
 use fvm_shared::econ::TokenAmount;
 use fvm_shared::event::StampedEvent;
@@ -26,7 +27,7 @@ use fvm_shared::piece::PieceInfo;
 use fvm_shared::randomness::RANDOMNESS_LENGTH;
 use fvm_shared::sector::{
     AggregateSealVerifyProofAndInfos, RegisteredSealProof, ReplicaUpdateInfo, SealVerifyInfo,
-    WindowPoStVerifyInfo,
+    WindowPoStVerifyInfo, WinningPoStVerifyInfo,
 };
 use fvm_shared::sys::SendFlags;
 use fvm_shared::version::NetworkVersion;
@@ -160,6 +161,10 @@ where
         self.machine.state_tree_mut()
     }
 
+    fn blockstore_stats(&self) -> Option<BlockstoreStats> {
+        self.machine.blockstore_stats()
+    }
+
     fn into_store(self) -> Self::Blockstore {
         self.machine.into_store()
     }
@@ -233,6 +238,17 @@ where
             .send::<TestKernel<K>>(from, to, method, params, value, gas_limit, read_only)
     }
 
+    fn upgrade_actor<K: Kernel<CallManager = Self>>(
+        &mut self,
+        caller: ActorID,
+        actor_id: ActorID,
+        new_code_cid: Cid,
+        params: Option<Block>,
+    ) -> Result<InvocationResult> {
+        self.0
+            .upgrade_actor::<TestKernel<K>>(caller, actor_id, new_code_cid, params)
+    }
+
     fn with_transaction(
         &mut self,
         f: impl FnOnce(&mut Self) -> Result<InvocationResult>,
@@ -318,6 +334,31 @@ where
         self.0.invocation_count()
     }
 
+    fn call_stack(&self) -> &[ActorID] {
+        self.0.call_stack()
+    }
+
+    fn record_block_write(&mut self) -> Result<()> {
+        self.0.record_block_write()
+    }
+
+    fn put_block(&mut self, k: Cid, data: &[u8]) -> Result<()> {
+        self.0.put_block(k, data)
+    }
+
+    fn get_block(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        self.0.get_block(k)
+    }
+
+    #[cfg(feature = "m2-native")]
+    fn record_code_compiled(&mut self, code: Cid) {
+        self.0.record_code_compiled(code)
+    }
+
+    fn record_syscall(&mut self, record: SyscallRecord) {
+        self.0.record_syscall(record)
+    }
+
     fn limiter_mut(&mut self) -> &mut <Self::Machine as Machine>::Limiter {
         self.0.limiter_mut()
     }
@@ -440,6 +481,11 @@ where
         Ok(())
     }
 
+    #[cfg(feature = "m2-native")]
+    fn install_actor_code(&mut self, wasm: &[u8]) -> Result<Cid> {
+        self.0.install_actor_code(wasm)
+    }
+
     fn balance_of(&self, actor_id: ActorID) -> Result<TokenAmount> {
         self.0.balance_of(actor_id)
     }
@@ -447,6 +493,10 @@ where
     fn lookup_delegated_address(&self, actor_id: ActorID) -> Result<Option<Address>> {
         self.0.lookup_delegated_address(actor_id)
     }
+
+    fn upgrade_actor(&mut self, new_code_cid: Cid, params_id: BlockId) -> Result<SendResult> {
+        self.0.upgrade_actor(new_code_cid, params_id)
+    }
 }
 
 impl<M, C, K> IpldBlockOps for TestKernel<K>
@@ -520,6 +570,17 @@ where
             .verify_signature(sig_type, signature, signer, plaintext)
     }
 
+    // forwarded
+    fn verify_aggregate_signature(
+        &self,
+        aggregate_sig: &[u8; BLS_SIG_LEN],
+        pub_keys: &[[u8; BLS_PUB_LEN]],
+        plaintexts: &[&[u8]],
+    ) -> Result<bool> {
+        self.0
+            .verify_aggregate_signature(aggregate_sig, pub_keys, plaintexts)
+    }
+
     // forwarded
     fn recover_secp_public_key(
         &self,
@@ -548,6 +609,13 @@ where
         Ok(true)
     }
 
+    // NOT forwarded
+    fn verify_winning_post(&self, vi: &WinningPoStVerifyInfo) -> Result<bool> {
+        let charge = self.1.price_list.on_verify_winning_post(vi);
+        let _ = self.0.charge_gas(&charge.name, charge.total())?;
+        Ok(true)
+    }
+
     // NOT forwarded
     fn verify_consensus_fault(
         &self,
@@ -588,13 +656,25 @@ where
         self.0.log(msg)
     }
 
+    fn log_at(&self, level: log::Level, msg: String) {
+        self.0.log_at(level, msg)
+    }
+
     fn debug_enabled(&self) -> bool {
         self.0.debug_enabled()
     }
 
+    fn call_stack(&self) -> Vec<ActorID> {
+        self.0.call_stack()
+    }
+
     fn store_artifact(&self, name: &str, data: &[u8]) -> Result<()> {
         self.0.store_artifact(name, data)
     }
+
+    fn record_syscall(&mut self, record: SyscallRecord) {
+        self.0.record_syscall(record)
+    }
 }
 
 impl<M, C, K> GasOps for TestKernel<K>
@@ -611,6 +691,10 @@ where
         self.0.charge_gas(name, compute)
     }
 
+    fn record_gas_milestone(&self, name: &str) -> Result<()> {
+        self.0.record_gas_milestone(name)
+    }
+
     fn price_list(&self) -> &PriceList {
         self.0.price_list()
     }
@@ -629,6 +713,10 @@ where
     fn msg_context(&self) -> Result<fvm_shared::sys::out::vm::MessageContext> {
         self.0.msg_context()
     }
+
+    fn msg_caller_code_cid(&self) -> Result<Cid> {
+        self.0.msg_caller_code_cid()
+    }
 }
 
 impl<M, C, K> NetworkOps for TestKernel<K>
@@ -683,6 +771,10 @@ where
         self.0.root()
     }
 
+    fn self_code_cid(&self) -> Result<Cid> {
+        self.0.self_code_cid()
+    }
+
     fn set_root(&mut self, root: Cid) -> Result<()> {
         self.0.set_root(root)
     }
@@ -0,0 +1,853 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! A fault-injecting kernel for testing the robustness of actors and clients.
+//!
+//! The [`ChaosKernel`] wraps another kernel and, as configured by a [`ChaosConfig`], fails specific
+//! syscalls, corrupts the data returned by syscalls, or misprices gas. Similar to Lotus's chaos
+//! actor, but at the kernel layer, so it works with unmodified actors.
+//!
+//! The configuration is attached to the machine (see [`ChaosMachine`]) and the kernels of nested
+//! calls are wrapped by the [`ChaosCallManager`], so faults apply to every call made by a message.
+//! Use [`Tester::instantiate_chaos_machine`](crate::tester::Tester::instantiate_chaos_machine) to
+//! create an executor.
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use cid::Cid;
+use fvm::call_manager::{CallManager, DefaultCallManager, FinishRet, InvocationResult};
+use fvm::engine::Engine;
+use fvm::executor::DefaultExecutor;
+use fvm::gas::{Gas, GasTimer, GasTracker, PriceList};
+use fvm::kernel::*;
+use fvm::machine::{DefaultMachine, Machine, MachineContext, Manifest};
+use fvm::state_tree::{ActorState, StateTree};
+use fvm::trace::SyscallRecord;
+use fvm::DefaultKernel;
+use fvm_ipld_blockstore::BlockstoreStats;
+use fvm_shared::address::Address;
+use fvm_shared::clock::ChainEpoch;
+use fvm_shared::consensus::ConsensusFault;
+use fvm_shared::crypto::signature::{
+    SignatureType, BLS_PUB_LEN, BLS_SIG_LEN, SECP_PUB_LEN, SECP_SIG_LEN, SECP_SIG_MESSAGE_HASH_SIZE,
+};
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ErrorNumber;
+use fvm_shared::event::StampedEvent;
+use fvm_shared::piece::PieceInfo;
+use fvm_shared::randomness::RANDOMNESS_LENGTH;
+use fvm_shared::sector::{
+    AggregateSealVerifyProofAndInfos, RegisteredSealProof, ReplicaUpdateInfo, SealVerifyInfo,
+    WindowPoStVerifyInfo, WinningPoStVerifyInfo,
+};
+use fvm_shared::sys::SendFlags;
+use fvm_shared::{ActorID, MethodNum};
+use multihash::MultihashGeneric;
+
+/// An executor whose kernels inject the faults configured by a [`ChaosConfig`].
+pub type ChaosExecutor<B, E> = DefaultExecutor<
+    ChaosKernel<
+        DefaultKernel<ChaosCallManager<DefaultCallManager<ChaosMachine<DefaultMachine<B, E>>>>>,
+    >,
+>;
+
+/// The faults injected by the [`ChaosKernel`]. Syscalls are identified by the name of the
+/// corresponding kernel method (e.g., `block_open` or `get_randomness_from_beacon`).
+#[derive(Clone, Debug, Default)]
+pub struct ChaosConfig {
+    /// Syscalls that fail, along with the error they fail with.
+    pub failures: HashMap<&'static str, ErrorNumber>,
+    /// Whether to corrupt the data returned by syscalls that read blocks or randomness.
+    pub corrupt_data: bool,
+    /// The percentage of the normal price charged for the gas charges made by syscalls. Below 100
+    /// undercharges, above 100 overcharges.
+    pub gas_percent: Option<u64>,
+}
+
+impl ChaosConfig {
+    /// Makes the given syscall fail with the given error.
+    pub fn fail_syscall(&mut self, syscall: &'static str, error: ErrorNumber) -> &mut Self {
+        self.failures.insert(syscall, error);
+        self
+    }
+
+    /// Corrupts the data returned by syscalls that read blocks or randomness.
+    pub fn corrupt_data(&mut self) -> &mut Self {
+        self.corrupt_data = true;
+        self
+    }
+
+    /// Charges the given percentage of the normal price for the gas charges made by syscalls.
+    pub fn charge_gas_percent(&mut self, percent: u64) -> &mut Self {
+        self.gas_percent = Some(percent);
+        self
+    }
+
+    /// Applies the configured gas mispricing, if any.
+    fn misprice(&self, gas: Gas) -> Gas {
+        match self.gas_percent {
+            Some(percent) => Gas::from_milligas(gas.as_milligas().saturating_mul(percent) / 100),
+            None => gas,
+        }
+    }
+}
+
+/// A machine carrying a [`ChaosConfig`] for the kernels executing on it.
+pub struct ChaosMachine<M> {
+    pub machine: M,
+    pub config: Arc<ChaosConfig>,
+}
+
+impl<M> ChaosMachine<M> {
+    pub fn new(machine: M, config: ChaosConfig) -> Self {
+        ChaosMachine {
+            machine,
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl<M> Machine for ChaosMachine<M>
+where
+    M: Machine,
+{
+    type Blockstore = M::Blockstore;
+    type Externs = M::Externs;
+    type Limiter = M::Limiter;
+
+    fn blockstore(&self) -> &Self::Blockstore {
+        self.machine.blockstore()
+    }
+
+    fn context(&self) -> &MachineContext {
+        self.machine.context()
+    }
+
+    fn externs(&self) -> &Self::Externs {
+        self.machine.externs()
+    }
+
+    fn builtin_actors(&self) -> &Manifest {
+        self.machine.builtin_actors()
+    }
+
+    fn state_tree(&self) -> &StateTree<Self::Blockstore> {
+        self.machine.state_tree()
+    }
+
+    fn state_tree_mut(&mut self) -> &mut StateTree<Self::Blockstore> {
+        self.machine.state_tree_mut()
+    }
+
+    fn blockstore_stats(&self) -> Option<BlockstoreStats> {
+        self.machine.blockstore_stats()
+    }
+
+    fn into_store(self) -> Self::Blockstore {
+        self.machine.into_store()
+    }
+
+    fn flush(&mut self) -> Result<Cid> {
+        self.machine.flush()
+    }
+
+    fn machine_id(&self) -> &str {
+        self.machine.machine_id()
+    }
+
+    fn new_limiter(&self) -> Self::Limiter {
+        self.machine.new_limiter()
+    }
+}
+
+/// A call manager that wraps the kernels of all (nested) calls in a [`ChaosKernel`], and misprices
+/// the gas charged by their syscalls.
+// NOTE: For now, this _must_ be transparent because we transmute a pointer.
+#[repr(transparent)]
+pub struct ChaosCallManager<C: CallManager>(pub C);
+
+impl<M, C> CallManager for ChaosCallManager<C>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+{
+    type Machine = C::Machine;
+
+    fn new(
+        machine: Self::Machine,
+        engine: Engine,
+        gas_limit: u64,
+        origin: ActorID,
+        origin_address: Address,
+        receiver: Option<ActorID>,
+        receiver_address: Address,
+        nonce: u64,
+        gas_premium: TokenAmount,
+    ) -> Self {
+        ChaosCallManager(C::new(
+            machine,
+            engine,
+            gas_limit,
+            origin,
+            origin_address,
+            receiver,
+            receiver_address,
+            nonce,
+            gas_premium,
+        ))
+    }
+
+    fn send<K: Kernel<CallManager = Self>>(
+        &mut self,
+        from: ActorID,
+        to: Address,
+        method: MethodNum,
+        params: Option<Block>,
+        value: &TokenAmount,
+        gas_limit: Option<Gas>,
+        read_only: bool,
+    ) -> Result<InvocationResult> {
+        self.0
+            .send::<ChaosKernel<K>>(from, to, method, params, value, gas_limit, read_only)
+    }
+
+    fn upgrade_actor<K: Kernel<CallManager = Self>>(
+        &mut self,
+        caller: ActorID,
+        actor_id: ActorID,
+        new_code_cid: Cid,
+        params: Option<Block>,
+    ) -> Result<InvocationResult> {
+        self.0
+            .upgrade_actor::<ChaosKernel<K>>(caller, actor_id, new_code_cid, params)
+    }
+
+    fn with_transaction(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<InvocationResult>,
+    ) -> Result<InvocationResult> {
+        // This transmute is _safe_ because this type is "repr transparent".
+        let inner_ptr = &mut self.0 as *mut C;
+        self.0.with_transaction(|inner: &mut C| unsafe {
+            // Make sure that we've got the right pointer. Otherwise, this cast definitely isn't
+            // safe.
+            assert_eq!(inner_ptr, inner as *mut C);
+
+            // Ok, we got the pointer we expected, casting back to the interceptor is safe.
+            f(&mut *(inner as *mut C as *mut Self))
+        })
+    }
+
+    fn finish(self) -> (Result<FinishRet>, Self::Machine) {
+        self.0.finish()
+    }
+
+    fn machine(&self) -> &Self::Machine {
+        self.0.machine()
+    }
+
+    fn machine_mut(&mut self) -> &mut Self::Machine {
+        self.0.machine_mut()
+    }
+
+    fn engine(&self) -> &Engine {
+        self.0.engine()
+    }
+
+    fn gas_tracker(&self) -> &GasTracker {
+        self.0.gas_tracker()
+    }
+
+    fn gas_premium(&self) -> &TokenAmount {
+        self.0.gas_premium()
+    }
+
+    fn origin(&self) -> ActorID {
+        self.0.origin()
+    }
+
+    fn nonce(&self) -> u64 {
+        self.0.nonce()
+    }
+
+    fn next_actor_address(&self) -> Address {
+        self.0.next_actor_address()
+    }
+
+    fn create_actor(
+        &mut self,
+        code_id: Cid,
+        actor_id: ActorID,
+        delegated_address: Option<Address>,
+    ) -> Result<()> {
+        self.0.create_actor(code_id, actor_id, delegated_address)
+    }
+
+    fn price_list(&self) -> &fvm::gas::PriceList {
+        self.0.price_list()
+    }
+
+    fn context(&self) -> &MachineContext {
+        self.0.context()
+    }
+
+    fn blockstore(&self) -> &<Self::Machine as Machine>::Blockstore {
+        self.0.blockstore()
+    }
+
+    fn externs(&self) -> &<Self::Machine as Machine>::Externs {
+        self.0.externs()
+    }
+
+    fn charge_gas(&self, mut charge: fvm::gas::GasCharge) -> Result<GasTimer> {
+        let config = &self.0.machine().config;
+        charge.compute_gas = config.misprice(charge.compute_gas);
+        charge.other_gas = config.misprice(charge.other_gas);
+        self.0.charge_gas(charge)
+    }
+
+    fn invocation_count(&self) -> u64 {
+        self.0.invocation_count()
+    }
+
+    fn call_stack(&self) -> &[ActorID] {
+        self.0.call_stack()
+    }
+
+    fn record_block_write(&mut self) -> Result<()> {
+        self.0.record_block_write()
+    }
+
+    fn put_block(&mut self, k: Cid, data: &[u8]) -> Result<()> {
+        self.0.put_block(k, data)
+    }
+
+    fn get_block(&self, k: &Cid) -> Result<Option<Vec<u8>>> {
+        self.0.get_block(k)
+    }
+
+    #[cfg(feature = "m2-native")]
+    fn record_code_compiled(&mut self, code: Cid) {
+        self.0.record_code_compiled(code)
+    }
+
+    fn record_syscall(&mut self, record: SyscallRecord) {
+        self.0.record_syscall(record)
+    }
+
+    fn limiter_mut(&mut self) -> &mut <Self::Machine as Machine>::Limiter {
+        self.0.limiter_mut()
+    }
+
+    fn append_event(&mut self, evt: StampedEvent) {
+        self.0.append_event(evt)
+    }
+
+    fn resolve_address(&self, address: &Address) -> Result<Option<ActorID>> {
+        self.0.resolve_address(address)
+    }
+
+    fn get_actor(&self, id: ActorID) -> Result<Option<ActorState>> {
+        self.0.get_actor(id)
+    }
+
+    fn set_actor(&mut self, id: ActorID, state: ActorState) -> Result<()> {
+        self.0.set_actor(id, state)
+    }
+
+    fn delete_actor(&mut self, id: ActorID) -> Result<()> {
+        self.0.delete_actor(id)
+    }
+
+    fn transfer(&mut self, from: ActorID, to: ActorID, value: &TokenAmount) -> Result<()> {
+        self.0.transfer(from, to, value)
+    }
+}
+
+/// A kernel that injects the faults configured by the machine's [`ChaosConfig`] into the syscalls
+/// of the wrapped kernel.
+pub struct ChaosKernel<K>(pub K, Arc<ChaosConfig>);
+
+impl<K: Kernel> ChaosKernel<K> {
+    /// Fails if the syscall is configured to fail.
+    fn chaos(&self, syscall: &'static str) -> Result<()> {
+        match self.1.failures.get(syscall) {
+            Some(&error) => Err(ExecutionError::Syscall(SyscallError::new(
+                error,
+                format!("chaos: injected {} failure", syscall),
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Flips the first byte of the data, if data corruption is enabled.
+    fn corrupt(&self, data: &mut [u8]) {
+        if self.1.corrupt_data {
+            if let Some(b) = data.first_mut() {
+                *b ^= 0xff;
+            }
+        }
+    }
+}
+
+impl<M, C, K> Kernel for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    type CallManager = C;
+
+    fn into_inner(self) -> (Self::CallManager, BlockRegistry)
+    where
+        Self: Sized,
+    {
+        let (cm, br) = self.0.into_inner();
+        (cm.0, br)
+    }
+
+    fn new(
+        mgr: Self::CallManager,
+        blocks: BlockRegistry,
+        caller: ActorID,
+        actor_id: ActorID,
+        method: MethodNum,
+        value_received: TokenAmount,
+        read_only: bool,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        let config = mgr.machine().config.clone();
+
+        ChaosKernel(
+            K::new(
+                ChaosCallManager(mgr),
+                blocks,
+                caller,
+                actor_id,
+                method,
+                value_received,
+                read_only,
+            ),
+            config,
+        )
+    }
+
+    fn machine(&self) -> &<Self::CallManager as CallManager>::Machine {
+        self.0.machine()
+    }
+}
+
+impl<M, C, K> ActorOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn resolve_address(&self, address: &Address) -> Result<ActorID> {
+        self.chaos("resolve_address")?;
+        self.0.resolve_address(address)
+    }
+
+    fn get_actor_code_cid(&self, id: ActorID) -> Result<Cid> {
+        self.chaos("get_actor_code_cid")?;
+        self.0.get_actor_code_cid(id)
+    }
+
+    fn next_actor_address(&self) -> Result<Address> {
+        self.chaos("next_actor_address")?;
+        self.0.next_actor_address()
+    }
+
+    fn create_actor(
+        &mut self,
+        code_id: Cid,
+        actor_id: ActorID,
+        delegated_address: Option<Address>,
+    ) -> Result<()> {
+        self.chaos("create_actor")?;
+        self.0.create_actor(code_id, actor_id, delegated_address)
+    }
+
+    fn get_builtin_actor_type(&self, code_cid: &Cid) -> Result<u32> {
+        self.chaos("get_builtin_actor_type")?;
+        self.0.get_builtin_actor_type(code_cid)
+    }
+
+    fn get_code_cid_for_type(&self, typ: u32) -> Result<Cid> {
+        self.chaos("get_code_cid_for_type")?;
+        self.0.get_code_cid_for_type(typ)
+    }
+
+    #[cfg(feature = "m2-native")]
+    fn install_actor(&mut self, code_id: Cid) -> Result<()> {
+        self.chaos("install_actor")?;
+        self.0.install_actor(code_id)
+    }
+
+    #[cfg(feature = "m2-native")]
+    fn install_actor_code(&mut self, wasm: &[u8]) -> Result<Cid> {
+        self.chaos("install_actor_code")?;
+        self.0.install_actor_code(wasm)
+    }
+
+    fn balance_of(&self, actor_id: ActorID) -> Result<TokenAmount> {
+        self.chaos("balance_of")?;
+        self.0.balance_of(actor_id)
+    }
+
+    fn lookup_delegated_address(&self, actor_id: ActorID) -> Result<Option<Address>> {
+        self.chaos("lookup_delegated_address")?;
+        self.0.lookup_delegated_address(actor_id)
+    }
+
+    fn upgrade_actor(&mut self, new_code_cid: Cid, params_id: BlockId) -> Result<SendResult> {
+        self.chaos("upgrade_actor")?;
+        self.0.upgrade_actor(new_code_cid, params_id)
+    }
+}
+
+impl<M, C, K> IpldBlockOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn block_open(&mut self, cid: &Cid) -> Result<(BlockId, BlockStat)> {
+        self.chaos("block_open")?;
+        self.0.block_open(cid)
+    }
+
+    fn block_create(&mut self, codec: u64, data: &[u8]) -> Result<BlockId> {
+        self.chaos("block_create")?;
+        self.0.block_create(codec, data)
+    }
+
+    fn block_link(&mut self, id: BlockId, hash_fun: u64, hash_len: u32) -> Result<Cid> {
+        self.chaos("block_link")?;
+        self.0.block_link(id, hash_fun, hash_len)
+    }
+
+    fn block_read(&self, id: BlockId, offset: u32, buf: &mut [u8]) -> Result<i32> {
+        self.chaos("block_read")?;
+        let ret = self.0.block_read(id, offset, buf)?;
+        self.corrupt(buf);
+        Ok(ret)
+    }
+
+    fn block_stat(&self, id: BlockId) -> Result<BlockStat> {
+        self.chaos("block_stat")?;
+        self.0.block_stat(id)
+    }
+}
+
+impl<M, C, K> CircSupplyOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn total_fil_circ_supply(&self) -> Result<TokenAmount> {
+        self.chaos("total_fil_circ_supply")?;
+        self.0.total_fil_circ_supply()
+    }
+}
+
+impl<M, C, K> CryptoOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn hash(&self, code: u64, data: &[u8]) -> Result<MultihashGeneric<64>> {
+        self.chaos("hash")?;
+        self.0.hash(code, data)
+    }
+
+    fn compute_unsealed_sector_cid(
+        &self,
+        proof_type: RegisteredSealProof,
+        pieces: &[PieceInfo],
+    ) -> Result<Cid> {
+        self.chaos("compute_unsealed_sector_cid")?;
+        self.0.compute_unsealed_sector_cid(proof_type, pieces)
+    }
+
+    fn verify_signature(
+        &self,
+        sig_type: SignatureType,
+        signature: &[u8],
+        signer: &Address,
+        plaintext: &[u8],
+    ) -> Result<bool> {
+        self.chaos("verify_signature")?;
+        self.0
+            .verify_signature(sig_type, signature, signer, plaintext)
+    }
+
+    fn verify_aggregate_signature(
+        &self,
+        aggregate_sig: &[u8; BLS_SIG_LEN],
+        pub_keys: &[[u8; BLS_PUB_LEN]],
+        plaintexts: &[&[u8]],
+    ) -> Result<bool> {
+        self.chaos("verify_aggregate_signature")?;
+        self.0
+            .verify_aggregate_signature(aggregate_sig, pub_keys, plaintexts)
+    }
+
+    fn recover_secp_public_key(
+        &self,
+        hash: &[u8; SECP_SIG_MESSAGE_HASH_SIZE],
+        signature: &[u8; SECP_SIG_LEN],
+    ) -> Result<[u8; SECP_PUB_LEN]> {
+        self.chaos("recover_secp_public_key")?;
+        self.0.recover_secp_public_key(hash, signature)
+    }
+
+    fn batch_verify_seals(&self, vis: &[SealVerifyInfo]) -> Result<Vec<bool>> {
+        self.chaos("batch_verify_seals")?;
+        self.0.batch_verify_seals(vis)
+    }
+
+    fn verify_seal(&self, vi: &SealVerifyInfo) -> Result<bool> {
+        self.chaos("verify_seal")?;
+        self.0.verify_seal(vi)
+    }
+
+    fn verify_post(&self, vi: &WindowPoStVerifyInfo) -> Result<bool> {
+        self.chaos("verify_post")?;
+        self.0.verify_post(vi)
+    }
+
+    fn verify_winning_post(&self, vi: &WinningPoStVerifyInfo) -> Result<bool> {
+        self.chaos("verify_winning_post")?;
+        self.0.verify_winning_post(vi)
+    }
+
+    fn verify_consensus_fault(
+        &self,
+        h1: &[u8],
+        h2: &[u8],
+        extra: &[u8],
+    ) -> Result<Option<ConsensusFault>> {
+        self.chaos("verify_consensus_fault")?;
+        self.0.verify_consensus_fault(h1, h2, extra)
+    }
+
+    fn verify_aggregate_seals(&self, agg: &AggregateSealVerifyProofAndInfos) -> Result<bool> {
+        self.chaos("verify_aggregate_seals")?;
+        self.0.verify_aggregate_seals(agg)
+    }
+
+    fn verify_replica_update(&self, rep: &ReplicaUpdateInfo) -> Result<bool> {
+        self.chaos("verify_replica_update")?;
+        self.0.verify_replica_update(rep)
+    }
+}
+
+impl<M, C, K> DebugOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn log(&self, msg: String) {
+        self.0.log(msg)
+    }
+
+    fn log_at(&self, level: log::Level, msg: String) {
+        self.0.log_at(level, msg)
+    }
+
+    fn debug_enabled(&self) -> bool {
+        self.0.debug_enabled()
+    }
+
+    fn call_stack(&self) -> Vec<ActorID> {
+        self.0.call_stack()
+    }
+
+    fn store_artifact(&self, name: &str, data: &[u8]) -> Result<()> {
+        self.chaos("store_artifact")?;
+        self.0.store_artifact(name, data)
+    }
+
+    fn record_syscall(&mut self, record: SyscallRecord) {
+        self.0.record_syscall(record)
+    }
+}
+
+impl<M, C, K> GasOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn gas_used(&self) -> Gas {
+        self.0.gas_used()
+    }
+
+    fn charge_gas(&self, name: &str, compute: Gas) -> Result<GasTimer> {
+        self.chaos("charge_gas")?;
+        self.0.charge_gas(name, self.1.misprice(compute))
+    }
+
+    fn record_gas_milestone(&self, name: &str) -> Result<()> {
+        self.chaos("record_gas_milestone")?;
+        self.0.record_gas_milestone(name)
+    }
+
+    fn price_list(&self) -> &PriceList {
+        self.0.price_list()
+    }
+
+    fn gas_available(&self) -> Gas {
+        self.0.gas_available()
+    }
+}
+
+impl<M, C, K> MessageOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn msg_context(&self) -> Result<fvm_shared::sys::out::vm::MessageContext> {
+        self.chaos("msg_context")?;
+        self.0.msg_context()
+    }
+
+    fn msg_caller_code_cid(&self) -> Result<Cid> {
+        self.chaos("msg_caller_code_cid")?;
+        self.0.msg_caller_code_cid()
+    }
+}
+
+impl<M, C, K> NetworkOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn network_context(&self) -> Result<fvm_shared::sys::out::network::NetworkContext> {
+        self.chaos("network_context")?;
+        self.0.network_context()
+    }
+
+    fn tipset_cid(&self, epoch: ChainEpoch) -> Result<Cid> {
+        self.chaos("tipset_cid")?;
+        self.0.tipset_cid(epoch)
+    }
+}
+
+impl<M, C, K> RandomnessOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn get_randomness_from_tickets(
+        &self,
+        personalization: i64,
+        rand_epoch: ChainEpoch,
+        entropy: &[u8],
+    ) -> Result<[u8; RANDOMNESS_LENGTH]> {
+        self.chaos("get_randomness_from_tickets")?;
+        let mut randomness =
+            self.0
+                .get_randomness_from_tickets(personalization, rand_epoch, entropy)?;
+        self.corrupt(&mut randomness);
+        Ok(randomness)
+    }
+
+    fn get_randomness_from_beacon(
+        &self,
+        personalization: i64,
+        rand_epoch: ChainEpoch,
+        entropy: &[u8],
+    ) -> Result<[u8; RANDOMNESS_LENGTH]> {
+        self.chaos("get_randomness_from_beacon")?;
+        let mut randomness =
+            self.0
+                .get_randomness_from_beacon(personalization, rand_epoch, entropy)?;
+        self.corrupt(&mut randomness);
+        Ok(randomness)
+    }
+}
+
+impl<M, C, K> SelfOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn root(&self) -> Result<Cid> {
+        self.chaos("root")?;
+        self.0.root()
+    }
+
+    fn self_code_cid(&self) -> Result<Cid> {
+        self.chaos("self_code_cid")?;
+        self.0.self_code_cid()
+    }
+
+    fn set_root(&mut self, root: Cid) -> Result<()> {
+        self.chaos("set_root")?;
+        self.0.set_root(root)
+    }
+
+    fn current_balance(&self) -> Result<TokenAmount> {
+        self.chaos("current_balance")?;
+        self.0.current_balance()
+    }
+
+    fn self_destruct(&mut self, beneficiary: &Address) -> Result<()> {
+        self.chaos("self_destruct")?;
+        self.0.self_destruct(beneficiary)
+    }
+}
+
+impl<M, C, K> SendOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn send(
+        &mut self,
+        recipient: &Address,
+        method: u64,
+        params: BlockId,
+        value: &TokenAmount,
+        gas_limit: Option<Gas>,
+        flags: SendFlags,
+    ) -> Result<SendResult> {
+        self.chaos("send")?;
+        self.0
+            .send(recipient, method, params, value, gas_limit, flags)
+    }
+}
+
+impl<K> LimiterOps for ChaosKernel<K>
+where
+    K: LimiterOps,
+{
+    type Limiter = K::Limiter;
+
+    fn limiter_mut(&mut self) -> &mut Self::Limiter {
+        self.0.limiter_mut()
+    }
+}
+
+impl<M, C, K> EventOps for ChaosKernel<K>
+where
+    M: Machine,
+    C: CallManager<Machine = ChaosMachine<M>>,
+    K: Kernel<CallManager = ChaosCallManager<C>>,
+{
+    fn emit_event(&mut self, raw_evt: &[u8]) -> Result<()> {
+        self.chaos("emit_event")?;
+        self.0.emit_event(raw_evt)
+    }
+}
@@ -1,16 +1,20 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
 use cid::Cid;
-use fvm::externs::{Chain, Consensus, Externs, Rand};
+use fvm::externs::{Chain, Consensus, Externs, Rand, Verifier};
 use fvm_ipld_encoding::DAG_CBOR;
 use fvm_shared::IDENTITY_HASH;
 use multihash::Multihash;
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
+
+#[derive(Clone, Copy)]
 pub struct DummyExterns;
 
 impl Externs for DummyExterns {}
 
+impl Verifier for DummyExterns {}
+
 impl Rand for DummyExterns {
     fn get_chain_randomness(
         &self,
@@ -2,7 +2,9 @@
 // SPDX-License-Identifier: Apache-2.0, MIT
 mod builtin;
 pub mod bundle;
+pub mod chaos;
 pub mod dummy;
 pub mod error;
+pub mod matrix;
 pub mod tester;
 pub mod testkit;
@@ -0,0 +1,287 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Runs the same messages under multiple network versions, checking that each version produces
+//! the expected outcome.
+//!
+//! This is meant to catch accidental behavior drift when adding upgrade-gated logic: a
+//! [`MatrixCase`] states what _should_ happen (optionally overridden for specific versions), and
+//! [`VersionMatrix::run`] reports every case and version where that doesn't hold. Unless a case
+//! pins the gas used, the gas must be the same in all versions sharing an expectation.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::{anyhow, Context, Result};
+use fvm::executor::{ApplyKind, Executor};
+use fvm::externs::Externs;
+use fvm::gas::supported_network_versions;
+use fvm::machine::SUPPORTED_VERSIONS;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_shared::error::ExitCode;
+use fvm_shared::message::Message;
+use fvm_shared::version::NetworkVersion;
+
+use crate::tester::Tester;
+
+/// The expected outcome of applying a [`MatrixCase`] message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Expectation {
+    /// The expected exit code.
+    pub exit_code: ExitCode,
+    /// The expected gas used. If `None`, the gas used must instead be the same in all versions
+    /// sharing this expectation.
+    pub gas_used: Option<u64>,
+}
+
+impl Expectation {
+    /// Expects the given exit code, and the same gas usage across versions.
+    pub fn exit_code(exit_code: ExitCode) -> Self {
+        Expectation {
+            exit_code,
+            gas_used: None,
+        }
+    }
+
+    /// Expects a successful execution, and the same gas usage across versions.
+    pub fn ok() -> Self {
+        Self::exit_code(ExitCode::OK)
+    }
+
+    /// Additionally expects exactly `gas_used` gas to be used.
+    pub fn with_gas(mut self, gas_used: u64) -> Self {
+        self.gas_used = Some(gas_used);
+        self
+    }
+}
+
+/// A message to apply under every network version of a [`VersionMatrix`].
+///
+/// Cases are applied in order (on the same machine) so later cases must account for the effects
+/// of earlier ones, e.g., by incrementing the sender's sequence.
+#[derive(Debug, Clone)]
+pub struct MatrixCase {
+    /// A name identifying the case in reports.
+    pub name: String,
+    /// The (explicit) message to apply.
+    pub message: Message,
+    /// The outcome expected in all versions without an override.
+    pub expected: Expectation,
+    /// Per-version overrides of the expected outcome.
+    pub overrides: BTreeMap<NetworkVersion, Expectation>,
+}
+
+impl MatrixCase {
+    /// Creates a case expecting the same outcome in all versions.
+    pub fn new(name: impl Into<String>, message: Message, expected: Expectation) -> Self {
+        MatrixCase {
+            name: name.into(),
+            message,
+            expected,
+            overrides: BTreeMap::new(),
+        }
+    }
+
+    /// Expects a different outcome in the given network version (e.g., because it changes the
+    /// behavior being tested).
+    pub fn with_override(mut self, nv: NetworkVersion, expected: Expectation) -> Self {
+        self.overrides.insert(nv, expected);
+        self
+    }
+
+    /// Returns the outcome expected in the given network version.
+    pub fn expected(&self, nv: NetworkVersion) -> Expectation {
+        self.overrides.get(&nv).copied().unwrap_or(self.expected)
+    }
+}
+
+/// The actual outcome of applying a [`MatrixCase`] message under a specific network version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub exit_code: ExitCode,
+    pub gas_used: u64,
+}
+
+/// A difference between the expected and actual behavior of a [`MatrixCase`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// The message exited with an unexpected exit code, or used an unexpected amount of gas.
+    Outcome {
+        case: String,
+        nv: NetworkVersion,
+        expected: Expectation,
+        actual: Outcome,
+    },
+    /// The message used different amounts of gas in versions expected to behave identically.
+    GasDrift {
+        case: String,
+        gas_used: BTreeMap<NetworkVersion, u64>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Outcome {
+                case,
+                nv,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "{case} @ nv{nv}: expected exit code {}, got {}",
+                    expected.exit_code, actual.exit_code
+                )?;
+                match expected.gas_used {
+                    Some(gas) => write!(f, "; expected gas {gas}, got {}", actual.gas_used),
+                    None => write!(f, "; used gas {}", actual.gas_used),
+                }
+            }
+            Mismatch::GasDrift { case, gas_used } => {
+                write!(f, "{case}: gas used differs between versions:")?;
+                for (nv, gas) in gas_used {
+                    write!(f, " nv{nv}={gas}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The outcomes of running a [`VersionMatrix`].
+#[derive(Debug, Clone, Default)]
+pub struct MatrixReport {
+    /// The outcome of each case (by name) in each network version.
+    pub outcomes: BTreeMap<String, BTreeMap<NetworkVersion, Outcome>>,
+    /// All differences from the expected behavior.
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl MatrixReport {
+    /// Returns an error listing all mismatches, if any.
+    pub fn check(&self) -> Result<()> {
+        if self.mismatches.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<String> = self.mismatches.iter().map(|m| m.to_string()).collect();
+        Err(anyhow!(
+            "{} network version matrix mismatch(es):\n  {}",
+            lines.len(),
+            lines.join("\n  ")
+        ))
+    }
+}
+
+/// A set of network versions to run [`MatrixCase`]s under.
+#[derive(Debug, Clone)]
+pub struct VersionMatrix {
+    versions: Vec<NetworkVersion>,
+}
+
+impl VersionMatrix {
+    /// Creates a matrix over the given network versions.
+    pub fn new(versions: impl IntoIterator<Item = NetworkVersion>) -> Self {
+        let mut versions: Vec<_> = versions.into_iter().collect();
+        versions.sort();
+        versions.dedup();
+        VersionMatrix { versions }
+    }
+
+    /// Creates a matrix over every network version this version of the FVM supports (see
+    /// [`SUPPORTED_VERSIONS`]) and has a price schedule for.
+    pub fn supported() -> Self {
+        Self::new(
+            supported_network_versions()
+                .into_iter()
+                .filter(|nv| SUPPORTED_VERSIONS.contains(nv)),
+        )
+    }
+
+    /// Returns the network versions in this matrix, in ascending order.
+    pub fn versions(&self) -> &[NetworkVersion] {
+        &self.versions
+    }
+
+    /// Applies all cases under every network version, returning the outcomes and any mismatches.
+    ///
+    /// `setup` must return a tester with an instantiated machine for the given network version.
+    /// It's called once per version, so any accounts or actors the cases rely on must be created
+    /// deterministically (e.g., with [`Tester::create_accounts`]).
+    ///
+    /// This only returns an error if a version can't be set up or a message fails to apply
+    /// (rather than failing with an exit code). Call [`MatrixReport::check`] to assert that there
+    /// are no mismatches.
+    pub fn run<B, E, F>(&self, mut setup: F, cases: &[MatrixCase]) -> Result<MatrixReport>
+    where
+        B: Blockstore,
+        E: Externs,
+        F: FnMut(NetworkVersion) -> Result<Tester<B, E>>,
+    {
+        let mut report = MatrixReport::default();
+        for &nv in &self.versions {
+            let mut tester = setup(nv).with_context(|| format!("failed to set up nv{nv}"))?;
+            let executor = tester
+                .executor
+                .as_mut()
+                .ok_or_else(|| anyhow!("nv{nv}: machine not instantiated"))?;
+            for case in cases {
+                let raw_length = fvm_ipld_encoding::to_vec(&case.message)?.len();
+                let ret = executor
+                    .execute_message(case.message.clone(), ApplyKind::Explicit, raw_length)
+                    .with_context(|| format!("{} @ nv{nv}: failed to apply message", case.name))?;
+                let actual = Outcome {
+                    exit_code: ret.msg_receipt.exit_code,
+                    gas_used: ret.msg_receipt.gas_used,
+                };
+                let expected = case.expected(nv);
+                if actual.exit_code != expected.exit_code
+                    || expected
+                        .gas_used
+                        .map_or(false, |gas| gas != actual.gas_used)
+                {
+                    report.mismatches.push(Mismatch::Outcome {
+                        case: case.name.clone(),
+                        nv,
+                        expected,
+                        actual,
+                    });
+                }
+                report
+                    .outcomes
+                    .entry(case.name.clone())
+                    .or_default()
+                    .insert(nv, actual);
+            }
+        }
+
+        // Versions sharing an expectation without a pinned gas value must agree on the gas used.
+        for case in cases {
+            let Some(outcomes) = report.outcomes.get(&case.name) else {
+                continue;
+            };
+            let mut groups: Vec<(Expectation, BTreeMap<NetworkVersion, u64>)> = Vec::new();
+            for (&nv, outcome) in outcomes {
+                let expected = case.expected(nv);
+                if expected.gas_used.is_some() {
+                    continue;
+                }
+                match groups.iter_mut().find(|(e, _)| *e == expected) {
+                    Some((_, gas)) => {
+                        gas.insert(nv, outcome.gas_used);
+                    }
+                    None => groups.push((expected, [(nv, outcome.gas_used)].into())),
+                }
+            }
+            for (_, gas_used) in groups {
+                let mut values = gas_used.values();
+                let first = values.next();
+                if values.any(|gas| Some(gas) != first) {
+                    report.mismatches.push(Mismatch::GasDrift {
+                        case: case.name.clone(),
+                        gas_used,
+                    });
+                }
+            }
+        }
+        Ok(report)
+    }
+}
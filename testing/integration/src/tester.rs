@@ -21,6 +21,7 @@ use libsecp256k1::{PublicKey, SecretKey};
 use multihash::Code;
 
 use crate::builtin::{fetch_builtin_code_cid, set_eam_actor, set_init_actor, set_sys_actor};
+use crate::chaos::{ChaosConfig, ChaosExecutor, ChaosMachine};
 use crate::dummy::DummyExterns;
 use crate::error::Error::{FailedToFlushTree, NoManifestInformation};
 
@@ -118,6 +119,14 @@ where
     /// Creates new accounts in the testing context
     /// Inserts the specified number of accounts in the state tree, all with 1000 FIL，returning their IDs and Addresses.
     pub fn create_accounts<const N: usize>(&mut self) -> Result<[Account; N]> {
+        self.create_accounts_with_balance(&INITIAL_ACCOUNT_BALANCE)
+    }
+
+    /// Like [`Tester::create_accounts`], but with the given balance for each account.
+    pub fn create_accounts_with_balance<const N: usize>(
+        &mut self,
+        balance: &TokenAmount,
+    ) -> Result<[Account; N]> {
         use rand::SeedableRng;
 
         let rng = &mut rand_chacha::ChaCha8Rng::seed_from_u64(8);
@@ -125,7 +134,7 @@ where
         let mut ret: [Account; N] = [(0, Address::default()); N];
         for account in ret.iter_mut().take(N) {
             let priv_key = SecretKey::random(rng);
-            *account = self.make_secp256k1_account(priv_key, INITIAL_ACCOUNT_BALANCE.clone())?;
+            *account = self.make_secp256k1_account(priv_key, balance.clone())?;
         }
         Ok(ret)
     }
@@ -258,6 +267,64 @@ where
         configure_nc: F,
         configure_mc: G,
     ) -> Result<()>
+    where
+        F: FnOnce(&mut NetworkConfig),
+        G: FnOnce(&mut MachineContext),
+    {
+        let (engine, machine) = self.new_machine(externs, None, configure_nc, configure_mc)?;
+
+        let executor =
+            DefaultExecutor::<DefaultKernel<DefaultCallManager<DefaultMachine<B, E>>>>::new(
+                engine, machine,
+            )?;
+
+        self.executor = Some(executor);
+        self.ready = true;
+
+        Ok(())
+    }
+
+    /// Sets the Machine and the Executor in our Tester structure, executing on the given (possibly
+    /// shared) engine pool instead of a new one.
+    pub fn instantiate_machine_with_engine(
+        &mut self,
+        externs: E,
+        engine: EnginePool,
+    ) -> Result<()> {
+        let (engine, machine) = self.new_machine(externs, Some(engine), |_| (), |_| ())?;
+        self.executor = Some(IntegrationExecutor::new(engine, machine)?);
+        self.ready = true;
+        Ok(())
+    }
+
+    /// Creates an executor whose kernels inject the faults configured by `config` (see
+    /// [`ChaosKernel`](crate::chaos::ChaosKernel)).
+    ///
+    /// Unlike [`Tester::instantiate_machine`], the executor is returned instead of being stored in
+    /// the tester.
+    pub fn instantiate_chaos_machine(
+        &mut self,
+        externs: E,
+        config: ChaosConfig,
+    ) -> Result<ChaosExecutor<B, E>> {
+        let (engine, machine) = self.new_machine(externs, None, |_| (), |_| ())?;
+        let executor = ChaosExecutor::new(engine, ChaosMachine::new(machine, config))?;
+        self.ready = true;
+        Ok(executor)
+    }
+
+    /// Flushes the state tree and creates a machine on top of it, along with an engine pool (unless
+    /// one is given) with the tester's actors preloaded.
+    ///
+    /// Unlike [`Tester::instantiate_machine`], no executor is created, so the machine may be
+    /// wrapped (e.g., to intercept syscalls) before executing messages on it.
+    pub fn new_machine<F, G>(
+        &mut self,
+        externs: E,
+        engine: Option<EnginePool>,
+        configure_nc: F,
+        configure_mc: G,
+    ) -> Result<(EnginePool, DefaultMachine<B, E>)>
     where
         F: FnOnce(&mut NetworkConfig),
         G: FnOnce(&mut MachineContext),
@@ -288,20 +355,15 @@ where
         // Custom configuration.
         configure_mc(&mut mc);
 
-        let engine = EnginePool::new_default((&mc.network.clone()).into())?;
+        let engine = match engine {
+            Some(engine) => engine,
+            None => EnginePool::new_default((&mc.network.clone()).into())?,
+        };
         engine.acquire().preload(&blockstore, &self.code_cids)?;
 
         let machine = DefaultMachine::new(&mc, blockstore, externs)?;
 
-        let executor =
-            DefaultExecutor::<DefaultKernel<DefaultCallManager<DefaultMachine<B, E>>>>::new(
-                engine, machine,
-            )?;
-
-        self.executor = Some(executor);
-        self.ready = true;
-
-        Ok(())
+        Ok((engine, machine))
     }
 
     /// Get blockstore
@@ -321,7 +383,15 @@ where
     ) -> Result<Account> {
         let pub_key = PublicKey::from_secret_key(&priv_key);
         let pub_key_addr = Address::new_secp256k1(&pub_key.serialize())?;
+        self.make_account(pub_key_addr, init_balance)
+    }
 
+    /// Put account with specified key (f1/f3) address and balance
+    pub fn make_account(
+        &mut self,
+        pub_key_addr: Address,
+        init_balance: TokenAmount,
+    ) -> Result<Account> {
         let state_tree = self
             .state_tree
             .as_mut()
@@ -44,7 +44,7 @@ fn basic_address_tests() {
     let executor = tester.executor.as_mut().unwrap();
 
     // Test all methods.
-    for (seq, method) in (2..=5).enumerate() {
+    for (seq, method) in (2..=6).enumerate() {
         let message = Message {
             from: sender_address,
             to: actor_address,
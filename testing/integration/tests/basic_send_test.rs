@@ -4,17 +4,35 @@
 
 mod bundles;
 use bundles::*;
-use fvm::executor::{ApplyKind, Executor};
-use fvm::gas::GasCharge;
-use fvm::machine::Machine;
+use fvm::engine::MultiEngine;
+use fvm::executor::{ApplyKind, BlockFullError, BlockPacker, Executor, PreflightError};
+use fvm::gas::{Gas, GasCharge};
+use fvm::machine::{Machine, NetworkConfig};
+use fvm::metrics::PrometheusMetrics;
+use fvm::trace::ExecutionEvent;
 use fvm_integration_tests::dummy::DummyExterns;
+use fvm_integration_tests::tester::{Account, BasicTester, INITIAL_ACCOUNT_BALANCE};
 use fvm_ipld_blockstore::MemoryBlockstore;
 use fvm_shared::address::Address;
 use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
 use fvm_shared::message::Message;
 use fvm_shared::state::StateTreeVersion;
 use fvm_shared::version::NetworkVersion;
 use fvm_shared::METHOD_SEND;
+use num_traits::Zero;
+use std::sync::Arc;
+
+/// Creates a tester with `N` secp256k1 accounts, each holding `balance`. The machine isn't
+/// instantiated yet so that tests can configure it.
+fn funded_tester<const N: usize>(
+    nv: NetworkVersion,
+    balance: TokenAmount,
+) -> (BasicTester, [Account; N]) {
+    let mut tester = new_tester(nv, StateTreeVersion::V5, MemoryBlockstore::default()).unwrap();
+    let accounts = tester.create_accounts_with_balance(&balance).unwrap();
+    (tester, accounts)
+}
 
 #[test]
 fn basic_send() {
@@ -108,15 +126,1042 @@ fn basic_send() {
             .unwrap();
         assert!(res.msg_receipt.exit_code.is_success());
 
-        let charges: Vec<_> = res
-            .exec_trace
+        // The gas reported on return should cover every charge made inside the call.
+        let mut in_call = false;
+        let mut call_gas = Gas::default();
+        let mut returned_gas = None;
+        for event in &res.exec_trace {
+            match event {
+                ExecutionEvent::Call { .. } => in_call = true,
+                ExecutionEvent::GasCharge(charge) if in_call => call_gas += charge.total(),
+                ExecutionEvent::CallReturn(_, _, gas_used) => {
+                    in_call = false;
+                    returned_gas = Some(*gas_used);
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(returned_gas, Some(call_gas));
+
+        let charges: Vec<_> = res.gas_charges().cloned().collect();
+
+        assert_eq!(charges, case.trace);
+    }
+}
+
+#[test]
+fn estimate_send_gas() {
+    let (mut tester, [(sender_id, sender)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    let receiver = Address::new_delegated(10, b"foobar").expect("failed to construct f4 address");
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    // No gas limit, fee cap, or (correct) sequence.
+    let message = Message {
+        from: sender,
+        to: receiver,
+        method_num: METHOD_SEND,
+        sequence: 42,
+        gas_fee_cap: TokenAmount::from_whole(1_000_000_000),
+        ..Message::default()
+    };
+
+    let res = executor.estimate_gas(message, 100).unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+    assert!(res.msg_receipt.gas_used > 0);
+
+    // Nothing should have changed.
+    let sender_state = executor.state_tree().get_actor(sender_id).unwrap().unwrap();
+    assert_eq!(sender_state.sequence, 0);
+    assert_eq!(executor.state_tree().lookup_id(&receiver).unwrap(), None);
+}
+
+#[test]
+fn implicit_send_charges_no_gas() {
+    let (mut tester, [(sender_id, sender)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    let receiver = Address::new_delegated(10, b"foobar").expect("failed to construct f4 address");
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let balance_before = executor
+        .state_tree()
+        .get_actor(sender_id)
+        .unwrap()
+        .unwrap()
+        .balance;
+
+    // The wrong sequence and an unaffordable fee cap would both fail an explicit message.
+    let message = Message {
+        from: sender,
+        to: receiver,
+        method_num: METHOD_SEND,
+        sequence: 42,
+        value: TokenAmount::from_atto(1),
+        gas_limit: 1000000000,
+        gas_fee_cap: TokenAmount::from_whole(1_000_000_000),
+        ..Message::default()
+    };
+
+    let res = executor.apply_implicit_message(message).unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+    assert!(res.msg_receipt.gas_used > 0);
+    assert!(res.penalty.is_zero());
+    assert!(res.miner_tip.is_zero());
+    assert!(res.base_fee_burn.is_zero());
+
+    // Only the transferred value should have left the sender; the nonce is untouched.
+    let sender_state = executor.state_tree().get_actor(sender_id).unwrap().unwrap();
+    assert_eq!(sender_state.sequence, 0);
+    assert_eq!(
+        sender_state.balance,
+        balance_before - TokenAmount::from_atto(1)
+    );
+}
+
+#[test]
+fn implicit_send_default_gas_limit() {
+    // Sends 1 atto without a gas limit, returning the result and the sender's balance change.
+    let run = |nv| {
+        let (mut tester, [(sender_id, sender), (_, receiver)]) =
+            funded_tester(nv, INITIAL_ACCOUNT_BALANCE.clone());
+
+        tester.instantiate_machine(DummyExterns).unwrap();
+        let executor = tester.executor.as_mut().unwrap();
+
+        let balance_before = executor
+            .state_tree()
+            .get_actor(sender_id)
+            .unwrap()
+            .unwrap()
+            .balance;
+
+        let message = Message {
+            from: sender,
+            to: receiver,
+            method_num: METHOD_SEND,
+            value: TokenAmount::from_atto(1),
+            gas_fee_cap: TokenAmount::from_atto(100),
+            ..Message::default()
+        };
+
+        let res = executor.apply_implicit_message(message).unwrap();
+        let sender_state = executor.state_tree().get_actor(sender_id).unwrap().unwrap();
+        (res, balance_before - sender_state.balance)
+    };
+
+    // Before NV21, the zero gas limit is used as-is.
+    let (res, _) = run(NetworkVersion::V20);
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::SYS_OUT_OF_GAS);
+
+    // From NV21, implicit messages without a gas limit get the (bounded) system allowance instead
+    // of immediately running out of gas. The gas used is still reported, but nobody pays for it.
+    let (res, spent) = run(NetworkVersion::V21);
+    assert!(res.msg_receipt.exit_code.is_success());
+    assert!(res.msg_receipt.gas_used > 0);
+    assert_eq!(res.gas_burned, 0);
+    assert!(res.refund.is_zero());
+    assert_eq!(spent, TokenAmount::from_atto(1));
+}
+
+#[test]
+fn send_limit_exceeded() {
+    let run = |nv| {
+        let (mut tester, [(_, sender), (_, receiver)]) =
+            funded_tester(nv, INITIAL_ACCOUNT_BALANCE.clone());
+
+        tester
+            .instantiate_machine_with_config(
+                DummyExterns,
+                |nc| {
+                    nc.max_sends_per_message(0);
+                },
+                |_| (),
+            )
+            .unwrap();
+        let executor = tester.executor.as_mut().unwrap();
+
+        let message = Message {
+            from: sender,
+            to: receiver,
+            gas_limit: 1000000000,
+            method_num: METHOD_SEND,
+            value: TokenAmount::from_atto(1),
+            ..Message::default()
+        };
+
+        executor
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .unwrap()
+            .msg_receipt
+            .exit_code
+    };
+
+    // Exceeding the limit is only reported as such from NV21.
+    assert_eq!(run(NetworkVersion::V20), ExitCode::SYS_ASSERTION_FAILED);
+    assert_eq!(run(NetworkVersion::V21), ExitCode::SYS_LIMIT_EXCEEDED);
+}
+
+#[test]
+fn gas_breakdown() {
+    let (mut tester, [(_, sender)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    let receiver = Address::new_delegated(10, b"foobar").expect("failed to construct f4 address");
+
+    tester
+        .instantiate_machine_with_config(
+            DummyExterns,
+            |_| (),
+            |mc| {
+                mc.enable_gas_breakdown();
+            },
+        )
+        .unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+
+    let res = executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+
+    let breakdown = res.gas_breakdown.expect("expected a gas breakdown");
+    assert_eq!(breakdown.total().round_up(), res.msg_receipt.gas_used);
+    assert!(!breakdown.storage_writes.is_zero());
+    assert!(breakdown.syscalls.contains_key("OnChainMessage"));
+}
+
+#[test]
+fn blockstore_stats() {
+    let (mut tester, [(_, sender)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    let receiver = Address::new_delegated(10, b"foobar").expect("failed to construct f4 address");
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+
+    let res = executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+
+    // Sending to a new f4 address loads the sender and creates a placeholder actor.
+    let stats = res.blockstore_stats.expect("expected blockstore stats");
+    assert!(stats.reads > 0);
+    assert!(stats.writes > 0);
+    assert!(stats.bytes_written > 0);
+}
+
+#[test]
+fn delete_actor() {
+    let (mut tester, [(deleted, _), (beneficiary, _)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    // The beneficiary must exist.
+    assert!(executor.delete_actor(deleted, 1234).is_err());
+
+    executor.delete_actor(deleted, beneficiary).unwrap();
+    assert!(executor.state_tree().get_actor(deleted).unwrap().is_none());
+    assert_eq!(
+        executor
+            .state_tree()
+            .get_actor(beneficiary)
+            .unwrap()
+            .unwrap()
+            .balance,
+        TokenAmount::from_atto(20000)
+    );
+}
+
+#[test]
+fn preflight() {
+    let (mut tester, [(sender_id, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+
+    assert_eq!(executor.preflight(&message, 100).unwrap(), Ok(sender_id));
+    // Pre-validation doesn't touch the sender's state.
+    assert_eq!(
+        executor
+            .state_tree()
+            .get_actor(sender_id)
+            .unwrap()
+            .unwrap()
+            .sequence,
+        0
+    );
+
+    let bad_sequence = Message {
+        sequence: 1,
+        ..message.clone()
+    };
+    assert_eq!(
+        executor.preflight(&bad_sequence, 100).unwrap(),
+        Err(PreflightError::SequenceMismatch {
+            expected: 0,
+            actual: 1
+        })
+    );
+
+    let unknown_sender = Message {
+        from: Address::new_id(1234),
+        ..message.clone()
+    };
+    assert_eq!(
+        executor.preflight(&unknown_sender, 100).unwrap(),
+        Err(PreflightError::SenderNotFound)
+    );
+
+    let too_expensive = Message {
+        gas_fee_cap: TokenAmount::from_atto(1),
+        ..message
+    };
+    let err = executor
+        .preflight(&too_expensive, 100)
+        .unwrap()
+        .unwrap_err();
+    assert!(matches!(err, PreflightError::InsufficientFunds { .. }));
+
+    // Execution fails with the same error.
+    let res = executor
+        .execute_message(too_expensive, ApplyKind::Explicit, 100)
+        .unwrap();
+    assert_eq!(res.msg_receipt.exit_code, err.exit_code());
+}
+
+#[test]
+fn execute_chain() {
+    let (mut tester, [(sender_id, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let message = |sequence| Message {
+        from: sender,
+        to: receiver,
+        sequence,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+
+    // Gaps are rejected before anything is applied.
+    assert!(executor
+        .execute_chain(vec![(message(0), 100), (message(2), 100)])
+        .is_err());
+
+    // Sequences don't wrap around.
+    assert!(executor
+        .execute_chain(vec![(message(u64::MAX), 100), (message(0), 100)])
+        .is_err());
+
+    // Messages are applied in sequence order.
+    let rets = executor
+        .execute_chain(vec![(message(1), 100), (message(0), 100)])
+        .unwrap();
+    assert_eq!(rets.len(), 2);
+    assert!(rets.iter().all(|r| r.msg_receipt.exit_code.is_success()));
+
+    // The chain stops at the first message that fails pre-validation.
+    let too_expensive = Message {
+        gas_fee_cap: TokenAmount::from_atto(1),
+        ..message(3)
+    };
+    let rets = executor
+        .execute_chain(vec![
+            (message(2), 100),
+            (too_expensive, 100),
+            (message(4), 100),
+        ])
+        .unwrap();
+    assert_eq!(rets.len(), 1);
+    assert_eq!(
+        executor
+            .state_tree()
+            .get_actor(sender_id)
+            .unwrap()
+            .unwrap()
+            .sequence,
+        3
+    );
+}
+
+#[test]
+fn call_readonly() {
+    let (mut tester, [(sender_id, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        method_num: METHOD_SEND,
+        ..Message::default()
+    };
+
+    let res = executor.call_readonly(message.clone()).unwrap();
+    assert!(
+        res.msg_receipt.exit_code.is_success(),
+        "{:?}",
+        res.failure_info
+    );
+    // Nothing was applied.
+    assert_eq!(
+        executor
+            .state_tree()
+            .get_actor(sender_id)
+            .unwrap()
+            .unwrap()
+            .sequence,
+        0
+    );
+
+    // Value transfers are forbidden.
+    assert!(executor
+        .call_readonly(Message {
+            value: TokenAmount::from_atto(1),
+            ..message.clone()
+        })
+        .is_err());
+
+    // As is creating actors.
+    let res = executor
+        .call_readonly(Message {
+            to: Address::new_secp256k1(&[1; 65]).unwrap(),
+            ..message
+        })
+        .unwrap();
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::USR_READ_ONLY);
+}
+
+#[test]
+fn restart_machine() {
+    let (mut tester, [(sender_id, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let mut executor = tester.executor.take().unwrap();
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+    let res = executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+
+    let root = executor.flush().unwrap();
+    let engine_pool = executor.engine_pool().clone();
+    let machine = executor.into_machine().unwrap();
+    let mut context = machine.context().clone();
+    context.epoch += 1;
+    context.initial_state_root = root;
+
+    let machine = machine.restart(&context).unwrap();
+    assert_eq!(machine.context().epoch, context.epoch);
+    let executor = fvm_integration_tests::tester::BasicExecutor::new(engine_pool, machine).unwrap();
+    assert_eq!(
+        executor
+            .state_tree()
+            .get_actor(sender_id)
+            .unwrap()
+            .unwrap()
+            .sequence,
+        1
+    );
+}
+
+#[test]
+fn send_creates_account() {
+    let (mut tester, [(_, sender)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    let receiver = Address::new_secp256k1(&[4u8; 65]).unwrap();
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+    assert_eq!(executor.state_tree().lookup_id(&receiver).unwrap(), None);
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+
+    let res = executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+
+    // The account actor should have been created (and charged for) before the transfer.
+    let on_create_actor = executor.context().price_list.on_create_actor(true);
+    assert!(res.gas_charges().any(|c| *c == on_create_actor));
+
+    let receiver_id = executor
+        .state_tree()
+        .lookup_id(&receiver)
+        .unwrap()
+        .expect("account actor not created");
+    let state = executor
+        .state_tree()
+        .get_actor(receiver_id)
+        .unwrap()
+        .unwrap();
+    assert_eq!(&state.code, executor.builtin_actors().get_account_code());
+    assert_eq!(state.balance, TokenAmount::from_atto(1));
+}
+
+#[test]
+fn authenticate_message() {
+    let (mut tester, [(sender_id, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    // Neither a bogus signature nor a missing sender should authenticate.
+    assert!(!executor
+        .authenticate_message(&sender, &[1; 65], b"payload")
+        .unwrap());
+    let missing = Address::new_secp256k1(&[4u8; 65]).unwrap();
+    assert!(!executor
+        .authenticate_message(&missing, &[1; 65], b"payload")
+        .unwrap());
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+    let res = executor
+        .execute_authenticated_message(message, &[1; 65], b"payload", ApplyKind::Explicit, 100)
+        .unwrap();
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::SYS_SENDER_INVALID);
+    assert!(res.penalty.is_zero());
+
+    // The message wasn't applied.
+    let sender_state = executor.state_tree().get_actor(sender_id).unwrap().unwrap();
+    assert_eq!(sender_state.sequence, 0);
+}
+
+#[test]
+fn metrics() {
+    let (mut tester, [(_, sender)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    let receiver = Address::new_delegated(10, b"foobar").expect("failed to construct f4 address");
+
+    let metrics = Arc::new(PrometheusMetrics::new());
+    tester
+        .instantiate_machine_with_config(
+            DummyExterns,
+            |_| (),
+            |mc| {
+                mc.set_metrics(metrics.clone());
+            },
+        )
+        .unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+    let res = executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+    executor.flush().unwrap();
+
+    let out = metrics.render();
+    assert!(out.contains("fvm_messages_applied_total 1\n"));
+    assert!(out.contains(&format!(
+        "fvm_gas_used_total {}\n",
+        res.msg_receipt.gas_used
+    )));
+    assert!(!out.contains("fvm_blockstore_flushed_blocks_total 0\n"));
+}
+
+#[test]
+fn shared_engine() {
+    // One engine pool, shared by two machines (with their own state) executing concurrently.
+    let mut nc = NetworkConfig::new(NetworkVersion::V18);
+    nc.enable_actor_debugging();
+    let engine = MultiEngine::new(2).get(&nc).unwrap();
+
+    let executors: Vec<_> = (0..2)
+        .map(|_| {
+            let (mut tester, [(_, sender), (_, receiver)]) =
+                funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+            tester
+                .instantiate_machine_with_engine(DummyExterns, engine.clone())
+                .unwrap();
+            (tester.executor.unwrap(), sender, receiver)
+        })
+        .collect();
+
+    let handles: Vec<_> = executors
+        .into_iter()
+        .map(|(mut executor, sender, receiver)| {
+            std::thread::spawn(move || {
+                let message = Message {
+                    from: sender,
+                    to: receiver,
+                    gas_limit: 1000000000,
+                    method_num: METHOD_SEND,
+                    value: TokenAmount::from_atto(1),
+                    ..Message::default()
+                };
+                let res = executor
+                    .execute_message(message, ApplyKind::Explicit, 100)
+                    .unwrap();
+                assert!(res.msg_receipt.exit_code.is_success());
+                executor.flush().unwrap()
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn invariant_checks() {
+    let (mut tester, [(_, sender)]) =
+        funded_tester(NetworkVersion::V18, TokenAmount::from_whole(1000));
+    let receiver = Address::new_secp256k1(&[5u8; 65]).unwrap();
+
+    tester
+        .instantiate_machine_with_config(
+            DummyExterns,
+            |_| {},
+            |mc| {
+                mc.set_base_fee(TokenAmount::from_atto(100))
+                    .enable_invariant_checks();
+            },
+        )
+        .unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    // Successful transfers (including fees and refunds) conserve the supply, as do messages that
+    // fail to transfer value.
+    for (sequence, value) in [(0, 10), (1, 0), (2, u64::MAX)] {
+        let message = Message {
+            from: sender,
+            to: receiver,
+            gas_limit: 1000000000,
+            gas_fee_cap: TokenAmount::from_atto(200),
+            gas_premium: TokenAmount::from_atto(10),
+            method_num: METHOD_SEND,
+            value: TokenAmount::from_whole(value),
+            sequence,
+            ..Message::default()
+        };
+
+        let res = executor
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .expect("invariants violated");
+        assert_eq!(res.msg_receipt.exit_code.is_success(), sequence < 2);
+    }
+}
+
+#[test]
+fn validate_block_messages() {
+    use cid::Cid;
+    use fvm::executor::{BlockMessages, BlockValidationError};
+    use fvm_ipld_encoding::{to_vec, DAG_CBOR};
+    use fvm_shared::crypto::signature::Signature;
+    use multihash::{Code, MultihashDigest};
+
+    let (mut tester, [(_, receiver)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+
+    let key = libsecp256k1::SecretKey::parse(&[1u8; 32]).unwrap();
+    let (sender_id, sender) = tester
+        .make_secp256k1_account(key, TokenAmount::from_whole(1000))
+        .unwrap();
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let message = |sequence| Message {
+        from: Address::new_id(sender_id),
+        to: receiver,
+        gas_limit: 1000000,
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        sequence,
+        ..Message::default()
+    };
+    let sign = |msg: &Message| {
+        let cid = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&to_vec(msg).unwrap()));
+        let hash = Code::Blake2b256.digest(&cid.to_bytes());
+        let (sig, recovery_id) = libsecp256k1::sign(
+            &libsecp256k1::Message::parse_slice(hash.digest()).unwrap(),
+            &key,
+        );
+        let mut bytes = sig.serialize().to_vec();
+        bytes.push(recovery_id.serialize());
+        Signature::new_secp256k1(bytes)
+    };
+    let block = |msgs: Vec<Message>| BlockMessages {
+        secp_messages: msgs
             .into_iter()
-            .filter_map(|x| match x {
-                fvm::trace::ExecutionEvent::GasCharge(charge) => Some(charge),
-                _ => None,
+            .map(|msg| {
+                let sig = sign(&msg);
+                (msg, sig)
             })
-            .collect();
+            .collect(),
+        ..BlockMessages::default()
+    };
 
-        assert_eq!(charges, case.trace);
+    // Consecutive nonces from the same sender are fine.
+    assert_eq!(
+        executor
+            .validate_block_messages(&block(vec![message(0), message(1)]))
+            .unwrap(),
+        Ok(())
+    );
+
+    // Gaps aren't.
+    assert_eq!(
+        executor
+            .validate_block_messages(&block(vec![message(0), message(2)]))
+            .unwrap(),
+        Err(BlockValidationError::InvalidMessage {
+            index: 1,
+            error: PreflightError::SequenceMismatch {
+                expected: 1,
+                actual: 2
+            }
+        })
+    );
+
+    // The sender must be able to cover the gas of all its messages: each of these costs 600 FIL of
+    // the sender's 1000 FIL.
+    let expensive = |sequence| Message {
+        gas_fee_cap: TokenAmount::from_atto(600_000_000_000_000u64),
+        ..message(sequence)
+    };
+    assert_eq!(
+        executor
+            .validate_block_messages(&block(vec![expensive(0)]))
+            .unwrap(),
+        Ok(())
+    );
+    assert_eq!(
+        executor
+            .validate_block_messages(&block(vec![expensive(0), expensive(1)]))
+            .unwrap(),
+        Err(BlockValidationError::InvalidMessage {
+            index: 1,
+            error: PreflightError::InsufficientFunds {
+                balance: TokenAmount::from_whole(400),
+                required: TokenAmount::from_whole(600),
+            }
+        })
+    );
+
+    // Neither are bad signatures.
+    let mut msgs = block(vec![message(0), message(1)]);
+    msgs.secp_messages[1].1 = sign(&message(0));
+    assert!(matches!(
+        executor.validate_block_messages(&msgs).unwrap(),
+        Err(BlockValidationError::InvalidSignature { index: 1, .. })
+    ));
+
+    // Or BLS messages from secp senders.
+    let msgs = BlockMessages {
+        bls_messages: vec![message(0)],
+        bls_aggregate: Some(Signature::new_bls(vec![0; 96])),
+        ..BlockMessages::default()
+    };
+    assert!(matches!(
+        executor.validate_block_messages(&msgs).unwrap(),
+        Err(BlockValidationError::InvalidSignature { index: 0, .. })
+    ));
+
+    // Nothing was applied.
+    assert_eq!(
+        executor
+            .state_tree()
+            .get_actor(sender_id)
+            .unwrap()
+            .unwrap()
+            .sequence,
+        0
+    );
+    assert_eq!(
+        executor.state_tree().lookup_id(&sender).unwrap(),
+        Some(sender_id)
+    );
+}
+
+#[test]
+fn miner_penalties() {
+    let (mut tester, [(_, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, TokenAmount::from_whole(1000));
+
+    let base_fee = TokenAmount::from_atto(100);
+    tester
+        .instantiate_machine_with_config(
+            DummyExterns,
+            |_| {},
+            |mc| {
+                mc.set_base_fee(base_fee.clone());
+            },
+        )
+        .unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let message = |sequence, gas_limit, gas_fee_cap: u64| Message {
+        from: sender,
+        to: receiver,
+        gas_limit,
+        gas_fee_cap: TokenAmount::from_atto(gas_fee_cap),
+        method_num: METHOD_SEND,
+        sequence,
+        ..Message::default()
+    };
+
+    // Failing pre-validation is penalized by the gas limit.
+    let res = executor
+        .execute_message(message(1, 1000000, 200), ApplyKind::Explicit, 100)
+        .unwrap();
+    assert_eq!(
+        res.msg_receipt.exit_code,
+        ExitCode::SYS_SENDER_STATE_INVALID
+    );
+    assert_eq!(res.penalty, &base_fee * 1000000);
+
+    // Unless the gas limit doesn't even cover inclusion.
+    let inclusion_gas = executor
+        .context()
+        .price_list
+        .on_chain_message(100)
+        .total()
+        .round_up();
+    let res = executor
+        .execute_message(message(0, 10, 200), ApplyKind::Explicit, 100)
+        .unwrap();
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::SYS_OUT_OF_GAS);
+    assert_eq!(res.penalty, &base_fee * inclusion_gas);
+
+    // Executed messages aren't penalized if they cover the base fee...
+    let res = executor
+        .execute_message(message(0, 1000000, 200), ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+    assert!(res.penalty.is_zero());
+
+    // ...but are penalized for the uncovered base fee otherwise.
+    let res = executor
+        .execute_message(message(1, 1000000, 40), ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+    assert_eq!(
+        res.penalty,
+        TokenAmount::from_atto(60) * (res.msg_receipt.gas_used + res.gas_burned)
+    );
+
+    // Implicit messages are never penalized.
+    let res = executor
+        .execute_message(message(0, 1000000, 0), ApplyKind::Implicit, 100)
+        .unwrap();
+    assert!(res.penalty.is_zero());
+}
+
+#[test]
+fn gas_outputs_settle_reservation() {
+    let (mut tester, [(sender_id, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, TokenAmount::from_whole(1000));
+
+    tester
+        .instantiate_machine_with_config(
+            DummyExterns,
+            |_| {},
+            |mc| {
+                mc.set_base_fee(TokenAmount::from_atto(100));
+            },
+        )
+        .unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    let balance_before = executor
+        .state_tree()
+        .get_actor(sender_id)
+        .unwrap()
+        .unwrap()
+        .balance;
+
+    let message = Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 10_000_000,
+        gas_fee_cap: TokenAmount::from_atto(200),
+        gas_premium: TokenAmount::from_atto(10),
+        method_num: METHOD_SEND,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+    let reserved = &message.gas_fee_cap * message.gas_limit;
+
+    let res = executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+
+    // The full gas cost is reserved up-front, then split between burning, the miner, and the
+    // sender's refund.
+    let outputs = res.gas_outputs();
+    assert_eq!(outputs.total_cost(), reserved);
+    assert!(!outputs.refund.is_zero());
+
+    let sender_state = executor.state_tree().get_actor(sender_id).unwrap().unwrap();
+    assert_eq!(
+        sender_state.balance,
+        balance_before - TokenAmount::from_atto(1) - reserved + outputs.refund
+    );
+}
+
+#[test]
+fn finish_reports_machine_stats() {
+    let (mut tester, [(_, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let mut executor = tester.executor.take().unwrap();
+
+    let send = |sequence| Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        sequence,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+
+    let mut gas_used = 0;
+    for sequence in 0..2 {
+        let res = executor
+            .execute_message(send(sequence), ApplyKind::Explicit, 100)
+            .unwrap();
+        assert!(res.msg_receipt.exit_code.is_success());
+        gas_used += res.msg_receipt.gas_used;
+    }
+
+    // Neither simulated messages nor messages that fail pre-validation are counted.
+    executor.estimate_gas(send(2), 100).unwrap();
+    let res = executor
+        .execute_message(send(5), ApplyKind::Explicit, 100)
+        .unwrap();
+    assert_eq!(
+        res.msg_receipt.exit_code,
+        ExitCode::SYS_SENDER_STATE_INVALID
+    );
+
+    let (root, stats) = executor.finish().unwrap();
+    assert_eq!(stats.messages_applied, 2);
+    assert_eq!(stats.gas_used, gas_used);
+    assert!(stats.blocks_written() > 0);
+    assert_ne!(root, cid::Cid::default());
+}
+
+#[test]
+fn block_packer_enforces_gas_limit() {
+    let (mut tester, [(_, sender), (_, receiver)]) =
+        funded_tester(NetworkVersion::V18, INITIAL_ACCOUNT_BALANCE.clone());
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.take().unwrap();
+    let mut packer = BlockPacker::with_gas_limit(executor, 25_000_000);
+
+    let send = |sequence| Message {
+        from: sender,
+        to: receiver,
+        gas_limit: 10_000_000,
+        method_num: METHOD_SEND,
+        sequence,
+        value: TokenAmount::from_atto(1),
+        ..Message::default()
+    };
+
+    for sequence in 0..2 {
+        let res = packer.pack_message(send(sequence), 100).unwrap().unwrap();
+        assert!(res.msg_receipt.exit_code.is_success());
     }
+    assert_eq!(packer.messages_packed(), 2);
+    assert_eq!(packer.gas_packed(), 20_000_000);
+
+    // Messages failing pre-validation don't take up space in the block.
+    let res = packer.pack_message(send(5), 100).unwrap().unwrap();
+    assert_eq!(
+        res.msg_receipt.exit_code,
+        ExitCode::SYS_SENDER_STATE_INVALID
+    );
+    assert_eq!(packer.gas_remaining(), 5_000_000);
+
+    // The next message doesn't fit, and isn't applied.
+    assert_eq!(
+        packer.pack_message(send(2), 100).unwrap().unwrap_err(),
+        BlockFullError {
+            gas_limit: 10_000_000,
+            remaining: 5_000_000,
+        }
+    );
+    let err = packer
+        .execute_message(send(2), ApplyKind::Explicit, 100)
+        .unwrap_err();
+    assert!(err.downcast_ref::<BlockFullError>().is_some());
+
+    // But it fits in the next block.
+    packer.reset();
+    let res = packer.pack_message(send(2), 100).unwrap().unwrap();
+    assert!(res.msg_receipt.exit_code.is_success());
+    assert_eq!(packer.messages_packed(), 1);
 }
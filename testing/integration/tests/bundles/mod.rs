@@ -12,10 +12,14 @@ use fvm_shared::version::NetworkVersion;
 use lazy_static::lazy_static;
 
 lazy_static! {
-    static ref BUNDLES: BTreeMap<NetworkVersion, &'static [u8]> =
-        [(NetworkVersion::V18, actors_v10::BUNDLE_CAR),]
-            .into_iter()
-            .collect();
+    // Later network versions use the same actors: we're testing the FVM, not the actors.
+    static ref BUNDLES: BTreeMap<NetworkVersion, &'static [u8]> = [
+        (NetworkVersion::V18, actors_v10::BUNDLE_CAR),
+        (NetworkVersion::V20, actors_v10::BUNDLE_CAR),
+        (NetworkVersion::V21, actors_v10::BUNDLE_CAR),
+    ]
+    .into_iter()
+    .collect();
 }
 
 #[allow(dead_code)]
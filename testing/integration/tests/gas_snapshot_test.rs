@@ -0,0 +1,193 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Golden tests for the gas used by a canned set of messages.
+//!
+//! The gas used by each message is compared against the baseline in
+//! `tests/snapshots/gas.json`, so any kernel or price-list change that shifts gas consumption shows
+//! up as a test failure (and as a diff to the baseline once it's regenerated). To regenerate the
+//! baseline after an intentional change, run:
+//!
+//! ```text
+//! UPDATE_GAS_SNAPSHOTS=1 cargo test -p fvm_integration_tests --test gas_snapshot_test
+//! ```
+//!
+//! A missing baseline is an error (rather than silently generating one), unless updating.
+mod bundles;
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use bundles::*;
+use fvm::executor::{ApplyKind, Executor};
+use fvm_integration_tests::dummy::DummyExterns;
+use fvm_ipld_blockstore::MemoryBlockstore;
+use fvm_ipld_encoding::tuple::*;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use fvm_shared::state::StateTreeVersion;
+use fvm_shared::version::NetworkVersion;
+use fvm_shared::METHOD_SEND;
+use fvm_test_actors::wasm_bin::{
+    EXIT_DATA_ACTOR_BINARY, HELLO_WORLD_ACTOR_BINARY, IPLD_ACTOR_BINARY,
+};
+use num_traits::Zero;
+
+const UPDATE_ENV: &str = "UPDATE_GAS_SNAPSHOTS";
+
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, Default)]
+struct State {
+    count: u64,
+}
+
+/// A canned message, sent by a fresh account.
+struct Case {
+    name: &'static str,
+    /// Actor code to deploy at the message's recipient, if any.
+    code: Option<&'static [u8]>,
+    /// The recipient, or `None` to send to a second (existing) account.
+    to: Option<Address>,
+    method: u64,
+    value: u64,
+}
+
+fn cases() -> Vec<Case> {
+    let actor = Address::new_id(10000);
+    vec![
+        Case {
+            name: "send_to_account",
+            code: None,
+            to: None,
+            method: METHOD_SEND,
+            value: 1,
+        },
+        Case {
+            name: "send_creates_account",
+            code: None,
+            to: Some(Address::new_secp256k1(&[4u8; 65]).unwrap()),
+            method: METHOD_SEND,
+            value: 1,
+        },
+        Case {
+            name: "send_creates_placeholder",
+            code: None,
+            to: Some(Address::new_delegated(10, b"snapshot").unwrap()),
+            method: METHOD_SEND,
+            value: 1,
+        },
+        Case {
+            name: "hello_world",
+            code: Some(HELLO_WORLD_ACTOR_BINARY),
+            to: Some(actor),
+            method: 1,
+            value: 0,
+        },
+        Case {
+            name: "ipld",
+            code: Some(IPLD_ACTOR_BINARY),
+            to: Some(actor),
+            method: 1,
+            value: 0,
+        },
+        Case {
+            name: "exit_data",
+            code: Some(EXIT_DATA_ACTOR_BINARY),
+            to: Some(actor),
+            method: 1,
+            value: 0,
+        },
+    ]
+}
+
+/// Applies the case's message on a fresh machine, returning the gas it used.
+fn gas_used(case: &Case) -> u64 {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let [(_, sender), (_, account)] = tester.create_accounts().unwrap();
+    let to = case.to.unwrap_or(account);
+
+    if let Some(code) = case.code {
+        let state_cid = tester.set_state(&State::default()).unwrap();
+        tester
+            .set_actor_from_bin(code, state_cid, to, TokenAmount::zero())
+            .unwrap();
+    }
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+
+    let message = Message {
+        from: sender,
+        to,
+        gas_limit: 1_000_000_000,
+        method_num: case.method,
+        value: TokenAmount::from_atto(case.value),
+        ..Message::default()
+    };
+    let res = tester
+        .executor
+        .as_mut()
+        .unwrap()
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    res.msg_receipt.gas_used
+}
+
+fn snapshot_path() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/snapshots/gas.json")
+}
+
+#[test]
+fn gas_snapshots() {
+    let actual: BTreeMap<String, u64> = cases()
+        .iter()
+        .map(|case| (case.name.to_owned(), gas_used(case)))
+        .collect();
+
+    let path = snapshot_path();
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let mut json = serde_json::to_string_pretty(&actual).unwrap();
+        json.push('\n');
+        std::fs::write(&path, json).unwrap();
+        println!("wrote gas snapshots to {}", path.display());
+        return;
+    }
+
+    let expected = std::fs::read(&path).unwrap_or_else(|e| {
+        panic!(
+            "failed to read gas snapshots from {} (run with {}=1 to generate them): {}",
+            path.display(),
+            UPDATE_ENV,
+            e
+        )
+    });
+    let expected: BTreeMap<String, u64> = serde_json::from_slice(&expected).unwrap();
+
+    let mismatches: Vec<_> = expected
+        .keys()
+        .chain(actual.keys())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .filter(|name| expected.get(*name) != actual.get(*name))
+        .map(|name| {
+            format!(
+                "{}: expected {:?}, got {:?}",
+                name,
+                expected.get(name),
+                actual.get(name)
+            )
+        })
+        .collect();
+
+    assert!(
+        mismatches.is_empty(),
+        "gas usage changed (re-run with {}=1 to update the baseline):\n{}",
+        UPDATE_ENV,
+        mismatches.join("\n")
+    );
+}
@@ -0,0 +1,129 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+//! Tests for user-deployed actor code, which is only supported with the `m2-native` feature.
+#![cfg(feature = "m2-native")]
+
+mod bundles;
+use bundles::*;
+use fvm::executor::{ApplyKind, Executor};
+use fvm_integration_tests::dummy::DummyExterns;
+use fvm_ipld_blockstore::MemoryBlockstore;
+use fvm_ipld_encoding::RawBytes;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::message::Message;
+use fvm_shared::state::StateTreeVersion;
+use fvm_shared::version::NetworkVersion;
+use fvm_test_actors::wasm_bin::{INSTALL_ACTOR_BINARY, READONLY_ACTOR_BINARY};
+use num_traits::Zero;
+
+#[test]
+fn compile_charged_once_per_message() {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let [(_, sender)] = tester.create_accounts().unwrap();
+
+    let state_cid = tester.set_state(&[(); 0]).unwrap();
+    let actor = Address::new_id(10000);
+    tester
+        .set_actor_from_bin(READONLY_ACTOR_BINARY, state_cid, actor, TokenAmount::zero())
+        .unwrap();
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    for sequence in 0..2 {
+        // The actor calls back into itself, and sends to a (builtin) account actor.
+        let message = Message {
+            from: sender,
+            to: actor,
+            gas_limit: 1000000000,
+            method_num: 2,
+            sequence,
+            value: TokenAmount::from_atto(100),
+            ..Message::default()
+        };
+        let res = executor
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .unwrap();
+        assert!(
+            res.msg_receipt.exit_code.is_success(),
+            "{:?}",
+            res.failure_info
+        );
+
+        // Only the user code is charged for, and only the first time it's loaded in each message.
+        let compiles = res
+            .gas_charges()
+            .filter(|charge| charge.name == "OnCompileActor")
+            .count();
+        assert_eq!(compiles, 1);
+    }
+}
+
+#[test]
+fn install_and_invoke_actor_code() {
+    // The test actor ID allowed to create actors.
+    const INSTALLER: u64 = 98;
+
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let [(_, sender)] = tester.create_accounts().unwrap();
+
+    let state_cid = tester.set_state(&[(); 0]).unwrap();
+    let installer = Address::new_id(INSTALLER);
+    tester
+        .set_actor_from_bin(
+            INSTALL_ACTOR_BINARY,
+            state_cid,
+            installer,
+            TokenAmount::zero(),
+        )
+        .unwrap();
+
+    tester.instantiate_machine(DummyExterns).unwrap();
+    let executor = tester.executor.as_mut().unwrap();
+
+    // The installer installs this code, creates an actor running it, and invokes that actor.
+    let wasm = wat::parse_str(
+        r#"(module
+             (memory (export "memory") 1)
+             (func (export "invoke") (param $x i32) (result i32)
+               (i32.const 0)))"#,
+    )
+    .unwrap();
+    let message = Message {
+        from: sender,
+        to: installer,
+        gas_limit: 1000000000,
+        method_num: 2,
+        params: RawBytes::new(wasm),
+        ..Message::default()
+    };
+    let res = executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    assert!(
+        res.msg_receipt.exit_code.is_success(),
+        "{:?}",
+        res.failure_info
+    );
+
+    // The installer and the installed code are each charged for compilation once. The installed
+    // code is charged when it's installed, not again when it's invoked.
+    let compiles = res
+        .gas_charges()
+        .filter(|charge| charge.name == "OnCompileActor")
+        .count();
+    assert_eq!(compiles, 2);
+}
@@ -5,8 +5,15 @@ use std::collections::HashSet;
 use std::rc::Rc;
 
 use anyhow::anyhow;
+use cid::multihash::MultihashDigest;
 use cid::Cid;
-use fvm::executor::{ApplyKind, Executor, ThreadedExecutor};
+use fvm::call_manager::backtrace::TrapKind;
+use fvm::call_manager::ReentrancyPolicy;
+use fvm::executor::{ApplyFailure, ApplyKind, ApplyRet, Executor, FailureInfo, ThreadedExecutor};
+use fvm::gas::price_list_by_network_version;
+use fvm::machine::NetworkConfig;
+use fvm::trace::{replay_syscalls, ExecutionEvent, ReplayOutcome, SyscallOutcome, SyscallRecord};
+use fvm_integration_tests::chaos::ChaosConfig;
 use fvm_integration_tests::dummy::DummyExterns;
 use fvm_integration_tests::tester::{Account, IntegrationExecutor};
 use fvm_ipld_blockstore::{Blockstore, MemoryBlockstore};
@@ -135,17 +142,32 @@ fn ipld() {
 
 #[test]
 fn syscalls() {
+    syscalls_at(NetworkVersion::V18)
+}
+
+#[test]
+fn syscalls_nv21() {
+    // Signers are resolved through their account actors from NV21.
+    syscalls_at(NetworkVersion::V21)
+}
+
+fn syscalls_at(nv: NetworkVersion) {
     // Instantiate tester
-    let mut tester = new_tester(
-        NetworkVersion::V18,
-        StateTreeVersion::V5,
-        MemoryBlockstore::default(),
-    )
-    .unwrap();
+    let mut tester = new_tester(nv, StateTreeVersion::V5, MemoryBlockstore::default()).unwrap();
 
     let sender: [Account; 1] = tester.create_accounts().unwrap();
     tester.set_account_sequence(sender[0].0, 100).unwrap();
 
+    // The account whose signature the actor verifies.
+    let signer = Address::new_secp256k1(&[
+        4, 223, 38, 78, 238, 254, 121, 58, 63, 120, 109, 108, 179, 105, 76, 211, 252, 223, 226, 1,
+        20, 220, 212, 77, 23, 190, 224, 138, 62, 103, 27, 48, 60, 150, 151, 233, 30, 217, 137, 151,
+        208, 24, 212, 117, 32, 94, 44, 118, 125, 40, 25, 31, 67, 154, 106, 97, 110, 32, 209, 62,
+        194, 146, 27, 16, 114,
+    ])
+    .unwrap();
+    tester.make_account(signer, TokenAmount::zero()).unwrap();
+
     let wasm_bin = SYSCALL_ACTOR_BINARY;
 
     // Set actor state
@@ -411,6 +433,13 @@ fn exit_data() {
             res.msg_receipt.return_data,
             RawBytes::from(vec![1u8, 2u8, 3u8, 3u8, 7u8])
         );
+
+        // The message is charged for its on-chain inclusion up-front, and for including its
+        // return value once it's done.
+        let pl = price_list_by_network_version(NetworkVersion::V18);
+        let charges: Vec<_> = res.gas_charges().cloned().collect();
+        assert_eq!(charges.first(), Some(&pl.on_chain_message(100)));
+        assert_eq!(charges.last(), Some(&pl.on_chain_return_value(5)));
     }
 
     {
@@ -541,14 +570,15 @@ fn native_stack_overflow() {
     assert_eq!(exec_test(&mut executor, 3), 0x80000042);
 }
 
-fn test_exitcode(wat: &str, code: ExitCode) {
+/// Applies a message to an actor running the given module.
+fn apply_wat(wat: &str) -> ApplyRet {
+    apply_wat_at(NetworkVersion::V18, wat)
+}
+
+/// Applies a message to an actor running the given module, in the given network version.
+fn apply_wat_at(nv: NetworkVersion, wat: &str) -> ApplyRet {
     // Instantiate tester
-    let mut tester = new_tester(
-        NetworkVersion::V18,
-        StateTreeVersion::V5,
-        MemoryBlockstore::default(),
-    )
-    .unwrap();
+    let mut tester = new_tester(nv, StateTreeVersion::V5, MemoryBlockstore::default()).unwrap();
 
     let sender: [Account; 1] = tester.create_accounts().unwrap();
 
@@ -579,10 +609,13 @@ fn test_exitcode(wat: &str, code: ExitCode) {
     };
 
     let mut executor = ThreadedExecutor(tester.executor.unwrap());
-    let res = executor
+    executor
         .execute_message(message, ApplyKind::Explicit, 100)
-        .unwrap();
+        .unwrap()
+}
 
+fn test_exitcode(wat: &str, code: ExitCode) {
+    let res = apply_wat(wat);
     assert_eq!(res.msg_receipt.exit_code, code)
 }
 
@@ -657,6 +690,805 @@ fn no_memory() {
     );
 }
 
+/// Returns a module that exits with the given exit code.
+fn exit_wat(code: u32) -> String {
+    format!(
+        r#"(module
+             (type (;0;) (func (param i32 i32 i32 i32) (result i32)))
+             (import "vm" "exit" (func $fvm_sdk::sys::vm::exit::syscall (type 0)))
+             (memory (export "memory") 1)
+             (func (export "invoke") (param $x i32) (result i32)
+               (i32.const {code})
+               (i32.const 0)
+               (i32.const 0)
+               (i32.const 0)
+               (call $fvm_sdk::sys::vm::exit::syscall)
+               unreachable))"#
+    )
+}
+
+#[test]
+fn actor_exit_code() {
+    test_exitcode(
+        &exit_wat(ExitCode::USR_ILLEGAL_ARGUMENT.value()),
+        ExitCode::USR_ILLEGAL_ARGUMENT,
+    );
+}
+
+#[test]
+fn failure_classification() {
+    let res = apply_wat(&exit_wat(0));
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::OK);
+    assert_eq!(res.failure, None);
+
+    let res = apply_wat(&exit_wat(ExitCode::USR_ILLEGAL_ARGUMENT.value()));
+    assert_eq!(
+        res.failure,
+        Some(FailureInfo::ActorAbort {
+            code: ExitCode::USR_ILLEGAL_ARGUMENT
+        })
+    );
+
+    let res = apply_wat(
+        r#"(module
+             (memory (export "memory") 1)
+             (func (export "invoke") (param $x i32) (result i32)
+               unreachable))"#,
+    );
+    assert_eq!(
+        res.failure,
+        Some(FailureInfo::Trap {
+            kind: TrapKind::UnreachableCodeReached
+        })
+    );
+
+    let res = apply_wat(
+        r#"(module
+             (memory (export "memory") 1)
+             (func (export "invoke") (param $x i32) (result i32)
+               (loop (br 0))
+               (i32.const 1)))"#,
+    );
+    assert_eq!(res.failure, Some(FailureInfo::OutOfGas));
+
+    // Opens a block with an invalid CID, then aborts.
+    let res = apply_wat(
+        r#"(module
+             (type (;0;) (func (param i32 i32) (result i32)))
+             (import "ipld" "block_open" (func $block_open (type 0)))
+             (type (;1;) (func (param i32 i32 i32 i32) (result i32)))
+             (import "vm" "exit" (func $exit (type 1)))
+             (memory (export "memory") 1)
+             (func (export "invoke") (param $x i32) (result i32)
+               (i32.const 0)
+               (i32.const 0)
+               (call $block_open)
+               (drop)
+               (i32.const 16)
+               (i32.const 0)
+               (i32.const 0)
+               (i32.const 0)
+               (call $exit)
+               unreachable))"#,
+    );
+    assert_eq!(
+        res.failure,
+        Some(FailureInfo::Syscall {
+            name: "ipld::block_open".into()
+        })
+    );
+}
+
+#[test]
+fn wasm_backtrace() {
+    // Traps two calls deep. Actor debugging is enabled by the tester, so the Wasm stack is captured.
+    let res = apply_wat(
+        r#"(module
+             (memory (export "memory") 1)
+             (func $inner
+               unreachable)
+             (func $outer
+               (call $inner))
+             (func $invoke (export "invoke") (param $x i32) (result i32)
+               (call $outer)
+               (i32.const 0)))"#,
+    );
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::SYS_ILLEGAL_INSTRUCTION);
+
+    let backtrace = match res.failure_info {
+        Some(ApplyFailure::MessageBacktrace(backtrace)) => backtrace,
+        other => panic!("expected a backtrace, got {:?}", other),
+    };
+    let frame = &backtrace.frames[0];
+    assert_eq!(frame.source, 10000);
+    assert_eq!(frame.trap, Some(TrapKind::UnreachableCodeReached));
+    // The stack limiter may wrap the exported function in an (unnamed) thunk, so only check the
+    // innermost frames.
+    let names: Vec<_> = frame
+        .wasm_backtrace
+        .iter()
+        .take(3)
+        .map(|frame| frame.func_name.as_deref())
+        .collect();
+    assert_eq!(names, [Some("inner"), Some("outer"), Some("invoke")]);
+}
+
+#[test]
+fn aborted_calls_dont_write_blocks() {
+    let data = b"buffered block";
+    let cid = Cid::new_v1(
+        fvm_shared::IPLD_RAW,
+        cid::multihash::Code::Blake2b256.digest(data),
+    );
+
+    // Creates and links a block, then exits with the given code.
+    let wat = |code: u32| {
+        format!(
+            r#"(module
+                 (type (;0;) (func (param i32 i64 i32 i32) (result i32)))
+                 (type (;1;) (func (param i32 i32 i64 i32 i32 i32) (result i32)))
+                 (type (;2;) (func (param i32 i32 i32 i32) (result i32)))
+                 (import "ipld" "block_create" (func $block_create (type 0)))
+                 (import "ipld" "block_link" (func $block_link (type 1)))
+                 (import "vm" "exit" (func $exit (type 2)))
+                 (memory (export "memory") 1)
+                 (data (i32.const 0) "buffered block")
+                 (func (export "invoke") (param $x i32) (result i32)
+                   (drop (call $block_create
+                     (i32.const 1024) (i64.const 0x55) (i32.const 0) (i32.const 14)))
+                   (drop (call $block_link
+                     (i32.const 1028) (i32.load (i32.const 1024)) (i64.const 0xb220)
+                     (i32.const 32) (i32.const 2048) (i32.const 100)))
+                   (call $exit (i32.const {code}) (i32.const 0) (i32.const 0) (i32.const 0))
+                   unreachable))"#
+        )
+    };
+
+    for (code, written) in [(0, true), (ExitCode::USR_ILLEGAL_ARGUMENT.value(), false)] {
+        let mut tester = new_tester(
+            NetworkVersion::V18,
+            StateTreeVersion::V5,
+            MemoryBlockstore::default(),
+        )
+        .unwrap();
+
+        let sender: [Account; 1] = tester.create_accounts().unwrap();
+
+        let wasm_bin = wat::parse_str(wat(code)).unwrap();
+        let state_cid = tester.set_state(&State { count: 0 }).unwrap();
+        let actor_address = Address::new_id(10000);
+        tester
+            .set_actor_from_bin(&wasm_bin, state_cid, actor_address, TokenAmount::zero())
+            .unwrap();
+
+        tester.instantiate_machine(DummyExterns).unwrap();
+        let executor = tester.executor.as_mut().unwrap();
+
+        let message = Message {
+            from: sender[0].1,
+            to: actor_address,
+            gas_limit: 10_000_000,
+            method_num: 1,
+            ..Message::default()
+        };
+        let res = executor
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .unwrap();
+        assert_eq!(res.msg_receipt.exit_code.value(), code);
+
+        // Blocks written by an aborted call are discarded.
+        assert_eq!(executor.blockstore().has(&cid).unwrap(), written);
+    }
+}
+
+#[test]
+fn block_links_limit() {
+    // Creates a DAG-CBOR block linking to two (inline) CIDs, exiting with 0x100 + the error number
+    // on failure.
+    const WAT: &str = r#"(module
+         (type (;0;) (func (param i32 i64 i32 i32) (result i32)))
+         (type (;1;) (func (param i32 i32 i32 i32) (result i32)))
+         (import "ipld" "block_create" (func $block_create (type 0)))
+         (import "vm" "exit" (func $exit (type 1)))
+         (memory (export "memory") 1)
+         ;; [bafkqaaa, bafkqaaa]
+         (data (i32.const 0) "\82\d8\2a\45\00\01\55\00\00\d8\2a\45\00\01\55\00\00")
+         (func (export "invoke") (param $x i32) (result i32)
+           (local $err i32)
+           (local.set $err (call $block_create (i32.const 1024) (i64.const 0x71) (i32.const 0) (i32.const 17)))
+           (if (local.get $err)
+             (then
+               (call $exit (i32.add (i32.const 0x100) (local.get $err)) (i32.const 0) (i32.const 0) (i32.const 0))
+               unreachable))
+           (i32.const 0)))"#;
+
+    let run = |max_links: usize| {
+        let mut tester = new_tester(
+            NetworkVersion::V18,
+            StateTreeVersion::V5,
+            MemoryBlockstore::default(),
+        )
+        .unwrap();
+
+        let sender: [Account; 1] = tester.create_accounts().unwrap();
+        let wasm_bin = wat::parse_str(WAT).unwrap();
+        let state_cid = tester.set_state(&State { count: 0 }).unwrap();
+        let actor_address = Address::new_id(10000);
+        tester
+            .set_actor_from_bin(&wasm_bin, state_cid, actor_address, TokenAmount::zero())
+            .unwrap();
+
+        tester
+            .instantiate_machine_with_config(
+                DummyExterns,
+                |nc| {
+                    nc.max_block_links(max_links);
+                },
+                |_| (),
+            )
+            .unwrap();
+
+        let message = Message {
+            from: sender[0].1,
+            to: actor_address,
+            gas_limit: 1_000_000_000,
+            method_num: 1,
+            ..Message::default()
+        };
+
+        tester
+            .executor
+            .as_mut()
+            .unwrap()
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .unwrap()
+            .msg_receipt
+            .exit_code
+    };
+
+    assert_eq!(run(2), ExitCode::OK);
+    assert_eq!(
+        run(1),
+        ExitCode::new(0x100 + ErrorNumber::LimitExceeded as u32)
+    );
+}
+
+#[test]
+fn reverted_block_writes_dont_count() {
+    // Calls itself once (using the call stack to detect the inner call), then writes a block,
+    // exiting with 0x100 + the error number on failure. The inner call writes a block and exits
+    // with the given code.
+    let wat = |code: u32| {
+        format!(
+            r#"(module
+                 (type (;0;) (func (param i32 i32 i32) (result i32)))
+                 (type (;1;) (func (param i32 i32 i32 i32) (result i32)))
+                 (type (;2;) (func (param i32 i32 i32 i64 i32 i64 i64 i64 i64) (result i32)))
+                 (type (;3;) (func (param i32 i64 i32 i32) (result i32)))
+                 (type (;4;) (func (param i32 i32 i64 i32 i32 i32) (result i32)))
+                 (import "debug" "call_stack" (func $call_stack (type 0)))
+                 (import "vm" "exit" (func $exit (type 1)))
+                 (import "send" "send" (func $send (type 2)))
+                 (import "ipld" "block_create" (func $block_create (type 3)))
+                 (import "ipld" "block_link" (func $block_link (type 4)))
+                 (memory (export "memory") 1)
+                 ;; f010000
+                 (data (i32.const 0) "\00\90\4e")
+                 (data (i32.const 16) "block")
+                 (func $write (result i32)
+                   (drop (call $block_create
+                     (i32.const 1024) (i64.const 0x55) (i32.const 16) (i32.const 5)))
+                   (call $block_link
+                     (i32.const 1028) (i32.load (i32.const 1024)) (i64.const 0xb220)
+                     (i32.const 32) (i32.const 3072) (i32.const 100)))
+                 (func (export "invoke") (param $x i32) (result i32)
+                   (local $err i32)
+                   (drop (call $call_stack (i32.const 1100) (i32.const 2048) (i32.const 64)))
+                   (if (i32.ge_u (i32.load (i32.const 1100)) (i32.const 2))
+                     (then
+                       (drop (call $write))
+                       (call $exit (i32.const {code}) (i32.const 0) (i32.const 0) (i32.const 0))
+                       unreachable))
+                   (drop (call $send (i32.const 1200) (i32.const 0) (i32.const 3) (i64.const 2) (i32.const 0) (i64.const 0) (i64.const 0) (i64.const -1) (i64.const 0)))
+                   (local.set $err (call $write))
+                   (if (local.get $err)
+                     (then
+                       (call $exit (i32.add (i32.const 0x100) (local.get $err)) (i32.const 0) (i32.const 0) (i32.const 0))
+                       unreachable))
+                   (i32.const 0)))"#
+        )
+    };
+
+    let run = |inner_code: u32| {
+        // `debug::call_stack` is available from NV21.
+        let mut tester = new_tester(
+            NetworkVersion::V21,
+            StateTreeVersion::V5,
+            MemoryBlockstore::default(),
+        )
+        .unwrap();
+
+        let sender: [Account; 1] = tester.create_accounts().unwrap();
+        let wasm_bin = wat::parse_str(wat(inner_code)).unwrap();
+        let state_cid = tester.set_state(&State { count: 0 }).unwrap();
+        let actor_address = Address::new_id(10000);
+        tester
+            .set_actor_from_bin(&wasm_bin, state_cid, actor_address, TokenAmount::zero())
+            .unwrap();
+
+        tester
+            .instantiate_machine_with_config(
+                DummyExterns,
+                |nc| {
+                    nc.enable_actor_debugging()
+                        .max_blocks_written_per_message(1);
+                },
+                |_| (),
+            )
+            .unwrap();
+
+        let message = Message {
+            from: sender[0].1,
+            to: actor_address,
+            gas_limit: 1_000_000_000,
+            method_num: 2,
+            ..Message::default()
+        };
+
+        tester
+            .executor
+            .as_mut()
+            .unwrap()
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .unwrap()
+            .msg_receipt
+            .exit_code
+    };
+
+    // The inner call's write counts towards the limit...
+    assert_eq!(
+        run(0),
+        ExitCode::new(0x100 + ErrorNumber::LimitExceeded as u32)
+    );
+    // ...unless the call is reverted.
+    assert_eq!(run(ExitCode::USR_ILLEGAL_ARGUMENT.value()), ExitCode::OK);
+}
+
+#[test]
+fn memory_copies_charged_from_nv21() {
+    // Copies the actor's state root into memory.
+    const WAT: &str = r#"(module
+        (type (;0;) (func (param i32 i32 i32) (result i32)))
+        (import "self" "root" (func $root (type 0)))
+        (memory (export "memory") 1)
+        (func (export "invoke") (param $x i32) (result i32)
+          (drop (call $root (i32.const 0) (i32.const 4) (i32.const 100)))
+          (i32.const 0)))"#;
+
+    let gas_used = |nv| {
+        let res = apply_wat_at(nv, WAT);
+        assert!(res.msg_receipt.exit_code.is_success());
+        res.msg_receipt.gas_used
+    };
+    // Copying the CID is free before NV21.
+    assert!(gas_used(NetworkVersion::V21) > gas_used(NetworkVersion::V20));
+}
+
+#[test]
+fn reserved_exit_code() {
+    // Actors may not exit with codes reserved for the system.
+    for code in 1..ExitCode::FIRST_USER_EXIT_CODE {
+        test_exitcode(&exit_wat(code), ExitCode::SYS_ILLEGAL_EXIT_CODE);
+    }
+}
+
+#[test]
+fn syscall_recording() {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let sender: [Account; 1] = tester.create_accounts().unwrap();
+
+    // Calls `vm::message_context`, `ipld::block_open` with an invalid CID, and `self::root`, then
+    // exits with `0x100 + ` the length of the root CID.
+    let wasm_bin = wat::parse_str(
+        r#"(module
+             (type (;0;) (func (param i32) (result i32)))
+             (type (;1;) (func (param i32 i32) (result i32)))
+             (type (;2;) (func (param i32 i32 i32) (result i32)))
+             (type (;3;) (func (param i32 i32 i32 i32) (result i32)))
+             (import "vm" "message_context" (func $message_context (type 0)))
+             (import "ipld" "block_open" (func $block_open (type 1)))
+             (import "self" "root" (func $root (type 2)))
+             (import "vm" "exit" (func $exit (type 3)))
+             (memory (export "memory") 1)
+             (func (export "invoke") (param $x i32) (result i32)
+               (drop (call $message_context (i32.const 0)))
+               (drop (call $block_open (i32.const 1024) (i32.const 2048)))
+               (drop (call $root (i32.const 3072) (i32.const 4096) (i32.const 100)))
+               (drop (call $exit (i32.add (i32.const 0x100) (i32.load (i32.const 3072))) (i32.const 0) (i32.const 0) (i32.const 0)))
+               unreachable))"#,
+    )
+    .unwrap();
+
+    let state_cid = tester.set_state(&State { count: 0 }).unwrap();
+    let actor_address = Address::new_id(10000);
+    tester
+        .set_actor_from_bin(&wasm_bin, state_cid, actor_address, TokenAmount::zero())
+        .unwrap();
+
+    tester
+        .instantiate_machine_with_config(
+            DummyExterns,
+            |_| (),
+            |mc| {
+                mc.enable_syscall_recording();
+            },
+        )
+        .unwrap();
+
+    let message = Message {
+        from: sender[0].1,
+        to: actor_address,
+        gas_limit: 1_000_000_000,
+        method_num: 1,
+        ..Message::default()
+    };
+
+    let executor = tester.executor.as_mut().unwrap();
+    let res = executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap();
+    let exit_code = ExitCode::new(0x100 + state_cid.to_bytes().len() as u32);
+    assert_eq!(res.msg_receipt.exit_code, exit_code);
+
+    let records: Vec<SyscallRecord> = res
+        .exec_trace
+        .iter()
+        .filter_map(|evt| match evt {
+            ExecutionEvent::Syscall(record) => Some(record.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(records.len(), 4);
+
+    assert_eq!(
+        (records[0].module, records[0].name),
+        ("vm", "message_context")
+    );
+    assert!(records[0].args.is_empty());
+    assert!(matches!(&records[0].result, SyscallOutcome::Ok(v) if !v.is_empty()));
+
+    assert_eq!((records[1].module, records[1].name), ("ipld", "block_open"));
+    // The return pointer isn't recorded.
+    assert_eq!(records[1].args, vec![2048]);
+    assert_eq!(
+        records[1].result,
+        SyscallOutcome::Error(ErrorNumber::IllegalArgument)
+    );
+
+    // The root CID is written into the output buffer.
+    assert_eq!((records[2].module, records[2].name), ("self", "root"));
+    let mut buf = vec![0u8; 100];
+    for (offset, bytes) in &records[2].writes {
+        let start = *offset as usize - 4096;
+        buf[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+    let cid = state_cid.to_bytes();
+    assert_eq!(&buf[..cid.len()], &cid[..]);
+
+    assert_eq!(records[3].result, SyscallOutcome::Abort(exit_code));
+
+    // Replaying the recorded syscalls reproduces the invocation without a machine...
+    assert_eq!(
+        replay_syscalls(&wasm_bin, 0, records.clone()).unwrap(),
+        ReplayOutcome::Aborted(exit_code)
+    );
+
+    // ...and detects diverging executions.
+    let mut diverged = records.clone();
+    diverged.remove(1);
+    assert!(replay_syscalls(&wasm_bin, 0, diverged).is_err());
+    assert!(replay_syscalls(&wasm_bin, 0, records[..2].to_vec()).is_err());
+}
+
+/// Returns a module that upgrades itself to the given code, exiting with `0x100 + errno` if the
+/// syscall fails and `0x200 + exit_code` if the upgrade entrypoint fails.
+fn upgrade_wat(new_code_cid: &Cid) -> String {
+    let cid: String = new_code_cid
+        .to_bytes()
+        .iter()
+        .map(|b| format!("\\{:02x}", b))
+        .collect();
+    format!(
+        r#"(module
+             (type (;0;) (func (param i32 i32 i32) (result i32)))
+             (type (;1;) (func (param i32 i32 i32 i32) (result i32)))
+             (import "actor" "upgrade_actor" (func $upgrade_actor (type 0)))
+             (import "vm" "exit" (func $exit (type 1)))
+             (memory (export "memory") 1)
+             (data (i32.const 0) "{cid}")
+             (func (export "invoke") (param $x i32) (result i32)
+               (local $err i32)
+               (local.set $err (call $upgrade_actor (i32.const 1024) (i32.const 0) (i32.const 0)))
+               (if (local.get $err)
+                 (then
+                   (call $exit (i32.add (i32.const 0x100) (local.get $err)) (i32.const 0) (i32.const 0) (i32.const 0))
+                   unreachable))
+               (call $exit (i32.add (i32.const 0x200) (i32.load (i32.const 1024))) (i32.const 0) (i32.const 0) (i32.const 0))
+               unreachable))"#
+    )
+}
+
+#[test]
+fn upgrade_actor() {
+    let run = |nv: NetworkVersion, new_code: Option<Cid>| {
+        let mut tester = new_tester(nv, StateTreeVersion::V5, MemoryBlockstore::default()).unwrap();
+
+        let sender: [Account; 1] = tester.create_accounts().unwrap();
+
+        // Upgrade to the account actor's code by default.
+        let new_code = new_code.unwrap_or_else(|| {
+            tester
+                .state_tree
+                .as_ref()
+                .unwrap()
+                .get_actor(sender[0].0)
+                .unwrap()
+                .unwrap()
+                .code
+        });
+        let wasm_bin = wat::parse_str(upgrade_wat(&new_code)).unwrap();
+
+        let state_cid = tester.set_state(&State { count: 0 }).unwrap();
+        let actor_address = Address::new_id(10000);
+        tester
+            .set_actor_from_bin(&wasm_bin, state_cid, actor_address, TokenAmount::zero())
+            .unwrap();
+
+        tester
+            .instantiate_machine_with_config(
+                DummyExterns,
+                |_| (),
+                |mc| {
+                    mc.enable_tracing();
+                },
+            )
+            .unwrap();
+
+        let message = Message {
+            from: sender[0].1,
+            to: actor_address,
+            gas_limit: 1_000_000_000,
+            method_num: 1,
+            ..Message::default()
+        };
+
+        tester
+            .executor
+            .as_mut()
+            .unwrap()
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .unwrap()
+    };
+
+    // The syscall isn't available before NV21, so the actor can't even be instantiated.
+    assert_eq!(
+        run(NetworkVersion::V20, None).msg_receipt.exit_code,
+        ExitCode::SYS_ASSERTION_FAILED
+    );
+
+    // The account actor has no upgrade entrypoint, so the upgrade fails (and is reverted).
+    let res = run(NetworkVersion::V21, None);
+    assert_eq!(
+        res.msg_receipt.exit_code,
+        ExitCode::new(0x200 + ExitCode::SYS_INVALID_RECEIVER.value())
+    );
+    assert!(res.exec_trace.iter().any(|evt| matches!(
+        evt,
+        ExecutionEvent::Call { method, .. } if *method == fvm_shared::METHOD_UPGRADE
+    )));
+
+    // Actors can only be upgraded to code that can be deployed.
+    let missing = Cid::new_v1(
+        fvm_shared::IPLD_RAW,
+        multihash::Multihash::wrap(fvm_shared::IDENTITY_HASH, b"missing").unwrap(),
+    );
+    let res = run(NetworkVersion::V21, Some(missing));
+    assert_eq!(
+        res.msg_receipt.exit_code,
+        ExitCode::new(0x100 + ErrorNumber::NotFound as u32)
+    );
+}
+
+#[test]
+fn return_size_limits() {
+    // Returns a raw block of `size` bytes.
+    let run = |size: u32, configure: fn(&mut NetworkConfig)| {
+        let mut tester = new_tester(
+            NetworkVersion::V18,
+            StateTreeVersion::V5,
+            MemoryBlockstore::default(),
+        )
+        .unwrap();
+
+        let sender: [Account; 1] = tester.create_accounts().unwrap();
+
+        let wasm_bin = wat::parse_str(format!(
+            r#"(module
+                 (type (;0;) (func (param i32 i64 i32 i32) (result i32)))
+                 (import "ipld" "block_create" (func $block_create (type 0)))
+                 (memory (export "memory") 1)
+                 (func (export "invoke") (param $x i32) (result i32)
+                   (drop (call $block_create (i32.const 0) (i64.const 0x55) (i32.const 1024) (i32.const {size})))
+                   (i32.load (i32.const 0))))"#
+        ))
+        .unwrap();
+
+        let state_cid = tester.set_state(&State { count: 0 }).unwrap();
+        let actor_address = Address::new_id(10000);
+        tester
+            .set_actor_from_bin(&wasm_bin, state_cid, actor_address, TokenAmount::zero())
+            .unwrap();
+
+        tester
+            .instantiate_machine_with_config(DummyExterns, configure, |_| ())
+            .unwrap();
+
+        let message = Message {
+            from: sender[0].1,
+            to: actor_address,
+            gas_limit: 1_000_000_000,
+            method_num: 1,
+            ..Message::default()
+        };
+
+        tester
+            .executor
+            .as_mut()
+            .unwrap()
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .unwrap()
+    };
+
+    let res = run(100, |nc| {
+        nc.max_return_size(100);
+    });
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::OK);
+    assert_eq!(res.msg_receipt.return_data.len(), 100);
+
+    let res = run(101, |nc| {
+        nc.max_return_size(100);
+    });
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::SYS_LIMIT_EXCEEDED);
+    assert!(res.msg_receipt.return_data.is_empty());
+
+    let res = run(100, |nc| {
+        nc.max_return_bytes_per_message(99);
+    });
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::SYS_LIMIT_EXCEEDED);
+}
+
+/// Invokes the actor, executing on a machine injecting the faults configured by `config`.
+fn chaos_test(wat: &str, config: ChaosConfig) -> ApplyRet {
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        MemoryBlockstore::default(),
+    )
+    .unwrap();
+
+    let sender: [Account; 1] = tester.create_accounts().unwrap();
+
+    let wasm_bin = wat::parse_str(wat).unwrap();
+
+    let state_cid = tester.set_state(&State { count: 0 }).unwrap();
+    let actor_address = Address::new_id(10000);
+    tester
+        .set_actor_from_bin(&wasm_bin, state_cid, actor_address, TokenAmount::zero())
+        .unwrap();
+
+    let mut executor = tester
+        .instantiate_chaos_machine(DummyExterns, config)
+        .unwrap();
+
+    let message = Message {
+        from: sender[0].1,
+        to: actor_address,
+        gas_limit: 1_000_000_000,
+        method_num: 1,
+        ..Message::default()
+    };
+
+    executor
+        .execute_message(message, ApplyKind::Explicit, 100)
+        .unwrap()
+}
+
+#[test]
+fn chaos_kernel() {
+    // Calls `vm::message_context`, trapping if it fails.
+    const WAT: &str = r#"(module
+         (type (;0;) (func (param i32) (result i32)))
+         (import "vm" "message_context" (func $message_context (type 0)))
+         (memory (export "memory") 1)
+         (func (export "invoke") (param $x i32) (result i32)
+           (if (call $message_context (i32.const 0))
+             (then unreachable))
+           (i32.const 0)))"#;
+
+    let res = chaos_test(WAT, ChaosConfig::default());
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::OK);
+
+    let mut config = ChaosConfig::default();
+    config.fail_syscall("msg_context", ErrorNumber::IllegalOperation);
+    let res = chaos_test(WAT, config);
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::SYS_ILLEGAL_INSTRUCTION);
+}
+
+#[test]
+fn chaos_kernel_misprice_gas() {
+    // Writes a 1KiB block.
+    const WAT: &str = r#"(module
+         (type (;0;) (func (param i32 i64 i32 i32) (result i32)))
+         (import "ipld" "block_create" (func $block_create (type 0)))
+         (memory (export "memory") 1)
+         (func (export "invoke") (param $x i32) (result i32)
+           (if (call $block_create (i32.const 0) (i64.const 0x55) (i32.const 16) (i32.const 1024))
+             (then unreachable))
+           (i32.const 0)))"#;
+
+    let res = chaos_test(WAT, ChaosConfig::default());
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::OK);
+    let normal_gas = res.msg_receipt.gas_used;
+
+    let mut config = ChaosConfig::default();
+    config.charge_gas_percent(200);
+    let res = chaos_test(WAT, config);
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::OK);
+    assert!(res.msg_receipt.gas_used > normal_gas);
+
+    let mut config = ChaosConfig::default();
+    config.charge_gas_percent(50);
+    let res = chaos_test(WAT, config);
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::OK);
+    assert!(res.msg_receipt.gas_used < normal_gas);
+}
+
+#[test]
+fn chaos_kernel_corrupt_data() {
+    // Writes a block and reads it back, trapping if the data read differs from the data written.
+    const WAT: &str = r#"(module
+         (type (;0;) (func (param i32 i64 i32 i32) (result i32)))
+         (type (;1;) (func (param i32 i32 i32 i32 i32) (result i32)))
+         (import "ipld" "block_create" (func $block_create (type 0)))
+         (import "ipld" "block_read" (func $block_read (type 1)))
+         (memory (export "memory") 1)
+         (data (i32.const 16) "\01")
+         (func (export "invoke") (param $x i32) (result i32)
+           ;; Create a raw block from the byte at 16, storing its ID at 0.
+           (if (call $block_create (i32.const 0) (i64.const 0x55) (i32.const 16) (i32.const 1))
+             (then unreachable))
+           ;; Read the block back to 32.
+           (if (call $block_read (i32.const 4) (i32.load (i32.const 0)) (i32.const 0) (i32.const 32) (i32.const 1))
+             (then unreachable))
+           (if (i32.ne (i32.load8_u (i32.const 16)) (i32.load8_u (i32.const 32)))
+             (then unreachable))
+           (i32.const 0)))"#;
+
+    let res = chaos_test(WAT, ChaosConfig::default());
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::OK);
+
+    let mut config = ChaosConfig::default();
+    config.corrupt_data();
+    let res = chaos_test(WAT, config);
+    assert_eq!(res.msg_receipt.exit_code, ExitCode::SYS_ILLEGAL_INSTRUCTION);
+}
+
 #[test]
 fn backtraces() {
     // Note: this test **does not actually assert anything**, but it's useful to
@@ -778,6 +1610,7 @@ fn backtraces() {
             .unwrap()
     };
 
+    assert_eq!(res.failure, Some(FailureInfo::Fatal));
     println!("fatal backtrace: {}", res.failure_info.unwrap());
 
     // Now make it panic.
@@ -1037,3 +1870,87 @@ impl Blockstore for FailingBlockstore {
         self.target.put_keyed(k, block)
     }
 }
+
+#[test]
+fn reentrancy_policy() {
+    // Calls itself once (using the call stack to detect the inner call), exiting with 0x100 + the
+    // send's error number, or 0x200 + the inner call's exit code.
+    const WAT: &str = r#"(module
+         (type (;0;) (func (param i32 i32 i32) (result i32)))
+         (type (;1;) (func (param i32 i32 i32 i32) (result i32)))
+         (type (;2;) (func (param i32 i32 i32 i64 i32 i64 i64 i64 i64) (result i32)))
+         (import "debug" "call_stack" (func $call_stack (type 0)))
+         (import "vm" "exit" (func $exit (type 1)))
+         (import "send" "send" (func $send (type 2)))
+         (memory (export "memory") 1)
+         ;; f010000
+         (data (i32.const 0) "\00\90\4e")
+         (func (export "invoke") (param $x i32) (result i32)
+           (local $err i32)
+           (drop (call $call_stack (i32.const 1024) (i32.const 2048) (i32.const 64)))
+           (if (i32.ge_u (i32.load (i32.const 1024)) (i32.const 2))
+             (then (return (i32.const 0))))
+           (if (i64.ne (i64.load (i32.const 2048)) (i64.const 10000))
+             (then
+               (call $exit (i32.const 0x300) (i32.const 0) (i32.const 0) (i32.const 0))
+               unreachable))
+           (local.set $err (call $send (i32.const 1100) (i32.const 0) (i32.const 3) (i64.const 2) (i32.const 0) (i64.const 0) (i64.const 0) (i64.const -1) (i64.const 0)))
+           (if (local.get $err)
+             (then
+               (call $exit (i32.add (i32.const 0x100) (local.get $err)) (i32.const 0) (i32.const 0) (i32.const 0))
+               unreachable))
+           (call $exit (i32.add (i32.const 0x200) (i32.load (i32.const 1100))) (i32.const 0) (i32.const 0) (i32.const 0))
+           unreachable))"#;
+
+    let run = |policy: ReentrancyPolicy| {
+        // `debug::call_stack` is available from NV21.
+        let mut tester = new_tester(
+            NetworkVersion::V21,
+            StateTreeVersion::V5,
+            MemoryBlockstore::default(),
+        )
+        .unwrap();
+
+        let sender: [Account; 1] = tester.create_accounts().unwrap();
+        let wasm_bin = wat::parse_str(WAT).unwrap();
+        let state_cid = tester.set_state(&State { count: 0 }).unwrap();
+        let actor_address = Address::new_id(10000);
+        tester
+            .set_actor_from_bin(&wasm_bin, state_cid, actor_address, TokenAmount::zero())
+            .unwrap();
+
+        tester
+            .instantiate_machine_with_config(
+                DummyExterns,
+                |nc| {
+                    nc.enable_actor_debugging().reentrancy_policy(policy);
+                },
+                |_| (),
+            )
+            .unwrap();
+
+        let message = Message {
+            from: sender[0].1,
+            to: actor_address,
+            gas_limit: 1_000_000_000,
+            method_num: 2,
+            ..Message::default()
+        };
+
+        tester
+            .executor
+            .as_mut()
+            .unwrap()
+            .execute_message(message, ApplyKind::Explicit, 100)
+            .unwrap()
+            .msg_receipt
+            .exit_code
+    };
+
+    assert_eq!(run(ReentrancyPolicy::Allow), ExitCode::new(0x200));
+    assert_eq!(run(ReentrancyPolicy::Warn), ExitCode::new(0x200));
+    assert_eq!(
+        run(ReentrancyPolicy::Deny),
+        ExitCode::new(0x100 + ErrorNumber::Forbidden as u32)
+    );
+}
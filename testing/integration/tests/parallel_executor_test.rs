@@ -0,0 +1,136 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+mod bundles;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bundles::*;
+use cid::Cid;
+use fvm::executor::{ApplyKind, Executor};
+use fvm::machine::{BatchMessage, Machine, ParallelExecutor};
+use fvm_integration_tests::dummy::DummyExterns;
+use fvm_integration_tests::tester::IntegrationExecutor;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_encoding::tuple::*;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::message::Message;
+use fvm_shared::state::StateTreeVersion;
+use fvm_shared::version::NetworkVersion;
+use fvm_shared::METHOD_SEND;
+use num_traits::Zero;
+
+/// Returns the balance of the burnt funds actor, as a raw block holding a `sys::TokenAmount`.
+const BURNT_FUNDS_READER: &str = r#"(module
+     (type (;0;) (func (param i32 i64) (result i32)))
+     (type (;1;) (func (param i32 i64 i32 i32) (result i32)))
+     (import "actor" "balance_of" (func $balance_of (type 0)))
+     (import "ipld" "block_create" (func $block_create (type 1)))
+     (memory (export "memory") 1)
+     (func (export "invoke") (param $x i32) (result i32)
+       (if (call $balance_of (i32.const 16) (i64.const 99))
+         (then unreachable))
+       (if (call $block_create (i32.const 0) (i64.const 0x55) (i32.const 16) (i32.const 16))
+         (then unreachable))
+       (i32.load (i32.const 0))))"#;
+
+/// A thread-safe in-memory blockstore.
+#[derive(Default)]
+struct SyncBlockstore(Mutex<HashMap<Cid, Vec<u8>>>);
+
+impl Blockstore for SyncBlockstore {
+    fn get(&self, k: &Cid) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.0.lock().unwrap().get(k).cloned())
+    }
+
+    fn put_keyed(&self, k: &Cid, block: &[u8]) -> anyhow::Result<()> {
+        self.0.lock().unwrap().insert(*k, block.into());
+        Ok(())
+    }
+}
+
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, Default)]
+struct State {
+    count: u64,
+}
+
+#[test]
+fn matches_serial_execution() {
+    let blockstore = Arc::new(SyncBlockstore::default());
+    let mut tester = new_tester(
+        NetworkVersion::V18,
+        StateTreeVersion::V5,
+        blockstore.clone(),
+    )
+    .unwrap();
+
+    let [(_, a), (_, b), (_, c), (_, d), (_, e)] = tester
+        .create_accounts_with_balance(&TokenAmount::from_whole(1000))
+        .unwrap();
+    let reader = Address::new_id(10000);
+    let state_cid = tester.set_state(&State::default()).unwrap();
+    tester
+        .set_actor_from_bin(
+            &wat::parse_str(BURNT_FUNDS_READER).unwrap(),
+            state_cid,
+            reader,
+            TokenAmount::zero(),
+        )
+        .unwrap();
+
+    let (engine, machine) = tester
+        .new_machine(DummyExterns, None, |_| (), |_| ())
+        .unwrap();
+    let context = machine.context().clone();
+
+    let message = |from, to, sequence, method_num| -> BatchMessage {
+        let message = Message {
+            from,
+            to,
+            sequence,
+            method_num,
+            value: TokenAmount::from_atto((method_num == METHOD_SEND) as u8),
+            gas_limit: 10_000_000,
+            gas_fee_cap: TokenAmount::from_atto(200),
+            gas_premium: TokenAmount::from_atto(1),
+            ..Message::default()
+        };
+        (message, ApplyKind::Explicit, 100)
+    };
+    // The sends are independent of each other, but every message burns gas fees, which the
+    // reader observes.
+    let msgs = vec![
+        message(a, b, 0, METHOD_SEND),
+        message(c, reader, 0, 1),
+        message(d, e, 0, METHOD_SEND),
+        message(a, b, 1, METHOD_SEND),
+        message(c, reader, 1, 1),
+    ];
+
+    let mut serial = IntegrationExecutor::new(engine.clone(), machine).unwrap();
+    let serial_rets: Vec<_> = msgs
+        .iter()
+        .cloned()
+        .map(|(msg, apply_kind, raw_length)| {
+            serial.execute_message(msg, apply_kind, raw_length).unwrap()
+        })
+        .collect();
+    let serial_root = serial.flush().unwrap();
+
+    let mut parallel: ParallelExecutor<Arc<SyncBlockstore>, DummyExterns> =
+        ParallelExecutor::new(engine, context, blockstore, DummyExterns);
+    let parallel_rets = parallel.execute_messages(msgs).unwrap();
+
+    for (serial, parallel) in serial_rets.iter().zip(&parallel_rets) {
+        assert_eq!(serial.msg_receipt.exit_code, ExitCode::OK);
+        assert_eq!(serial.msg_receipt, parallel.msg_receipt);
+    }
+    // The reader saw the first message's fees being burnt.
+    assert_ne!(
+        serial_rets[1].msg_receipt.return_data,
+        serial_rets[4].msg_receipt.return_data
+    );
+    assert_eq!(parallel.state_root(), serial_root);
+}
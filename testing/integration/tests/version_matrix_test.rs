@@ -0,0 +1,110 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+#![cfg(test)]
+
+use fvm::machine::SUPPORTED_VERSIONS;
+use fvm_integration_tests::bundle;
+use fvm_integration_tests::dummy::DummyExterns;
+use fvm_integration_tests::matrix::{Expectation, MatrixCase, Mismatch, VersionMatrix};
+use fvm_integration_tests::tester::{BasicTester, Tester};
+use fvm_ipld_blockstore::MemoryBlockstore;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+use fvm_shared::error::ExitCode;
+use fvm_shared::message::Message;
+use fvm_shared::state::StateTreeVersion;
+use fvm_shared::version::NetworkVersion;
+use fvm_shared::METHOD_SEND;
+
+fn matrix() -> VersionMatrix {
+    VersionMatrix::supported()
+}
+
+/// Sets up a tester with a single account, using the same builtin actors for every version (we're
+/// testing the FVM here, not the actors).
+fn setup(nv: NetworkVersion) -> anyhow::Result<BasicTester> {
+    let blockstore = MemoryBlockstore::default();
+    let root = bundle::import_bundle(&blockstore, actors_v10::BUNDLE_CAR)?;
+    let mut tester = Tester::new(nv, StateTreeVersion::V5, root, blockstore)?;
+    tester.create_account()?;
+    tester.instantiate_machine(DummyExterns)?;
+    Ok(tester)
+}
+
+fn send(sequence: u64, value: u64) -> Message {
+    // create_accounts is deterministic, so the first account always gets the same ID.
+    Message {
+        from: Address::new_id(100),
+        to: Address::new_delegated(10, b"foobar").unwrap(),
+        gas_limit: 1000000000,
+        method_num: METHOD_SEND,
+        sequence,
+        value: TokenAmount::from_atto(value),
+        ..Message::default()
+    }
+}
+
+#[test]
+fn covers_supported_versions() {
+    let versions = matrix().versions().to_vec();
+    assert_eq!(versions.first(), Some(SUPPORTED_VERSIONS.start()));
+    assert!(versions.iter().all(|nv| SUPPORTED_VERSIONS.contains(nv)));
+}
+
+#[test]
+fn sends_behave_the_same_in_all_versions() {
+    let cases = [
+        MatrixCase::new("create placeholder", send(0, 0), Expectation::ok()),
+        MatrixCase::new("transfer", send(1, 1), Expectation::ok()),
+        MatrixCase::new(
+            "insufficient funds",
+            send(2, 1_000_000),
+            Expectation::exit_code(ExitCode::SYS_INSUFFICIENT_FUNDS),
+        ),
+    ];
+    let report = matrix().run(setup, &cases).unwrap();
+    report.check().unwrap();
+
+    assert_eq!(report.outcomes.len(), cases.len());
+    for outcomes in report.outcomes.values() {
+        assert_eq!(
+            outcomes.keys().copied().collect::<Vec<_>>(),
+            matrix().versions()
+        );
+    }
+}
+
+#[test]
+fn reports_mismatches() {
+    let cases = [
+        // Wrong gas everywhere.
+        MatrixCase::new("pinned gas", send(0, 0), Expectation::ok().with_gas(1)),
+        // Wrong exit code in a single version.
+        MatrixCase::new("transfer", send(1, 1), Expectation::ok()).with_override(
+            NetworkVersion::V19,
+            Expectation::exit_code(ExitCode::SYS_INSUFFICIENT_FUNDS),
+        ),
+    ];
+    let report = matrix().run(setup, &cases).unwrap();
+    assert!(report.check().is_err());
+
+    let mut pinned = 0;
+    for mismatch in &report.mismatches {
+        match mismatch {
+            Mismatch::Outcome { case, nv, .. } if case == "pinned gas" => {
+                assert!(matrix().versions().contains(nv));
+                pinned += 1;
+            }
+            Mismatch::Outcome {
+                case, nv, actual, ..
+            } => {
+                assert_eq!(case, "transfer");
+                assert_eq!(*nv, NetworkVersion::V19);
+                assert_eq!(actual.exit_code, ExitCode::OK);
+            }
+            Mismatch::GasDrift { case, .. } => panic!("unexpected gas drift in {case}"),
+        }
+    }
+    assert_eq!(pinned, matrix().versions().len());
+    assert_eq!(report.mismatches.len(), pinned + 1);
+}
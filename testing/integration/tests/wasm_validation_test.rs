@@ -0,0 +1,133 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use fvm::engine::{validate_wasm_for_deployment, WasmLimits, WasmValidationError};
+use fvm::machine::NetworkConfig;
+use fvm_shared::version::NetworkVersion;
+use fvm_test_actors::wasm_bin::HELLO_WORLD_ACTOR_BINARY;
+
+fn validate(wat: &str, nc: &NetworkConfig) -> Result<(), WasmValidationError> {
+    let wasm = wat::parse_str(wat).unwrap();
+    validate_wasm_for_deployment(&wasm, nc).map(|_| ())
+}
+
+#[test]
+fn validates_sdk_actors() {
+    let mut nc = NetworkConfig::new(NetworkVersion::V18);
+    // The standard library may use floats (e.g., for formatting).
+    nc.wasm_limits(WasmLimits {
+        allow_floats: true,
+        ..nc.wasm_limits
+    });
+    let stats = validate_wasm_for_deployment(HELLO_WORLD_ACTOR_BINARY, &nc).unwrap();
+    assert_eq!(stats.size, HELLO_WORLD_ACTOR_BINARY.len());
+    assert!(stats.functions > 0);
+}
+
+#[test]
+fn rejects_non_syscall_imports() {
+    let nc = NetworkConfig::new(NetworkVersion::V18);
+
+    validate(
+        r#"(module (import "vm" "exit" (func (param i32 i32 i32))))"#,
+        &nc,
+    )
+    .unwrap();
+
+    let err = validate(r#"(module (import "env" "foo" (func)))"#, &nc).unwrap_err();
+    assert_eq!(
+        err,
+        WasmValidationError::IllegalImport {
+            module: "env".into(),
+            name: "foo".into(),
+        }
+    );
+
+    // Syscalls introduced by a network upgrade may only be imported from that network version.
+    for (module, name) in [
+        ("actor", "upgrade_actor"),
+        ("crypto", "verify_aggregate_signature"),
+        ("crypto", "verify_winning_post"),
+        ("vm", "caller_code_cid"),
+        ("self", "code_cid"),
+        ("gas", "milestone"),
+        ("debug", "log_at"),
+        ("debug", "call_stack"),
+    ] {
+        let wat = format!(r#"(module (import "{module}" "{name}" (func)))"#);
+        let err = validate(&wat, &nc).unwrap_err();
+        assert!(matches!(err, WasmValidationError::IllegalImport { .. }));
+        validate(&wat, &NetworkConfig::new(NetworkVersion::V21)).unwrap();
+    }
+
+    // Only functions may be imported, even from syscall modules.
+    let err = validate(r#"(module (import "vm" "exit" (memory 1)))"#, &nc).unwrap_err();
+    assert!(matches!(err, WasmValidationError::IllegalImport { .. }));
+    let err = validate(
+        r#"(module (import "gas" "gas_counter" (global (mut i64))))"#,
+        &nc,
+    )
+    .unwrap_err();
+    assert!(matches!(err, WasmValidationError::IllegalImport { .. }));
+}
+
+#[test]
+fn rejects_start_functions() {
+    let nc = NetworkConfig::new(NetworkVersion::V18);
+    let err = validate(r#"(module (func $init) (start $init))"#, &nc).unwrap_err();
+    assert_eq!(err, WasmValidationError::StartFunction);
+}
+
+#[test]
+fn rejects_floats_unless_allowed() {
+    const FLOATS: &str = r#"(module
+        (func (param f64 f64) (result f64) (f64.add (local.get 0) (local.get 1)))
+    )"#;
+
+    let mut nc = NetworkConfig::new(NetworkVersion::V18);
+    let err = validate(FLOATS, &nc).unwrap_err();
+    assert!(matches!(err, WasmValidationError::FloatingPoint(_)));
+    assert!(!err.is_limit_exceeded());
+
+    nc.wasm_limits(WasmLimits {
+        allow_floats: true,
+        ..nc.wasm_limits
+    });
+    validate(FLOATS, &nc).unwrap();
+}
+
+#[test]
+fn limits_tables_and_memories() {
+    let nc = NetworkConfig::new(NetworkVersion::V18);
+    let limits = nc.wasm_limits;
+
+    validate(r#"(module (memory 17) (table 10 funcref))"#, &nc).unwrap();
+
+    let err = validate(
+        &format!("(module (memory {}))", limits.max_memory_pages + 1),
+        &nc,
+    )
+    .unwrap_err();
+    assert_eq!(
+        err,
+        WasmValidationError::MemoryTooLarge {
+            pages: limits.max_memory_pages + 1,
+            max: limits.max_memory_pages,
+        }
+    );
+    assert!(err.is_limit_exceeded());
+
+    // Declared maximums count too.
+    let err = validate(
+        &format!(
+            "(module (table 1 {} funcref))",
+            limits.max_table_elements + 1
+        ),
+        &nc,
+    )
+    .unwrap_err();
+    assert!(matches!(err, WasmValidationError::TableTooLarge { .. }));
+
+    // Malformed modules are reported as such.
+    let err = validate_wasm_for_deployment(b"not wasm", &nc).unwrap_err();
+    assert!(matches!(err, WasmValidationError::Invalid(_)));
+}
@@ -1,10 +1,38 @@
 // Copyright 2021-2023 Protocol Labs
 // SPDX-License-Identifier: Apache-2.0, MIT
+use cid::Cid;
+use fvm_ipld_encoding::ipld_block::IpldBlock;
+use fvm_ipld_encoding::RawBytes;
 use fvm_sdk as sdk;
 use fvm_shared::address::{Address, SECP_PUB_LEN};
 use fvm_shared::bigint::Zero;
+use fvm_shared::clock::ChainEpoch;
 use fvm_shared::error::ExitCode;
 use sdk::sys::ErrorNumber;
+use serde_tuple::*;
+
+/// The init actor's `Exec` parameters.
+#[derive(Serialize_tuple)]
+struct ExecParams {
+    code_cid: Cid,
+    constructor_params: RawBytes,
+}
+
+/// The init actor's `Exec` return value.
+#[derive(Deserialize_tuple)]
+struct ExecReturn {
+    id_address: Address,
+    robust_address: Address,
+}
+
+/// The multisig actor's constructor parameters.
+#[derive(Serialize_tuple)]
+struct MultisigConstructorParams {
+    signers: Vec<Address>,
+    num_approvals_threshold: u64,
+    unlock_duration: ChainEpoch,
+    start_epoch: ChainEpoch,
+}
 
 #[no_mangle]
 pub fn invoke(params: u32) -> u32 {
@@ -78,6 +106,50 @@ pub fn invoke(params: u32) -> u32 {
                 "system actor shouldn't have a 'delegated' address"
             );
         }
+        // create an actor through the init actor, then send to new f1 and f4 addresses (in the
+        // same message, while the init actor's new state is still buffered).
+        6 => {
+            // The builtin multisig actor type.
+            let msig_cid = sdk::actor::get_code_cid_for_type(9);
+            let params = ExecParams {
+                code_cid: msig_cid,
+                constructor_params: RawBytes::serialize(MultisigConstructorParams {
+                    signers: vec![Address::new_id(sdk::message::receiver())],
+                    num_approvals_threshold: 1,
+                    unlock_duration: 0,
+                    start_epoch: 0,
+                })
+                .unwrap(),
+            };
+            let ret = sdk::send::send(
+                &Address::new_id(1),
+                2,
+                IpldBlock::serialize_cbor(&params).unwrap(),
+                Zero::zero(),
+                None,
+                Default::default(),
+            )
+            .unwrap();
+            assert!(ret.exit_code.is_success(), "exec failed: {}", ret.exit_code);
+            let ret: ExecReturn = ret.return_data.unwrap().deserialize().unwrap();
+            assert_eq!(
+                sdk::actor::resolve_address(&ret.robust_address),
+                ret.id_address.id().ok()
+            );
+
+            for addr in [
+                Address::new_secp256k1(&[1; SECP_PUB_LEN]).unwrap(),
+                Address::new_delegated(10, b"after exec").unwrap(),
+            ] {
+                assert!(
+                    sdk::send::send(&addr, 0, None, Zero::zero(), None, Default::default())
+                        .unwrap()
+                        .exit_code
+                        .is_success()
+                );
+                assert!(sdk::actor::resolve_address(&addr).is_some());
+            }
+        }
         _ => sdk::vm::abort(
             ExitCode::USR_UNHANDLED_MESSAGE.value(),
             Some("unknown method number"),
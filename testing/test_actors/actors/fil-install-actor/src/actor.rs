@@ -0,0 +1,39 @@
+// Copyright 2021-2023 Protocol Labs
+// SPDX-License-Identifier: Apache-2.0, MIT
+use fvm_sdk as sdk;
+use fvm_shared::address::Address;
+use fvm_shared::econ::TokenAmount;
+
+/// The ID of the actor created with the installed code.
+const NEW_ACTOR_ID: u64 = 1000;
+
+/// Installs the Wasm module passed as parameters, creates an actor running it, and invokes the new
+/// actor. Must be deployed at the test actor ID allowed to create actors.
+#[no_mangle]
+pub fn invoke(params: u32) -> u32 {
+    sdk::initialize();
+
+    let wasm = sdk::message::params_raw(params)
+        .unwrap()
+        .expect("expected wasm parameters")
+        .data;
+
+    // The code can be used as soon as it's installed, within the same message.
+    let code = sdk::actor::install_actor_code(&wasm).unwrap();
+    sdk::actor::create_actor(NEW_ACTOR_ID, &code, None).unwrap();
+
+    let new_actor = Address::new_id(NEW_ACTOR_ID);
+    assert_eq!(sdk::actor::get_actor_code_cid(&new_actor), Some(code));
+    let resp = sdk::send::send(
+        &new_actor,
+        1,
+        None,
+        TokenAmount::default(),
+        None,
+        Default::default(),
+    )
+    .unwrap();
+    assert!(resp.exit_code.is_success(), "{}", resp.exit_code);
+
+    0
+}
@@ -7,6 +7,7 @@ use fvm_shared::crypto::hash::SupportedHashes as SharedSupportedHashes;
 use fvm_shared::crypto::signature::{Signature, SECP_SIG_LEN};
 use fvm_shared::error::ErrorNumber;
 use fvm_shared::sector::RegisteredSealProof;
+use fvm_shared::version::NetworkVersion;
 use multihash::derive::Multihash;
 use multihash::{Blake2b256, Blake2b512, Keccak256, Ripemd160, Sha2_256};
 
@@ -37,6 +38,7 @@ pub fn invoke(_: u32) -> u32 {
     test_network_context();
     test_message_context();
     test_balance();
+    test_actor_types();
 
     #[cfg(coverage)]
     sdk::debug::store_artifact("syscall_actor.profraw", minicov::capture_coverage());
@@ -90,6 +92,26 @@ fn test_signature() {
     let res = sdk::crypto::verify_signature(&signature, &address, invalid_message.as_slice());
     assert_eq!(res, Ok(false));
 
+    // test that, from NV21, other signers are resolved through their account actors: missing
+    // actors aren't found, non-account actors can't sign, and accounts sign with their key
+    // addresses. Before NV21, only key addresses are supported.
+    //
+    let res = sdk::crypto::verify_signature(&signature, &Address::new_id(u64::MAX), &message);
+    if sdk::network::version() >= NetworkVersion::V21 {
+        assert_eq!(res, Err(ErrorNumber::NotFound));
+    } else {
+        assert_eq!(res, Err(ErrorNumber::IllegalArgument));
+    }
+    let res = sdk::crypto::verify_signature(&signature, &Address::new_id(0), &message);
+    assert_eq!(res, Err(ErrorNumber::IllegalArgument));
+    let signer = Address::new_id(sdk::actor::resolve_address(&address).expect("signer not found"));
+    let res = sdk::crypto::verify_signature(&signature, &signer, &message);
+    if sdk::network::version() >= NetworkVersion::V21 {
+        assert_eq!(res, Ok(true));
+    } else {
+        assert_eq!(res, Err(ErrorNumber::IllegalArgument));
+    }
+
     // test that calling sdk::sys::crypto::verify_signature with invalid parameters result
     // in correct error value
     //
@@ -322,10 +344,13 @@ fn test_compute_unsealed_sector_cid() {
 
 fn test_network_context() {
     use fvm_shared::econ::TokenAmount;
-    use fvm_shared::version::NetworkVersion;
     assert_eq!(sdk::network::chain_id(), ChainID::from(1)); // hehe we are ETH now
     assert_eq!(sdk::network::curr_epoch(), 0);
-    assert_eq!(sdk::network::version(), NetworkVersion::V18);
+    // We're run on NV18 and NV21.
+    assert!(matches!(
+        sdk::network::version(),
+        NetworkVersion::V18 | NetworkVersion::V21
+    ));
     assert_eq!(sdk::network::tipset_timestamp(), 0);
     assert_eq!(sdk::network::base_fee(), TokenAmount::from_atto(100));
 }
@@ -350,3 +375,26 @@ fn test_balance() {
         Some(sdk::sself::current_balance())
     );
 }
+
+fn test_actor_types() {
+    // Non-existent actors have neither a code CID nor a type.
+    let missing = Address::new_id(9191919);
+    assert_eq!(sdk::actor::get_actor_code_cid(&missing), None);
+    assert_eq!(sdk::actor::resolve_builtin_actor_type(&missing), None);
+
+    // The caller is a builtin account actor, and its type maps back to its code CID.
+    let caller = Address::new_id(sdk::message::caller());
+    let caller_code = sdk::actor::get_actor_code_cid(&caller).expect("caller has no code");
+    let caller_type =
+        sdk::actor::get_builtin_actor_type(&caller_code).expect("caller isn't a builtin actor");
+    assert_eq!(
+        sdk::actor::resolve_builtin_actor_type(&caller),
+        Some(caller_type)
+    );
+    assert_eq!(sdk::actor::get_code_cid_for_type(caller_type), caller_code);
+
+    // We're not a builtin actor.
+    let receiver = Address::new_id(sdk::message::receiver());
+    assert!(sdk::actor::get_actor_code_cid(&receiver).is_some());
+    assert_eq!(sdk::actor::resolve_builtin_actor_type(&receiver), None);
+}
@@ -24,6 +24,7 @@ const ACTORS: &[&str] = &[
     "fil_create_actor",
     "fil_oom_actor",
     "fil_sself_actor",
+    "fil_install_actor",
 ];
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -36,3 +36,4 @@ pub const GASLIMIT_ACTOR_BINARY: &[u8] = include_bytes!(wasm_bin!("fil_gaslimit_
 pub const CREATE_ACTOR_BINARY: &[u8] = include_bytes!(wasm_bin!("fil_create_actor"));
 pub const OOM_ACTOR_BINARY: &[u8] = include_bytes!(wasm_bin!("fil_oom_actor"));
 pub const SSELF_ACTOR_BINARY: &[u8] = include_bytes!(wasm_bin!("fil_sself_actor"));
+pub const INSTALL_ACTOR_BINARY: &[u8] = include_bytes!(wasm_bin!("fil_install_actor"));